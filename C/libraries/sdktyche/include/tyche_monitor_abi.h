@@ -0,0 +1,527 @@
+/* Generated by the monitor_abi crate, do not edit by hand. */
+#pragma once
+
+#define TYCHE_NB_ARGS    6
+#define TYCHE_NB_RESULTS 6
+
+/* Status */
+#define TYCHE_STATUS_SUCCESS 0
+#define TYCHE_STATUS_FAILURE 1
+#define TYCHE_STATUS_DOMAIN_REVOKED 66
+
+/* Report modes */
+#define TYCHE_REPORT_CREATE 0
+#define TYCHE_REPORT_READ 1
+#define TYCHE_REPORT_READ_CERTIFICATE 2
+
+/* Lease conditions */
+#define TYCHE_LEASE_ON_RETURN 1
+#define TYCHE_LEASE_SWITCHES 2
+#define TYCHE_LEASE_DEADLINE 3
+#define TYCHE_LEASE_TRAP 0x100000000UL
+
+/* Notifications */
+#define TYCHE_NOTIFICATION_NO_VECTOR 0
+#define TYCHE_NOTIFICATION_MIN_VECTOR 32
+#define TYCHE_NOTIFICATION_MAX_VECTOR 255
+
+/* Revocation notifications */
+#define TYCHE_REVOCATION_TRAP 0x200000000UL
+
+/* Channels */
+#define TYCHE_CHANNEL_NB_WORDS 3
+#define TYCHE_CHANNEL_NO_REGION 0xffffffffffffffffUL
+#define TYCHE_CHANNEL_TRAP 0x400000000UL
+
+/* Information-flow labels */
+#define TYCHE_LABELS_INITIAL 0x8000000000000000UL
+#define TYCHE_LABELS_SEALED 0x4000000000000000UL
+#define TYCHE_LABELS_USER 0x3fffffffffffffffUL
+
+/* Fast switches */
+#define TYCHE_FAST_SWITCH_SIZE 0x2000
+#define TYCHE_FAST_SWITCH_TO_OWNER 0x0
+#define TYCHE_FAST_SWITCH_TO_PEER 0x40
+
+/* Transactions */
+#define TYCHE_TRANSACTION_MAX_OPERATIONS 32
+#define TYCHE_TRANSACTION_LINK(op, result) ((op) * TYCHE_NB_RESULTS + (result))
+#ifndef __ASSEMBLER__
+typedef struct tyche_operation_t {
+  unsigned long call;
+  unsigned long links;
+  unsigned long args[TYCHE_NB_ARGS];
+  unsigned long res[TYCHE_NB_RESULTS];
+} tyche_operation_t;
+#endif
+
+/* Submission rings */
+#define TYCHE_RING_MAX_ENTRIES 256
+#ifndef __ASSEMBLER__
+typedef struct tyche_ring_header_t {
+  unsigned long sq_head;
+  unsigned long sq_tail;
+  unsigned long cq_head;
+  unsigned long cq_tail;
+} tyche_ring_header_t;
+typedef struct tyche_ring_submission_t {
+  unsigned long call;
+  unsigned long user_data;
+  unsigned long args[TYCHE_NB_ARGS];
+} tyche_ring_submission_t;
+typedef struct tyche_ring_completion_t {
+  unsigned long user_data;
+  unsigned long status;
+  unsigned long res[TYCHE_NB_RESULTS];
+} tyche_ring_completion_t;
+#endif
+
+/* Monitor calls */
+
+/* Create a new domain managed by the caller. */
+/* res[0]: management capability. */
+#define TYCHE_CALL_CREATE_DOMAIN 1
+
+/* Seal a domain, its configuration can not be modified afterward. */
+/* args[0]: management capability. */
+/* res[0]: switch capability for the current core. */
+#define TYCHE_CALL_SEAL_DOMAIN 2
+
+/* Send a non-region capability. */
+/* args[0]: capability, args[1]: management or channel capability of the destination. */
+#define TYCHE_CALL_SEND 3
+
+/* Alias or carve a region capability. */
+/* args[0]: region capability, args[1]: alias if non-zero, carve otherwise, */
+/* args[2]: start, args[3]: end, args[4]: memory access rights. */
+/* res[0]: new region capability, res[1]: revocation capability for the new region. */
+#define TYCHE_CALL_SEGMENT_REGION 4
+
+/* Revoke a capability. */
+/* args[0]: capability. */
+#define TYCHE_CALL_REVOKE 5
+
+/* Duplicate a capability. */
+/* args[0]: capability, args[1]: rights kept by a duplicated management capability, among */
+/* configure (1 << 0), switch (1 << 1), revoke (1 << 2), read registers (1 << 3), write */
+/* registers (1 << 4) and send (1 << 5). */
+/* res[0]: new capability. */
+#define TYCHE_CALL_DUPLICATE 6
+
+/* Enumerate the capabilities of the caller. */
+/* args[0]: token, 0 to start the enumeration. */
+/* res[0..3]: serialized capability, res[3]: next token, 0 once done. */
+#define TYCHE_CALL_ENUMERATE 7
+
+/* Switch to another domain, the call returns once switching back. */
+/* args[0]: switch capability, args[1]: quantum delta. */
+/* res[0]: return switch capability, as seen by the target domain. */
+#define TYCHE_CALL_SWITCH 8
+
+/* Not implemented. */
+#define TYCHE_CALL_EXIT 9
+
+/* Log a value from the monitor. */
+/* args[0]: value. */
+#define TYCHE_CALL_DEBUG 10
+
+/* Configure a permission of a child domain. */
+/* args[0]: permission index, args[1]: management capability, args[2]: value. */
+/* res[0]: 0 on success, 1 otherwise. */
+#define TYCHE_CALL_CONFIGURE 11
+
+/* Send a region capability and map it in the destination. */
+/* args[0]: region capability, args[1]: management capability of the destination, */
+/* args[2]: guest physical address, args[3]: repeat the mapping if non-zero, */
+/* args[4]: size of the mapping, args[5]: extra memory operations (hash, cleanup, vital). */
+/* res[0]: the region capability. */
+#define TYCHE_CALL_SEND_REGION 12
+
+/* Write a field of a child's core context. */
+/* args[0]: management capability, args[1]: core, args[2]: field, args[3]: value. */
+#define TYCHE_CALL_CONFIGURE_CORE 13
+
+/* Read a field of a child's core context. */
+/* args[0]: management capability, args[1]: core, args[2]: field. */
+/* res[0]: value. */
+#define TYCHE_CALL_GET_CONFIG_CORE 14
+
+/* Allocate a core context for a child domain. */
+/* args[0]: management capability, args[1]: core. */
+/* res[0]: switch capability for that core. */
+#define TYCHE_CALL_ALLOC_CORE_CONTEXT 15
+
+/* Read all general purpose registers of a child's core context into the caller's registers. */
+/* args[0]: management capability, args[1]: core. */
+#define TYCHE_CALL_READ_ALL_GP 16
+
+/* Not implemented. */
+#define TYCHE_CALL_WRITE_ALL_GP 17
+
+/* Write up to 6 fields of a child's core context, passed as (field, value) pairs in the */
+/* caller's general purpose registers. */
+/* args[0]: management capability, args[1]: core. */
+#define TYCHE_CALL_WRITE_FIELDS 18
+
+/* Not implemented. */
+#define TYCHE_CALL_SELF_CONFIG 19
+
+/* Attest the caller with the attestation key of this boot: the report is the signature of */
+/* the caller's measurement followed by the nonce, see the `report` modes. */
+/* args[0]: nonce, args[1]: mode. */
+/* Create report: res[0..4]: attestation public key, res[4..6]: signature bytes 0 to 15. */
+/* Read report: res[0..6]: signature bytes 16 to 63. */
+/* Read certificate: args[2]: buffer address, args[3]: buffer size, args[4]: address is */
+/* virtual if non-zero. res[0]: number of bytes written, i.e. the device (TPM) signature of */
+/* "tyche-attest-key" followed by the public key. */
+#define TYCHE_CALL_ENCLAVE_ATTESTATION 20
+
+/* Unmap and revoke a region previously sent with `SEND_REGION`. */
+/* args[0]: revocation capability, args[1]: management capability of the holder, */
+/* args[2]: guest physical address, args[3]: size of the mapping. */
+#define TYCHE_CALL_REVOKE_ALIASED_REGION 21
+
+/* Serialize the capability graph into a buffer of the caller. */
+/* args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero. */
+/* res[0]: number of bytes written. */
+#define TYCHE_CALL_SERIALIZE_ATTESTATION 22
+
+/* Return to the manager, as if a fault happened. */
+#define TYCHE_CALL_RETURN_TO_MANAGER 23
+
+/* Translate a guest physical address of the caller. */
+/* args[0]: guest physical address, args[1]: size. */
+/* res[0]: host physical address, res[1]: size of the contiguous mapping. */
+#define TYCHE_CALL_GET_HPA 24
+
+/* Install a CPUID entry for a child domain (x86_64 only). */
+/* args[0]: management capability, args[1]: function, args[2]: index | flags << 32, */
+/* args[3]: eax | ebx << 32, args[4]: ecx | edx << 32. */
+#define TYCHE_CALL_SET_CPUID_ENTRY 26
+
+/* Configure the cores a child domain can run on, 64 cores at a time. `CONFIGURE` with the */
+/* allowed cores permission is equivalent to using the group 0. */
+/* args[0]: management capability, args[1]: group of cores, covering cores 64 * args[1] to */
+/* 64 * args[1] + 63, args[2]: bitmap of the allowed cores within the group. */
+#define TYCHE_CALL_CONFIGURE_CORE_MAP 27
+
+/* Configure the resources a child domain and the domains it manages can use. The quota is */
+/* inherited by the domains the child creates, and can not exceed the caller's own quota. */
+/* args[0]: management capability, args[1]: maximum number of managed domains, */
+/* args[2]: maximum number of region capabilities, args[3]: maximum number of tracked */
+/* regions, args[4]: maximum number of bytes of accessible memory. */
+#define TYCHE_CALL_CONFIGURE_QUOTA 28
+
+/* Lend a region to another domain and map it in the destination, the region is revoked */
+/* automatically once the lease expires. Deadlines are checked on every monitor entry, i.e. */
+/* monitor call, interrupt or fault of any domain on any core. */
+/* args[0]: region capability, args[1]: management capability of the destination, */
+/* args[2]: guest physical address, args[3]: lease condition, args[4]: number of switches */
+/* or timestamp deadline, args[5]: extra memory operations (hash, cleanup, vital). */
+/* res[0]: the region capability. */
+#define TYCHE_CALL_SEND_REGION_LEASED 29
+
+/* For benchmarks to measure the cost of communication with tyche. */
+#define TYCHE_CALL_TEST_CALL 30
+
+/* Run the TPM self test. */
+/* args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero. */
+/* res[0]: number of bytes of manufacturer info written, res[1]: self test result. */
+#define TYCHE_CALL_TPM_SELFTEST 31
+
+/* Serialize the capability graph followed by a nonce, and sign both with the TPM. */
+/* args[0]: attestation address, args[1]: attestation size, args[2]: signature address, */
+/* args[3]: signature size, args[4]: addresses are virtual if non-zero, args[5]: nonce. */
+/* res[0]: number of bytes of signature written, res[1]: number of bytes of attestation */
+/* written, including the 8 bytes of nonce. */
+#define TYCHE_CALL_SIGNED_ATTESTATION 32
+
+/* Read the TPM signing key. */
+/* args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero. */
+/* res[0]: number of bytes written. */
+#define TYCHE_CALL_GET_SIGNING_KEY 33
+
+/* Sign a digest with the TPM. */
+/* args[0]: digest address, args[1]: digest size, args[2]: signature address, */
+/* args[3]: signature size, args[4]: addresses are virtual if non-zero. */
+/* res[0]: number of bytes of signature written. */
+#define TYCHE_CALL_TPM_SIGN 34
+
+/* Sign a digest with the monitor's virtual TPM key. */
+/* args[0]: digest address, args[1]: digest size, args[2]: signature address, */
+/* args[3]: signature size, args[4]: addresses are virtual if non-zero. */
+/* res[0]: number of bytes of signature written. */
+#define TYCHE_CALL_VTPM_SIGN 35
+
+/* Append data to the caller's Argos transcript. */
+/* args[0]: buffer address, args[1]: buffer size, args[2]: hash the data if non-zero, */
+/* args[3]: address is virtual if non-zero. */
+#define TYCHE_CALL_ARGOS_APPEND_TRANSCRIPT 36
+
+/* Finalize the caller's Argos transcript and sign it with the TPM. */
+/* args[0]: transcript address, args[1]: transcript size, args[2]: signature address, */
+/* args[3]: signature size, args[4]: addresses are virtual if non-zero. */
+#define TYCHE_CALL_ARGOS_GET_SIGNED_TRANSCRIPT 37
+
+/* Poll the oldest region revoked from under the caller by another domain. A bounded number */
+/* of revocations are kept pending, see `TYCHE_REVOCATION_TRAP` to be notified of new ones. */
+/* res[0]: revoked region capability, res[1]: start address, res[2]: end address, res[3]: 1 */
+/* if a revocation was pending, 0 otherwise. */
+#define TYCHE_CALL_POLL_REVOCATION 38
+
+/* Apply a list of operations all-or-nothing: if one of them fails the previous ones are rolled */
+/* back. The operations are laid out as in `transaction::Operation`, and their results are */
+/* written back once all of them succeed. On failure the error details hold the index of the */
+/* failed operation. */
+/* args[0]: operations address, args[1]: number of operations, args[2]: address is virtual */
+/* if non-zero. */
+#define TYCHE_CALL_TRANSACTION 39
+
+/* Register the caller's submission ring, replacing the previous one. The ring is laid out as */
+/* described in the `ring` module and must stay accessible to the caller while registered. */
+/* args[0]: ring address, or 0 to unregister, args[1]: number of entries, a power of two, */
+/* args[2]: address is virtual if non-zero. */
+#define TYCHE_CALL_REGISTER_RING 40
+
+/* Process the calls submitted to the caller's ring, until the submission ring is empty, the */
+/* completion ring is full, or the given number of calls is reached. Calls that switch domain */
+/* or access the caller's registers are completed with an error. */
+/* args[0]: maximum number of calls to process, 0 for one ring's worth. */
+/* res[0]: number of calls processed. */
+#define TYCHE_CALL_RING_ENTER 41
+
+/* Create a channel to a domain, over which messages can be sent to it with `CHANNEL_SEND`. */
+/* The channel can be handed over to other domains with `SEND`. */
+/* args[0]: management capability with the send right, args[1]: if non-zero, create a channel */
+/* to the caller instead. res[0]: channel capability. */
+#define TYCHE_CALL_CREATE_CHANNEL 42
+
+/* Send a message over a channel, optionally transferring a page-aligned region along. The */
+/* message is queued by the receiver, see `TYCHE_CHANNEL_TRAP` to be notified of new ones. */
+/* args[0]: channel capability, args[1]: region capability, or `TYCHE_CHANNEL_NO_REGION`, */
+/* args[2..5]: message words. */
+#define TYCHE_CALL_CHANNEL_SEND 43
+
+/* Poll the oldest message received over a channel. */
+/* res[0]: number of pending messages, including this one, 0 if no message was pending, */
+/* res[1]: id of the sending domain, res[2]: received region capability, or */
+/* `TYCHE_CHANNEL_NO_REGION`, res[3..6]: message words. */
+#define TYCHE_CALL_CHANNEL_RECEIVE 44
+
+/* Add information-flow labels to a child domain, checked against the policy loaded at boot on */
+/* capability transfers. Labels can't be removed, and can only be added before sealing. */
+/* args[0]: management capability, args[1]: labels, within `TYCHE_LABELS_USER`. */
+/* res[0]: labels of the child, including the ones set by the monitor. */
+#define TYCHE_CALL_CONFIGURE_LABELS 45
+
+/* Pair the caller with a sealed domain, so that both switch to each other on the current */
+/* core with `vmfunc` rather than with `SWITCH`. The monitor maps a trampoline of */
+/* `TYCHE_FAST_SWITCH_SIZE` bytes at the same address in both domains, which enters the peer */
+/* at its entry point (`TYCHE_FAST_SWITCH_TO_PEER`) or the owner at the given one */
+/* (`TYCHE_FAST_SWITCH_TO_OWNER`). The domains must map the trampoline at the same virtual */
+/* address. Only the trampoline is executable right after `vmfunc`, the monitor checks that */
+/* a domain is entered at its entry point, with its stack and page tables. The monitor serves */
+/* the calls of the peer, its other exits and invalid entries abort it: the owner is then */
+/* entered with a `TYCHE_ERROR_FAST_SWITCH_ABORTED` error. */
+/* args[0]: switch capability for the current core, args[1]: guest physical address of the */
+/* trampoline, args[2]: entry point of the owner, args[3]: stack of the owner. */
+#define TYCHE_CALL_ENABLE_FAST_SWITCH 46
+
+/* Dissolve the pair enabled by the caller, see `ENABLE_FAST_SWITCH`. */
+#define TYCHE_CALL_DISABLE_FAST_SWITCH 47
+
+/* Query the version of the error space of the monitor, clients must not decode error codes */
+/* if it differs from their `ERROR_VERSION`. */
+/* res[0]: version of the error space. */
+#define TYCHE_CALL_GET_ERROR_VERSION 48
+
+/* Set the interrupt the caller is signaled with when a notification becomes pending, see */
+/* `POLL_NOTIFICATIONS`. The interrupt is raised on the cores running the caller, or on its */
+/* next switch in. */
+/* args[0]: vector within [`TYCHE_NOTIFICATION_MIN_VECTOR`, `TYCHE_NOTIFICATION_MAX_VECTOR`], */
+/* or `TYCHE_NOTIFICATION_NO_VECTOR` to only poll the notifications. */
+#define TYCHE_CALL_SET_NOTIFICATION_VECTOR 49
+
+/* Poll and clear the notifications pending for the caller, raised for the traps its manager */
+/* allowed (see `CONFIGURE`). */
+/* res[0]: trap bits of the pending notifications, res[1]: start of the last region whose */
+/* lease expired (`TYCHE_LEASE_TRAP`), res[2]: start of the last revoked region */
+/* (`TYCHE_REVOCATION_TRAP`), res[3]: number of pending messages when last notified */
+/* (`TYCHE_CHANNEL_TRAP`). */
+#define TYCHE_CALL_POLL_NOTIFICATIONS 50
+
+/* Error codes, returned in res[0] on failure, details in res[1] */
+
+#define TYCHE_ERROR_VERSION 1
+
+/* The call number does not correspond to any monitor call. */
+#define TYCHE_ERROR_UNKNOWN_CALL 0x101
+
+/* The domain the caller switched to has been revoked. */
+#define TYCHE_ERROR_DOMAIN_REVOKED 0x102
+
+/* The peer of a fast switch pair exited for something else than a monitor call, a domain of */
+/* the pair was not entered through the trampoline, or the pair was disabled: the owner is */
+/* back at its entry point, see `ENABLE_FAST_SWITCH`. */
+#define TYCHE_ERROR_FAST_SWITCH_ABORTED 0x103
+
+/* The monitor has no attestation key, as it found no entropy source at boot. */
+#define TYCHE_ERROR_ATTESTATION_UNAVAILABLE 0x104
+
+/* The capability can not be duplicated. */
+#define TYCHE_ERROR_CANNOT_DUPLICATE 0x201
+
+/* Invalid duplicate operation. */
+#define TYCHE_ERROR_INVALID_DUPLICATE 0x202
+
+/* The capability can not be installed in the target domain. */
+#define TYCHE_ERROR_INVALID_INSTALL 0x203
+
+/* Internal error of the region tracker. */
+#define TYCHE_ERROR_INTERNAL_REGION_ERROR 0x204
+
+/* The region is invalid (e.g. empty, or not contained in the parent). */
+#define TYCHE_ERROR_INVALID_REGION 0x205
+
+/* The capability is invalid. */
+#define TYCHE_ERROR_INVALID_CAPA 0x206
+
+/* The capability is not of the expected type. */
+#define TYCHE_ERROR_WRONG_CAPABILITY_TYPE 0x207
+
+/* The capability does not exist. */
+#define TYCHE_ERROR_CAPABILITY_DOES_NOT_EXIST 0x208
+
+/* The domain is sealed and can not be modified. */
+#define TYCHE_ERROR_ALREADY_SEALED 0x209
+
+/* The caller lacks the permissions for the operation. */
+#define TYCHE_ERROR_INSUFFICIENT_PERMISSIONS 0x20a
+
+/* The requested permissions are invalid. */
+#define TYCHE_ERROR_INVALID_PERMISSIONS 0x20b
+
+/* The monitor ran out of memory, i.e. one of the engine's pools is full. */
+#define TYCHE_ERROR_OUT_OF_MEMORY 0x20c
+
+/* The capability information can not be deserialized. */
+#define TYCHE_ERROR_COULD_NOT_DESERIALIZE_INFO 0x20d
+
+/* The core is invalid or not allowed. */
+#define TYCHE_ERROR_INVALID_CORE 0x20e
+
+/* No domain could handle the trap. */
+#define TYCHE_ERROR_COULD_NOT_HANDLE_TRAP 0x20f
+
+/* A trap was handled by exiting the domain. */
+#define TYCHE_ERROR_VALID_TRAP_CAUSED_EXIT 0x210
+
+/* The switch is invalid. */
+#define TYCHE_ERROR_INVALID_SWITCH 0x211
+
+/* The core context is not of the expected type. */
+#define TYCHE_ERROR_INVALID_VCPU_TYPE 0x212
+
+/* The operation is not valid in the current state. */
+#define TYCHE_ERROR_INVALID_OPERATION 0x213
+
+/* An argument has an invalid value. */
+#define TYCHE_ERROR_INVALID_VALUE 0x214
+
+/* The memory operations are invalid. */
+#define TYCHE_ERROR_INVALID_MEM_OPS 0x215
+
+/* The region is already aliased. */
+#define TYCHE_ERROR_ALREADY_ALIASED 0x216
+
+/* Unspecified platform error. */
+#define TYCHE_ERROR_PLATFORM_ERROR 0x217
+
+/* The domain, or one of its managers, would exceed its resource quota. */
+#define TYCHE_ERROR_QUOTA_EXCEEDED 0x218
+
+/* The receiver of a message has too many pending messages. */
+#define TYCHE_ERROR_CHANNEL_FULL 0x219
+
+/* The transfer is denied by the information-flow policy loaded at boot. */
+#define TYCHE_ERROR_DENIED_BY_POLICY 0x21a
+
+/* A VMX instruction failed with a valid VMCS. */
+/* Details: the VM-instruction error number. */
+#define TYCHE_ERROR_VM_FAIL_VALID 0x301
+
+/* A VMX instruction failed with an invalid VMCS pointer. */
+#define TYCHE_ERROR_VM_FAIL_INVALID 0x302
+
+/* VMX is not supported by the CPU. */
+#define TYCHE_ERROR_VMX_NOT_SUPPORTED 0x303
+
+/* VMX is supported by the CPU but not enabled. */
+#define TYCHE_ERROR_VMX_NOT_ENABLED 0x304
+
+/* A VMX feature is not supported. */
+#define TYCHE_ERROR_FEATURE_NOT_SUPPORTED 0x305
+
+/* A configuration bit can not be set. */
+/* Details: the VMX field index, and the bit index in bits 8 to 15. */
+#define TYCHE_ERROR_DISALLOWED1 0x306
+
+/* A configuration bit can not be cleared. */
+/* Details: the VMX field index, and the bit index in bits 8 to 15. */
+#define TYCHE_ERROR_DISALLOWED0 0x307
+
+/* A configuration bit has an invalid value. */
+/* Details: the VMX field index, and the bit index in bits 8 to 15. */
+#define TYCHE_ERROR_MISCONFIGURED_BIT 0x308
+
+/* A VMX field has an invalid value. */
+/* Details: the VMX field index. */
+#define TYCHE_ERROR_MISCONFIGURED 0x309
+
+/* Returns the name of an error code, or NULL if unknown. */
+#ifndef __ASSEMBLER__
+static inline const char* tyche_error_name(unsigned long code) {
+  switch (code) {
+    case TYCHE_ERROR_UNKNOWN_CALL: return "UnknownCall";
+    case TYCHE_ERROR_DOMAIN_REVOKED: return "DomainRevoked";
+    case TYCHE_ERROR_FAST_SWITCH_ABORTED: return "FastSwitchAborted";
+    case TYCHE_ERROR_ATTESTATION_UNAVAILABLE: return "AttestationUnavailable";
+    case TYCHE_ERROR_CANNOT_DUPLICATE: return "CannotDuplicate";
+    case TYCHE_ERROR_INVALID_DUPLICATE: return "InvalidDuplicate";
+    case TYCHE_ERROR_INVALID_INSTALL: return "InvalidInstall";
+    case TYCHE_ERROR_INTERNAL_REGION_ERROR: return "InternalRegionError";
+    case TYCHE_ERROR_INVALID_REGION: return "InvalidRegion";
+    case TYCHE_ERROR_INVALID_CAPA: return "InvalidCapa";
+    case TYCHE_ERROR_WRONG_CAPABILITY_TYPE: return "WrongCapabilityType";
+    case TYCHE_ERROR_CAPABILITY_DOES_NOT_EXIST: return "CapabilityDoesNotExist";
+    case TYCHE_ERROR_ALREADY_SEALED: return "AlreadySealed";
+    case TYCHE_ERROR_INSUFFICIENT_PERMISSIONS: return "InsufficientPermissions";
+    case TYCHE_ERROR_INVALID_PERMISSIONS: return "InvalidPermissions";
+    case TYCHE_ERROR_OUT_OF_MEMORY: return "OutOfMemory";
+    case TYCHE_ERROR_COULD_NOT_DESERIALIZE_INFO: return "CouldNotDeserializeInfo";
+    case TYCHE_ERROR_INVALID_CORE: return "InvalidCore";
+    case TYCHE_ERROR_COULD_NOT_HANDLE_TRAP: return "CouldNotHandleTrap";
+    case TYCHE_ERROR_VALID_TRAP_CAUSED_EXIT: return "ValidTrapCausedExit";
+    case TYCHE_ERROR_INVALID_SWITCH: return "InvalidSwitch";
+    case TYCHE_ERROR_INVALID_VCPU_TYPE: return "InvalidVcpuType";
+    case TYCHE_ERROR_INVALID_OPERATION: return "InvalidOperation";
+    case TYCHE_ERROR_INVALID_VALUE: return "InvalidValue";
+    case TYCHE_ERROR_INVALID_MEM_OPS: return "InvalidMemOps";
+    case TYCHE_ERROR_ALREADY_ALIASED: return "AlreadyAliased";
+    case TYCHE_ERROR_PLATFORM_ERROR: return "PlatformError";
+    case TYCHE_ERROR_QUOTA_EXCEEDED: return "QuotaExceeded";
+    case TYCHE_ERROR_CHANNEL_FULL: return "ChannelFull";
+    case TYCHE_ERROR_DENIED_BY_POLICY: return "DeniedByPolicy";
+    case TYCHE_ERROR_VM_FAIL_VALID: return "VmFailValid";
+    case TYCHE_ERROR_VM_FAIL_INVALID: return "VmFailInvalid";
+    case TYCHE_ERROR_VMX_NOT_SUPPORTED: return "VmxNotSupported";
+    case TYCHE_ERROR_VMX_NOT_ENABLED: return "VmxNotEnabled";
+    case TYCHE_ERROR_FEATURE_NOT_SUPPORTED: return "FeatureNotSupported";
+    case TYCHE_ERROR_DISALLOWED1: return "Disallowed1";
+    case TYCHE_ERROR_DISALLOWED0: return "Disallowed0";
+    case TYCHE_ERROR_MISCONFIGURED_BIT: return "MisconfiguredBit";
+    case TYCHE_ERROR_MISCONFIGURED: return "Misconfigured";
+    default: return (const char*) 0;
+  }
+}
+#endif
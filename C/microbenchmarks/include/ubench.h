@@ -4,7 +4,7 @@
 #include <stddef.h>
 
 // ————————————————————————— Environment Variables —————————————————————————— //
-#define NB_ENV_VARS (11)
+#define NB_ENV_VARS (12)
 /// Benchmarks
 #define CREATION "CREATION"
 #define TRANSITION "TRANSITION"
@@ -19,6 +19,7 @@
 #define MAX_SIZE "MAX_SIZE"
 #define INNER "INNER"
 #define OUTER "OUTER"
+#define FAST_SWITCH_GPA "FAST_SWITCH_GPA"
 
 // ————————————————————————————————— Types —————————————————————————————————— //
 
@@ -62,4 +63,6 @@ typedef struct ubench_config_t {
   size_t inner;
   /// Outer loop value.
   size_t outer;
+  /// Guest physical address of the fast switch trampoline, 0 to skip fast switches.
+  size_t fast_switch_gpa;
 } ubench_config_t;
@@ -64,6 +64,9 @@ void display_config(ubench_config_t *bench) {
   }
   printf("\n");
   printf("Running %ld (outer) times %ld (inner) repetitions/run\n", bench->outer, bench->inner);
+  if (bench->hwcomm && bench->fast_switch_gpa != 0) {
+    printf("Fast switch trampoline at 0x%lx\n", bench->fast_switch_gpa);
+  }
 }
 
 
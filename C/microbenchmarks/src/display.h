@@ -3,7 +3,7 @@
 #include "ubench.h"
 
 #define COL_WIDTH (25)
-#define MAX_NB_COLS (4)
+#define MAX_NB_COLS (5)
 #define DISP_INPUT (COL_WIDTH * 2)
 
 void print_line(char** cols, size_t len);
@@ -81,6 +81,23 @@ failure:
   return FAILURE;
 }
 
+/// Parse an address, in decimal or in hexadecimal with a 0x prefix.
+static int parse_addr(char* value, size_t* addr) {
+  unsigned long v = 0;
+  char *endptr = NULL;
+  if (value == NULL || addr == NULL) {
+    goto failure;
+  }
+  v = strtoul(value, &endptr, 0);
+  if (errno != 0 || (v == ULONG_MAX)) {
+    goto failure;
+  }
+  *addr = v;
+  return SUCCESS;
+failure:
+  return FAILURE;
+}
+
 /// Benchmarks: Boolean parsers
 DECLARE_PARSER(creation, bool, parse_bool);
 DECLARE_PARSER(transition, bool, parse_bool);
@@ -96,6 +113,8 @@ DECLARE_PARSER(max_size, domain_size_t, parse_size);
 /// Iterations to be applied.
 DECLARE_PARSER(inner, size_t, parse_size_t);
 DECLARE_PARSER(outer, size_t, parse_size_t);
+/// Fast switch trampoline.
+DECLARE_PARSER(fast_switch_gpa, size_t, parse_addr);
 
 // ———————————————————————————— Global Constants ———————————————————————————— //
 const char* env_variables[NB_ENV_VARS] = {
@@ -110,6 +129,7 @@ const char* env_variables[NB_ENV_VARS] = {
   MAX_SIZE,
   INNER,
   OUTER,
+  FAST_SWITCH_GPA,
 };
 
 // ————————————————————————————— API functions —————————————————————————————— //
@@ -127,6 +147,7 @@ env_parser_fn parsers[NB_ENV_VARS] = {
   parse_max_size_f,
   parse_inner_f,
   parse_outer_f,
+  parse_fast_switch_gpa_f,
 };
 
 /// Parses the configuration from evironment variables.
@@ -7,11 +7,225 @@
 #include "tyche_api.h"
 #include "ecs.h"
 #include "tyche_driver.h"
+#include "tyche_monitor_abi.h"
 #include <assert.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include <stdbool.h>
+#include <fcntl.h>
+#include <unistd.h>
+#include <sys/mman.h>
+
+// ————————————————————————————— Submission ring —————————————————————————————— //
+
+/// Number of test calls batched per exit.
+#define HWCOMM_RING_ENTRIES 64
+
+/// A submission ring, laid out as expected by the monitor.
+typedef struct hwcomm_ring_t {
+  tyche_ring_header_t header;
+  tyche_ring_submission_t submissions[HWCOMM_RING_ENTRIES];
+  tyche_ring_completion_t completions[HWCOMM_RING_ENTRIES];
+} hwcomm_ring_t;
+
+static hwcomm_ring_t hwcomm_ring __attribute__((aligned(0x1000)));
+
+/// Issues a monitor call with up to four arguments, returns the status.
+static usize hwcomm_call(usize call, usize arg0, usize arg1, usize arg2, usize arg3) {
+#if defined(CONFIG_RISCV) || defined(__riscv)
+  register usize a0 asm("a0") = call;
+  register usize a1 asm("a1") = arg0;
+  register usize a2 asm("a2") = arg1;
+  register usize a3 asm("a3") = arg2;
+  register usize a4 asm("a4") = arg3;
+  asm volatile(
+      "li a7, 0x5479636865\n\t"
+      "mret\n\t"
+      : "+r" (a0), "+r" (a1), "+r" (a2), "+r" (a3), "+r" (a4)
+      :
+      : "a5", "a6", "a7", "memory");
+  return a0;
+#else
+  usize status = call;
+  asm volatile(
+      "vmcall\n\t"
+      : "+a" (status), "+D" (arg0), "+S" (arg1), "+d" (arg2), "+c" (arg3)
+      :
+      : "r8", "r9", "memory");
+  return status;
+#endif
+}
+
+/// Registers the ring, the benchmark is skipped if the monitor does not support rings.
+static bool hwcomm_ring_register(void) {
+  // Make sure the ring is mapped before handing it to the monitor.
+  memset(&hwcomm_ring, 0, sizeof(hwcomm_ring));
+  return hwcomm_call(TYCHE_CALL_REGISTER_RING, (usize) &hwcomm_ring,
+      HWCOMM_RING_ENTRIES, 1, 0) == TYCHE_STATUS_SUCCESS;
+}
+
+/// Issues `count` test calls through the ring, `HWCOMM_RING_ENTRIES` per exit.
+static void hwcomm_ring_calls(size_t count) {
+  tyche_ring_header_t* header = &hwcomm_ring.header;
+  while (count > 0) {
+    size_t batch = count < HWCOMM_RING_ENTRIES ? count : HWCOMM_RING_ENTRIES;
+    for (size_t i = 0; i < batch; i++) {
+      tyche_ring_submission_t* sub =
+        &hwcomm_ring.submissions[header->sq_tail % HWCOMM_RING_ENTRIES];
+      sub->call = TYCHE_CALL_TEST_CALL;
+      sub->user_data = i;
+      header->sq_tail++;
+    }
+    assert(hwcomm_call(TYCHE_CALL_RING_ENTER, 0, 0, 0, 0) == TYCHE_STATUS_SUCCESS);
+    // Drop the completions, test calls can't fail.
+    header->cq_head = header->cq_tail;
+    count -= batch;
+  }
+}
+
+// ————————————————————————————— Domain switches ————————————————————————————— //
+
+/// Defined in transition.c.
+bool bench_find_switch(capa_index_t* res);
+
+#if !(defined(CONFIG_RISCV) || defined(__riscv))
+
+#define HWCOMM_XSTR(x) #x
+#define HWCOMM_STR(x) HWCOMM_XSTR(x)
+
+/// The stack pointer of `hwcomm_fast_switch_loop`, restored by the owner entry point.
+static usize hwcomm_fast_switch_rsp __attribute__((used)) = 0;
+
+/// The stack the trampoline enters the owner with, until it restores its own.
+static char hwcomm_fast_switch_stack[0x1000] __attribute__((aligned(16)));
+
+/// Does `count` fast switch round trips to the peer through the trampoline mapped at
+/// `trampoline`, returns 0 or the error code if the monitor aborted the pair.
+usize hwcomm_fast_switch_loop(usize trampoline, usize count);
+
+/// The owner entry point, within `hwcomm_fast_switch_loop`.
+void hwcomm_fast_switch_entry(void);
+
+asm(
+    ".text\n"
+    ".globl hwcomm_fast_switch_loop\n"
+    "hwcomm_fast_switch_loop:\n"
+    "  pushq %rbx\n"
+    "  pushq %rbp\n"
+    "  pushq %r12\n"
+    "  pushq %r13\n"
+    "  pushq %r14\n"
+    "  pushq %r15\n"
+    "  movq %rsp, hwcomm_fast_switch_rsp(%rip)\n"
+    "  movq %rdi, %r12\n"
+    "  movq %rsi, %r13\n"
+    // The transition domain jumps back to the owner through %r15, see its call gate.
+    "  leaq " HWCOMM_STR(TYCHE_FAST_SWITCH_TO_OWNER) "(%r12), %r15\n"
+    "  xorq %rdi, %rdi\n"
+    "1:\n"
+    "  testq %r13, %r13\n"
+    "  jz 2f\n"
+    "  decq %r13\n"
+    "  leaq " HWCOMM_STR(TYCHE_FAST_SWITCH_TO_PEER) "(%r12), %rax\n"
+    "  jmp *%rax\n"
+    ".globl hwcomm_fast_switch_entry\n"
+    "hwcomm_fast_switch_entry:\n"
+    "  movq hwcomm_fast_switch_rsp(%rip), %rsp\n"
+    // The monitor puts an error code in %rdi when it aborts the pair.
+    "  testq %rdi, %rdi\n"
+    "  jz 1b\n"
+    "2:\n"
+    "  movq %rdi, %rax\n"
+    "  popq %r15\n"
+    "  popq %r14\n"
+    "  popq %r13\n"
+    "  popq %r12\n"
+    "  popq %rbp\n"
+    "  popq %rbx\n"
+    "  ret\n");
+
+/// Measures fast switch round trips, returns false if the pair can not be set up. The
+/// trampoline must not overlap the memory of the benchmark or of the domain, and the domain must
+/// map it at the same virtual address.
+static bool hwcomm_fast_switches(ubench_config_t* bench, capa_index_t capa_switch,
+    time_diff_t* fast) {
+  bool success = false;
+  if (bench->fast_switch_gpa == 0) {
+    return false;
+  }
+  int fd = open("/dev/mem", O_RDONLY | O_SYNC);
+  if (fd < 0) {
+    ERROR("Unable to open /dev/mem to map the fast switch trampoline");
+    return false;
+  }
+  void* trampoline = mmap(NULL, TYCHE_FAST_SWITCH_SIZE, PROT_READ | PROT_EXEC,
+      MAP_SHARED, fd, bench->fast_switch_gpa);
+  close(fd);
+  if (trampoline == MAP_FAILED) {
+    ERROR("Unable to map the fast switch trampoline");
+    return false;
+  }
+  usize stack = (usize) &hwcomm_fast_switch_stack[sizeof(hwcomm_fast_switch_stack)];
+  if (hwcomm_call(TYCHE_CALL_ENABLE_FAST_SWITCH, capa_switch, bench->fast_switch_gpa,
+        (usize) hwcomm_fast_switch_entry, stack) != TYCHE_STATUS_SUCCESS) {
+    ERROR("Unable to enable fast switches");
+    goto unmap;
+  }
+  for (int i = 0; i < bench->outer; i++) {
+    time_measurement_t start = {0};
+    time_measurement_t end = {0};
+    assert(take_time(&start));
+    usize error = hwcomm_fast_switch_loop((usize) trampoline, bench->inner);
+    assert(take_time(&end));
+    if (error != 0) {
+      ERROR("Fast switches aborted: %s", tyche_error_name(error));
+      goto disable;
+    }
+    fast[i] = (compute_elapsed(&start, &end))/((double)bench->inner);
+  }
+  success = true;
+disable:
+  hwcomm_call(TYCHE_CALL_DISABLE_FAST_SWITCH, 0, 0, 0, 0);
+unmap:
+  munmap(trampoline, TYCHE_FAST_SWITCH_SIZE);
+  return success;
+}
+
+#endif
+
+/// Measures switch round trips to the workload's transition domain, with `SWITCH` and with fast
+/// switches. Returns whether `fast` was filled.
+static bool hwcomm_switches(char* prefix, ubench_config_t* bench, time_diff_t* regular,
+    time_diff_t* fast) {
+  tyche_domain_t domain;
+  char name[100] = {0};
+  capa_index_t capa_switch = 0;
+  bool fast_filled = false;
+  usize core_mask = sdk_pin_to_current_core();
+
+  sprintf(name, "%s/transition", prefix);
+  assert(sdk_create_domain(&domain, name, core_mask, ALL_TRAPS, DEFAULT_PERM) == SUCCESS);
+  // Warmup transition, the domain then waits in its call gate.
+  assert(sdk_call_domain(&domain) == SUCCESS);
+  assert(bench_find_switch(&capa_switch));
+
+  for (int i = 0; i < bench->outer; i++) {
+    time_measurement_t start = {0};
+    time_measurement_t end = {0};
+    assert(take_time(&start));
+    for (int j = 0; j < bench->inner; j++) {
+      hwcomm_call(TYCHE_CALL_SWITCH, capa_switch, 0, 0, 0);
+    }
+    assert(take_time(&end));
+    regular[i] = (compute_elapsed(&start, &end))/((double)bench->inner);
+  }
+#if !(defined(CONFIG_RISCV) || defined(__riscv))
+  fast_filled = hwcomm_fast_switches(bench, capa_switch, fast);
+#endif
+  assert(sdk_delete_domain(&domain) == SUCCESS);
+  return fast_filled;
+}
 
 // ——————————————————————————— Display functions ———————————————————————————— //
 
@@ -19,21 +233,39 @@ static void display_hwcomm_header(char* prefix, ubench_config_t* bench) {
   assert(bench != NULL);
   printf("Hardware communication showing %ld (outer) averages of %ld (inner)\n",
       bench->outer, bench->inner);
-  ;
+  if (prefix != NULL) {
+    printf("Switch round trips to %s/transition\n", prefix);
+  }
   char** cols = allocate_buffer();
   sprintf(cols[0], "outer #");
   sprintf(cols[1], "call-return (%s)", TIME_MEASUREMENT_UNIT);
-  print_line(cols, 2);
+  sprintf(cols[2], "ring amortized (%s)", TIME_MEASUREMENT_UNIT);
+  sprintf(cols[3], "switch (%s)", TIME_MEASUREMENT_UNIT);
+  sprintf(cols[4], "fast switch (%s)", TIME_MEASUREMENT_UNIT);
+  print_line(cols, 5);
   free_buffer(cols);
 }
 
-static void display_hwcomm_results(time_diff_t* timings, size_t len) {
+/// Formats a measurement, or n/a if it was not taken.
+static void display_hwcomm_value(char* col, time_diff_t* values, int i) {
+  if (values != NULL) {
+    sprintf(col, "%.3f", values[i]);
+  } else {
+    sprintf(col, "n/a");
+  }
+}
+
+static void display_hwcomm_results(time_diff_t* timings, time_diff_t* ring,
+    time_diff_t* switches, time_diff_t* fast, size_t len) {
   assert(timings != NULL && len > 0);
   char** cols = allocate_buffer();
   for (int i = 0; i < len; i++) {
     sprintf(cols[0], "iter %d", i);
     sprintf(cols[1], "%.3f", timings[i]);
-    print_line(cols, 2);
+    display_hwcomm_value(cols[2], ring, i);
+    display_hwcomm_value(cols[3], switches, i);
+    display_hwcomm_value(cols[4], fast, i);
+    print_line(cols, 5);
   }
   free_buffer(cols);
 }
@@ -44,6 +276,9 @@ void run_hwcomm(char* prefix, ubench_config_t *bench) {
   assert(bench != NULL);
   time_diff_t* timings = calloc(bench->outer, sizeof(time_diff_t));
   memset(timings, 0, bench->outer * sizeof(time_diff_t));
+  time_diff_t* ring = calloc(bench->outer, sizeof(time_diff_t));
+  time_diff_t* switches = NULL;
+  time_diff_t* fast = NULL;
 
   display_hwcomm_header(prefix, bench);
 
@@ -77,8 +312,37 @@ void run_hwcomm(char* prefix, ubench_config_t *bench) {
     assert(take_time(&end));
     timings[i] = (compute_elapsed(&start, &end))/((double)bench->inner);
   }
-  // Clean up the domain.
-  
+
+  // Same calls, batched through the submission ring.
+  if (hwcomm_ring_register()) {
+    for (int i = 0; i < bench->outer; i++) {
+      time_measurement_t start = {0};
+      time_measurement_t end = {0};
+      assert(take_time(&start));
+      hwcomm_ring_calls(bench->inner);
+      assert(take_time(&end));
+      ring[i] = (compute_elapsed(&start, &end))/((double)bench->inner);
+    }
+    hwcomm_call(TYCHE_CALL_REGISTER_RING, 0, 0, 0, 0);
+  } else {
+    free(ring);
+    ring = NULL;
+  }
+
+  // Switches to the workload's transition domain and back.
+  if (prefix != NULL) {
+    switches = calloc(bench->outer, sizeof(time_diff_t));
+    fast = calloc(bench->outer, sizeof(time_diff_t));
+    if (!hwcomm_switches(prefix, bench, switches, fast)) {
+      free(fast);
+      fast = NULL;
+    }
+  }
+
   // Display the results.
-  display_hwcomm_results(timings, bench->outer);
+  display_hwcomm_results(timings, ring, switches, fast, bench->outer);
+  free(fast);
+  free(switches);
+  free(ring);
+  free(timings);
 }
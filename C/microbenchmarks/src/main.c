@@ -42,6 +42,7 @@ static ubench_config_t bench = {
   .max_size = default_max_size,
   .inner = defautl_inner,
   .outer = default_outer,
+  .fast_switch_gpa = 0,
 };
 
 // ———————————————————————————— Local functions ————————————————————————————— //
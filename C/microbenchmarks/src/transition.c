@@ -49,7 +49,7 @@ static void display_transition_results(time_diff_t* sdk, time_diff_t* raw, size_
 
 // ———————————————————————————— Helper functions ———————————————————————————— //
 
-static bool bench_find_switch(capa_index_t* res) {
+bool bench_find_switch(capa_index_t* res) {
   capa_index_t next = 0;
   assert(res != NULL);
   do {
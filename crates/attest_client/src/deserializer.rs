@@ -1,92 +1,138 @@
+use capa_engine::flow::Rule;
 use capa_engine::serializer::serde;
 use capa_engine::MemOps;
 
-use crate::{Capa, Context, Domain, Region, RegionKind};
+use crate::{AttestError, Capa, Context, Domain, Region, RegionKind};
 
-pub fn deserialize(buff: &[u8]) -> Result<Context, ()> {
+/// Deserializes an attestation produced by the monitor.
+///
+/// Returns the number of bytes consumed along with the context, the attestation might be
+/// followed by other data (such as a nonce).
+pub fn deserialize_prefix(buff: &[u8]) -> Result<(Context, usize), AttestError> {
     let mut ctx = Context::new();
     let mut buff = Buffer::new(buff);
 
     // Check magic value
-    let magic = buff.u32();
-    assert_eq!(serde::MAGIC, magic.to_le_bytes());
-    deserialize_regions(&mut ctx, &mut buff);
-    deserialize_domains(&mut ctx, &mut buff);
+    let magic = buff.u32()?;
+    expect(serde::MAGIC == magic.to_le_bytes(), "Invalid magic value")?;
+    deserialize_regions(&mut ctx, &mut buff)?;
+    deserialize_domains(&mut ctx, &mut buff)?;
+    deserialize_policy(&mut ctx, &mut buff)?;
+    expect(buff.u8()? == serde::END_MARKER, "Missing end marker")?;
 
-    Ok(ctx)
+    Ok((ctx, buff.cursor))
 }
 
-fn deserialize_regions(ctx: &mut Context, buff: &mut Buffer) {
-    assert_eq!(serde::REGION_HEADER, buff.u8());
-    while buff.peek_u8() != serde::END_MARKER {
-        let kind = match buff.u8() {
+/// Deserializes an attestation produced by the monitor, ignoring any trailing data.
+pub fn deserialize(buff: &[u8]) -> Result<Context, AttestError> {
+    deserialize_prefix(buff).map(|(ctx, _)| ctx)
+}
+
+fn expect(condition: bool, reason: &'static str) -> Result<(), AttestError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(AttestError::Malformed(reason))
+    }
+}
+
+fn deserialize_regions(ctx: &mut Context, buff: &mut Buffer) -> Result<(), AttestError> {
+    expect(buff.u8()? == serde::REGION_HEADER, "Missing region header")?;
+    while buff.peek_u8()? != serde::END_MARKER {
+        let kind = match buff.u8()? {
             serde::REGION_ROOT => RegionKind::Root,
             serde::REGION_ALIAS => {
-                let idx = buff.u32() as usize;
-                let handle = ctx.regions.as_handle(idx).unwrap();
-                RegionKind::Alias(handle)
+                let idx = buff.u32()? as usize;
+                let handle = ctx.regions.as_handle(idx);
+                RegionKind::Alias(handle.ok_or(AttestError::Malformed("Invalid region"))?)
             }
             serde::REGION_CARVE => {
-                let idx = buff.u32() as usize;
-                let handle = ctx.regions.as_handle(idx).unwrap();
-                RegionKind::Carve(handle)
+                let idx = buff.u32()? as usize;
+                let handle = ctx.regions.as_handle(idx);
+                RegionKind::Carve(handle.ok_or(AttestError::Malformed("Invalid region"))?)
             }
-            _ => panic!("Invalid region kind"),
+            _ => return Err(AttestError::Malformed("Invalid region kind")),
         };
-        let ops = buff.u8();
-        let start = buff.u64();
-        let end = buff.u64();
-        let has_hash = buff.u8();
+        let ops = buff.u8()?;
+        let start = buff.u64()?;
+        let end = buff.u64()?;
+        let has_hash = buff.u8()?;
         let hash = match has_hash {
             serde::REGION_HAS_HASH => {
-                let len = buff.u64();
+                let len = buff.u64()?;
                 let mut hash = Vec::new();
-                assert!(len <= 64, "Unsuported hash size");
+                expect(len <= 64, "Unsuported hash size")?;
                 for _ in 0..len {
-                    hash.push(buff.u8());
+                    hash.push(buff.u8()?);
                 }
                 Some(hash)
             }
             serde::REGION_NO_HASH => None,
-            _ => panic!("Invalid 'has hash' token"),
+            _ => return Err(AttestError::Malformed("Invalid 'has hash' token")),
         };
         ctx.regions.push(Region {
             start,
             end,
             kind,
             hash,
-            ops: MemOps::from_bits(ops).expect("Invalid MemOps"),
+            ops: MemOps::from_bits(ops).ok_or(AttestError::Malformed("Invalid MemOps"))?,
         });
     }
-    assert_eq!(serde::END_MARKER, buff.u8());
+    expect(buff.u8()? == serde::END_MARKER, "Missing end marker")
 }
 
-fn deserialize_domains(ctx: &mut Context, buff: &mut Buffer) {
-    assert_eq!(serde::DOMAIN_HEADER, buff.u8());
-    while buff.peek_u8() != serde::END_MARKER {
-        let id = buff.u64();
-        let permissions = buff.u64();
+fn deserialize_domains(ctx: &mut Context, buff: &mut Buffer) -> Result<(), AttestError> {
+    expect(buff.u8()? == serde::DOMAIN_HEADER, "Missing domain header")?;
+    while buff.peek_u8()? != serde::END_MARKER {
+        let id = buff.u64()?;
+        let permissions = buff.u64()?;
+        let labels = buff.u64()?;
         let mut td = Domain::new(id, permissions);
-        assert_eq!(serde::DOMAIN_CAPA_START, buff.u8());
-        while buff.peek_u8() != serde::DOMAIN_CAPA_END {
-            match buff.u8() {
+        td.labeled(labels);
+        match buff.u8()? {
+            serde::DOMAIN_HAS_MEASUREMENT => {
+                td.measured(buff.bytes()?);
+            }
+            serde::DOMAIN_NO_MEASUREMENT => (),
+            _ => return Err(AttestError::Malformed("Invalid 'has measurement' token")),
+        }
+        expect(buff.u8()? == serde::DOMAIN_CAPA_START, "Missing capa start")?;
+        while buff.peek_u8()? != serde::DOMAIN_CAPA_END {
+            match buff.u8()? {
                 serde::CAPA_REGION => {
-                    let region_id = buff.u64();
-                    let handle = ctx.regions.as_handle(region_id as usize).unwrap();
-                    td.capa.push(Capa::Region(handle));
+                    let region_id = buff.u64()?;
+                    let handle = ctx.regions.as_handle(region_id as usize);
+                    td.capa.push(Capa::Region(
+                        handle.ok_or(AttestError::Malformed("Invalid region"))?,
+                    ));
                 }
                 serde::CAPA_DOMAIN => {
-                    let td_id = buff.u64();
+                    let td_id = buff.u64()?;
                     let handle = ctx.domains.as_unknown_handle(td_id as usize);
                     td.capa.push(Capa::Management(handle));
                 }
-                _ => panic!("Invalid capa, could not deserialize"),
+                _ => return Err(AttestError::Malformed("Invalid capa")),
             }
         }
-        assert_eq!(serde::DOMAIN_CAPA_END, buff.u8());
+        expect(buff.u8()? == serde::DOMAIN_CAPA_END, "Missing capa end")?;
         ctx.domains.push(td);
     }
-    assert_eq!(serde::END_MARKER, buff.u8());
+    expect(buff.u8()? == serde::END_MARKER, "Missing end marker")
+}
+
+fn deserialize_policy(ctx: &mut Context, buff: &mut Buffer) -> Result<(), AttestError> {
+    expect(buff.u8()? == serde::POLICY_HEADER, "Missing policy header")?;
+    while buff.peek_u8()? != serde::END_MARKER {
+        expect(buff.u8()? == serde::POLICY_RULE, "Invalid policy rule")?;
+        ctx.add_flow_rule(Rule {
+            transfers: buff.u64()?,
+            from: buff.u64()?,
+            to: buff.u64()?,
+            region: buff.u64()?,
+            ops: buff.u64()?,
+        });
+    }
+    expect(buff.u8()? == serde::END_MARKER, "Missing end marker")
 }
 
 // ————————————————————————————————— Buffer ————————————————————————————————— //
@@ -101,27 +147,31 @@ impl<'a> Buffer<'a> {
         Self { buff, cursor: 0 }
     }
 
-    fn u8(&mut self) -> u8 {
-        let val = self.buff[self.cursor];
-        self.cursor += 1;
-        val
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], AttestError> {
+        let val = self
+            .buff
+            .get(self.cursor..(self.cursor + N))
+            .ok_or(AttestError::Truncated)?;
+        self.cursor += N;
+        Ok(val.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, AttestError> {
+        Ok(u8::from_le_bytes(self.bytes()?))
     }
 
-    fn u32(&mut self) -> u32 {
-        let val = &self.buff[self.cursor..(self.cursor + 4)];
-        let val = u32::from_le_bytes(val.try_into().unwrap());
-        self.cursor += 4;
-        val
+    fn u32(&mut self) -> Result<u32, AttestError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
     }
 
-    fn u64(&mut self) -> u64 {
-        let val = &self.buff[self.cursor..(self.cursor + 8)];
-        let val = u64::from_le_bytes(val.try_into().unwrap());
-        self.cursor += 8;
-        val
+    fn u64(&mut self) -> Result<u64, AttestError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
     }
 
-    fn peek_u8(&self) -> u8 {
-        self.buff[self.cursor]
+    fn peek_u8(&self) -> Result<u8, AttestError> {
+        self.buff
+            .get(self.cursor)
+            .copied()
+            .ok_or(AttestError::Truncated)
     }
 }
@@ -1,12 +1,16 @@
 mod deserializer;
+pub mod policy;
+pub mod tpm;
+pub mod verifier;
 
 use core::fmt;
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::ops::{Index, IndexMut};
 
+use capa_engine::flow::{self, Rule};
 pub use capa_engine::{permission, MemOps};
-pub use deserializer::deserialize;
+pub use deserializer::{deserialize, deserialize_prefix};
 
 #[derive(Clone, Copy)]
 pub enum RegionKind {
@@ -49,6 +53,10 @@ pub struct Domain {
     id: u64,
     capa: Vec<Capa>,
     permissions: u64,
+    /// Information-flow labels, including the ones set by the monitor.
+    labels: u64,
+    /// Measurement of the domain, computed by the monitor when the domain is sealed.
+    measurement: Option<[u8; 32]>,
 }
 
 impl Domain {
@@ -56,10 +64,22 @@ impl Domain {
         Domain {
             id,
             permissions,
+            labels: 0,
+            measurement: None,
             capa: Vec::new(),
         }
     }
 
+    pub fn labeled(&mut self, labels: u64) -> &mut Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn measured(&mut self, measurement: [u8; 32]) -> &mut Self {
+        self.measurement = Some(measurement);
+        self
+    }
+
     pub fn add(&mut self, capa: impl IntoCapa) -> &mut Self {
         self.capa.push(capa.into_capa());
         self
@@ -69,6 +89,14 @@ impl Domain {
 pub struct Context {
     regions: Arena<Region>,
     domains: Arena<Domain>,
+    /// The information-flow policy enforced by the monitor.
+    flow_rules: Vec<Rule>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Context {
@@ -76,9 +104,19 @@ impl Context {
         Self {
             regions: Arena::new(),
             domains: Arena::new(),
+            flow_rules: Vec::new(),
         }
     }
 
+    /// The rules of the information-flow policy enforced by the monitor, empty if none.
+    pub fn flow_rules(&self) -> &[Rule] {
+        &self.flow_rules
+    }
+
+    pub fn add_flow_rule(&mut self, rule: Rule) {
+        self.flow_rules.push(rule);
+    }
+
     pub fn root(&mut self, start: u64, end: u64, ops: MemOps) -> Handle<Region> {
         // TODO: check validity
         let region = Region {
@@ -128,11 +166,7 @@ impl Context {
     }
 
     pub fn add_domain(&mut self, id: u64, permissions: u64) -> Handle<Domain> {
-        self.domains.push(Domain {
-            id,
-            permissions,
-            capa: Vec::new(),
-        })
+        self.domains.push(Domain::new(id, permissions))
     }
 }
 
@@ -152,7 +186,37 @@ impl IndexMut<Handle<Domain>> for Context {
 
 // ————————————————————————————————— Error —————————————————————————————————— //
 
-pub enum AttestError {}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttestError {
+    /// The attestation ends unexpectedly.
+    Truncated,
+    /// The attestation is not well formed.
+    Malformed(&'static str),
+    /// The signature does not match the trusted key.
+    InvalidSignature,
+    /// The attestation key is not certified by the trusted key.
+    InvalidCertificate,
+    /// The attestation was not produced for the expected nonce.
+    StaleNonce { expected: u64, found: u64 },
+}
+
+impl fmt::Display for AttestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttestError::Truncated => write!(f, "truncated attestation"),
+            AttestError::Malformed(reason) => write!(f, "malformed attestation: {}", reason),
+            AttestError::InvalidSignature => write!(f, "invalid signature"),
+            AttestError::InvalidCertificate => write!(f, "invalid attestation key certificate"),
+            AttestError::StaleNonce { expected, found } => write!(
+                f,
+                "stale attestation: expected nonce 0x{:x}, found 0x{:x}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AttestError {}
 
 // ————————————————————————————————— Arena —————————————————————————————————— //
 
@@ -167,10 +231,7 @@ pub struct Handle<T> {
 
 impl<T> Clone for Handle<T> {
     fn clone(&self) -> Self {
-        Self {
-            idx: self.idx,
-            _t: PhantomData,
-        }
+        *self
     }
 }
 
@@ -190,6 +251,12 @@ impl<T> Hash for Handle<T> {
     }
 }
 
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Arena<T> {
     pub fn new() -> Self {
         Self { store: Vec::new() }
@@ -274,6 +341,24 @@ fn display_permissions(f: &mut fmt::Formatter<'_>, permissions: u64) -> fmt::Res
     Ok(())
 }
 
+fn display_labels(f: &mut fmt::Formatter<'_>, labels: u64) -> fmt::Result {
+    let mut first = true;
+    if labels & flow::labels::INITIAL != 0 {
+        separator(f, &mut first)?;
+        write!(f, "INITIAL")?;
+    }
+    if labels & flow::labels::SEALED != 0 {
+        separator(f, &mut first)?;
+        write!(f, "SEALED")?;
+    }
+    if labels & !flow::labels::IMPLICIT != 0 {
+        separator(f, &mut first)?;
+        write!(f, "0x{:x}", labels & !flow::labels::IMPLICIT)?;
+    }
+
+    Ok(())
+}
+
 fn display_capas(f: &mut fmt::Formatter<'_>, capas: &Vec<Capa>) -> fmt::Result {
     let mut first = true;
     for capa in capas.iter() {
@@ -344,9 +429,23 @@ impl fmt::Debug for Context {
             display_capas(f, &domain.capa)?;
             write!(f, "}} with ")?;
             display_permissions(f, domain.permissions)?;
+            if domain.labels != 0 {
+                write!(f, " labeled ")?;
+                display_labels(f, domain.labels)?;
+            }
+            if let Some(measurement) = &domain.measurement {
+                write!(f, " measured ")?;
+                for byte in measurement {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
             writeln!(f, "")?;
             idx += 1;
         }
+
+        for rule in &self.flow_rules {
+            writeln!(f, "  {}", rule)?;
+        }
         writeln!(f, "}}")?;
 
         Ok(())
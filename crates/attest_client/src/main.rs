@@ -1,9 +1,214 @@
-use attest_client::{Context, MemOps};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use attest_client::policy::Policy;
+use attest_client::tpm::TpmKey;
+use attest_client::verifier::{verify_attestation, verify_report, TrustedKey};
+use attest_client::{deserialize, Context, MemOps};
 use capa_engine::permission;
+use clap::{Parser, Subcommand};
+use clap_num::maybe_hex;
+use ed25519_compact::PublicKey;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Display an example attestation.
+    Demo,
+    /// Verify a signed attestation, exits with a non-zero code on failure.
+    Verify {
+        /// Attestation written by the monitor, followed by the nonce.
+        #[arg(short, long, value_name = "ATTESTATION")]
+        attestation: PathBuf,
+        /// Signature of the attestation.
+        #[arg(short, long, value_name = "SIGNATURE")]
+        signature: PathBuf,
+        /// Trusted public key, as raw ed25519 bytes.
+        #[arg(short, long, value_name = "KEY")]
+        key: PathBuf,
+        /// The trusted key is the TPM key returned by `GET_SIGNING_KEY`.
+        #[arg(long)]
+        tpm: bool,
+        /// Nonce provided when requesting the attestation.
+        #[arg(short, long, value_name = "NONCE", value_parser=maybe_hex::<u64>)]
+        nonce: u64,
+        /// Policy to check the attestation against.
+        #[arg(short, long, value_name = "POLICY")]
+        policy: Option<PathBuf>,
+    },
+    /// Verify the report of a domain, exits with a non-zero code on failure.
+    VerifyReport {
+        /// Attestation key of the monitor, as raw ed25519 bytes.
+        #[arg(short, long, value_name = "KEY")]
+        key: PathBuf,
+        /// Signature of the report.
+        #[arg(short, long, value_name = "SIGNATURE")]
+        signature: PathBuf,
+        /// Certificate of the attestation key.
+        #[arg(short, long, value_name = "CERTIFICATE")]
+        certificate: PathBuf,
+        /// TPM key returned by `GET_SIGNING_KEY`.
+        #[arg(short, long, value_name = "TPM_KEY")]
+        tpm_key: PathBuf,
+        /// Expected measurement of the domain, in hexadecimal.
+        #[arg(short, long, value_name = "MEASUREMENT")]
+        measurement: String,
+        /// Nonce provided when requesting the report.
+        #[arg(short, long, value_name = "NONCE", value_parser=maybe_hex::<u64>)]
+        nonce: u64,
+    },
+    /// Check an attestation against a policy, exits with a non-zero code on violation.
+    ///
+    /// The attestation is not authenticated, use `verify` with a policy for signed attestations.
+    Check {
+        #[arg(short, long, value_name = "ATTESTATION")]
+        attestation: PathBuf,
+        #[arg(short, long, value_name = "POLICY")]
+        policy: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match &cli.command {
+        Commands::Demo => {
+            demo();
+            ExitCode::SUCCESS
+        }
+        Commands::Verify {
+            attestation,
+            signature,
+            key,
+            tpm,
+            nonce,
+            policy,
+        } => match verify(attestation, signature, key, *tpm, *nonce) {
+            Ok(ctx) => {
+                println!("{}", ctx);
+                match policy {
+                    Some(policy) => check_policy(&ctx, policy),
+                    None => ExitCode::SUCCESS,
+                }
+            }
+            Err(err) => {
+                eprintln!("Verification failed: {}", err);
+                ExitCode::FAILURE
+            }
+        },
+        Commands::VerifyReport {
+            key,
+            signature,
+            certificate,
+            tpm_key,
+            measurement,
+            nonce,
+        } => {
+            match verify_domain_report(key, signature, certificate, tpm_key, measurement, *nonce) {
+                Ok(()) => {
+                    println!("Valid report");
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("Verification failed: {}", err);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Commands::Check {
+            attestation,
+            policy,
+        } => {
+            let ctx = std::fs::read(attestation)
+                .map_err(|err| err.to_string())
+                .and_then(|file| deserialize(&file).map_err(|err| err.to_string()));
+            match ctx {
+                Ok(ctx) => check_policy(&ctx, policy),
+                Err(err) => {
+                    eprintln!("Invalid attestation: {}", err);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+fn check_policy(ctx: &Context, policy: &PathBuf) -> ExitCode {
+    let policy = std::fs::read_to_string(policy)
+        .map_err(|err| err.to_string())
+        .and_then(|json| Policy::from_json(&json).map_err(|err| err.to_string()));
+    let policy = match policy {
+        Ok(policy) => policy,
+        Err(err) => {
+            eprintln!("Invalid policy: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = policy.evaluate(ctx);
+    print!("{}", report);
+    if report.passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
 
-fn main() {
-    println!("Hello, World!");
+fn verify(
+    attestation: &PathBuf,
+    signature: &PathBuf,
+    key: &PathBuf,
+    tpm: bool,
+    nonce: u64,
+) -> Result<Context, Box<dyn std::error::Error>> {
+    let attestation = std::fs::read(attestation)?;
+    let signature = std::fs::read(signature)?;
+    let key = std::fs::read(key)?;
+    let key: Box<dyn TrustedKey> = if tpm {
+        Box::new(TpmKey::from_modulus(&key).ok_or("Invalid TPM key")?)
+    } else {
+        Box::new(PublicKey::from_slice(&key).map_err(|_| "Invalid public key")?)
+    };
+    Ok(verify_attestation(&attestation, &signature, &key, nonce)?)
+}
+
+fn verify_domain_report(
+    key: &PathBuf,
+    signature: &PathBuf,
+    certificate: &PathBuf,
+    tpm_key: &PathBuf,
+    measurement: &str,
+    nonce: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let key = PublicKey::from_slice(&std::fs::read(key)?).map_err(|_| "Invalid public key")?;
+    let signature = std::fs::read(signature)?;
+    let certificate = std::fs::read(certificate)?;
+    let tpm_key = TpmKey::from_modulus(&std::fs::read(tpm_key)?).ok_or("Invalid TPM key")?;
+    let measurement = (0..measurement.len())
+        .step_by(2)
+        .map(|idx| {
+            measurement
+                .get(idx..idx + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or("Invalid measurement")?;
+    Ok(verify_report(
+        &key,
+        &signature,
+        &certificate,
+        &tpm_key,
+        &measurement,
+        nonce,
+    )?)
+}
 
+fn demo() {
     let mut ctx = Context::new();
     let ops = MemOps::all();
     let r0 = ctx.root(0, 0x100, ops);
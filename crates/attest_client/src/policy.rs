@@ -0,0 +1,388 @@
+//! Policy
+//!
+//! Evaluates a declarative policy against the capability graph of an attestation. A policy is a
+//! list of rules, loaded from JSON:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "rule": "exclusive_region", "domain": { "id": 2 }, "start": 4096, "end": 8192 },
+//!     {
+//!       "rule": "no_foreign_management",
+//!       "domain": { "measurement": "fefe..." },
+//!       "allowed": [{ "id": 0 }]
+//!     },
+//!     { "rule": "exec_not_aliased" }
+//!   ]
+//! }
+//! ```
+//!
+//! Domains are selected either by their ID within the attestation, or by measurement, in which
+//! case the selected domains are the ones whose measurement, computed by the monitor when the
+//! domain was sealed, matches.
+//!
+//! Evaluating a policy produces a [Report], listing for each rule the capabilities violating it.
+
+use core::fmt;
+
+use serde::Deserialize;
+
+use crate::{Capa, Context, Handle, Region, RegionKind};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Rule {
+    /// The domain has access to `[start, end)`, and no other domain has access to any part of it.
+    ExclusiveRegion {
+        domain: DomainSelector,
+        start: u64,
+        end: u64,
+    },
+    /// No domain holds a management capability to the domain, except the `allowed` ones.
+    NoForeignManagement {
+        domain: DomainSelector,
+        #[serde(default)]
+        allowed: Vec<DomainSelector>,
+    },
+    /// Executable memory is accessible only to the domain holding the executable region.
+    ///
+    /// If `domain` is set, only the executable regions of that domain are checked.
+    ExecNotAliased {
+        #[serde(default)]
+        domain: Option<DomainSelector>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainSelector {
+    /// The ID of the domain within the attestation.
+    Id(u64),
+    /// A hex-encoded measurement, matching the domains with that measurement.
+    Measurement(String),
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Parse(serde_json::Error),
+    InvalidMeasurement(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Parse(err) => write!(f, "invalid policy: {}", err),
+            PolicyError::InvalidMeasurement(m) => write!(f, "invalid measurement: '{}'", m),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl Policy {
+    pub fn from_json(json: &str) -> Result<Self, PolicyError> {
+        let policy: Policy = serde_json::from_str(json).map_err(PolicyError::Parse)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    fn validate(&self) -> Result<(), PolicyError> {
+        let mut selectors = Vec::new();
+        for rule in &self.rules {
+            match rule {
+                Rule::ExclusiveRegion { domain, .. } => selectors.push(domain),
+                Rule::NoForeignManagement { domain, allowed } => {
+                    selectors.push(domain);
+                    selectors.extend(allowed);
+                }
+                Rule::ExecNotAliased { domain } => selectors.extend(domain),
+            }
+        }
+        for selector in selectors {
+            if let DomainSelector::Measurement(m) = selector {
+                parse_hex(m).ok_or_else(|| PolicyError::InvalidMeasurement(m.clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the policy against an attestation.
+    pub fn evaluate(&self, ctx: &Context) -> Report {
+        let results = self
+            .rules
+            .iter()
+            .map(|rule| RuleResult {
+                rule: rule.to_string(),
+                violations: rule.evaluate(ctx),
+            })
+            .collect();
+        Report { results }
+    }
+}
+
+// ————————————————————————————————— Rules —————————————————————————————————— //
+
+impl Rule {
+    fn evaluate(&self, ctx: &Context) -> Vec<String> {
+        match self {
+            Rule::ExclusiveRegion { domain, start, end } => {
+                exclusive_region(ctx, domain, *start, *end)
+            }
+            Rule::NoForeignManagement { domain, allowed } => {
+                no_foreign_management(ctx, domain, allowed)
+            }
+            Rule::ExecNotAliased { domain } => exec_not_aliased(ctx, domain.as_ref()),
+        }
+    }
+}
+
+fn exclusive_region(ctx: &Context, selector: &DomainSelector, start: u64, end: u64) -> Vec<String> {
+    let owners = select(ctx, selector);
+    if owners.is_empty() {
+        return vec![format!("no domain matches {}", selector)];
+    }
+
+    let mut violations = Vec::new();
+    for &owner in &owners {
+        let covered = region_capas(ctx, owner)
+            .flat_map(|r| effective_ranges(ctx, r))
+            .fold(vec![(start, end)], |missing, covered| {
+                subtract(&missing, covered)
+            });
+        if !covered.is_empty() {
+            violations.push(format!(
+                "d{} has no access to 0x{:x}-0x{:x}",
+                owner, covered[0].0, covered[0].1
+            ));
+        }
+    }
+    for (d, r) in holders(ctx) {
+        if owners.contains(&d) {
+            continue;
+        }
+        if overlaps(ctx, r, (start, end)) {
+            violations.push(format!("d{}: r{}", d, r.idx));
+        }
+    }
+    violations
+}
+
+fn no_foreign_management(
+    ctx: &Context,
+    selector: &DomainSelector,
+    allowed: &[DomainSelector],
+) -> Vec<String> {
+    let targets = select(ctx, selector);
+    if targets.is_empty() {
+        return vec![format!("no domain matches {}", selector)];
+    }
+    let allowed: Vec<usize> = allowed.iter().flat_map(|s| select(ctx, s)).collect();
+
+    let mut violations = Vec::new();
+    for (idx, domain) in ctx.domains.store.iter().enumerate() {
+        if allowed.contains(&idx) {
+            continue;
+        }
+        for capa in &domain.capa {
+            if let Capa::Management(h) = capa {
+                if targets.contains(&h.idx) && h.idx != idx {
+                    violations.push(format!("d{}: d{}", idx, h.idx));
+                }
+            }
+        }
+    }
+    violations
+}
+
+fn exec_not_aliased(ctx: &Context, selector: Option<&DomainSelector>) -> Vec<String> {
+    let owners = selector.map(|s| select(ctx, s));
+    let holders = holders(ctx);
+
+    let mut violations = Vec::new();
+    for &(owner, exec) in &holders {
+        if !ctx.regions[exec].ops.contains(crate::MemOps::EXEC) {
+            continue;
+        }
+        if owners.as_ref().is_some_and(|o| !o.contains(&owner)) {
+            continue;
+        }
+        for range in effective_ranges(ctx, exec) {
+            for &(d, r) in &holders {
+                if d != owner && overlaps(ctx, r, range) {
+                    violations.push(format!(
+                        "d{}: r{} aliases d{}: r{}",
+                        d, r.idx, owner, exec.idx
+                    ));
+                }
+            }
+        }
+    }
+    violations
+}
+
+// ———————————————————————————————— Helpers ————————————————————————————————— //
+
+/// Returns the indices of the domains matching the selector.
+fn select(ctx: &Context, selector: &DomainSelector) -> Vec<usize> {
+    match selector {
+        DomainSelector::Id(id) => ctx
+            .domains
+            .store
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.id == *id)
+            .map(|(idx, _)| idx)
+            .collect(),
+        DomainSelector::Measurement(m) => {
+            let Some(hash) = parse_hex(m) else {
+                return Vec::new();
+            };
+            ctx.domains
+                .store
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| d.measurement.is_some_and(|m| m[..] == hash[..]))
+                .map(|(idx, _)| idx)
+                .collect()
+        }
+    }
+}
+
+/// Returns the (domain index, region) pairs for all the region capabilities.
+fn holders(ctx: &Context) -> Vec<(usize, Handle<Region>)> {
+    (0..ctx.domains.store.len())
+        .flat_map(|d| region_capas(ctx, d).map(move |r| (d, r)))
+        .collect()
+}
+
+fn region_capas(ctx: &Context, domain: usize) -> impl Iterator<Item = Handle<Region>> + '_ {
+    ctx.domains.store[domain]
+        .capa
+        .iter()
+        .filter_map(|c| match c {
+            Capa::Region(r) => Some(*r),
+            Capa::Management(_) => None,
+        })
+}
+
+/// Returns the ranges accessible through a region, i.e. its range minus the carved children.
+fn effective_ranges(ctx: &Context, region: Handle<Region>) -> Vec<(u64, u64)> {
+    let r = &ctx.regions[region];
+    ctx.regions
+        .store
+        .iter()
+        .filter(|child| matches!(child.kind, RegionKind::Carve(p) if p == region))
+        .fold(vec![(r.start, r.end)], |ranges, child| {
+            subtract(&ranges, (child.start, child.end))
+        })
+}
+
+fn overlaps(ctx: &Context, region: Handle<Region>, (start, end): (u64, u64)) -> bool {
+    effective_ranges(ctx, region)
+        .iter()
+        .any(|&(s, e)| s < end && start < e)
+}
+
+/// Removes `hole` from a list of ranges.
+fn subtract(ranges: &[(u64, u64)], (hole_start, hole_end): (u64, u64)) -> Vec<(u64, u64)> {
+    let mut result = Vec::new();
+    for &(start, end) in ranges {
+        if hole_end <= start || end <= hole_start {
+            result.push((start, end));
+            continue;
+        }
+        if start < hole_start {
+            result.push((start, hole_start));
+        }
+        if hole_end < end {
+            result.push((hole_end, end));
+        }
+    }
+    result
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some(hex_digit(*hi) << 4 | hex_digit(*lo)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(digit: u8) -> u8 {
+    (digit as char).to_digit(16).unwrap() as u8
+}
+
+// ———————————————————————————————— Report —————————————————————————————————— //
+
+pub struct Report {
+    pub results: Vec<RuleResult>,
+}
+
+pub struct RuleResult {
+    /// A description of the rule.
+    pub rule: String,
+    /// The capabilities violating the rule, empty if the rule is satisfied.
+    pub violations: Vec<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.violations.is_empty())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.violations.is_empty() {
+                "PASS"
+            } else {
+                "FAIL"
+            };
+            writeln!(f, "{} {}", status, result.rule)?;
+            for violation in &result.violations {
+                writeln!(f, "  {}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DomainSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainSelector::Id(id) => write!(f, "d{}", id),
+            DomainSelector::Measurement(m) => write!(f, "measurement {}", m),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::ExclusiveRegion { domain, start, end } => {
+                write!(f, "exclusive_region {} 0x{:x}-0x{:x}", domain, start, end)
+            }
+            Rule::NoForeignManagement { domain, allowed } => {
+                write!(f, "no_foreign_management {}", domain)?;
+                for a in allowed {
+                    write!(f, " allowed {}", a)?;
+                }
+                Ok(())
+            }
+            Rule::ExecNotAliased { domain: Some(d) } => write!(f, "exec_not_aliased {}", d),
+            Rule::ExecNotAliased { domain: None } => write!(f, "exec_not_aliased"),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! TPM keys
+//!
+//! The monitor signs with the TPM through `wolftpm_sys::hash_and_sign`, which hashes the data with
+//! SHA-256 and signs the digest with the RSA signing key of the TPM, using the RSASSA-PKCS1-v1_5
+//! scheme. The public part of that key is returned by the `GET_SIGNING_KEY` monitor call, as the
+//! big-endian modulus of the key.
+//!
+//! Only verification is needed, so the arithmetic does not have to be constant time.
+
+use crate::verifier::TrustedKey;
+
+/// The default public exponent of TPM RSA keys.
+pub const DEFAULT_EXPONENT: u32 = 65537;
+
+/// The public RSA signing key of a TPM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TpmKey {
+    /// Little endian limbs of the modulus, without leading zero limbs.
+    modulus: Vec<u64>,
+    /// Size of the modulus, in bytes.
+    len: usize,
+    exponent: u32,
+}
+
+impl TpmKey {
+    /// Creates a key from its big-endian modulus, as returned by `GET_SIGNING_KEY`, and the
+    /// default exponent.
+    pub fn from_modulus(modulus: &[u8]) -> Option<Self> {
+        Self::new(modulus, DEFAULT_EXPONENT)
+    }
+
+    /// Creates a key from its big-endian modulus and its public exponent.
+    pub fn new(modulus: &[u8], exponent: u32) -> Option<Self> {
+        let first = modulus.iter().position(|&byte| byte != 0)?;
+        let modulus = &modulus[first..];
+        // Leaves room for the PKCS#1 padding around the digest
+        if modulus.len() < DIGEST_INFO.len() + SHA256_SZ + 11 || exponent < 3 {
+            return None;
+        }
+        Some(TpmKey {
+            modulus: from_be_bytes(modulus),
+            len: modulus.len(),
+            exponent,
+        })
+    }
+}
+
+impl TrustedKey for TpmKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != self.len {
+            return false;
+        }
+        let signature = from_be_bytes(signature);
+        if cmp(&signature, &self.modulus) != core::cmp::Ordering::Less {
+            return false;
+        }
+
+        // Recover the encoded message, and compare it to the expected one (RFC 8017, 8.2.2)
+        let mut encoded = vec![0; self.len];
+        let recovered = mod_pow(&signature, self.exponent, &self.modulus);
+        for (idx, byte) in encoded.iter_mut().rev().enumerate() {
+            *byte = (recovered[idx / 8] >> (8 * (idx % 8))) as u8;
+        }
+        encoded == self.encode(&sha256(message))
+    }
+}
+
+/// DER encoding of the SHA-256 algorithm identifier, prepended to the digest.
+const DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+impl TpmKey {
+    /// EMSA-PKCS1-v1_5 encoding of a SHA-256 digest.
+    fn encode(&self, digest: &[u8; SHA256_SZ]) -> Vec<u8> {
+        let padding = self.len - DIGEST_INFO.len() - SHA256_SZ - 3;
+        let mut encoded = Vec::with_capacity(self.len);
+        encoded.extend_from_slice(&[0x00, 0x01]);
+        encoded.resize(2 + padding, 0xff);
+        encoded.push(0x00);
+        encoded.extend_from_slice(&DIGEST_INFO);
+        encoded.extend_from_slice(digest);
+        encoded
+    }
+}
+
+// ———————————————————————————————— Big integers ———————————————————————————————— //
+
+/// Converts big-endian bytes into little endian limbs.
+fn from_be_bytes(bytes: &[u8]) -> Vec<u64> {
+    let mut limbs = vec![0; bytes.len().div_ceil(8)];
+    for (idx, &byte) in bytes.iter().rev().enumerate() {
+        limbs[idx / 8] |= (byte as u64) << (8 * (idx % 8));
+    }
+    limbs
+}
+
+/// Compares two integers, given as little endian limbs.
+fn cmp(a: &[u64], b: &[u64]) -> core::cmp::Ordering {
+    for idx in (0..usize::max(a.len(), b.len())).rev() {
+        let x = a.get(idx).copied().unwrap_or(0);
+        let y = b.get(idx).copied().unwrap_or(0);
+        if x != y {
+            return x.cmp(&y);
+        }
+    }
+    core::cmp::Ordering::Equal
+}
+
+/// Computes `(a + b) mod m`, with `a` and `b` lower than `m`.
+fn add_mod(a: &[u64], b: &[u64], m: &[u64]) -> Vec<u64> {
+    let mut sum = Vec::with_capacity(m.len() + 1);
+    let mut carry = 0;
+    for idx in 0..m.len() {
+        let (s, c1) = a[idx].overflowing_add(b[idx]);
+        let (s, c2) = s.overflowing_add(carry);
+        sum.push(s);
+        carry = (c1 || c2) as u64;
+    }
+    sum.push(carry);
+    if cmp(&sum, m) != core::cmp::Ordering::Less {
+        let mut borrow = 0;
+        for (idx, limb) in sum.iter_mut().enumerate() {
+            let y = m.get(idx).copied().unwrap_or(0);
+            let (d, b1) = limb.overflowing_sub(y);
+            let (d, b2) = d.overflowing_sub(borrow);
+            *limb = d;
+            borrow = (b1 || b2) as u64;
+        }
+    }
+    sum.truncate(m.len());
+    sum
+}
+
+/// Computes `(a * b) mod m` by double-and-add, with `a` and `b` lower than `m`.
+fn mul_mod(a: &[u64], b: &[u64], m: &[u64]) -> Vec<u64> {
+    let mut acc = vec![0; m.len()];
+    for idx in (0..b.len() * 64).rev() {
+        acc = add_mod(&acc, &acc, m);
+        if (b[idx / 64] >> (idx % 64)) & 1 == 1 {
+            acc = add_mod(&acc, a, m);
+        }
+    }
+    acc
+}
+
+/// Computes `base^exponent mod m`, with `base` lower than `m`.
+fn mod_pow(base: &[u64], exponent: u32, m: &[u64]) -> Vec<u64> {
+    let mut base = base.to_vec();
+    base.resize(m.len(), 0);
+    let mut acc = base.clone();
+    for bit in (0..31 - exponent.leading_zeros()).rev() {
+        acc = mul_mod(&acc, &acc, m);
+        if (exponent >> bit) & 1 == 1 {
+            acc = mul_mod(&acc, &base, m);
+        }
+    }
+    acc
+}
+
+// ——————————————————————————————————— SHA-256 ——————————————————————————————————— //
+
+const SHA256_SZ: usize = 32;
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 digest of `data`, as computed by the TPM before signing.
+pub fn sha256(data: &[u8]) -> [u8; SHA256_SZ] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (idx, word) in block.chunks_exact(4).enumerate() {
+            w[idx] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for idx in 16..64 {
+            let s0 =
+                w[idx - 15].rotate_right(7) ^ w[idx - 15].rotate_right(18) ^ (w[idx - 15] >> 3);
+            let s1 = w[idx - 2].rotate_right(17) ^ w[idx - 2].rotate_right(19) ^ (w[idx - 2] >> 10);
+            w[idx] = w[idx - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[idx - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for idx in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[idx])
+                .wrapping_add(w[idx]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; SHA256_SZ];
+    for (idx, word) in state.iter().enumerate() {
+        digest[4 * idx..4 * idx + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
@@ -0,0 +1,164 @@
+//! Verifier
+//!
+//! Checks the signed attestations produced by the monitor, before trusting their content.
+//!
+//! Two kinds of evidence are supported:
+//!
+//! - The capability graph, as returned by the `SIGNED_ATTESTATION` monitor call. The monitor
+//!   appends the nonce chosen by the verifier (8 bytes, little endian) to the serialized graph and
+//!   signs both.
+//! - The Argos transcript of a domain, as returned by `ARGOS_GET_SIGNED_TRANSCRIPT`. The
+//!   transcript chains the measurement of the domain with the data appended by the domain, the
+//!   verifier recomputes the expected transcript with [Transcript], including its nonce.
+//! - The report of a domain, as returned by `ENCLAVE_ATTESTATION`. The report signs the
+//!   measurement of the domain and the nonce with the attestation key of the monitor, which is
+//!   generated at boot and certified by the TPM, see [verify_report].
+//!
+//! The TPM signs with an RSA key, see [TpmKey](crate::tpm::TpmKey).
+
+use attestation::hashing::{hash_region, TycheHasher};
+use attestation::signature::{certificate_data, ATTESTATION_DATA_SZ};
+use ed25519_compact::{PublicKey, Signature};
+
+use crate::{deserialize_prefix, AttestError, Context};
+
+/// Size of the nonce appended to the attestation.
+pub const NONCE_SZ: usize = core::mem::size_of::<u64>();
+
+/// A key trusted to sign attestations, such as the TPM or the monitor key.
+pub trait TrustedKey {
+    /// Returns true if `signature` is a valid signature of `message` by this key.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+impl<K: TrustedKey + ?Sized> TrustedKey for Box<K> {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        K::verify(self, message, signature)
+    }
+}
+
+impl TrustedKey for PublicKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return false;
+        };
+        PublicKey::verify(self, message, &signature).is_ok()
+    }
+}
+
+/// Verifies an attestation of the capability graph, and returns its content.
+///
+/// `attestation` is the buffer written by `SIGNED_ATTESTATION`, that is the serialized graph
+/// followed by the nonce, and `nonce` the value provided by the verifier when requesting the
+/// attestation.
+pub fn verify_attestation(
+    attestation: &[u8],
+    signature: &[u8],
+    key: &impl TrustedKey,
+    nonce: u64,
+) -> Result<Context, AttestError> {
+    if !key.verify(attestation, signature) {
+        return Err(AttestError::InvalidSignature);
+    }
+
+    let (ctx, len) = deserialize_prefix(attestation)?;
+    let found = attestation
+        .get(len..)
+        .and_then(|rest| <[u8; NONCE_SZ]>::try_from(rest).ok())
+        .ok_or(AttestError::Malformed(
+            "Expected a nonce after the attestation",
+        ))?;
+    let found = u64::from_le_bytes(found);
+    if found != nonce {
+        return Err(AttestError::StaleNonce {
+            expected: nonce,
+            found,
+        });
+    }
+
+    Ok(ctx)
+}
+
+/// Verifies a signed Argos transcript against the transcript expected by the verifier.
+pub fn verify_transcript(
+    transcript: &[u8],
+    signature: &[u8],
+    key: &impl TrustedKey,
+    expected: &Transcript,
+) -> Result<(), AttestError> {
+    if !key.verify(transcript, signature) {
+        return Err(AttestError::InvalidSignature);
+    }
+    if transcript != expected.digest() {
+        return Err(AttestError::Malformed("Unexpected transcript"));
+    }
+    Ok(())
+}
+
+/// Verifies the report of a domain against its expected measurement.
+///
+/// `public_key` and `signature` are the attestation key and the report signature returned by
+/// `ENCLAVE_ATTESTATION`, and `certificate` the certificate of the attestation key, signed by
+/// `device_key`. The report is only trusted if the attestation key is certified.
+pub fn verify_report(
+    public_key: &PublicKey,
+    signature: &[u8],
+    certificate: &[u8],
+    device_key: &impl TrustedKey,
+    measurement: &[u8; 32],
+    nonce: u64,
+) -> Result<(), AttestError> {
+    if !device_key.verify(&certificate_data(public_key), certificate) {
+        return Err(AttestError::InvalidCertificate);
+    }
+
+    // Same layout as the data signed by the monitor: the measurement followed by the nonce
+    let mut data = [0; ATTESTATION_DATA_SZ];
+    data[..measurement.len()].copy_from_slice(measurement);
+    data[measurement.len()..].copy_from_slice(&nonce.to_le_bytes());
+    if !TrustedKey::verify(public_key, &data, signature) {
+        return Err(AttestError::InvalidSignature);
+    }
+    Ok(())
+}
+
+/// The expected Argos transcript of a domain.
+///
+/// Mirrors the transcript maintained by the monitor: each append hashes the previous transcript
+/// (or the measurement of the domain, for the first append) with the new data.
+#[derive(Clone)]
+pub struct Transcript {
+    digest: [u8; 32],
+}
+
+impl Transcript {
+    /// Starts a transcript from the measurement of the domain.
+    pub fn new(measurement: [u8; 32]) -> Self {
+        Self {
+            digest: measurement,
+        }
+    }
+
+    /// Appends data, as done by `ARGOS_APPEND_TRANSCRIPT` without hashing.
+    pub fn append(&mut self, data: &[u8]) -> &mut Self {
+        let mut hasher = TycheHasher::new();
+        hasher.update(&self.digest);
+        hasher.update(data);
+        self.digest = *hasher.finalize().as_bytes();
+        self
+    }
+
+    /// Appends the hash of data, as done by `ARGOS_APPEND_TRANSCRIPT` with hashing.
+    pub fn append_hashed(&mut self, data: &[u8]) -> &mut Self {
+        self.append(&hash_region(data))
+    }
+
+    /// Appends a nonce, as the domain is expected to do to prove the freshness of the transcript.
+    pub fn append_nonce(&mut self, nonce: u64) -> &mut Self {
+        self.append(&nonce.to_le_bytes())
+    }
+
+    pub fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+}
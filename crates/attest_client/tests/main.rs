@@ -1,5 +1,17 @@
-use attest_client::deserialize;
-use capa_engine::{permission, AccessRights, CapaEngine, MemOps, MEMOPS_ALL};
+use attest_client::policy::Policy;
+use attest_client::tpm::{self, TpmKey};
+use attest_client::verifier::{
+    verify_attestation, verify_report, verify_transcript, Transcript, TrustedKey,
+};
+use attest_client::{deserialize, AttestError};
+use attestation::hashing::HashEnclave;
+use attestation::signature::{
+    certificate_data, AttestationIdentity, ATTESTATION_DATA_SZ, ATTESTATION_SEED_SZ,
+};
+use capa_engine::flow::{self, labels, transfer, Rule};
+use capa_engine::pool::PoolMemory;
+use capa_engine::{permission, AccessRights, CapaEngine, EngineConfig, MemOps, MEMOPS_ALL};
+use ed25519_compact::{KeyPair, Noise, Seed};
 
 /// Snapshot testing
 ///
@@ -13,7 +25,7 @@ macro_rules! snap {
 /// Creates a static CapaEngine and returns a mutable reference
 ///
 /// This is required to avoid stack overflow when creating a new engine on the stack, due to the
-/// size of the engine.
+/// size of the engine. The engine pools are backed by leaked memory.
 ///
 /// # SAFETY:
 /// The macro returns a mutable reference to a global static, thus the usual rules applies.
@@ -21,6 +33,11 @@ macro_rules! snap {
 macro_rules! static_engine {
     () => {{
         static mut ENGINE: CapaEngine = CapaEngine::new();
+        let size = EngineConfig::DEFAULT.memory_size();
+        let memory = Box::leak(vec![0u8; size].into_boxed_slice());
+        ENGINE
+            .init(EngineConfig::DEFAULT, &mut PoolMemory::from_slice(memory))
+            .unwrap();
         &mut ENGINE
     }};
 }
@@ -71,7 +88,7 @@ fn scenario_1() {
     // Send some of the regions
     engine.send(d0, r1, d1).unwrap();
     engine
-        .send_with_flags(d0, r2, d1, Some(MemOps::HASH), Some([0xfe; 32]))
+        .send_with_flags(d0, r2, d1, Some(MemOps::HASH), Some([0xfe; 32]), None)
         .unwrap();
     engine.send(d0, r3, d2).unwrap();
     engine.send(d0, r4, d2).unwrap();
@@ -86,7 +103,7 @@ fn scenario_1() {
   r2 = carve r0 0x30 0x50 with RWXS fefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefe
   r3 = alias r2 0x40 0x50 with RWXS
   r4 = carve r0 0x60 0x80 with RWXS
-  d0 = domain { d1, d2, r0 } with SPAWN | SEND | ALIAS | CARVE
+  d0 = domain { d1, d2, r0 } with SPAWN | SEND | ALIAS | CARVE labeled INITIAL
   d1 = domain { r1, r2 } with NONE
   d2 = domain { r3, r4 } with NONE
 }
@@ -95,6 +112,320 @@ fn scenario_1() {
     );
 }
 
+#[test]
+fn flow_policy() {
+    let engine = unsafe { static_engine!() };
+    let rule = Rule {
+        transfers: transfer::SEND_REGION,
+        to: labels::INITIAL,
+        region: 0x1,
+        ..Rule::EMPTY
+    };
+    let policy = flow::Policy::new(&[rule]).unwrap();
+    engine.load_flow_policy(policy).unwrap();
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let d1 = engine.create_domain(d0).unwrap();
+    engine.set_child_labels(d0, d1, 0x1).unwrap();
+
+    // The labels and the policy are part of the attestation
+    let mut buff = vec![0; 4096];
+    let n = engine.serialize_attestation(&mut buff).unwrap();
+    let ctx = deserialize(&buff[..n]).unwrap();
+    assert_eq!(ctx.flow_rules(), [rule]);
+    snap!(
+        r#"Attestation {
+  d0 = domain { d1 } with SPAWN | SEND | ALIAS | CARVE labeled INITIAL
+  d1 = domain { } with NONE labeled 0x1
+  Rule(deny 0x1 from 0x0 to 0x8000000000000000, region 0x1 ops 0x0)
+}
+"#,
+        ctx
+    );
+}
+
+// ——————————————————————————————— Verifier ————————————————————————————————— //
+
+#[test]
+fn verify_signed_attestation() {
+    let engine = unsafe { static_engine!() };
+    let key = KeyPair::from_seed(Seed::new([1; Seed::BYTES]));
+    let nonce = 0xcafe;
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine
+        .create_root_region(d0, dummy_access(0, 0x1000))
+        .unwrap();
+
+    // Same layout as the SIGNED_ATTESTATION monitor call
+    let mut buff = vec![0; 4096];
+    let n = engine.serialize_attestation(&mut buff).unwrap();
+    buff.truncate(n);
+    buff.extend_from_slice(&u64::to_le_bytes(nonce));
+    let signature = key.sk.sign(&buff, Some(Noise::default()));
+
+    let ctx = verify_attestation(&buff, signature.as_ref(), &key.pk, nonce).unwrap();
+    snap!(
+        r#"Attestation {
+  r0 = root 0x0 0x1000 with RWXS
+  d0 = domain { r0 } with SPAWN | SEND | ALIAS | CARVE labeled INITIAL
+}
+"#,
+        ctx
+    );
+
+    // Replayed attestation
+    assert_eq!(
+        verify_attestation(&buff, signature.as_ref(), &key.pk, nonce + 1).err(),
+        Some(AttestError::StaleNonce {
+            expected: nonce + 1,
+            found: nonce
+        })
+    );
+
+    // Untrusted key
+    let other = KeyPair::from_seed(Seed::new([2; Seed::BYTES]));
+    assert_eq!(
+        verify_attestation(&buff, signature.as_ref(), &other.pk, nonce).err(),
+        Some(AttestError::InvalidSignature)
+    );
+
+    // Tampered attestation
+    buff[n - 2] ^= 1;
+    assert_eq!(
+        verify_attestation(&buff, signature.as_ref(), &key.pk, nonce).err(),
+        Some(AttestError::InvalidSignature)
+    );
+
+    // Missing nonce
+    let signature = key.sk.sign(&buff[..n], Some(Noise::default()));
+    assert!(verify_attestation(&buff[..n], signature.as_ref(), &key.pk, nonce).is_err());
+}
+
+#[test]
+fn verify_signed_transcript() {
+    let key = KeyPair::from_seed(Seed::new([1; Seed::BYTES]));
+    let mut expected = Transcript::new([0xab; 32]);
+    expected.append_hashed(b"enclave data").append_nonce(42);
+
+    // Transcript as computed by the monitor
+    let data_hash = attestation::hashing::hash_region(b"enclave data");
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[0xab; 32]);
+    hasher.update(&data_hash);
+    let first = *hasher.finalize().as_bytes();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&first);
+    hasher.update(&42u64.to_le_bytes());
+    let transcript = *hasher.finalize().as_bytes();
+    let signature = key.sk.sign(transcript, Some(Noise::default()));
+
+    verify_transcript(&transcript, signature.as_ref(), &key.pk, &expected).unwrap();
+
+    let mut stale = Transcript::new([0xab; 32]);
+    stale.append_hashed(b"enclave data").append_nonce(43);
+    assert!(verify_transcript(&transcript, signature.as_ref(), &key.pk, &stale).is_err());
+}
+
+#[test]
+fn truncated_attestation() {
+    assert_eq!(deserialize(b"capa").err(), Some(AttestError::Truncated));
+    assert!(matches!(
+        deserialize(b"nope\x01\xff"),
+        Err(AttestError::Malformed(_))
+    ));
+}
+
+#[test]
+fn verify_domain_report() {
+    let tpm = TpmKey::from_modulus(&from_hex(TPM_MODULUS)).unwrap();
+    let measurement = [0xab; 32];
+    let nonce: u64 = 0xcafe;
+
+    // Same signing path as the monitor, with the TPM signature precomputed
+    let identity = AttestationIdentity::new([7; ATTESTATION_SEED_SZ], |_, dest| {
+        let signature = from_hex(TPM_CERTIFICATE);
+        dest[..signature.len()].copy_from_slice(&signature);
+        signature.len()
+    });
+    let mut data = [0; ATTESTATION_DATA_SZ];
+    data[..32].copy_from_slice(&measurement);
+    data[32..].copy_from_slice(&nonce.to_le_bytes());
+    let report = identity.report(&data);
+    let certificate = report.certificate.signature();
+    let signature = report.signed_enclave_data.as_ref();
+
+    verify_report(
+        &report.public_key,
+        signature,
+        certificate,
+        &tpm,
+        &measurement,
+        nonce,
+    )
+    .unwrap();
+
+    // Replayed report
+    assert_eq!(
+        verify_report(
+            &report.public_key,
+            signature,
+            certificate,
+            &tpm,
+            &measurement,
+            nonce + 1
+        ),
+        Err(AttestError::InvalidSignature)
+    );
+
+    // Unexpected measurement
+    assert_eq!(
+        verify_report(
+            &report.public_key,
+            signature,
+            certificate,
+            &tpm,
+            &[0xac; 32],
+            nonce
+        ),
+        Err(AttestError::InvalidSignature)
+    );
+
+    // Attestation key not certified by the TPM
+    let other = KeyPair::from_seed(Seed::new([2; Seed::BYTES]));
+    let signature = other.sk.sign(data, Some(Noise::default()));
+    assert_eq!(
+        verify_report(
+            &other.pk,
+            signature.as_ref(),
+            certificate,
+            &tpm,
+            &measurement,
+            nonce
+        ),
+        Err(AttestError::InvalidCertificate)
+    );
+
+    // Tampered certificate
+    let mut certificate = certificate.to_vec();
+    certificate[10] ^= 1;
+    assert_eq!(
+        verify_report(
+            &report.public_key,
+            report.signed_enclave_data.as_ref(),
+            &certificate,
+            &tpm,
+            &measurement,
+            nonce
+        ),
+        Err(AttestError::InvalidCertificate)
+    );
+}
+
+#[test]
+fn tpm_key() {
+    assert_eq!(
+        tpm::sha256(b"abc"),
+        from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")[..]
+    );
+
+    let tpm = TpmKey::from_modulus(&from_hex(TPM_MODULUS)).unwrap();
+    let certificate = from_hex(TPM_CERTIFICATE);
+    let identity = AttestationIdentity::new([7; ATTESTATION_SEED_SZ], |_, _| 0);
+    let data = certificate_data(&identity.public_key());
+    assert!(tpm.verify(&data, &certificate));
+    assert!(!tpm.verify(&data[1..], &certificate));
+    assert!(!tpm.verify(&data, &certificate[1..]));
+
+    // Keys too small for the signature scheme
+    assert_eq!(TpmKey::from_modulus(&[0xff; 32]), None);
+    assert_eq!(TpmKey::from_modulus(&[]), None);
+}
+
+// ———————————————————————————————— Policies ———————————————————————————————— //
+
+#[test]
+fn policy() {
+    let engine = unsafe { static_engine!() };
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let d1 = engine.create_domain(d0).unwrap();
+    let d2 = engine.create_domain(d0).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x100))
+        .unwrap();
+    let r1 = engine
+        .carve_region(d0, r0, dummy_access(0x10, 0x30))
+        .unwrap();
+    let r2 = engine
+        .alias_region(d0, r1, dummy_access(0x20, 0x30))
+        .unwrap();
+    engine
+        .send_with_flags(d0, r1, d1, Some(MemOps::HASH), Some([0xfe; 32]), None)
+        .unwrap();
+    engine.send(d0, r2, d2).unwrap();
+    let d1_handle = engine.get_domain_capa(d0, d1).unwrap();
+    engine.set_hash(d1_handle, HashEnclave::from_bytes(&[0xfd; 32]));
+
+    let mut buff = vec![0; 4096];
+    let n = engine.serialize_attestation(&mut buff).unwrap();
+    let ctx = deserialize(&buff[..n]).unwrap();
+    assert!(format!("{}", ctx).contains(&format!(
+        "d1 = domain {{ r1 }} with NONE measured {}",
+        "fd".repeat(32)
+    )));
+
+    // Domains are selected by their measurement, not by the hash of their regions
+    let policy = Policy::from_json(&format!(
+        r#"{{
+            "rules": [
+                {{ "rule": "exclusive_region", "domain": {{ "measurement": "{}" }}, "start": 16, "end": 32 }},
+                {{ "rule": "exclusive_region", "domain": {{ "measurement": "{}" }}, "start": 16, "end": 48 }},
+                {{ "rule": "exclusive_region", "domain": {{ "id": 1 }}, "start": 16, "end": 48 }},
+                {{ "rule": "no_foreign_management", "domain": {{ "id": 1 }}, "allowed": [{{ "id": 0 }}] }},
+                {{ "rule": "no_foreign_management", "domain": {{ "id": 2 }} }},
+                {{ "rule": "exec_not_aliased", "domain": {{ "id": 1 }} }}
+            ]
+        }}"#,
+        "fd".repeat(32),
+        "fe".repeat(32)
+    ))
+    .unwrap();
+    let report = policy.evaluate(&ctx);
+    assert!(!report.passed());
+    snap!(
+        &format!(
+            r#"PASS exclusive_region measurement {} 0x10-0x20
+FAIL exclusive_region measurement {} 0x10-0x30
+  no domain matches measurement {}
+FAIL exclusive_region d1 0x10-0x30
+  d2: r2
+PASS no_foreign_management d1 allowed d0
+FAIL no_foreign_management d2
+  d0: d2
+FAIL exec_not_aliased d1
+  d2: r2 aliases d1: r1
+"#,
+            "fd".repeat(32),
+            "fe".repeat(32),
+            "fe".repeat(32)
+        ),
+        report
+    );
+
+    assert!(Policy::from_json(r#"{ "rules": [{ "rule": "exec_not_aliased" }] }"#).is_ok());
+    assert!(Policy::from_json(r#"{ "rules": [{ "rule": "unknown" }] }"#).is_err());
+    assert!(Policy::from_json(
+        r#"{ "rules": [{ "rule": "exec_not_aliased", "domain": { "measurement": "xyz" } }] }"#
+    )
+    .is_err());
+}
+
 // ————————————————————————————————— Utils —————————————————————————————————— //
 
 fn dummy_access(start: usize, end: usize) -> AccessRights {
@@ -104,3 +435,29 @@ fn dummy_access(start: usize, end: usize) -> AccessRights {
         ops: MEMOPS_ALL,
     }
 }
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
+        .collect()
+}
+
+/// Modulus of an RSA-2048 TPM signing key, generated with OpenSSL.
+const TPM_MODULUS: &str = "\
+    beae84eb3dabf9409fd6095d9b48bde310663189a9c47c89415cdbaab8974e98ab512a28ec2e227400a8ffbc\
+    83fa39fff4c80c17e78bda62e443484d49e9224f559cd54cf655d66d2f115d563a90539c4006ef1e4d959849\
+    31d02f6df520f6a1f4ade0a69c4310b799c60748709ec289690924973ff1b0ad4054919268dc394815080090\
+    d17e773c03a42a133bd0f532dcd5e76a02ac9dab5d820c69e3e23ddb7abe1d4758e367b7f7f5f1624a970732\
+    9d65026fefe6161ab387c9295ceecad53150b1b218d395eb4019d1368661dc1972bac3c4ce5f1c5fa0c3069e\
+    a5af6262d20501f185d11020c8a0769c727ded0afe0c441f96617a3728a8944c53272955";
+
+/// Certificate of the attestation key derived from `[7; ATTESTATION_SEED_SZ]`, that is the
+/// RSASSA-PKCS1-v1_5 SHA-256 signature of its certificate data by the TPM key.
+const TPM_CERTIFICATE: &str = "\
+    aa835caa0e936fd94ed8a1833c84d156b593da8a359f3158b9303eecbf9a3f3d8b90ac853bc5c376a56af58c\
+    e933c2b24d1d96fae475aa19f7ca55f0cc48644da9127210ac65576f30ca27fd2716593ed789faaf3c25769f\
+    487fe5c492e5cf35ac9d2db5ea30367b3bfbdbe52e05d883ad13f75e2f43ddf381e75105226f974c5a4d58de\
+    d4176bf554b495fd7b415a666002318868c018e71c25d05d0f877539b9fa9ff2b2c01c5a3918c400359c67cf\
+    8a7baf1382f4e2a6aa688c3adc32e0d7e1f6da500ca872d0d66bd1ce19cc9b487a4215438a0886c60d68621e\
+    f0b0372e5f041c636a30e43667897729f9fd9174f5d25ccd92f397766f876ca908db0ea5";
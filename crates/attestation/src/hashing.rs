@@ -8,6 +8,15 @@ pub struct HashEnclave {
 }
 
 impl HashEnclave {
+    /// Creates a hash from the bytes of a digest, such that [HashEnclave::to_byte_arr] returns
+    /// the digest bytes in the same order.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        HashEnclave {
+            low: u128::from_le_bytes(bytes[0..16].try_into().unwrap()),
+            high: u128::from_le_bytes(bytes[16..32].try_into().unwrap()),
+        }
+    }
+
     pub fn bytes_size(&self) -> u64 {
         u128::BITS as u64 / 4
     }
@@ -39,14 +48,7 @@ pub fn get_hash(hasher: TycheHasher) -> HashEnclave {
     let result = hasher.finalize();
     log::trace!("Computed hash: ");
     log::trace!("{}", result);
-    // TODO: this is swapped and wrong!
-    let hash_low: u128 = u128::from_be_bytes(result.as_bytes()[0..16].try_into().unwrap());
-    let hash_high: u128 = u128::from_be_bytes(result.as_bytes()[16..32].try_into().unwrap());
-    let henc = HashEnclave {
-        low: hash_low,
-        high: hash_high,
-    };
-    henc
+    HashEnclave::from_bytes(result.as_bytes())
 }
 
 pub fn hash_region(region: &[u8]) -> [u8; 32] {
@@ -2,4 +2,5 @@
 
 pub mod hashing;
 pub mod hashset;
+pub mod measurement;
 pub mod signature;
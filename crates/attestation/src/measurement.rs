@@ -0,0 +1,243 @@
+//! Enclave measurement
+//!
+//! The measurement of an enclave is computed by the monitor when the enclave is sealed, and
+//! offline by tychools from the enclave binary. Both rely on this module so that the offline
+//! prediction matches bit-for-bit what the monitor reports.
+//!
+//! # Specification (version 1)
+//!
+//! The measurement is the blake3 digest of the concatenation of one entry per memory region of the
+//! enclave, in the order of the region capabilities of the domain (i.e. the order in which the
+//! loader sends the segments). An entry is laid out as follows:
+//!
+//! | Field        | Size (bytes) | Value                                            |
+//! |--------------|--------------|--------------------------------------------------|
+//! | start        | 8            | Start address, little endian                     |
+//! | end          | 8            | End address (excluded), little endian            |
+//! | hash         | 1            | 1 if the content is measured, 0 otherwise        |
+//! | exec         | 1            | 1 if executable, 0 otherwise                     |
+//! | write        | 1            | 1 if writable, 0 otherwise                       |
+//! | read         | 1            | 1 if readable, 0 otherwise                       |
+//! | confidential | 1            | 1 if confidential, 0 if shared                   |
+//! | content      | end - start  | Content of the region, present only if hash is 1 |
+//!
+//! The measurement is the 32 bytes of the digest, in the order produced by blake3.
+//!
+//! # Test vectors
+//!
+//! | Regions                                                                  | Measurement |
+//! |--------------------------------------------------------------------------|-------------|
+//! | None                                                                     | `af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262` |
+//! | `[0x1000, 0x2000)`, hash, exec, read, confidential, content `0xAA` bytes | `90841596796de3aec3410abb512d527924ef44e66cf167de8052ea1612440f46` |
+//! | Above, then `[0x2000, 0x3000)`, write, read, shared, not hashed          | `27249a964c10cb5e7e69e899af18a1935efef18ed94f3da8e8d20fc523838184` |
+//!
+//! The test vectors are checked by the unit tests of this module.
+
+use crate::hashing::{get_hasher, TycheHasher};
+
+/// Version of the measurement specification.
+pub const MEASUREMENT_VERSION: usize = 1;
+
+/// Size of a measurement, in bytes.
+pub const MEASUREMENT_SZ: usize = 32;
+
+/// The measurement of an enclave.
+pub type Measurement = [u8; MEASUREMENT_SZ];
+
+/// A memory region, as seen by the measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeasuredRegion {
+    pub start: u64,
+    pub end: u64,
+    /// Is the content of the region part of the measurement?
+    pub hash: bool,
+    pub exec: bool,
+    pub write: bool,
+    pub read: bool,
+    pub confidential: bool,
+}
+
+impl MeasuredRegion {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// Computes the measurement of an enclave, one region at a time.
+pub struct Measurer {
+    hasher: TycheHasher,
+    /// Number of bytes of content expected for the current region.
+    remaining: u64,
+}
+
+impl Measurer {
+    pub fn new() -> Self {
+        Measurer {
+            hasher: get_hasher(),
+            remaining: 0,
+        }
+    }
+
+    /// Adds a region to the measurement.
+    ///
+    /// If the region is hashed its content must then be provided through [Measurer::content],
+    /// before adding the next region.
+    pub fn region(&mut self, region: &MeasuredRegion) {
+        assert_eq!(self.remaining, 0, "Missing content of the previous region");
+        self.hasher.update(&region.start.to_le_bytes());
+        self.hasher.update(&region.end.to_le_bytes());
+        self.hasher.update(&[
+            region.hash as u8,
+            region.exec as u8,
+            region.write as u8,
+            region.read as u8,
+            region.confidential as u8,
+        ]);
+        if region.hash {
+            self.remaining = region.size();
+        }
+    }
+
+    /// Adds a chunk of the content of the current region.
+    pub fn content(&mut self, data: &[u8]) {
+        assert!(
+            data.len() as u64 <= self.remaining,
+            "Content larger than the region"
+        );
+        self.hasher.update(data);
+        self.remaining -= data.len() as u64;
+    }
+
+    /// Returns the measurement.
+    pub fn finalize(self) -> Measurement {
+        assert_eq!(self.remaining, 0, "Missing content of the last region");
+        *self.hasher.finalize().as_bytes()
+    }
+}
+
+impl Default for Measurer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::format;
+    use std::string::String;
+
+    use super::*;
+    use crate::hashing::HashEnclave;
+
+    fn hex(measurement: &Measurement) -> String {
+        measurement.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    const CODE: MeasuredRegion = MeasuredRegion {
+        start: 0x1000,
+        end: 0x2000,
+        hash: true,
+        exec: true,
+        write: false,
+        read: true,
+        confidential: true,
+    };
+
+    const SHARED: MeasuredRegion = MeasuredRegion {
+        start: 0x2000,
+        end: 0x3000,
+        hash: false,
+        exec: false,
+        write: true,
+        read: true,
+        confidential: false,
+    };
+
+    #[test]
+    fn vector_empty() {
+        let measurement = Measurer::new().finalize();
+        assert_eq!(
+            hex(&measurement),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+
+    #[test]
+    fn vector_one_region() {
+        let mut measurer = Measurer::new();
+        measurer.region(&CODE);
+        measurer.content(&[0xAA; 0x1000]);
+        assert_eq!(
+            hex(&measurer.finalize()),
+            "90841596796de3aec3410abb512d527924ef44e66cf167de8052ea1612440f46"
+        );
+    }
+
+    #[test]
+    fn vector_two_regions() {
+        let mut measurer = Measurer::new();
+        measurer.region(&CODE);
+        measurer.content(&[0xAA; 0x1000]);
+        measurer.region(&SHARED);
+        assert_eq!(
+            hex(&measurer.finalize()),
+            "27249a964c10cb5e7e69e899af18a1935efef18ed94f3da8e8d20fc523838184"
+        );
+    }
+
+    /// Checks the layout against a hand-written encoding of the specification.
+    #[test]
+    fn layout() {
+        let mut raw = std::vec::Vec::new();
+        raw.extend_from_slice(&0x1000u64.to_le_bytes());
+        raw.extend_from_slice(&0x2000u64.to_le_bytes());
+        raw.extend_from_slice(&[1, 1, 0, 1, 1]);
+        raw.extend_from_slice(&[0xAA; 0x1000]);
+        raw.extend_from_slice(&0x2000u64.to_le_bytes());
+        raw.extend_from_slice(&0x3000u64.to_le_bytes());
+        raw.extend_from_slice(&[0, 0, 1, 1, 0]);
+
+        let mut measurer = Measurer::new();
+        measurer.region(&CODE);
+        measurer.content(&[0xAA; 0x1000]);
+        measurer.region(&SHARED);
+        assert_eq!(&measurer.finalize(), blake3::hash(&raw).as_bytes());
+    }
+
+    #[test]
+    fn content_can_be_chunked() {
+        let mut a = Measurer::new();
+        a.region(&CODE);
+        a.content(&[0xAA; 0x1000]);
+        let mut b = Measurer::new();
+        b.region(&CODE);
+        for chunk in [0xAA; 0x1000].chunks(0x300) {
+            b.content(chunk);
+        }
+        assert_eq!(a.finalize(), b.finalize());
+    }
+
+    /// The measurement must be reported in the same byte order.
+    #[test]
+    fn report_byte_order() {
+        let mut measurer = Measurer::new();
+        measurer.region(&SHARED);
+        let measurement = measurer.finalize();
+        let mut reported = [0; MEASUREMENT_SZ];
+        HashEnclave::from_bytes(&measurement).to_byte_arr(&mut reported, 0);
+        assert_eq!(reported, measurement);
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_content() {
+        let mut measurer = Measurer::new();
+        measurer.region(&CODE);
+        measurer.content(&[0xAA; 0x10]);
+        measurer.finalize();
+    }
+}
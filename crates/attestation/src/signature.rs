@@ -9,21 +9,132 @@ pub type AttestationSignature = Signature;
 pub const MAX_ATTESTATION_DATA_SZ: usize = 8;
 pub const ATTESTATION_DATA_SZ: usize = MAX_ATTESTATION_DATA_SZ + 32;
 
+/// Size of the seed the attestation key is derived from.
+pub const ATTESTATION_SEED_SZ: usize = Seed::BYTES;
+
+/// Maximum size of a signature produced by the device key (e.g. the TPM).
+pub const MAX_DEVICE_SIGNATURE_SZ: usize = 512;
+
+/// Domain separation tag prepended to the attestation public key when it is certified.
+pub const CERTIFICATE_TAG: &[u8; 16] = b"tyche-attest-key";
+
+/// Size of the data signed by the device key to certify an attestation key.
+pub const CERTIFICATE_DATA_SZ: usize = CERTIFICATE_TAG.len() + PublicKey::BYTES;
+
 #[derive(Copy, Clone)]
 pub struct EnclaveReport {
     pub public_key: PublicKey,
     pub signed_enclave_data: AttestationSignature,
+    /// Certifies `public_key` with the device key.
+    pub certificate: AttestationKeyCertificate,
+}
+
+/// Certificate of an attestation key, i.e. a signature of the key by the device key.
+///
+/// The device key is usually held by the TPM, its public part can be retrieved with the
+/// `GET_SIGNING_KEY` monitor call.
+#[derive(Copy, Clone)]
+pub struct AttestationKeyCertificate {
+    signature: [u8; MAX_DEVICE_SIGNATURE_SZ],
+    signature_len: usize,
+}
+
+impl AttestationKeyCertificate {
+    /// A certificate without signature, for attestation keys that could not be certified.
+    pub const fn empty() -> Self {
+        AttestationKeyCertificate {
+            signature: [0; MAX_DEVICE_SIGNATURE_SZ],
+            signature_len: 0,
+        }
+    }
+
+    /// Creates a certificate from a device signature, returns `None` if the signature is empty
+    /// or too large.
+    pub fn from_signature(signature: &[u8]) -> Option<Self> {
+        if signature.is_empty() || signature.len() > MAX_DEVICE_SIGNATURE_SZ {
+            return None;
+        }
+        let mut cert = Self::empty();
+        cert.signature[..signature.len()].copy_from_slice(signature);
+        cert.signature_len = signature.len();
+        Some(cert)
+    }
+
+    /// The device signature over [certificate_data] of the attestation key.
+    pub fn signature(&self) -> &[u8] {
+        &self.signature[..self.signature_len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signature_len == 0
+    }
+}
+
+/// The attestation key of the monitor, generated once per boot.
+pub struct AttestationIdentity {
+    key_pair: KeyPair,
+    certificate: AttestationKeyCertificate,
 }
 
-pub fn get_attestation_keys() -> (AttestationPublicKey, AttestationPrivateKey) {
-    let key_pair = KeyPair::from_seed(Seed::default());
-    (key_pair.pk, key_pair.sk)
+impl AttestationIdentity {
+    /// Generates a fresh attestation key from a seed, and certifies it with the device key.
+    ///
+    /// `device_sign` signs its first argument with the device key, writes the signature in the
+    /// second argument and returns the size of the signature, or 0 on failure. The resulting
+    /// certificate is empty if the key could not be certified.
+    pub fn new<F>(seed: [u8; ATTESTATION_SEED_SZ], device_sign: F) -> Self
+    where
+        F: FnOnce(&[u8], &mut [u8]) -> usize,
+    {
+        let key_pair = KeyPair::from_seed(Seed::new(seed));
+        let data = certificate_data(&key_pair.pk);
+        let mut signature = [0; MAX_DEVICE_SIGNATURE_SZ];
+        let written = device_sign(&data, &mut signature);
+        let certificate = if written <= MAX_DEVICE_SIGNATURE_SZ {
+            AttestationKeyCertificate::from_signature(&signature[..written])
+        } else {
+            None
+        };
+        let certificate = certificate.unwrap_or_else(|| {
+            log::warn!("Could not certify the attestation key");
+            AttestationKeyCertificate::empty()
+        });
+        AttestationIdentity {
+            key_pair,
+            certificate,
+        }
+    }
+
+    pub fn public_key(&self) -> AttestationPublicKey {
+        self.key_pair.pk
+    }
+
+    pub fn certificate(&self) -> &AttestationKeyCertificate {
+        &self.certificate
+    }
+
+    /// Signs the attestation data of an enclave, and bundles it with the certificate chain.
+    pub fn report(&self, data: &[u8]) -> EnclaveReport {
+        EnclaveReport {
+            public_key: self.key_pair.pk,
+            signed_enclave_data: sign_attestation_data(data, self.key_pair.sk.clone()),
+            certificate: self.certificate,
+        }
+    }
+}
+
+/// The data signed by the device key to certify an attestation key.
+pub fn certificate_data(key: &AttestationPublicKey) -> [u8; CERTIFICATE_DATA_SZ] {
+    let mut data = [0; CERTIFICATE_DATA_SZ];
+    data[..CERTIFICATE_TAG.len()].copy_from_slice(CERTIFICATE_TAG);
+    data[CERTIFICATE_TAG.len()..].copy_from_slice(key.as_ref());
+    data
 }
 
 pub fn vtpm_sign(data: &[u8], dest: &mut [u8]) -> usize {
     let key = AttestationPrivateKey::new([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 3, 161, 7, 191, 243, 206, 16, 190, 29, 112, 221, 24, 231, 75, 192, 153, 103, 228, 214, 48, 155, 165, 13, 95, 29, 220, 134, 100, 18, 85, 49, 184]);
     dest[..Signature::BYTES].copy_from_slice(key.sign(data, Some(Noise::default())).as_ref());
-    Signature::BYTES as usize
+    Signature::BYTES
 }
 
 pub fn sign_attestation_data(data: &[u8], key: AttestationPrivateKey) -> AttestationSignature {
@@ -35,3 +146,47 @@ pub fn sign_by_device(data: &[u8], key: DevicePrivateKey) -> AttestationSignatur
     let sig = key.sign(data, Some(Noise::default()));
     sig
 }
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_depend_on_seed() {
+        let a = AttestationIdentity::new([1; ATTESTATION_SEED_SZ], |_, _| 0);
+        let b = AttestationIdentity::new([2; ATTESTATION_SEED_SZ], |_, _| 0);
+        assert_ne!(a.public_key(), b.public_key());
+        assert!(a.certificate().is_empty());
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let device = KeyPair::from_seed(Seed::new([42; Seed::BYTES]));
+        let identity = AttestationIdentity::new([7; ATTESTATION_SEED_SZ], |data, dest| {
+            let sig = sign_by_device(data, device.sk.clone());
+            dest[..Signature::BYTES].copy_from_slice(sig.as_ref());
+            Signature::BYTES
+        });
+
+        let data = [3; ATTESTATION_DATA_SZ];
+        let report = identity.report(&data);
+        let cert = Signature::from_slice(report.certificate.signature()).unwrap();
+        device
+            .pk
+            .verify(certificate_data(&report.public_key), &cert)
+            .unwrap();
+        report
+            .public_key
+            .verify(data, &report.signed_enclave_data)
+            .unwrap();
+    }
+
+    #[test]
+    fn oversized_certificate() {
+        let identity =
+            AttestationIdentity::new([1; ATTESTATION_SEED_SZ], |_, _| MAX_DEVICE_SIGNATURE_SZ + 1);
+        assert!(identity.certificate().is_empty());
+    }
+}
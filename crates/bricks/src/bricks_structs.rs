@@ -1,7 +1,5 @@
 pub const PUB_KEY_SIZE: usize = 32;
 pub const SIGNED_DATA_SIZE: usize = 64;
-pub const CALC_REPORT: usize = 0;
-pub const READ_REPORT: usize = 1;
 
 #[derive(Copy, Clone)]
 #[repr(C, align(16))]
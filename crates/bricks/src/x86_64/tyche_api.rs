@@ -1,7 +1,9 @@
 use core::arch::asm;
 
-use crate::bricks_const::SUCCESS;
-use crate::bricks_structs::{AttestationResult, CALC_REPORT, READ_REPORT};
+use monitor_abi::{calls, report, status};
+
+use crate::bricks_const::{FAILURE, SUCCESS};
+use crate::bricks_structs::AttestationResult;
 use crate::bricks_utils::{copy_to_pub_key, copy_to_signed_data};
 
 pub struct TycheCallArgs {
@@ -40,16 +42,17 @@ pub fn call_tyche(args: &mut TycheCallArgs) {
 
 // ———————————————————————————————— Helpers to return make tyche calls and return result ————————————————————————————————— //
 
-const ENCLAVE_ATTESTATION: usize = 14;
-
 pub fn enclave_attestation_tyche(nonce: u64, result_struct: &mut AttestationResult) -> u64 {
     let mut call_args = TycheCallArgs::default();
 
     // First call to Tyche
-    call_args.vmmcall = ENCLAVE_ATTESTATION;
+    call_args.vmmcall = calls::ENCLAVE_ATTESTATION;
     call_args.arg_1 = nonce as usize;
-    call_args.arg_2 = CALC_REPORT;
+    call_args.arg_2 = report::CREATE;
     call_tyche(&mut call_args);
+    if call_args.res != status::SUCCESS {
+        return FAILURE;
+    }
 
     // Copy Tyche response to structure
     copy_to_pub_key(call_args.value_1 as u64, 0, result_struct);
@@ -62,10 +65,13 @@ pub fn enclave_attestation_tyche(nonce: u64, result_struct: &mut AttestationResu
     call_args.clean_args();
 
     //Second call to Tyche
-    call_args.vmmcall = ENCLAVE_ATTESTATION;
+    call_args.vmmcall = calls::ENCLAVE_ATTESTATION;
     call_args.arg_1 = nonce as usize;
-    call_args.arg_2 = READ_REPORT;
+    call_args.arg_2 = report::READ;
     call_tyche(&mut call_args);
+    if call_args.res != status::SUCCESS {
+        return FAILURE;
+    }
 
     // Copy Tyche response to structure
     copy_to_signed_data(call_args.value_1 as u64, 16, result_struct);
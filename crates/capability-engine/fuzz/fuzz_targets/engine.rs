@@ -1,7 +1,12 @@
 #![no_main]
 
-use capa_engine::config::NB_CAPAS_PER_DOMAIN;
-use capa_engine::{permission, AccessRights, CapaEngine, Domain, Handle, LocalCapa, MEMOPS_ALL};
+use capa_engine::config::{NB_CAPAS_PER_DOMAIN, NB_MESSAGE_WORDS};
+use capa_engine::flow::{labels, transfer, Policy, Rule};
+use capa_engine::pool::PoolMemory;
+use capa_engine::{
+    permission, AccessRights, CapaEngine, Domain, EngineConfig, Handle, Lease, LeaseCondition,
+    LocalCapa, MgmtRights, MEMOPS_ALL,
+};
 use libfuzzer_sys::arbitrary::Arbitrary;
 use libfuzzer_sys::{arbitrary, fuzz_target};
 
@@ -13,8 +18,19 @@ pub enum Action {
     Seal(CapaIdx),
     Send(CapaIdx, CapaIdx),
     Revoke(CapaIdx),
+    Duplicate(CapaIdx, u8),
     CreateSwitch,
-    SegmentRegion(CapaIdx, Access, Access),
+    Alias(CapaIdx, Access),
+    Carve(CapaIdx, Access),
+    Lend(CapaIdx, CapaIdx, u8),
+    Tick(u8),
+    CreateChannel(Option<CapaIdx>),
+    ChannelSend(CapaIdx, Option<CapaIdx>),
+    Receive,
+    Label(CapaIdx, u8),
+    Begin,
+    Commit,
+    Abort,
 }
 
 #[derive(Arbitrary, Debug)]
@@ -27,13 +43,23 @@ struct State {
     current_domain: Handle<Domain>,
 }
 
+/// Backing memory for the engine pools, re-used across runs.
+const MEMORY_SIZE: usize = EngineConfig::DEFAULT.memory_size();
+static mut MEMORY: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
+
 fuzz_target!(|actions: Vec<Action>| {
     fuzz(actions);
 });
 
 fn fuzz(actions: Vec<Action>) {
     let mut engine = CapaEngine::new();
-    let root_domain = engine.create_manager_domain(permission::ALL).unwrap();
+    // SAFETY: runs are sequential, and the previous engine is gone by the time we get here.
+    let mut memory = unsafe { PoolMemory::new(MEMORY.as_mut_ptr() as usize, MEMORY_SIZE) };
+    engine.init(EngineConfig::DEFAULT, &mut memory).unwrap();
+    engine.load_flow_policy(flow_policy()).unwrap();
+    let root_domain = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
     let current_core = 0;
     engine
         .start_domain_on_core(root_domain, current_core)
@@ -71,32 +97,104 @@ fn fuzz(actions: Vec<Action>) {
             Action::Revoke(capa) => {
                 engine.revoke(s.current_domain, as_capa(*capa)).ok();
             }
+            Action::Duplicate(capa, rights) => {
+                let rights = MgmtRights::from_bits_truncate(*rights);
+                engine
+                    .duplicate_with_rights(s.current_domain, as_capa(*capa), rights)
+                    .ok();
+            }
             Action::CreateSwitch => {
                 engine.create_switch(s.current_domain, current_core).ok();
             }
-            Action::SegmentRegion(capa, left, right) => {
+            Action::Alias(capa, access) => {
+                engine
+                    .alias_region(s.current_domain, as_capa(*capa), access.as_rights())
+                    .ok();
+            }
+            Action::Carve(capa, access) => {
                 engine
-                    .segment_region(
+                    .carve_region(s.current_domain, as_capa(*capa), access.as_rights())
+                    .ok();
+            }
+            Action::Lend(capa, to, deadline) => {
+                let lease = Lease {
+                    condition: LeaseCondition::Deadline(*deadline as u64),
+                    alias: 0,
+                    size: 0,
+                };
+                engine
+                    .send_with_lease(s.current_domain, as_capa(*capa), as_capa(*to), lease)
+                    .ok();
+            }
+            Action::Tick(now) => {
+                engine.expire_leases(*now as u64, current_core).ok();
+            }
+            Action::CreateChannel(capa) => {
+                engine
+                    .create_channel(s.current_domain, capa.map(as_capa))
+                    .ok();
+            }
+            Action::ChannelSend(channel, region) => {
+                engine
+                    .channel_send(
                         s.current_domain,
-                        as_capa(*capa),
-                        AccessRights {
-                            start: left.start as usize,
-                            end: left.end as usize,
-                            ops: MEMOPS_ALL,
-                        },
-                        AccessRights {
-                            start: right.start as usize,
-                            end: right.end as usize,
-                            ops: MEMOPS_ALL,
-                        },
+                        as_capa(*channel),
+                        [0; NB_MESSAGE_WORDS],
+                        region.map(as_capa),
                     )
                     .ok();
             }
+            Action::Receive => {
+                engine.next_message(s.current_domain);
+            }
+            Action::Label(capa, labels) => {
+                engine
+                    .set_child_labels(s.current_domain, as_capa(*capa), *labels as u64)
+                    .ok();
+            }
+            Action::Begin => {
+                engine.begin_transaction().ok();
+            }
+            Action::Commit => {
+                engine.commit_transaction().ok();
+            }
+            Action::Abort => {
+                engine.abort_transaction().ok();
+            }
         }
         apply_updates(&mut engine, &mut s);
+        engine.check_invariants();
     }
 }
 
+impl Access {
+    fn as_rights(&self) -> AccessRights {
+        AccessRights {
+            start: self.start as usize,
+            end: self.end as usize,
+            ops: MEMOPS_ALL,
+        }
+    }
+}
+
+/// A small policy, so that the labels actually deny some transfers.
+fn flow_policy() -> Policy {
+    Policy::new(&[
+        Rule {
+            transfers: transfer::SEND_REGION | transfer::ALIAS,
+            to: 0b01,
+            region: 0b10,
+            ..Rule::EMPTY
+        },
+        Rule {
+            transfers: transfer::SPAWN | transfer::CARVE,
+            from: labels::SEALED,
+            ..Rule::EMPTY
+        },
+    ])
+    .unwrap()
+}
+
 fn as_capa(idx: u8) -> LocalCapa {
     LocalCapa::new((idx as usize) % NB_CAPAS_PER_DOMAIN)
 }
@@ -113,7 +211,10 @@ fn apply_updates(engine: &mut CapaEngine, s: &mut State) {
             capa_engine::Update::Trap { manager, .. } => {
                 s.current_domain = manager;
             }
-            capa_engine::Update::UpdateTraps { .. } => (),
+            capa_engine::Update::Cleanup { .. } => (),
+            capa_engine::Update::LeaseExpired { .. } => (),
+            capa_engine::Update::RegionRevoked { .. } => (),
+            capa_engine::Update::ChannelMessage { .. } => (),
         }
     }
 }
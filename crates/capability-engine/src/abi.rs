@@ -0,0 +1,85 @@
+//! Error Codes
+//!
+//! Maps the engine errors to the stable error codes returned by monitor calls.
+
+use monitor_abi::error::{Error, ErrorCode};
+
+use crate::CapaError;
+
+impl From<CapaError> for Error {
+    fn from(err: CapaError) -> Self {
+        let code = match err {
+            CapaError::CannotDuplicate => ErrorCode::CannotDuplicate,
+            CapaError::InvalidDuplicate => ErrorCode::InvalidDuplicate,
+            CapaError::InvalidInstall => ErrorCode::InvalidInstall,
+            CapaError::InternalRegionError => ErrorCode::InternalRegionError,
+            CapaError::InvalidRegion => ErrorCode::InvalidRegion,
+            CapaError::InvalidCapa => ErrorCode::InvalidCapa,
+            CapaError::WrongCapabilityType => ErrorCode::WrongCapabilityType,
+            CapaError::CapabilityDoesNotExist => ErrorCode::CapabilityDoesNotExist,
+            CapaError::AlreadySealed => ErrorCode::AlreadySealed,
+            CapaError::InsufficientPermissions => ErrorCode::InsufficientPermissions,
+            CapaError::InvalidPermissions => ErrorCode::InvalidPermissions,
+            CapaError::OutOfMemory => ErrorCode::OutOfMemory,
+            CapaError::CouldNotDeserializeInfo => ErrorCode::CouldNotDeserializeInfo,
+            CapaError::InvalidCore => ErrorCode::InvalidCore,
+            CapaError::CouldNotHandleTrap => ErrorCode::CouldNotHandleTrap,
+            CapaError::ValidTrapCausedExit => ErrorCode::ValidTrapCausedExit,
+            CapaError::InvalidSwitch => ErrorCode::InvalidSwitch,
+            CapaError::InvalidVcpuType => ErrorCode::InvalidVcpuType,
+            CapaError::InvalidOperation => ErrorCode::InvalidOperation,
+            CapaError::InvalidValue => ErrorCode::InvalidValue,
+            CapaError::InvalidMemOps => ErrorCode::InvalidMemOps,
+            CapaError::AlreadyAliased => ErrorCode::AlreadyAliased,
+            CapaError::PlatformError => ErrorCode::PlatformError,
+            CapaError::QuotaExceeded => ErrorCode::QuotaExceeded,
+            CapaError::ChannelFull => ErrorCode::ChannelFull,
+            CapaError::DeniedByPolicy => ErrorCode::DeniedByPolicy,
+        };
+        Error::new(code)
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capa_errors_are_distinct() {
+        let errors = [
+            CapaError::CannotDuplicate,
+            CapaError::InvalidDuplicate,
+            CapaError::InvalidInstall,
+            CapaError::InternalRegionError,
+            CapaError::InvalidRegion,
+            CapaError::InvalidCapa,
+            CapaError::WrongCapabilityType,
+            CapaError::CapabilityDoesNotExist,
+            CapaError::AlreadySealed,
+            CapaError::InsufficientPermissions,
+            CapaError::InvalidPermissions,
+            CapaError::OutOfMemory,
+            CapaError::CouldNotDeserializeInfo,
+            CapaError::InvalidCore,
+            CapaError::CouldNotHandleTrap,
+            CapaError::ValidTrapCausedExit,
+            CapaError::InvalidSwitch,
+            CapaError::InvalidVcpuType,
+            CapaError::InvalidOperation,
+            CapaError::InvalidValue,
+            CapaError::InvalidMemOps,
+            CapaError::AlreadyAliased,
+            CapaError::PlatformError,
+            CapaError::QuotaExceeded,
+            CapaError::ChannelFull,
+            CapaError::DeniedByPolicy,
+        ];
+        for (i, a) in errors.iter().enumerate() {
+            for b in &errors[i + 1..] {
+                assert_ne!(Error::from(*a), Error::from(*b));
+            }
+        }
+    }
+}
@@ -2,9 +2,12 @@
 
 use core::fmt;
 
+use bitflags::bitflags;
+
 use crate::domain::{Domain, DomainPool};
 use crate::gen_arena::Handle;
 use crate::segment::{RegionCapa, RegionPool};
+use crate::snapshot::{self, Reader, Snapshot, Writer};
 use crate::{CapaError, MemOps};
 
 #[derive(Clone, Copy, Debug)]
@@ -12,13 +15,33 @@ pub enum Capa {
     None,
     Region(Handle<RegionCapa>),
     RegionRevoke(Handle<RegionCapa>),
-    Management(Handle<Domain>),
-    #[allow(dead_code)] // TODO: remove once channels are implemented
+    Management(Handle<Domain>, MgmtRights),
     Channel(Handle<Domain>),
-    Switch {
-        to: Handle<Domain>,
-        core: usize,
-    },
+    Switch { to: Handle<Domain>, core: usize },
+}
+
+bitflags! {
+    /// The operations allowed by a management capability.
+    ///
+    /// A domain is created with a management capability holding all the rights, attenuated copies
+    /// can then be created with `duplicate` and handed over to other domains.
+    pub struct MgmtRights: u8 {
+        /// Configure the domain: permissions, cores, quota and seal.
+        const CONFIGURE  = 1 << 0;
+        /// Create switch capabilities to the domain, i.e. run it.
+        const SWITCH     = 1 << 1;
+        /// Revoke the domain.
+        const REVOKE     = 1 << 2;
+        /// Read the registers of the domain.
+        const READ_REGS  = 1 << 3;
+        /// Write the registers of the domain.
+        const WRITE_REGS = 1 << 4;
+        /// Send capabilities to the domain.
+        const SEND       = 1 << 5;
+        /// Held by the manager of the domain only, sending it transfers the management of the
+        /// domain. Duplicates never hold this right.
+        const MANAGE     = 1 << 6;
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -39,6 +62,7 @@ pub enum CapaInfo {
     Management {
         domain_id: usize,
         sealed: bool,
+        rights: MgmtRights,
     },
     Channel {
         domain_id: usize,
@@ -88,13 +112,18 @@ impl CapaInfo {
                 flags |= ops.bits() << 2;
                 capa_type = capa_type::REGION_REVOKE;
             }
-            CapaInfo::Management { domain_id, sealed } => {
+            CapaInfo::Management {
+                domain_id,
+                sealed,
+                rights,
+            } => {
                 v1 = *domain_id;
                 if *sealed {
                     v2 = 1 << 1;
                 } else {
                     v2 = 1 << 0;
                 }
+                flags = rights.bits();
                 capa_type = capa_type::MANAGEMENT;
             }
             CapaInfo::Channel { domain_id } => {
@@ -120,6 +149,7 @@ impl CapaInfo {
             capa_type::MANAGEMENT => Self::Management {
                 domain_id: v1,
                 sealed: v2 == 2,
+                rights: MgmtRights::from_bits_truncate(flags as u8),
             },
             capa_type::CHANNEL => Self::Channel { domain_id: v1 },
             capa_type::SWITCH => Self::Switch {
@@ -156,7 +186,7 @@ pub mod capa_type {
 
 impl Capa {
     pub(crate) fn management(managee: Handle<Domain>) -> Self {
-        Capa::Management(managee)
+        Capa::Management(managee, MgmtRights::all())
     }
 
     pub fn as_region(self) -> Result<Handle<RegionCapa>, CapaError> {
@@ -168,14 +198,23 @@ impl Capa {
 
     pub fn as_management(self) -> Result<Handle<Domain>, CapaError> {
         match self {
-            Capa::Management(domain) => Ok(domain),
+            Capa::Management(domain, _) => Ok(domain),
+            _ => Err(CapaError::WrongCapabilityType),
+        }
+    }
+
+    /// Returns the managed domain if this is a management capability holding all the `rights`.
+    pub fn as_management_with(self, rights: MgmtRights) -> Result<Handle<Domain>, CapaError> {
+        match self {
+            Capa::Management(domain, held) if held.contains(rights) => Ok(domain),
+            Capa::Management(..) => Err(CapaError::InsufficientPermissions),
             _ => Err(CapaError::WrongCapabilityType),
         }
     }
 
     pub fn as_channel(self) -> Result<Handle<Domain>, CapaError> {
         match self {
-            Capa::Management(domain) => Ok(domain),
+            Capa::Management(domain, _) => Ok(domain),
             Capa::Channel(domain) => Ok(domain),
             _ => Err(CapaError::WrongCapabilityType),
         }
@@ -183,7 +222,7 @@ impl Capa {
 
     pub fn as_domain(self) -> Result<Handle<Domain>, CapaError> {
         match self {
-            Capa::Management(domain) => Ok(domain),
+            Capa::Management(domain, _) => Ok(domain),
             Capa::Channel(domain) => Ok(domain),
             _ => Err(CapaError::WrongCapabilityType),
         }
@@ -218,11 +257,12 @@ impl Capa {
                     ops: region.access.ops,
                 })
             }
-            Capa::Management(h) => {
+            Capa::Management(h, rights) => {
                 let domain = &domains[h];
                 Some(CapaInfo::Management {
                     domain_id: domain.id(),
                     sealed: domain.is_sealed(),
+                    rights,
                 })
             }
             Capa::Channel(h) => {
@@ -259,6 +299,61 @@ impl IntoCapa for Capa {
     }
 }
 
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for Capa {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match *self {
+            Capa::None => 0u8.save(w),
+            Capa::Region(region) => {
+                1u8.save(w)?;
+                region.save(w)
+            }
+            Capa::RegionRevoke(region) => {
+                2u8.save(w)?;
+                region.save(w)
+            }
+            Capa::Management(domain, rights) => {
+                3u8.save(w)?;
+                domain.save(w)?;
+                rights.bits().save(w)
+            }
+            Capa::Channel(domain) => {
+                4u8.save(w)?;
+                domain.save(w)
+            }
+            Capa::Switch { to, core } => {
+                5u8.save(w)?;
+                to.save(w)?;
+                core.save(w)
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let capa = match u8::load(r)? {
+            0 => Capa::None,
+            1 => Capa::Region(Handle::load(r)?),
+            2 => Capa::RegionRevoke(Handle::load(r)?),
+            3 => {
+                let domain = Handle::load(r)?;
+                let bits = u8::load(r)?;
+                let Some(rights) = MgmtRights::from_bits(bits) else {
+                    return snapshot::invalid("management rights", bits as u64);
+                };
+                Capa::Management(domain, rights)
+            }
+            4 => Capa::Channel(Handle::load(r)?),
+            5 => Capa::Switch {
+                to: Handle::load(r)?,
+                core: usize::load(r)?,
+            },
+            tag => return snapshot::invalid("capability", tag as u64),
+        };
+        Ok(capa)
+    }
+}
+
 // ———————————————————————————————— Display ————————————————————————————————— //
 
 impl fmt::Display for CapaInfo {
@@ -292,9 +387,23 @@ impl fmt::Display for CapaInfo {
                     start, end, c, ops
                 )
             }
-            CapaInfo::Management { domain_id, sealed } => {
+            CapaInfo::Management {
+                domain_id,
+                sealed,
+                rights,
+            } => {
                 let s = if *sealed { 'S' } else { '_' };
-                write!(f, "Management({} | {})", domain_id, s)
+                if rights.is_all() {
+                    write!(f, "Management({} | {})", domain_id, s)
+                } else {
+                    write!(
+                        f,
+                        "Management({} | {} | {:#x})",
+                        domain_id,
+                        s,
+                        rights.bits()
+                    )
+                }
             }
             CapaInfo::Channel { domain_id } => {
                 write!(f, "Channel({})", domain_id)
@@ -0,0 +1,104 @@
+//! Channels
+//!
+//! A channel capability lets a domain send messages to the domain it points to, for instance to let
+//! two enclaves talk to each other without going through their manager. A message carries a few
+//! words and optionally a region capability, which is transferred to the receiver. Delivery is
+//! asynchronous: messages are queued by the receiver until it polls them.
+
+use core::fmt;
+
+use crate::config::{NB_MESSAGE_WORDS, NB_PENDING_MESSAGES};
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{AccessRights, CapaError, LocalCapa};
+
+/// Regions are transferred over channels with a page granularity.
+const PAGE_SIZE: usize = 0x1000;
+
+pub(crate) fn is_page_aligned(access: &AccessRights) -> bool {
+    access.start % PAGE_SIZE == 0 && access.end % PAGE_SIZE == 0
+}
+
+/// A message sent over a channel.
+#[derive(Clone, Copy, Debug)]
+pub struct Message {
+    /// The id of the sending domain.
+    pub from: usize,
+    pub words: [usize; NB_MESSAGE_WORDS],
+    /// The capability of the receiver to the region transferred with the message, if any.
+    pub region: Option<LocalCapa>,
+}
+
+/// The messages not yet polled by a domain.
+///
+/// Unlike revocations, messages are not dropped once the queue is full: sending fails instead, so
+/// that the sender keeps the region it meant to transfer.
+pub(crate) struct MessageQueue {
+    entries: [Option<Message>; NB_PENDING_MESSAGES],
+    read: usize,
+    len: usize,
+}
+
+impl MessageQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [None; NB_PENDING_MESSAGES],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn has_capacity(&self) -> Result<(), CapaError> {
+        if self.len == NB_PENDING_MESSAGES {
+            return Err(CapaError::ChannelFull);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push(&mut self, message: Message) -> Result<(), CapaError> {
+        self.has_capacity()?;
+        let write = (self.read + self.len) % NB_PENDING_MESSAGES;
+        self.entries[write] = Some(message);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Message> {
+        if self.len == 0 {
+            return None;
+        }
+        let message = self.entries[self.read].take();
+        self.read = (self.read + 1) % NB_PENDING_MESSAGES;
+        self.len -= 1;
+        message
+    }
+}
+
+impl Snapshot for MessageQueue {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.entries.save(w)?;
+        self.read.save(w)?;
+        self.len.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Self {
+            entries: Snapshot::load(r)?,
+            read: usize::load(r)?,
+            len: usize::load(r)?,
+        })
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Message(from {}, {:x?}", self.from, self.words)?;
+        if let Some(region) = self.region {
+            write!(f, ", region {}", region.as_usize())?;
+        }
+        write!(f, ")")
+    }
+}
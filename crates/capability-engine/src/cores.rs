@@ -1,12 +1,159 @@
 //§ Cores
 
+use core::fmt;
+
 use crate::config::NB_CORES;
-use crate::domain::DomainHandle;
+use crate::domain::{DomainHandle, DomainPool};
 use crate::gen_arena::Handle;
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::utils::BitmapIterator;
 use crate::CapaError;
 
 pub(crate) type CoreList = [Core; NB_CORES];
 
+// ———————————————————————————————— Core Set ———————————————————————————————— //
+
+/// Number of 64 bits words in a core set.
+const NB_WORDS: usize = NB_CORES.div_ceil(64);
+
+/// A set of cores, with room for all the [NB_CORES] cores.
+///
+/// Core sets are made of 64 bits words, which is also how they are exchanged with the monitor
+/// clients: word `i` holds cores `64 * i` to `64 * i + 63`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoreSet {
+    words: [u64; NB_WORDS],
+}
+
+impl CoreSet {
+    /// Number of 64 bits words in a core set.
+    pub const NB_WORDS: usize = NB_WORDS;
+
+    /// No core.
+    pub const NONE: Self = Self {
+        words: [0; NB_WORDS],
+    };
+
+    /// All cores.
+    pub const ALL: Self = {
+        let mut words = [0; NB_WORDS];
+        let mut idx = 0;
+        while idx < NB_CORES {
+            words[idx / 64] |= 1 << (idx % 64);
+            idx += 1;
+        }
+        Self { words }
+    };
+
+    /// Creates a set with a single core.
+    pub fn single(core: usize) -> Self {
+        let mut set = Self::NONE;
+        set.insert(core);
+        set
+    }
+
+    /// Returns true if the core is part of the set, cores out of range are never in the set.
+    pub fn contains(&self, core: usize) -> bool {
+        core < NB_CORES && self.words[core / 64] & (1 << (core % 64)) != 0
+    }
+
+    /// Adds a core to the set.
+    ///
+    /// Panics if the core is out of range.
+    pub fn insert(&mut self, core: usize) {
+        assert!(core < NB_CORES, "Invalid core {}", core);
+        self.words[core / 64] |= 1 << (core % 64);
+    }
+
+    /// Removes a core from the set, does nothing if the core is out of range.
+    pub fn remove(&mut self, core: usize) {
+        if core < NB_CORES {
+            self.words[core / 64] &= !(1 << (core % 64));
+        }
+    }
+
+    /// Returns true if there is no core in the set.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Returns the number of cores in the set.
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns true if all the cores of `self` are also in `other`.
+    pub fn is_subset(&self, other: &CoreSet) -> bool {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .all(|(&word, &other)| word & !other == 0)
+    }
+
+    /// Returns the word `idx` of the set, or `None` if out of range.
+    pub fn word(&self, idx: usize) -> Option<u64> {
+        self.words.get(idx).copied()
+    }
+
+    /// Replaces the word `idx` of the set, cores above [NB_CORES] are ignored.
+    pub fn set_word(&mut self, idx: usize, word: u64) -> Result<(), CapaError> {
+        let mask = Self::ALL.word(idx).ok_or(CapaError::InvalidValue)?;
+        self.words[idx] = word & mask;
+        Ok(())
+    }
+
+    /// Iterates over the cores of the set, in increasing order.
+    pub fn iter(&self) -> CoreSetIterator {
+        CoreSetIterator {
+            words: self.words,
+            idx: 0,
+            bitmap: BitmapIterator::new(0),
+        }
+    }
+}
+
+pub struct CoreSetIterator {
+    words: [u64; NB_WORDS],
+    /// Index of the next word to iterate over.
+    idx: usize,
+    bitmap: BitmapIterator,
+}
+
+impl Iterator for CoreSetIterator {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.bitmap.next() {
+                return Some((self.idx - 1) * 64 + bit);
+            }
+            let word = *self.words.get(self.idx)?;
+            self.bitmap = BitmapIterator::new(word);
+            self.idx += 1;
+        }
+    }
+}
+
+impl fmt::Binary for CoreSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Skip the leading zero words, as for a plain bitmap
+        let mut words = self.words.iter().rev().skip_while(|&&word| word == 0);
+        match words.next() {
+            Some(word) => write!(f, "{:b}", word)?,
+            None => return write!(f, "0"),
+        }
+        for word in words {
+            write!(f, "{:064b}", word)?;
+        }
+        Ok(())
+    }
+}
+
+// —————————————————————————————————— Core —————————————————————————————————— //
+
 pub struct Core {
     /// The domain currently running on the core.
     domain: DomainHandle,
@@ -39,3 +186,118 @@ impl Core {
         self.domain = domain;
     }
 }
+
+/// Panics if the cores and the core bitmaps of the domains disagree.
+pub(crate) fn check_invariants(cores: &CoreList, domains: &DomainPool) {
+    for (core_id, core) in cores.iter().enumerate() {
+        if !core.is_initialized {
+            continue;
+        }
+        let domain = domains
+            .get(core.domain)
+            .unwrap_or_else(|| panic!("Core {} runs an invalid domain", core_id));
+        assert!(
+            domain.cores().contains(core_id),
+            "Domain {} runs on core {} but is missing from its bitmap",
+            core.domain,
+            core_id
+        );
+    }
+
+    for handle in domains {
+        // The peer of a pair runs on the core along with its owner.
+        let owner = domains[handle]
+            .fast_switch()
+            .filter(|pair| !pair.is_owner)
+            .map(|pair| pair.peer);
+        for core_id in domains[handle].cores().iter() {
+            let core = cores.get(core_id);
+            assert!(
+                core.is_some_and(|core| core.is_initialized
+                    && (core.domain == handle || Some(core.domain) == owner)),
+                "Domain {} is in the bitmap of core {} but does not run on it",
+                handle,
+                core_id
+            );
+        }
+        if let Some(pair) = domains[handle].fast_switch() {
+            let other = domains
+                .get(pair.peer)
+                .and_then(|peer| peer.fast_switch())
+                .unwrap_or_else(|| panic!("Domain {} has a dangling fast switch", handle));
+            assert!(
+                other.peer == handle && other.core == pair.core && other.is_owner != pair.is_owner,
+                "Domains {} and {} disagree on their fast switch",
+                handle,
+                pair.peer
+            );
+        }
+    }
+}
+
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for Core {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.domain.save(w)?;
+        self.is_initialized.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Core {
+            domain: Handle::load(r)?,
+            is_initialized: bool::load(r)?,
+        })
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_set() {
+        let mut set = CoreSet::NONE;
+        assert!(set.is_empty());
+        set.insert(1);
+        set.insert(NB_CORES - 1);
+        assert!(set.contains(1));
+        assert!(set.contains(NB_CORES - 1));
+        assert!(!set.contains(0));
+        assert!(!set.contains(NB_CORES));
+        assert_eq!(set.count(), 2);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![1, NB_CORES - 1]);
+
+        set.remove(1);
+        set.remove(NB_CORES);
+        assert_eq!(set, CoreSet::single(NB_CORES - 1));
+        assert!(set.is_subset(&CoreSet::ALL));
+        assert!(!CoreSet::ALL.is_subset(&set));
+        assert_eq!(CoreSet::ALL.count(), NB_CORES);
+    }
+
+    #[test]
+    fn core_set_words() {
+        let mut set = CoreSet::NONE;
+        set.set_word(0, 0b101).unwrap();
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(format!("{:b}", set), "101");
+        assert_eq!(
+            set.set_word(CoreSet::NB_WORDS, 1),
+            Err(CapaError::InvalidValue)
+        );
+
+        if CoreSet::NB_WORDS > 1 {
+            set.set_word(1, 0b1).unwrap();
+            assert!(set.contains(64));
+            assert_eq!(set.word(1), Some(1));
+            assert_eq!(format!("{:b}", set), format!("1{:064b}", 0b101));
+        }
+
+        // Cores beyond the limit are dropped
+        set.set_word(NB_WORDS - 1, u64::MAX).unwrap();
+        assert!(set.is_subset(&CoreSet::ALL));
+    }
+}
@@ -0,0 +1,436 @@
+//! Permission Deltas
+//!
+//! Platforms used to re-apply the full permissions of a domain on every permission update, which
+//! is expensive for domains with many regions, such as the initial domain. Instead, each domain
+//! keeps a small log of the ranges whose permissions might have changed, together with the
+//! permissions the ranges had before the first change. Comparing the log with the current
+//! permissions gives the precise ranges that were added, removed or changed since the platform
+//! last acknowledged the permissions of the domain.
+
+use core::{cmp, fmt};
+
+use crate::config::NB_PERMISSION_DELTAS;
+use crate::region::{RegionTracker, TrackerPool};
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{CapaError, MemOps};
+
+/// A change to the permissions of a domain over a range of physical memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermissionDelta {
+    /// The range was not accessible and now is, with the given permissions.
+    Added {
+        start: usize,
+        end: usize,
+        ops: MemOps,
+    },
+    /// The range was accessible and is not anymore.
+    Removed { start: usize, end: usize },
+    /// The range is still accessible, but with different permissions.
+    Changed {
+        start: usize,
+        end: usize,
+        ops: MemOps,
+    },
+}
+
+impl PermissionDelta {
+    pub fn start(&self) -> usize {
+        match self {
+            PermissionDelta::Added { start, .. }
+            | PermissionDelta::Removed { start, .. }
+            | PermissionDelta::Changed { start, .. } => *start,
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        match self {
+            PermissionDelta::Added { end, .. }
+            | PermissionDelta::Removed { end, .. }
+            | PermissionDelta::Changed { end, .. } => *end,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end() - self.start()
+    }
+}
+
+// ———————————————————————————————— Delta Log ——————————————————————————————— //
+
+/// A range of memory, with the permissions it had when it was first logged.
+#[derive(Clone, Copy, Debug)]
+struct LoggedRange {
+    start: usize,
+    end: usize,
+    /// The permissions before the change, empty if the range was not accessible.
+    ops: MemOps,
+}
+
+const EMPTY_LOGGED_RANGE: LoggedRange = LoggedRange {
+    start: 0,
+    end: 0,
+    ops: MemOps::NONE,
+};
+
+impl Default for LoggedRange {
+    fn default() -> Self {
+        EMPTY_LOGGED_RANGE
+    }
+}
+
+/// The ranges of a domain whose permissions might have changed since they were last acknowledged.
+///
+/// The ranges are sorted and do not overlap. Once the log is full it overflows, and the platform
+/// must re-apply the permissions of the domain from scratch.
+pub(crate) struct DeltaLog {
+    entries: [LoggedRange; NB_PERMISSION_DELTAS],
+    len: usize,
+    overflow: bool,
+}
+
+impl DeltaLog {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [EMPTY_LOGGED_RANGE; NB_PERMISSION_DELTAS],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    pub(crate) fn has_overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// Forgets about all the logged ranges, once the platform applied them.
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+    }
+
+    /// Logs the current permissions over `[start, end)`, must be called before they are modified.
+    ///
+    /// The parts of the range that are already logged are left untouched, as they hold the
+    /// permissions from before the first modification.
+    pub(crate) fn record(
+        &mut self,
+        start: usize,
+        end: usize,
+        regions: &RegionTracker,
+        tracker: &TrackerPool,
+    ) {
+        let mut cursor = start;
+        while cursor < end && !self.overflow {
+            // Find the first logged range ending after the cursor
+            let idx = self.entries[..self.len]
+                .iter()
+                .position(|entry| entry.end > cursor)
+                .unwrap_or(self.len);
+            let gap_end = if idx < self.len {
+                let entry = self.entries[idx];
+                if entry.start <= cursor {
+                    // Already logged
+                    cursor = entry.end;
+                    continue;
+                }
+                cmp::min(entry.start, end)
+            } else {
+                end
+            };
+            self.record_gap(cursor, gap_end, idx, regions, tracker);
+            cursor = gap_end;
+        }
+    }
+
+    /// Logs the permissions of a range not yet logged, by inserting entries starting at `idx`.
+    fn record_gap(
+        &mut self,
+        start: usize,
+        end: usize,
+        mut idx: usize,
+        regions: &RegionTracker,
+        tracker: &TrackerPool,
+    ) {
+        let mut cursor = start;
+        while cursor < end {
+            let (ops, next) = regions.permission_at(cursor, tracker);
+            let next = cmp::min(next, end);
+
+            // Extend the previous entry if possible
+            if idx > 0 {
+                let previous = &mut self.entries[idx - 1];
+                if previous.end == cursor && previous.ops == ops {
+                    previous.end = next;
+                    cursor = next;
+                    continue;
+                }
+            }
+            if self.len == NB_PERMISSION_DELTAS {
+                log::trace!("Permission delta log is full");
+                self.overflow = true;
+                self.len = 0;
+                return;
+            }
+            self.entries.copy_within(idx..self.len, idx + 1);
+            self.entries[idx] = LoggedRange {
+                start: cursor,
+                end: next,
+                ops,
+            };
+            self.len += 1;
+            idx += 1;
+            cursor = next;
+        }
+    }
+
+    /// Returns an iterator over the changes between the logged and current permissions.
+    pub(crate) fn deltas<'a>(
+        &'a self,
+        regions: &'a RegionTracker,
+        tracker: &'a TrackerPool,
+    ) -> DeltaIterator<'a> {
+        DeltaIterator {
+            log: self,
+            regions,
+            tracker,
+            idx: 0,
+            cursor: 0,
+        }
+    }
+}
+
+// ———————————————————————————————— Iterator ———————————————————————————————— //
+
+pub struct DeltaIterator<'a> {
+    log: &'a DeltaLog,
+    regions: &'a RegionTracker,
+    tracker: &'a TrackerPool,
+    idx: usize,
+    cursor: usize,
+}
+
+impl<'a> Iterator for DeltaIterator<'a> {
+    type Item = PermissionDelta;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx < self.log.len {
+            let entry = &self.log.entries[self.idx];
+            let start = cmp::max(self.cursor, entry.start);
+            if start >= entry.end {
+                self.idx += 1;
+                continue;
+            }
+
+            // Find the largest range with the same permissions, within the logged range
+            let (ops, mut end) = self.regions.permission_at(start, self.tracker);
+            while end < entry.end {
+                let (next_ops, next_end) = self.regions.permission_at(end, self.tracker);
+                if next_ops != ops {
+                    break;
+                }
+                end = next_end;
+            }
+            let end = cmp::min(end, entry.end);
+            self.cursor = end;
+
+            if ops == entry.ops {
+                continue;
+            } else if entry.ops.is_empty() {
+                return Some(PermissionDelta::Added { start, end, ops });
+            } else if ops.is_empty() {
+                return Some(PermissionDelta::Removed { start, end });
+            } else {
+                return Some(PermissionDelta::Changed { start, end, ops });
+            }
+        }
+        None
+    }
+}
+
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for LoggedRange {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.start.save(w)?;
+        self.end.save(w)?;
+        self.ops.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Self {
+            start: usize::load(r)?,
+            end: usize::load(r)?,
+            ops: MemOps::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for DeltaLog {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.entries.save(w)?;
+        self.len.save(w)?;
+        self.overflow.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let log = Self {
+            entries: Snapshot::load(r)?,
+            len: usize::load(r)?,
+            overflow: bool::load(r)?,
+        };
+        if log.len > NB_PERMISSION_DELTAS {
+            return Err(CapaError::InvalidValue);
+        }
+        Ok(log)
+    }
+}
+
+impl fmt::Display for PermissionDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermissionDelta::Added { start, end, ops } => {
+                write!(f, "Added([0x{:x}, 0x{:x} | {}])", start, end, ops)
+            }
+            PermissionDelta::Removed { start, end } => {
+                write!(f, "Removed([0x{:x}, 0x{:x}])", start, end)
+            }
+            PermissionDelta::Changed { start, end, ops } => {
+                write!(f, "Changed([0x{:x}, 0x{:x} | {}])", start, end, ops)
+            }
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NB_TRACKER;
+    use crate::region::EMPTY_REGION;
+    use crate::MEMOPS_ALL;
+
+    const NB_PAGES: usize = 64;
+    const PAGE: usize = 0x1000;
+    const OPS: [MemOps; 3] = [MEMOPS_ALL, MemOps::READ, MemOps::READ.union(MemOps::WRITE)];
+
+    /// A small deterministic pseudo-random generator.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    fn page_ops(regions: &RegionTracker, tracker: &TrackerPool) -> [MemOps; NB_PAGES] {
+        let mut pages = [MemOps::NONE; NB_PAGES];
+        for (page, ops) in pages.iter_mut().enumerate() {
+            *ops = regions.permission_at(page * PAGE, tracker).0;
+        }
+        pages
+    }
+
+    #[test]
+    fn record_merges_ranges() {
+        let mut regions = RegionTracker::new();
+        let mut tracker = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
+        let mut log = DeltaLog::new();
+
+        log.record(0x1000, 0x3000, &regions, &tracker);
+        regions
+            .add_region(0x1000, 0x3000, MEMOPS_ALL, &mut tracker)
+            .unwrap();
+
+        // The overlapping part keeps the permissions from before the first change, and the new
+        // part extends the range as it was not accessible either
+        log.record(0x2000, 0x4000, &regions, &tracker);
+        regions
+            .add_region(0x2000, 0x4000, MemOps::READ, &mut tracker)
+            .unwrap();
+        assert_eq!(log.len, 1);
+        assert_eq!((log.entries[0].start, log.entries[0].end), (0x1000, 0x4000));
+
+        let mut deltas = log.deltas(&regions, &tracker);
+        assert_eq!(
+            deltas.next(),
+            Some(PermissionDelta::Added {
+                start: 0x1000,
+                end: 0x3000,
+                ops: MEMOPS_ALL
+            })
+        );
+        assert_eq!(
+            deltas.next(),
+            Some(PermissionDelta::Added {
+                start: 0x3000,
+                end: 0x4000,
+                ops: MemOps::READ
+            })
+        );
+        assert_eq!(deltas.next(), None);
+    }
+
+    /// Applies the deltas to a copy of the permissions, as a platform would, and checks that the
+    /// copy matches the permissions.
+    #[test]
+    fn random_deltas() {
+        let mut regions = RegionTracker::new();
+        let mut tracker = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
+        let mut log = DeltaLog::new();
+        let mut applied = [MemOps::NONE; NB_PAGES];
+        let mut added = Vec::new();
+        let mut rng = Rng(0xde17a);
+
+        for _ in 0..1000 {
+            for _ in 0..rng.below(8) + 1 {
+                if added.is_empty() || rng.below(3) != 0 {
+                    let start = rng.below(NB_PAGES - 8) * PAGE;
+                    let end = start + (rng.below(8) + 1) * PAGE;
+                    let ops = OPS[rng.below(OPS.len())];
+                    log.record(start, end, &regions, &tracker);
+                    regions.add_region(start, end, ops, &mut tracker).unwrap();
+                    added.push((start, end, ops));
+                } else {
+                    let (start, end, ops) = added.swap_remove(rng.below(added.len()));
+                    log.record(start, end, &regions, &tracker);
+                    regions
+                        .remove_region(start, end, ops, &mut tracker)
+                        .unwrap();
+                }
+            }
+
+            if log.has_overflowed() {
+                applied = page_ops(&regions, &tracker);
+            } else {
+                let mut previous_end = 0;
+                for delta in log.deltas(&regions, &tracker) {
+                    assert!(delta.start() >= previous_end, "Unsorted deltas");
+                    assert!(delta.start() < delta.end(), "Empty delta");
+                    previous_end = delta.end();
+                    for page in &mut applied[delta.start() / PAGE..delta.end() / PAGE] {
+                        match delta {
+                            PermissionDelta::Added { ops, .. } => {
+                                assert!(page.is_empty());
+                                *page = ops;
+                            }
+                            PermissionDelta::Removed { .. } => {
+                                assert!(!page.is_empty());
+                                *page = MemOps::NONE;
+                            }
+                            PermissionDelta::Changed { ops, .. } => {
+                                assert!(!page.is_empty());
+                                assert_ne!(*page, ops);
+                                *page = ops;
+                            }
+                        }
+                    }
+                }
+            }
+            assert_eq!(applied, page_ops(&regions, &tracker));
+            log.clear();
+        }
+    }
+}
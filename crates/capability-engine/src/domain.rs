@@ -4,18 +4,23 @@ use core::iter::Iterator;
 use attestation::hashing::{TycheHasher, HashEnclave};
 use attestation::signature::EnclaveReport;
 
-use crate::capa::{Capa, IntoCapa};
-use crate::config::{NB_CAPAS_PER_DOMAIN, NB_DOMAINS};
+use crate::capa::{Capa, IntoCapa, MgmtRights};
+use crate::channel::{Message, MessageQueue};
+use crate::delta::DeltaLog;
 use crate::free_list::FreeList;
 use crate::gen_arena::GenArena;
 use crate::permission::{self, PermissionIndex, Permissions};
+use crate::pool::{pool_size, PoolMemory, PoolSlice};
+use crate::quota::{self, Quota, Usage};
 use crate::region::{PermissionChange, RegionTracker, TrackerPool};
+use crate::revocation::{RevocationQueue, RevokedRegion};
 use crate::segment::{self, RegionPool};
+use crate::snapshot::{Reader, Restore, Writer};
 use crate::update::{Update, UpdateBuffer};
-use crate::{AccessRights, CapaError, Handle};
+use crate::{AccessRights, CapaError, CoreSet, Handle};
 
 pub type DomainHandle = Handle<Domain>;
-pub(crate) type DomainPool = GenArena<Domain, NB_DOMAINS>;
+pub(crate) type DomainPool = GenArena<Domain>;
 
 // —————————————————————————— Domain Capabilities ——————————————————————————— //
 
@@ -65,21 +70,49 @@ impl NextCapaToken {
 
 // ————————————————————————————————— Domain ————————————————————————————————— //
 
+/// A pair of domains switching to each other on a core without going through the monitor, see
+/// [crate::CapaEngine::enable_fast_switch].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastSwitch {
+    /// The other domain of the pair.
+    pub peer: Handle<Domain>,
+    /// The core shared by the pair.
+    pub core: usize,
+    /// Whether the domain enabled the pair, the peer only runs on the core along with it.
+    pub is_owner: bool,
+}
+
 pub struct Domain {
     /// Unique domain ID.
     id: usize,
     /// Domain capabilities.
-    capas: [Capa; NB_CAPAS_PER_DOMAIN],
+    capas: PoolSlice<Capa>,
     /// Free list of capabilities, used for allocating new capabilities.
-    free_list: FreeList<NB_CAPAS_PER_DOMAIN>,
+    free_list: FreeList,
     /// Tracker for region permissions.
     regions: RegionTracker,
+    /// Permission changes not yet acknowledged by the platform.
+    deltas: DeltaLog,
     /// The (optional) manager of this domain.
     manager: Option<Handle<Domain>>,
     /// Permissions bitmaps for the domain.
     permissions: Permissions,
-    /// A bitmap of cores the domain runs on.
-    cores: u64,
+    /// The cores the domain runs on.
+    cores: CoreSet,
+    /// The pair the domain belongs to, if any.
+    fast_switch: Option<FastSwitch>,
+    /// The information-flow labels set by the manager, see [crate::flow].
+    labels: u64,
+    /// The resources the domain and the domains it manages can use.
+    pub(crate) quota: Quota,
+    /// The resources used by the domain and the domains it manages.
+    pub(crate) usage: Usage,
+    /// A previous usage, used to detect resources growing above the quota.
+    pub(crate) usage_mark: Usage,
+    /// Regions revoked from the domain, not yet polled.
+    revocations: RevocationQueue,
+    /// Messages received over channels, not yet polled.
+    messages: MessageQueue,
     /// Is this domain in the process of being revoked?
     is_being_revoked: bool,
     /// Is the domain sealed?
@@ -101,17 +134,24 @@ pub struct Domain {
 }
 
 impl Domain {
+    /// Creates a domain without capability table, see [Domain::init_capas].
     pub const fn new(id: usize, io: bool) -> Self {
-        const INVALID_CAPA: Capa = Capa::None;
-
         Self {
             id,
-            capas: [INVALID_CAPA; NB_CAPAS_PER_DOMAIN],
-            free_list: FreeList::new(),
+            capas: PoolSlice::empty(),
+            free_list: FreeList::empty(),
             regions: RegionTracker::new(),
+            deltas: DeltaLog::new(),
             manager: None,
             permissions: permission::DEFAULT,
-            cores: permission::core_bits::NONE,
+            cores: CoreSet::NONE,
+            fast_switch: None,
+            labels: 0,
+            quota: Quota::UNLIMITED,
+            usage: Usage::NONE,
+            usage_mark: Usage::NONE,
+            revocations: RevocationQueue::new(),
+            messages: MessageQueue::new(),
             is_being_revoked: false,
             is_sealed: false,
             attestation_hash: None,
@@ -124,6 +164,34 @@ impl Domain {
         }
     }
 
+    /// Allocates a capability table of `nb_capas` entries from `memory`.
+    pub(crate) fn init_capas(
+        &mut self,
+        memory: &mut PoolMemory,
+        nb_capas: usize,
+    ) -> Result<(), CapaError> {
+        self.capas = memory.carve(nb_capas, |_| Capa::None)?;
+        self.free_list = FreeList::new(memory, nb_capas)?;
+        Ok(())
+    }
+
+    /// Number of bytes needed for a capability table of `nb_capas` entries.
+    pub(crate) const fn capas_memory_size(nb_capas: usize) -> usize {
+        pool_size::<Capa>(nb_capas) + FreeList::memory_size(nb_capas)
+    }
+
+    /// Resets the domain to a fresh state, keeping its capability table memory.
+    pub(crate) fn reset(&mut self, id: usize, io: bool) {
+        let mut capas = core::mem::replace(&mut self.capas, PoolSlice::empty());
+        let mut free_list = core::mem::replace(&mut self.free_list, FreeList::empty());
+        capas.fill(Capa::None);
+        free_list.reset();
+
+        *self = Domain::new(id, io);
+        self.capas = capas;
+        self.free_list = free_list;
+    }
+
     /*pub fn get_config(&self, bitmap: Bitmaps) -> u64 {
         self.config.values[bitmap as usize]
     }
@@ -193,37 +261,107 @@ impl Domain {
 
     /// Mark the domain as executing on the given core.
     pub(crate) fn execute_on_core(&mut self, core_id: usize) {
-        let core_id = 1 << core_id;
-        self.cores |= core_id;
+        self.cores.insert(core_id);
     }
 
-    /// Remove the core from the bitmap of cores runing the domain.
+    /// Remove the core from the set of cores runing the domain.
     pub(crate) fn remove_from_core(&mut self, core_id: usize) {
-        let core_id = 1 << core_id;
-        if self.cores & core_id == 0 {
+        if !self.cores.contains(core_id) {
             log::error!("Removing from a core in which the domains was NOT executing");
         }
-        self.cores &= !core_id
+        self.cores.remove(core_id)
     }
 
     pub(crate) fn regions(&self) -> &RegionTracker {
         &self.regions
     }
 
+    pub(crate) fn deltas(&self) -> &DeltaLog {
+        &self.deltas
+    }
+
+    pub(crate) fn deltas_mut(&mut self) -> &mut DeltaLog {
+        &mut self.deltas
+    }
+
     pub fn id(&self) -> usize {
         self.id
     }
 
+    pub(crate) fn is_being_revoked(&self) -> bool {
+        self.is_being_revoked
+    }
+
+    /// Records that a region was revoked from the domain.
+    pub(crate) fn notify_revocation(&mut self, revoked: RevokedRegion) {
+        self.revocations.push(revoked);
+    }
+
+    /// Returns the oldest revocation the domain was not yet told about.
+    pub(crate) fn next_revocation(&mut self) -> Option<RevokedRegion> {
+        self.revocations.pop()
+    }
+
+    /// Checks that the domain can receive one more message.
+    pub(crate) fn can_receive_message(&self) -> Result<(), CapaError> {
+        self.messages.has_capacity()
+    }
+
+    /// Queues a message received over a channel.
+    pub(crate) fn receive_message(&mut self, message: Message) -> Result<(), CapaError> {
+        self.messages.push(message)
+    }
+
+    /// Returns the oldest message the domain did not poll yet.
+    pub(crate) fn next_message(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+
+    /// The number of messages the domain did not poll yet.
+    pub(crate) fn pending_messages(&self) -> usize {
+        self.messages.len()
+    }
+
     pub fn traps(&self) -> u64 {
         self.permissions.perm[PermissionIndex::AllowedTraps as usize]
     }
 
-    pub fn cores(&self) -> u64 {
+    pub fn cores(&self) -> CoreSet {
         self.cores
     }
 
-    pub fn core_map(&self) -> u64 {
-        self.permissions.perm[PermissionIndex::AllowedCores as usize]
+    pub fn core_map(&self) -> CoreSet {
+        self.permissions.cores
+    }
+
+    pub fn fast_switch(&self) -> Option<FastSwitch> {
+        self.fast_switch
+    }
+
+    pub(crate) fn set_fast_switch(&mut self, fast_switch: Option<FastSwitch>) {
+        self.fast_switch = fast_switch;
+    }
+
+    /// The pair the domain belongs to on the given core, if any.
+    pub(crate) fn fast_switch_on(&self, core: usize) -> Option<FastSwitch> {
+        self.fast_switch.filter(|pair| pair.core == core)
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// The labels set by the manager, without the ones set by the engine.
+    pub fn labels(&self) -> u64 {
+        self.labels
+    }
+
+    pub(crate) fn set_labels(&mut self, labels: u64) {
+        self.labels = labels;
     }
 
     pub fn monitor_interface(&self) -> u64 {
@@ -244,6 +382,11 @@ impl Domain {
         }
     }
 
+    /// Reverts [Domain::seal], used to roll back transactions.
+    pub(crate) fn unseal(&mut self) {
+        self.is_sealed = false;
+    }
+
     pub fn is_sealed(&self) -> bool {
         self.is_sealed
     }
@@ -257,7 +400,7 @@ impl Domain {
             Capa::None => false,
             Capa::Region(handle) => regions.get(handle).is_some(),
             Capa::RegionRevoke(handle) => regions.get(handle).is_some(),
-            Capa::Management(handle) => domains.get(handle).is_some(),
+            Capa::Management(handle, _) => domains.get(handle).is_some(),
             Capa::Channel(handle) => domains.get(handle).is_some(),
             Capa::Switch { to, .. } => domains.get(to).is_some(),
         }
@@ -317,6 +460,11 @@ impl Domain {
         }
     }
 
+    /// The measurement of the domain, if it has been measured when sealed.
+    pub fn measurement(&self) -> Option<&HashEnclave> {
+        self.attestation_hash.as_ref()
+    }
+
     pub fn get_hash(&self) -> HashEnclave {
         if let Some(he) = &self.attestation_hash {
             *he
@@ -392,7 +540,7 @@ pub fn has_capacity_for(
 /// This is necessary as some capabilities are invalidated but not removed eagerly.
 fn free_invalid_capas(domain: Handle<Domain>, regions: &mut RegionPool, domains: &mut DomainPool) {
     log::trace!("Runing garbage collection");
-    for idx in 0..NB_CAPAS_PER_DOMAIN {
+    for idx in 0..domains[domain].capas.len() {
         if domains[domain].free_list.is_free(idx) {
             // Capa is already free
             continue;
@@ -404,7 +552,7 @@ fn free_invalid_capas(domain: Handle<Domain>, regions: &mut RegionPool, domains:
             Capa::None => true,
             Capa::Region(h) => regions.get(h).is_none(),
             Capa::RegionRevoke(h) => regions.get(h).is_none(),
-            Capa::Management(h) => domains.get(h).is_none(),
+            Capa::Management(h, _) => domains.get(h).is_none(),
             Capa::Channel(h) => domains.get(h).is_none(),
             Capa::Switch { to, .. } => domains.get(to).is_none(),
         };
@@ -425,10 +573,8 @@ pub(crate) fn has_permission(
     perm: PermissionIndex,
     value: u64,
 ) -> Result<(), CapaError> {
-    let domain = &domains[domain];
     // Let's ignore the read/write for the moment.
-    if perm >= PermissionIndex::MgmtRead16
-        || domain.permissions.perm[perm as usize] & value == value
+    if perm >= PermissionIndex::MgmtRead16 || get_permission(domain, domains, perm) & value == value
     {
         Ok(())
     } else {
@@ -442,6 +588,11 @@ pub(crate) fn set_permission(
     perm: PermissionIndex,
     value: u64,
 ) -> Result<(), CapaError> {
+    if perm == PermissionIndex::AllowedCores {
+        let mut cores = domains[domain].permissions.cores;
+        cores.set_word(0, value)?;
+        return set_core_map(domain, domains, cores);
+    }
     let domain = &mut domains[domain];
     if domain.is_sealed() {
         return Err(CapaError::AlreadySealed);
@@ -456,32 +607,87 @@ pub(crate) fn get_permission(
     perm: PermissionIndex,
 ) -> u64 {
     let domain = &domains[domain];
+    if perm == PermissionIndex::AllowedCores {
+        return domain.permissions.cores.word(0).unwrap_or(0);
+    }
     domain.permissions.perm[perm as usize]
 }
 
+pub(crate) fn set_core_map(
+    domain: Handle<Domain>,
+    domains: &mut DomainPool,
+    cores: CoreSet,
+) -> Result<(), CapaError> {
+    let domain = &mut domains[domain];
+    if domain.is_sealed() {
+        return Err(CapaError::AlreadySealed);
+    }
+    domain.permissions.cores = cores;
+    Ok(())
+}
+
+pub(crate) fn set_quota(
+    domain: Handle<Domain>,
+    domains: &mut DomainPool,
+    quota: Quota,
+) -> Result<(), CapaError> {
+    let domain = &mut domains[domain];
+    if domain.is_sealed() {
+        return Err(CapaError::AlreadySealed);
+    }
+    domain.quota = quota;
+    Ok(())
+}
+
 // —————————————————————————————————— Send —————————————————————————————————— //
 
-pub(crate) fn send_management(
+/// Checks that the management capability of `capa` can be sent to `to`.
+pub(crate) fn check_send_management(
     capa: Handle<Domain>,
-    domains: &mut DomainPool,
+    domains: &DomainPool,
     to: Handle<Domain>,
 ) -> Result<(), CapaError> {
-    // Update manager
-    if (!domains[to].core_map()) & domains[capa].core_map() != 0 {
+    if !domains[capa].core_map().is_subset(&domains[to].core_map()) {
         log::debug!("Sending management to a domain with less cores on its map.");
         log::debug!("manager cores: {:b}", domains[to].core_map());
         log::debug!("domain  cores: {:b}", domains[capa].core_map());
         return Err(CapaError::InsufficientPermissions);
     }
-    domains[capa].set_manager(to);
+
+    // The new manager must not be managed by the domain, otherwise we would create a cycle.
+    let mut cursor = Some(to);
+    for _ in 0..=domains.size() {
+        match cursor {
+            Some(handle) if handle == capa => {
+                log::debug!("Sending management would create a cycle of managers.");
+                return Err(CapaError::InvalidOperation);
+            }
+            Some(handle) => cursor = domains.get(handle).and_then(|d| d.manager),
+            None => break,
+        }
+    }
     Ok(())
 }
 
+pub(crate) fn send_management(capa: Handle<Domain>, domains: &mut DomainPool, to: Handle<Domain>) {
+    // The resources of the domain are now accounted to the new manager
+    let usage = Usage::DOMAIN.plus(domains[capa].usage);
+    if let Some(manager) = domains[capa].manager {
+        quota::release(manager, domains, usage);
+    }
+
+    // Update manager
+    domains[capa].set_manager(to);
+    quota::charge(to, domains, usage);
+}
+
 // ——————————————————————————————— Duplicate ———————————————————————————————— //
 
+/// Duplicates a capability, management capabilities keep at most `rights`.
 pub(crate) fn duplicate_capa(
     domain: Handle<Domain>,
     capa: LocalCapa,
+    rights: MgmtRights,
     regions: &mut RegionPool,
     domains: &mut DomainPool,
 ) -> Result<LocalCapa, CapaError> {
@@ -489,13 +695,18 @@ pub(crate) fn duplicate_capa(
 
     match capa {
         // Capa that can not be duplicated
-        Capa::None | Capa::Region(_) | Capa::Management(_) | Capa::Switch { .. } => {
+        Capa::None | Capa::Region(_) | Capa::Switch { .. } => {
             return Err(CapaError::CannotDuplicate);
         }
         Capa::Channel(_) | Capa::RegionRevoke(_) => {
             // NOTE: there is no side effects when duplicating these capas
             insert_capa(domain, capa, regions, domains)
         }
+        Capa::Management(managed, held) => {
+            // Only the original capability manages the domain
+            let rights = held.intersection(rights).difference(MgmtRights::MANAGE);
+            insert_capa(domain, Capa::Management(managed, rights), regions, domains)
+        }
     }
 }
 
@@ -526,9 +737,14 @@ pub(crate) fn activate_region(
     if dom.is_being_revoked {
         return Ok(());
     }
+    let capacity = tracker.capacity();
+    dom.deltas
+        .record(access.start, access.end, &dom.regions, tracker);
     let change = dom
         .regions
         .add_region(access.start, access.end, access.ops, tracker)?;
+    quota::update_trackers(domain, domains, capacity, tracker.capacity());
+    let dom = &domains[domain];
 
     let filter = |up: Update| match up {
         Update::PermissionUpdate {
@@ -562,9 +778,14 @@ pub(crate) fn deactivate_region(
 ) -> Result<(), CapaError> {
     let dom = &mut domains[domain];
 
+    let capacity = tracker.capacity();
+    dom.deltas
+        .record(access.start, access.end, &dom.regions, tracker);
     let change = dom
         .regions
         .remove_region(access.start, access.end, access.ops, tracker)?;
+    quota::update_trackers(domain, domains, capacity, tracker.capacity());
+    let dom = &domains[domain];
 
     // Drop updates on domain in the process of being revoked
     if dom.is_being_revoked {
@@ -657,6 +878,36 @@ pub(crate) fn next_capa(
 
 // ——————————————————————————————— Revocation ——————————————————————————————— //
 
+/// Dissolves the pair the domain belongs to, if any.
+///
+/// The peer stops running on the core of the pair along with the owner, and the platform is
+/// notified so that the two domains can no longer switch to each other.
+pub(crate) fn dissolve_fast_switch(
+    handle: DomainHandle,
+    domains: &mut DomainPool,
+    updates: &mut UpdateBuffer,
+) -> Result<(), CapaError> {
+    let Some(pair) = domains[handle].fast_switch else {
+        return Ok(());
+    };
+    let (owner, peer) = if pair.is_owner {
+        (handle, pair.peer)
+    } else {
+        (pair.peer, handle)
+    };
+    updates.push(Update::FastSwitchDisabled {
+        owner,
+        peer,
+        core: pair.core,
+    })?;
+    if domains[owner].cores.contains(pair.core) {
+        domains[peer].remove_from_core(pair.core);
+    }
+    domains[owner].fast_switch = None;
+    domains[peer].fast_switch = None;
+    Ok(())
+}
+
 pub(crate) fn revoke(
     handle: DomainHandle,
     regions: &mut RegionPool,
@@ -673,10 +924,12 @@ pub(crate) fn revoke(
     } else {
         // Mark as being revoked
         domain.is_being_revoked = true;
-        // The domain is still scheduled on some cores.
-        if domain.cores() != 0 {
-            return Err(CapaError::InvalidOperation);
-        }
+    }
+    // The peer of a pair leaves the core along with the pair.
+    dissolve_fast_switch(handle, domains, updates)?;
+    // The domain is still scheduled on some cores.
+    if !domains[handle].cores().is_empty() {
+        return Err(CapaError::InvalidOperation);
     }
 
     // Drop all capabilities
@@ -686,10 +939,36 @@ pub(crate) fn revoke(
         revoke_capa(handle, capa, regions, domains, tracker, updates)?;
     }
 
+    // Give back the remaining resources to the managers
+    if let Some(manager) = domains[handle].manager {
+        let usage = Usage::DOMAIN.plus(domains[handle].usage);
+        quota::release(manager, domains, usage);
+    }
+
+    drop_management_copies(handle, domains);
     domains.free(handle);
     Ok(())
 }
 
+/// Drops the attenuated management capabilities to a revoked domain held by other domains.
+fn drop_management_copies(revoked: Handle<Domain>, domains: &mut DomainPool) {
+    loop {
+        let copy = domains.into_iter().find_map(|holder| {
+            domains[holder]
+                .find_capa(|capa| match capa {
+                    Capa::Management(h, r) => *h == revoked && !r.contains(MgmtRights::MANAGE),
+                    _ => false,
+                })
+                .map(|capa| (holder, capa))
+        });
+        let Some((holder, capa)) = copy else {
+            break;
+        };
+        // We just found the capa, it exists
+        remove_capa(holder, capa, domains).unwrap();
+    }
+}
+
 pub(crate) fn revoke_capa(
     handle: Handle<Domain>,
     local: LocalCapa,
@@ -708,15 +987,18 @@ pub(crate) fn revoke_capa(
         Capa::Switch { .. } => {}
 
         Capa::Region(region) => {
-            segment::revoke(region, regions, domains, tracker, updates)?;
+            segment::revoke(region, handle, regions, domains, tracker, updates)?;
         }
         Capa::RegionRevoke(region) => {
             if regions.get(region).is_some() {
-                segment::revoke(region, regions, domains, tracker, updates)?;
+                segment::revoke(region, handle, regions, domains, tracker, updates)?;
             }
         }
-        Capa::Management(domain) => {
-            revoke(domain, regions, domains, tracker, updates)?;
+        Capa::Management(domain, rights) => {
+            // Dropping an attenuated copy leaves the domain alive
+            if rights.contains(MgmtRights::MANAGE) {
+                revoke(domain, regions, domains, tracker, updates)?;
+            }
         }
     }
 
@@ -728,6 +1010,123 @@ pub(crate) fn revoke_capa(
     Ok(())
 }
 
+// ——————————————————————————————— Invariants ——————————————————————————————— //
+
+/// Panics if the manager links are inconsistent with the management capabilities.
+pub(crate) fn check_invariants(domains: &DomainPool) {
+    for handle in domains {
+        let domain = &domains[handle];
+
+        // The manager must be valid and hold the management capability
+        if let Some(manager) = domain.manager {
+            assert!(
+                domains.get(manager).is_some(),
+                "Domain {} has an invalid manager",
+                handle
+            );
+            assert!(
+                domains[manager].iter_capa().any(|capa| matches!(
+                    capa,
+                    Capa::Management(h, r) if h == handle && r.contains(MgmtRights::MANAGE)
+                )),
+                "Manager of domain {} does not hold its management capability",
+                handle
+            );
+        }
+
+        // The chain of managers must not contain cycles
+        let mut cursor = domain.manager;
+        for _ in 0..domains.size() {
+            match cursor {
+                Some(manager) => {
+                    assert!(manager != handle, "Domain {} manages itself", handle);
+                    cursor = domains.get(manager).and_then(|d| d.manager);
+                }
+                None => break,
+            }
+        }
+        assert!(
+            cursor.is_none(),
+            "Cycle in the managers of domain {}",
+            handle
+        );
+
+        // Only the manager can hold the capability managing a domain
+        for capa in domain.iter_capa() {
+            if let Capa::Management(managed, rights) = capa {
+                if !rights.contains(MgmtRights::MANAGE) {
+                    continue;
+                }
+                if let Some(managed_domain) = domains.get(managed) {
+                    assert!(
+                        managed_domain.manager == Some(handle),
+                        "Domain {} holds a management capability to {} without managing it",
+                        handle,
+                        managed
+                    );
+                }
+            }
+        }
+    }
+}
+
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+/// The capability table is restored in place, the temporary ID is not part of snapshots.
+impl Restore for Domain {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.id.snapshot(w)?;
+        self.capas.snapshot(w)?;
+        self.free_list.snapshot(w)?;
+        self.regions.snapshot(w)?;
+        self.deltas.snapshot(w)?;
+        self.manager.snapshot(w)?;
+        self.permissions.snapshot(w)?;
+        self.cores.snapshot(w)?;
+        self.fast_switch.snapshot(w)?;
+        self.labels.snapshot(w)?;
+        self.quota.snapshot(w)?;
+        self.usage.snapshot(w)?;
+        self.usage_mark.snapshot(w)?;
+        self.revocations.snapshot(w)?;
+        self.messages.snapshot(w)?;
+        self.is_being_revoked.snapshot(w)?;
+        self.is_sealed.snapshot(w)?;
+        self.attestation_hash.snapshot(w)?;
+        self.attestation_report.snapshot(w)?;
+        self.is_io.snapshot(w)?;
+        self.argos_measurement.snapshot(w)?;
+        self.argos_finalized.snapshot(w)?;
+        self.argos_transcript.snapshot(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        self.id.restore(r)?;
+        self.capas.restore(r)?;
+        self.free_list.restore(r)?;
+        self.regions.restore(r)?;
+        self.deltas.restore(r)?;
+        self.manager.restore(r)?;
+        self.permissions.restore(r)?;
+        self.cores.restore(r)?;
+        self.fast_switch.restore(r)?;
+        self.labels.restore(r)?;
+        self.quota.restore(r)?;
+        self.usage.restore(r)?;
+        self.usage_mark.restore(r)?;
+        self.revocations.restore(r)?;
+        self.messages.restore(r)?;
+        self.is_being_revoked.restore(r)?;
+        self.is_sealed.restore(r)?;
+        self.attestation_hash.restore(r)?;
+        self.attestation_report.restore(r)?;
+        self.is_io.restore(r)?;
+        self.argos_measurement.restore(r)?;
+        self.argos_finalized.restore(r)?;
+        self.argos_transcript.restore(r)
+    }
+}
+
 // ———————————————————————————————— Iterator ———————————————————————————————— //
 
 pub struct DomainCapaIterator<'a> {
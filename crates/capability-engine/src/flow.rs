@@ -0,0 +1,195 @@
+//! Information Flow Policy
+//!
+//! The monitor interface permissions only say what a domain may do with its own capabilities. On
+//! top of those, the platform can load a mandatory policy at boot that denies transfers based on
+//! the labels of the domains and regions involved, for instance to prevent confidential regions
+//! from ever reaching the initial domain.
+//!
+//! Domains are labeled by their manager before being sealed, and inherit the labels of the domain
+//! that created them. Regions carry the labels of all the domains they went through, so that a
+//! region can't be laundered by an intermediary domain. The policy is a list of deny rules, a
+//! transfer is allowed if no rule matches it.
+
+use core::fmt;
+
+use crate::config::NB_FLOW_RULES;
+use crate::snapshot::{self, Reader, Snapshot, Writer};
+use crate::{CapaError, Domain, MemOps};
+
+/// Labels set by the engine, managers can only set the other labels.
+#[rustfmt::skip]
+pub mod labels {
+    /// Domains created at boot, such as the initial domain.
+    pub const INITIAL: u64 = 1 << 63;
+    /// Domains sealed by their manager.
+    pub const SEALED:  u64 = 1 << 62;
+
+    /// All the labels set by the engine.
+    pub const IMPLICIT: u64 = INITIAL | SEALED;
+}
+
+/// The kinds of transfers subject to the policy.
+#[rustfmt::skip]
+pub mod transfer {
+    /// Sending a region to another domain, including over a channel.
+    pub const SEND_REGION: u64 = 1 << 0;
+    /// Sending any other capability to another domain.
+    pub const SEND_CAPA:   u64 = 1 << 1;
+    /// Aliasing a region.
+    pub const ALIAS:       u64 = 1 << 2;
+    /// Carving a region.
+    pub const CARVE:       u64 = 1 << 3;
+    /// Creating a child domain.
+    pub const SPAWN:       u64 = 1 << 4;
+
+    pub const ALL: u64 = SEND_REGION | SEND_CAPA | ALIAS | CARVE | SPAWN;
+}
+
+/// A rule denying the transfers that match all of its fields.
+///
+/// The label fields match if all of their labels are set, an empty set of labels matches anything.
+/// For transfers that don't involve a region, the region labels and operations are empty.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rule {
+    /// The kinds of transfers denied by the rule, see [transfer].
+    pub transfers: u64,
+    /// Labels of the domain performing the transfer.
+    pub from: u64,
+    /// Labels of the domain receiving the capability (the actor itself for alias and carve).
+    pub to: u64,
+    /// Labels of the region being transferred.
+    pub region: u64,
+    /// The rule only matches regions with one of those memory operations, or any region if empty.
+    pub ops: u64,
+}
+
+/// A transfer checked against the policy.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Transfer {
+    pub kind: u64,
+    pub from: u64,
+    pub to: u64,
+    pub region: u64,
+    pub ops: MemOps,
+}
+
+impl Rule {
+    /// A rule that denies nothing.
+    pub const EMPTY: Self = Self {
+        transfers: 0,
+        from: 0,
+        to: 0,
+        region: 0,
+        ops: 0,
+    };
+
+    fn denies(&self, transfer: &Transfer) -> bool {
+        let contains = |labels: u64, required: u64| labels & required == required;
+        self.transfers & transfer.kind != 0
+            && contains(transfer.from, self.from)
+            && contains(transfer.to, self.to)
+            && contains(transfer.region, self.region)
+            && (self.ops == 0 || self.ops & transfer.ops.bits() as u64 != 0)
+    }
+}
+
+/// The information-flow policy of the engine, empty unless one is loaded at boot.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    rules: [Rule; NB_FLOW_RULES],
+    len: usize,
+}
+
+impl Policy {
+    /// A policy allowing all transfers.
+    pub const fn empty() -> Self {
+        Self {
+            rules: [Rule::EMPTY; NB_FLOW_RULES],
+            len: 0,
+        }
+    }
+
+    /// Creates a policy from a list of rules, returns `InvalidValue` if there are too many rules.
+    pub fn new(rules: &[Rule]) -> Result<Self, CapaError> {
+        if rules.len() > NB_FLOW_RULES {
+            log::error!("Too many flow rules: {}", rules.len());
+            return Err(CapaError::InvalidValue);
+        }
+        let mut policy = Self::empty();
+        policy.rules[..rules.len()].copy_from_slice(rules);
+        policy.len = rules.len();
+        Ok(policy)
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules[..self.len]
+    }
+
+    pub(crate) fn check(&self, transfer: Transfer) -> Result<(), CapaError> {
+        match self.rules().iter().find(|rule| rule.denies(&transfer)) {
+            Some(rule) => {
+                log::info!("Transfer {:x?} denied by {}", transfer, rule);
+                Err(CapaError::DeniedByPolicy)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the labels of a domain, including the ones set by the engine.
+pub(crate) fn domain_labels(domain: &Domain) -> u64 {
+    let mut labels = domain.labels();
+    match domain.get_manager() {
+        None => labels |= labels::INITIAL,
+        Some(_) if domain.is_sealed() => labels |= labels::SEALED,
+        Some(_) => (),
+    }
+    labels
+}
+
+impl Snapshot for Rule {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.transfers.save(w)?;
+        self.from.save(w)?;
+        self.to.save(w)?;
+        self.region.save(w)?;
+        self.ops.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Rule {
+            transfers: u64::load(r)?,
+            from: u64::load(r)?,
+            to: u64::load(r)?,
+            region: u64::load(r)?,
+            ops: u64::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for Policy {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.rules.save(w)?;
+        self.len.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let rules = Snapshot::load(r)?;
+        let len = usize::load(r)?;
+        if len > NB_FLOW_RULES {
+            return snapshot::invalid("number of flow rules", len as u64);
+        }
+        Ok(Policy { rules, len })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rule(deny 0x{:x} from 0x{:x} to 0x{:x}, region 0x{:x} ops 0x{:x})",
+            self.transfers, self.from, self.to, self.region, self.ops
+        )
+    }
+}
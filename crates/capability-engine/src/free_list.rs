@@ -1,5 +1,9 @@
 //! A Free List used for managing memory pools.
 
+use crate::pool::{pool_size, PoolMemory, PoolSlice};
+use crate::snapshot::{Reader, Restore, Snapshot, Writer};
+use crate::CapaError;
+
 /// Free list node.
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum NextFree {
@@ -11,9 +15,9 @@ enum NextFree {
 }
 
 /// A typed arena, from which objects can be dynamically allocated and freed.
-pub(crate) struct FreeList<const N: usize> {
+pub(crate) struct FreeList {
     /// The free list, where free_list[n] returns the index of the next free object.
-    free_list: [NextFree; N],
+    free_list: PoolSlice<NextFree>,
 
     /// The next free block, if any.
     head: u32,
@@ -22,26 +26,48 @@ pub(crate) struct FreeList<const N: usize> {
     count: u32,
 }
 
-impl<const N: usize> FreeList<N> {
-    pub const fn new() -> Self {
-        let mut free_list = [NextFree::NotFree; N];
-        let mut i = 0;
-        while i < N {
-            let next = (i as u32 + 1) % (N as u32);
-            free_list[i] = NextFree::Free(next);
-            i += 1;
-        }
+impl FreeList {
+    /// An empty free list, from which nothing can be allocated.
+    pub const fn empty() -> Self {
         Self {
+            free_list: PoolSlice::empty(),
+            head: 0,
+            count: 0,
+        }
+    }
+
+    /// Creates a free list of `len` slots, carved from `memory`.
+    pub fn new(memory: &mut PoolMemory, len: usize) -> Result<Self, CapaError> {
+        if len > u32::MAX as usize {
+            return Err(CapaError::InvalidValue);
+        }
+        let free_list = memory.carve(len, |i| NextFree::Free(((i + 1) % len) as u32))?;
+        Ok(Self {
             free_list,
             head: 0,
             count: 0,
+        })
+    }
+
+    /// Number of bytes needed to create a free list of `len` slots.
+    pub const fn memory_size(len: usize) -> usize {
+        pool_size::<NextFree>(len)
+    }
+
+    /// Marks all the slots as free.
+    pub fn reset(&mut self) {
+        let len = self.free_list.len();
+        for (i, next) in self.free_list.iter_mut().enumerate() {
+            *next = NextFree::Free(((i + 1) % len) as u32);
         }
+        self.head = 0;
+        self.count = 0;
     }
 
     /// Return the index of the allocated slot.
     pub fn allocate(&mut self) -> Option<usize> {
         let head = self.head as usize;
-        match self.free_list[head] {
+        match *self.free_list.get(head)? {
             NextFree::Free(next) => {
                 self.head = next;
                 self.free_list[head] = NextFree::NotFree;
@@ -66,25 +92,32 @@ impl<const N: usize> FreeList<N> {
         self.count = self.count.checked_sub(1).unwrap();
     }
 
-    /// Check if a slot is free.
+    /// Check if a slot is free, slots out of the list are never allocated.
     pub fn is_free(&self, idx: usize) -> bool {
-        self.free_list[idx] != NextFree::NotFree
+        self.free_list
+            .get(idx)
+            .map_or(true, |next| *next != NextFree::NotFree)
     }
 
     /// Return the remaining capacity.
     pub fn capacity(&self) -> usize {
-        N.checked_sub(self.count as usize).unwrap()
+        self.len().checked_sub(self.count as usize).unwrap()
+    }
+
+    /// Return the total number of slots.
+    pub fn len(&self) -> usize {
+        self.free_list.len()
     }
 }
 
 // ———————————————————————————————— Iterator ———————————————————————————————— //
 
-pub(crate) struct FreeListIterator<'a, const N: usize> {
-    free_list: &'a FreeList<N>,
+pub(crate) struct FreeListIterator<'a> {
+    free_list: &'a FreeList,
     next: usize,
 }
 
-impl<'a, const N: usize> Iterator for FreeListIterator<'a, N> {
+impl<'a> Iterator for FreeListIterator<'a> {
     type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -105,9 +138,9 @@ impl<'a, const N: usize> Iterator for FreeListIterator<'a, N> {
     }
 }
 
-impl<'a, const N: usize> IntoIterator for &'a FreeList<N> {
+impl<'a> IntoIterator for &'a FreeList {
     type Item = usize;
-    type IntoIter = FreeListIterator<'a, N>;
+    type IntoIter = FreeListIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
         FreeListIterator {
@@ -117,15 +150,49 @@ impl<'a, const N: usize> IntoIterator for &'a FreeList<N> {
     }
 }
 
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for NextFree {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match *self {
+            NextFree::Free(next) => Some(next),
+            NextFree::NotFree => None,
+        }
+        .save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(match Option::load(r)? {
+            Some(next) => NextFree::Free(next),
+            None => NextFree::NotFree,
+        })
+    }
+}
+
+impl Restore for FreeList {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.free_list.snapshot(w)?;
+        self.head.snapshot(w)?;
+        self.count.snapshot(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        self.free_list.restore(r)?;
+        self.head.restore(r)?;
+        self.count.restore(r)
+    }
+}
+
 // ————————————————————————————————— Tests —————————————————————————————————— //
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pool::leak_memory;
 
     #[test]
     fn capacity() {
-        let mut list: FreeList<10> = FreeList::new();
+        let mut list = FreeList::new(&mut leak_memory(0x100), 10).unwrap();
         assert_eq!(list.capacity(), 10);
 
         // Insert a few items
@@ -150,4 +217,36 @@ mod tests {
         assert_eq!(list.allocate(), Some(2));
         assert_eq!(list.capacity(), 6);
     }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut list = FreeList::new(&mut leak_memory(0x100), 2).unwrap();
+        assert_eq!(list.allocate(), Some(0));
+        assert_eq!(list.allocate(), Some(1));
+        assert!(!list.is_free(1));
+        assert!(list.is_free(2));
+        assert!(list.is_free(usize::MAX));
+        assert_eq!(list.allocate(), None);
+    }
+
+    #[test]
+    fn empty() {
+        let mut list = FreeList::empty();
+        assert_eq!(list.capacity(), 0);
+        assert_eq!(list.allocate(), None);
+
+        let mut list = FreeList::new(&mut leak_memory(0x100), 0).unwrap();
+        assert_eq!(list.allocate(), None);
+    }
+
+    #[test]
+    fn reset() {
+        let mut list = FreeList::new(&mut leak_memory(0x100), 3).unwrap();
+        assert_eq!(list.allocate(), Some(0));
+        assert_eq!(list.allocate(), Some(1));
+        list.reset();
+        assert_eq!(list.capacity(), 3);
+        assert_eq!(list.into_iter().count(), 0);
+        assert_eq!(list.allocate(), Some(0));
+    }
 }
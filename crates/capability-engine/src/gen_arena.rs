@@ -4,31 +4,59 @@ use core::marker::PhantomData;
 use core::ops::{Index, IndexMut};
 
 use super::free_list::{FreeList, FreeListIterator};
+use crate::pool::{pool_size, PoolMemory, PoolSlice};
+use crate::snapshot::{Reader, Restore, Snapshot, Writer};
 use crate::CapaError;
 
 // ——————————————————————————— Generational Arena ——————————————————————————— //
 
 /// A generational arena.
-pub struct GenArena<T, const N: usize> {
+pub struct GenArena<T> {
     /// The baking store from which objects are allocated.
-    store: [T; N],
+    store: PoolSlice<T>,
 
     /// The free list, where free_list[n] returns the index of the next free object.
-    free_list: FreeList<N>,
+    free_list: FreeList,
 
     /// The generation, used to protect from use after free.
-    gen: [u64; N],
+    gen: PoolSlice<u64>,
 }
 
-impl<T, const N: usize> GenArena<T, N> {
-    pub const fn new(store: [T; N]) -> Self {
+impl<T> GenArena<T> {
+    /// An empty arena, from which nothing can be allocated.
+    pub const fn empty() -> Self {
         Self {
-            store,
-            free_list: FreeList::new(),
-            gen: [0; N],
+            store: PoolSlice::empty(),
+            free_list: FreeList::empty(),
+            gen: PoolSlice::empty(),
         }
     }
 
+    /// Creates an arena of `len` objects initialized with `init(index)`, carved from `memory`.
+    pub fn new(
+        memory: &mut PoolMemory,
+        len: usize,
+        init: impl FnMut(usize) -> T,
+    ) -> Result<Self, CapaError> {
+        let store = memory.carve(len, init)?;
+        Self::from_store(store, memory)
+    }
+
+    /// Creates an arena backed by an existing store, carving the metadata from `memory`.
+    pub fn from_store(store: PoolSlice<T>, memory: &mut PoolMemory) -> Result<Self, CapaError> {
+        let len = store.len();
+        Ok(Self {
+            store,
+            free_list: FreeList::new(memory, len)?,
+            gen: memory.carve(len, |_| 0)?,
+        })
+    }
+
+    /// Number of bytes needed to create an arena of `len` objects.
+    pub const fn memory_size(len: usize) -> usize {
+        pool_size::<T>(len) + FreeList::memory_size(len) + pool_size::<u64>(len)
+    }
+
     pub fn allocate(&mut self, item: T) -> Option<Handle<T>> {
         self.free_list.allocate().map(|idx| {
             let gen = self.gen[idx];
@@ -43,6 +71,19 @@ impl<T, const N: usize> GenArena<T, N> {
         })
     }
 
+    /// Allocates an object in place, re-using the existing slot.
+    pub fn allocate_with(&mut self, init: impl FnOnce(&mut T)) -> Option<Handle<T>> {
+        self.free_list.allocate().map(|idx| {
+            let gen = self.gen[idx];
+            init(&mut self.store[idx]);
+            Handle {
+                idx,
+                gen,
+                _type: PhantomData,
+            }
+        })
+    }
+
     /// Free the handle and allocated memory. This invalidate all existing handles to that object.
     pub fn free(&mut self, handle: Handle<T>) {
         self.free_list.free(handle.idx);
@@ -81,11 +122,16 @@ impl<T, const N: usize> GenArena<T, N> {
     pub fn capacity(&self) -> usize {
         self.free_list.capacity()
     }
+
+    /// Returns the total number of objects in the arena, allocated or not.
+    pub fn size(&self) -> usize {
+        self.store.len()
+    }
 }
 
 // ———————————————————————————————— Indexing ———————————————————————————————— //
 
-impl<T, const N: usize> Index<Handle<T>> for GenArena<T, N> {
+impl<T> Index<Handle<T>> for GenArena<T> {
     type Output = T;
 
     #[inline]
@@ -98,7 +144,7 @@ impl<T, const N: usize> Index<Handle<T>> for GenArena<T, N> {
     }
 }
 
-impl<T, const N: usize> IndexMut<Handle<T>> for GenArena<T, N> {
+impl<T> IndexMut<Handle<T>> for GenArena<T> {
     fn index_mut(&mut self, handle: Handle<T>) -> &mut Self::Output {
         let idx = handle.idx;
         if self.gen[idx] != handle.gen {
@@ -158,12 +204,12 @@ impl<T> Eq for Handle<T> {}
 
 // ———————————————————————————————— Iterator ———————————————————————————————— //
 
-pub struct ArenaIterator<'a, T, const N: usize> {
-    arena: &'a GenArena<T, N>,
-    iterator: FreeListIterator<'a, N>,
+pub struct ArenaIterator<'a, T> {
+    arena: &'a GenArena<T>,
+    iterator: FreeListIterator<'a>,
 }
 
-impl<'a, T, const N: usize> Iterator for ArenaIterator<'a, T, N> {
+impl<'a, T> Iterator for ArenaIterator<'a, T> {
     type Item = Handle<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -176,9 +222,9 @@ impl<'a, T, const N: usize> Iterator for ArenaIterator<'a, T, N> {
     }
 }
 
-impl<'a, T, const N: usize> IntoIterator for &'a GenArena<T, N> {
+impl<'a, T> IntoIterator for &'a GenArena<T> {
     type Item = Handle<T>;
-    type IntoIter = ArenaIterator<'a, T, N>;
+    type IntoIter = ArenaIterator<'a, T>;
 
     fn into_iter(self) -> Self::IntoIter {
         ArenaIterator {
@@ -188,6 +234,38 @@ impl<'a, T, const N: usize> IntoIterator for &'a GenArena<T, N> {
     }
 }
 
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl<T> Snapshot for Handle<T> {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.idx.save(w)?;
+        self.gen.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Handle {
+            idx: usize::load(r)?,
+            gen: u64::load(r)?,
+            _type: PhantomData,
+        })
+    }
+}
+
+/// Arenas are restored slot by slot, so that existing handles remain valid.
+impl<T: Restore> Restore for GenArena<T> {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.store.snapshot(w)?;
+        self.free_list.snapshot(w)?;
+        self.gen.snapshot(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        self.store.restore(r)?;
+        self.free_list.restore(r)?;
+        self.gen.restore(r)
+    }
+}
+
 // ———————————————————————————————— Display ————————————————————————————————— //
 
 impl<T> core::fmt::Debug for Handle<T> {
@@ -201,3 +279,48 @@ impl<T> core::fmt::Display for Handle<T> {
         write!(f, "H({}, gen {})", self.idx, self.gen)
     }
 }
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+impl<T> GenArena<T> {
+    /// Creates an arena backed by leaked memory.
+    pub(crate) fn leak(len: usize, init: impl FnMut(usize) -> T) -> Self {
+        let mut memory = crate::pool::leak_memory(Self::memory_size(len));
+        Self::new(&mut memory, len, init).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_in_place() {
+        let mut arena = GenArena::leak(2, |idx| [idx; 4]);
+        assert_eq!(arena.size(), 2);
+
+        let first = arena.allocate_with(|item| item[0] = 42).unwrap();
+        assert_eq!(arena[first], [42, 0, 0, 0]);
+        let second = arena.allocate([3; 4]).unwrap();
+        assert_eq!(arena[second], [3; 4]);
+        assert!(arena.allocate_with(|_| ()).is_none());
+        assert_eq!(arena.has_capacity_for(1), Err(CapaError::OutOfMemory));
+
+        // Freed objects keep their content until re-allocated
+        arena.free(first);
+        assert!(arena.get(first).is_none());
+        let first = arena.allocate_with(|item| item[1] = 43).unwrap();
+        assert_eq!(arena[first], [42, 43, 0, 0]);
+        assert_eq!(arena.size(), 2);
+    }
+
+    #[test]
+    fn empty() {
+        let mut arena: GenArena<u64> = GenArena::empty();
+        assert_eq!(arena.size(), 0);
+        assert_eq!(arena.capacity(), 0);
+        assert!(arena.allocate(0).is_none());
+        assert_eq!(arena.into_iter().count(), 0);
+    }
+}
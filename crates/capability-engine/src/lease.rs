@@ -0,0 +1,153 @@
+//! Region Leases
+//!
+//! A region can be lent to another domain for a limited time, for instance for the duration of a
+//! call into an enclave. The lease is attached to the region capability when sending it, and the
+//! region is revoked automatically once the lease expires, as if the sender had revoked it.
+
+use crate::config::NB_LEASES;
+use crate::segment::{RegionCapa, RegionPool};
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{CapaError, Domain, Handle};
+
+/// The condition under which a lent region is revoked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseCondition {
+    /// Revoke on the next switch back to the sender.
+    OnReturn,
+    /// Revoke once the holder has been switched out this number of times.
+    Switches(usize),
+    /// Revoke once the timestamp counter reaches the deadline, see [CapaEngine::expire_leases].
+    ///
+    /// [CapaEngine::expire_leases]: crate::CapaEngine::expire_leases
+    Deadline(u64),
+}
+
+/// A lease on a region sent to another domain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub condition: LeaseCondition,
+    /// Guest physical address at which the region is mapped in the holder.
+    pub alias: usize,
+    /// Size of the mapping in the holder.
+    pub size: usize,
+}
+
+impl Lease {
+    /// Returns `InvalidValue` if the lease would expire right away.
+    pub(crate) fn check(&self) -> Result<(), CapaError> {
+        match self.condition {
+            LeaseCondition::Switches(0) => Err(CapaError::InvalidValue),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ActiveLease {
+    pub(crate) region: Handle<RegionCapa>,
+    pub(crate) lender: Handle<Domain>,
+    pub(crate) holder: Handle<Domain>,
+    pub(crate) lease: Lease,
+    pub(crate) expired: bool,
+}
+
+/// The leases currently in effect.
+pub(crate) struct LeaseTable {
+    leases: [Option<ActiveLease>; NB_LEASES],
+}
+
+impl LeaseTable {
+    pub(crate) const fn new() -> Self {
+        Self {
+            leases: [None; NB_LEASES],
+        }
+    }
+
+    pub(crate) fn has_capacity(&self) -> Result<(), CapaError> {
+        if self.leases.iter().any(|lease| lease.is_none()) {
+            Ok(())
+        } else {
+            log::debug!("Lease table is full");
+            Err(CapaError::OutOfMemory)
+        }
+    }
+
+    pub(crate) fn insert(&mut self, lease: ActiveLease) -> Result<(), CapaError> {
+        self.has_capacity()?;
+        let slot = self
+            .leases
+            .iter_mut()
+            .find(|lease| lease.is_none())
+            .unwrap();
+        *slot = Some(lease);
+        Ok(())
+    }
+
+    /// Marks the leases expiring when switching from `from` to `to`.
+    pub(crate) fn on_switch(&mut self, from: Handle<Domain>, to: Handle<Domain>) {
+        for lease in self.leases.iter_mut().flatten() {
+            match &mut lease.lease.condition {
+                LeaseCondition::OnReturn => lease.expired |= to == lease.lender,
+                LeaseCondition::Switches(count) => {
+                    if from == lease.holder {
+                        *count = count.saturating_sub(1);
+                        lease.expired |= *count == 0;
+                    }
+                }
+                LeaseCondition::Deadline(_) => (),
+            }
+        }
+    }
+
+    /// Marks the leases whose deadline is past `now`.
+    pub(crate) fn on_timestamp(&mut self, now: u64) {
+        for lease in self.leases.iter_mut().flatten() {
+            if let LeaseCondition::Deadline(deadline) = lease.lease.condition {
+                lease.expired |= now >= deadline;
+            }
+        }
+    }
+
+    /// The earliest deadline of the leases that have not expired yet.
+    pub(crate) fn next_deadline(&self) -> Option<u64> {
+        self.leases
+            .iter()
+            .flatten()
+            .filter(|lease| !lease.expired)
+            .filter_map(|lease| match lease.lease.condition {
+                LeaseCondition::Deadline(deadline) => Some(deadline),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Removes and returns an expired lease, if any.
+    ///
+    /// Leases whose region no longer exists, for instance because it was revoked by the sender,
+    /// are dropped along the way.
+    pub(crate) fn take_expired(&mut self, regions: &RegionPool) -> Option<ActiveLease> {
+        for slot in self.leases.iter_mut() {
+            let Some(lease) = slot else {
+                continue;
+            };
+            if regions.get(lease.region).is_none() {
+                *slot = None;
+            } else if lease.expired {
+                return slot.take();
+            }
+        }
+        None
+    }
+}
+
+impl Snapshot for LeaseTable {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.leases.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(LeaseTable {
+            leases: Snapshot::load(r)?,
+        })
+    }
+}
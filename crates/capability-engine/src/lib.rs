@@ -1,17 +1,27 @@
 #![cfg_attr(not(test), no_std)]
 
+mod abi;
 mod capa;
+mod channel;
 pub mod context;
 mod cores;
 mod debug;
+mod delta;
 mod domain;
+pub mod flow;
 mod free_list;
 mod gen_arena;
+mod lease;
 pub mod permission;
+pub mod pool;
+mod quota;
 mod region;
 mod remapper;
+mod revocation;
 mod segment;
 pub mod serializer;
+pub mod snapshot;
+mod transaction;
 mod update;
 pub mod utils;
 
@@ -20,34 +30,54 @@ use core::ops::Index;
 use attestation::hashing::HashEnclave;
 use attestation::signature::EnclaveReport;
 use capa::Capa;
-pub use capa::{capa_type, CapaInfo};
+pub use capa::{capa_type, CapaInfo, MgmtRights};
+pub use channel::Message;
 use cores::{Core, CoreList};
+pub use cores::{CoreSet, CoreSetIterator};
+pub use delta::{DeltaIterator, PermissionDelta};
 use domain::{insert_capa, remove_capa, DomainHandle, DomainPool};
-pub use domain::{Domain, LocalCapa, NextCapaToken};
+pub use domain::{Domain, FastSwitch, LocalCapa, NextCapaToken};
+use flow::Transfer;
 pub use gen_arena::{GenArena, Handle};
+use lease::{ActiveLease, LeaseTable};
+pub use lease::{Lease, LeaseCondition};
+pub use quota::{Quota, Usage};
 pub use region::{
     AccessRights, MemOps, MemoryPermission, Region, RegionIterator, RegionTracker, MEMOPS_ALL,
     MEMOPS_EXTRAS,
 };
 use region::{PermissionIterator, TrackerPool, EMPTY_REGION};
 pub use remapper::Remapper;
+pub use revocation::RevokedRegion;
 pub use segment::EffectiveRegionIterator;
 use segment::{RegionCapa, RegionHash, RegionPool};
+use transaction::{Transaction, Undo};
 use update::UpdateBuffer;
 pub use update::{Buffer, Update};
 
-use crate::permission::{core_bits, trap_bits};
+use crate::permission::trap_bits;
+use crate::pool::PoolMemory;
 use crate::segment::EMPTY_REGION_CAPA;
 
 /// Configuration for the static Capa Engine size.
+///
+/// The domains, capabilities, regions and tracker sizes are only defaults, the actual pool sizes
+/// are chosen at boot time through an [EngineConfig].
 pub mod config {
     pub const NB_DOMAINS: usize = 32;
     pub const NB_CAPAS_PER_DOMAIN: usize = 128;
     pub const NB_REGIONS: usize = 1024;
     pub const NB_TRACKER: usize = 1024;
     pub const NB_UPDATES: usize = 128;
-    pub const NB_CORES: usize = 32; // NOTE: Can't be greater than 64 as we use 64 bits bitmaps.
+    pub const NB_CORES: usize = 128;
     pub const NB_REMAP_REGIONS: usize = 128;
+    pub const NB_LEASES: usize = 64;
+    pub const NB_PENDING_REVOCATIONS: usize = 16;
+    pub const NB_PENDING_MESSAGES: usize = 16;
+    pub const NB_MESSAGE_WORDS: usize = 3;
+    pub const NB_TRANSACTION_OPS: usize = 128;
+    pub const NB_FLOW_RULES: usize = 16;
+    pub const NB_PERMISSION_DELTAS: usize = 32;
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -75,6 +105,41 @@ pub enum CapaError {
     InvalidMemOps,
     AlreadyAliased,
     PlatformError,
+    QuotaExceeded,
+    ChannelFull,
+    DeniedByPolicy,
+}
+
+/// The size of the engine pools, chosen at boot time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub nb_domains: usize,
+    pub nb_capas_per_domain: usize,
+    pub nb_regions: usize,
+    pub nb_tracker: usize,
+}
+
+impl EngineConfig {
+    pub const DEFAULT: Self = Self {
+        nb_domains: config::NB_DOMAINS,
+        nb_capas_per_domain: config::NB_CAPAS_PER_DOMAIN,
+        nb_regions: config::NB_REGIONS,
+        nb_tracker: config::NB_TRACKER,
+    };
+
+    /// Number of bytes of pool memory needed to initialize an engine with this configuration.
+    pub const fn memory_size(&self) -> usize {
+        DomainPool::memory_size(self.nb_domains)
+            + self.nb_domains * Domain::capas_memory_size(self.nb_capas_per_domain)
+            + RegionPool::memory_size(self.nb_regions)
+            + TrackerPool::memory_size(self.nb_tracker)
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 pub struct CapaEngine {
@@ -83,29 +148,80 @@ pub struct CapaEngine {
     regions: RegionPool,
     tracker: TrackerPool,
     updates: UpdateBuffer,
+    leases: LeaseTable,
+    transaction: Transaction,
+    policy: flow::Policy,
     id_counter: usize,
+    /// The size of the pools, once initialized.
+    config: Option<EngineConfig>,
 }
 
 impl CapaEngine {
+    /// Creates an engine with empty pools, [CapaEngine::init] must be called before use.
     pub const fn new() -> Self {
-        const EMPTY_DOMAIN: Domain = Domain::new(0, false);
         const EMPTY_CORE: Core = Core::new();
 
         Self {
             cores: [EMPTY_CORE; config::NB_CORES],
-            domains: GenArena::new([EMPTY_DOMAIN; config::NB_DOMAINS]),
-            regions: GenArena::new([EMPTY_REGION_CAPA; config::NB_REGIONS]),
-            tracker: GenArena::new([EMPTY_REGION; config::NB_TRACKER]),
+            domains: GenArena::empty(),
+            regions: GenArena::empty(),
+            tracker: GenArena::empty(),
             updates: UpdateBuffer::new(),
+            leases: LeaseTable::new(),
+            transaction: Transaction::new(),
+            policy: flow::Policy::empty(),
             id_counter: 0,
+            config: None,
         }
     }
 
+    /// Allocates the engine pools from `memory`.
+    ///
+    /// Returns `OutOfMemory` if the memory is too small for the configuration, see
+    /// [EngineConfig::memory_size].
+    pub fn init(&mut self, config: EngineConfig, memory: &mut PoolMemory) -> Result<(), CapaError> {
+        log::info!(
+            "Engine pools: {} domains, {} capas per domain, {} regions, {} tracker regions",
+            config.nb_domains,
+            config.nb_capas_per_domain,
+            config.nb_regions,
+            config.nb_tracker
+        );
+        let mut domains = memory.carve(config.nb_domains, |_| Domain::new(0, false))?;
+        for domain in domains.iter_mut() {
+            domain.init_capas(memory, config.nb_capas_per_domain)?;
+        }
+        self.domains = GenArena::from_store(domains, memory)?;
+        self.regions = GenArena::new(memory, config.nb_regions, |_| EMPTY_REGION_CAPA)?;
+        self.tracker = GenArena::new(memory, config.nb_tracker, |_| EMPTY_REGION)?;
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// Loads the information-flow policy enforced on capability transfers, see [flow].
+    ///
+    /// The policy must be loaded at boot, before any domain is created.
+    pub fn load_flow_policy(&mut self, policy: flow::Policy) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
+        if self.id_counter != 0 {
+            log::error!("The flow policy must be loaded before creating domains");
+            return Err(CapaError::InvalidOperation);
+        }
+        log::info!("Flow policy: {} rules", policy.rules().len());
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub fn flow_policy(&self) -> &flow::Policy {
+        &self.policy
+    }
+
     pub fn create_manager_domain(&mut self, permissions: u64) -> Result<DomainHandle, CapaError> {
         //log::trace!("Create new manager domain");
+        self.transaction.forbid()?;
 
         let id = self.domain_id();
-        match self.domains.allocate(Domain::new(id, false)) {
+        match self.domains.allocate_with(|domain| domain.reset(id, false)) {
             Some(handle) => {
                 domain::set_permission(
                     handle,
@@ -113,12 +229,7 @@ impl CapaEngine {
                     permission::PermissionIndex::MonitorInterface,
                     permissions,
                 )?;
-                domain::set_permission(
-                    handle,
-                    &mut self.domains,
-                    permission::PermissionIndex::AllowedCores,
-                    core_bits::ALL,
-                )?;
+                domain::set_core_map(handle, &mut self.domains, CoreSet::ALL)?;
                 domain::set_permission(
                     handle,
                     &mut self.domains,
@@ -146,6 +257,7 @@ impl CapaEngine {
         core_id: usize,
     ) -> Result<(), CapaError> {
         log::trace!("Start CPU");
+        self.transaction.forbid()?;
 
         if core_id > self.cores.len() {
             log::warn!(
@@ -171,6 +283,7 @@ impl CapaEngine {
     }
 
     pub fn revoke_domain(&mut self, domain: Handle<Domain>) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
         domain::revoke(
             domain,
             &mut self.regions,
@@ -186,6 +299,7 @@ impl CapaEngine {
         access: AccessRights,
     ) -> Result<LocalCapa, CapaError> {
         log::trace!("Create new root region");
+        self.transaction.forbid()?;
 
         self.domains.get(domain).ok_or(CapaError::InvalidCapa)?;
         segment::create_root_region(
@@ -213,6 +327,15 @@ impl CapaEngine {
         )?;
 
         let region = self.domains[domain].get(region)?.as_region()?;
+        let labels = flow::domain_labels(&self.domains[domain]);
+        self.check_flow(
+            flow::transfer::ALIAS,
+            domain,
+            labels,
+            Some((region, access.ops)),
+        )?;
+        self.transaction.reserve()?;
+        quota::mark(domain, &mut self.domains);
         let handle = segment::alias(
             region,
             &mut self.regions,
@@ -221,7 +344,9 @@ impl CapaEngine {
             &mut self.updates,
             access,
         )?;
-        Ok(handle)
+        let capa = self.enforce_region_quota(domain, handle)?;
+        self.transaction.record(Undo::CreateRegion { domain, capa });
+        Ok(capa)
     }
 
     pub fn carve_region(
@@ -239,6 +364,15 @@ impl CapaEngine {
         )?;
 
         let region = self.domains[domain].get(region)?.as_region()?;
+        let labels = flow::domain_labels(&self.domains[domain]);
+        self.check_flow(
+            flow::transfer::CARVE,
+            domain,
+            labels,
+            Some((region, access.ops)),
+        )?;
+        self.transaction.reserve()?;
+        quota::mark(domain, &mut self.domains);
         let handle = segment::carve(
             region,
             &mut self.regions,
@@ -247,7 +381,61 @@ impl CapaEngine {
             &mut self.updates,
             access,
         )?;
-        Ok(handle)
+        let capa = self.enforce_region_quota(domain, handle)?;
+        self.transaction.record(Undo::CreateRegion { domain, capa });
+        Ok(capa)
+    }
+
+    /// Removes a newly created region if it made the domain grow above its quota.
+    fn enforce_region_quota(
+        &mut self,
+        domain: Handle<Domain>,
+        capa: LocalCapa,
+    ) -> Result<LocalCapa, CapaError> {
+        if let Err(err) = quota::check_growth(domain, &self.domains) {
+            self.remove_region(domain, capa)?;
+            return Err(err);
+        }
+        Ok(capa)
+    }
+
+    /// Checks a transfer from `domain` to a domain labeled `to` against the flow policy.
+    ///
+    /// The region is checked with the labels it would carry once transferred, including the ones
+    /// of `domain`.
+    fn check_flow(
+        &self,
+        kind: u64,
+        domain: Handle<Domain>,
+        to: u64,
+        region: Option<(Handle<RegionCapa>, MemOps)>,
+    ) -> Result<(), CapaError> {
+        let (region, ops) = match region {
+            Some((region, ops)) => (
+                self.regions[region].labels | self.domains[domain].labels(),
+                ops,
+            ),
+            None => (0, MemOps::NONE),
+        };
+        self.policy.check(Transfer {
+            kind,
+            from: flow::domain_labels(&self.domains[domain]),
+            to,
+            region,
+            ops,
+        })
+    }
+
+    /// Removes a region created by aliasing or carving, giving the memory back to the parent.
+    fn remove_region(&mut self, domain: Handle<Domain>, capa: LocalCapa) -> Result<(), CapaError> {
+        let region = remove_capa(domain, capa, &mut self.domains)?.as_region()?;
+        segment::remove(
+            region,
+            &mut self.regions,
+            &mut self.domains,
+            &mut self.tracker,
+            &mut self.updates,
+        )
     }
 
     pub fn create_revoke_capa(
@@ -257,8 +445,11 @@ impl CapaEngine {
     ) -> Result<LocalCapa, CapaError> {
         let region = self.domains[domain].get(region)?.as_region()?;
         domain::has_capacity_for(domain, 1, &mut self.regions, &mut self.domains)?;
+        self.transaction.reserve()?;
         let revoke_capa = Capa::RegionRevoke(region);
-        insert_capa(domain, revoke_capa, &mut self.regions, &mut self.domains)
+        let capa = insert_capa(domain, revoke_capa, &mut self.regions, &mut self.domains)?;
+        self.transaction.record(Undo::InsertCapa { domain, capa });
+        Ok(capa)
     }
 
     pub fn duplicate(
@@ -266,14 +457,31 @@ impl CapaEngine {
         domain: Handle<Domain>,
         capa: LocalCapa,
     ) -> Result<LocalCapa, CapaError> {
-        // Enforce permissions
+        self.duplicate_with_rights(domain, capa, MgmtRights::all())
+    }
+
+    /// Duplicates a capability, a duplicated management capability only keeps the `rights` that
+    /// are also held by the original.
+    ///
+    /// This lets a manager delegate some operations on a domain, such as running it, without
+    /// delegating the others.
+    pub fn duplicate_with_rights(
+        &mut self,
+        domain: Handle<Domain>,
+        capa: LocalCapa,
+        rights: MgmtRights,
+    ) -> Result<LocalCapa, CapaError> {
         domain::has_permission(
             domain,
             &self.domains,
             permission::PermissionIndex::MonitorInterface,
             permission::monitor_inter_perm::DUPLICATE,
         )?;
-        domain::duplicate_capa(domain, capa, &mut self.regions, &mut self.domains)
+        self.transaction.reserve()?;
+        let capa =
+            domain::duplicate_capa(domain, capa, rights, &mut self.regions, &mut self.domains)?;
+        self.transaction.record(Undo::InsertCapa { domain, capa });
+        Ok(capa)
     }
 
     pub fn send(
@@ -282,7 +490,18 @@ impl CapaEngine {
         capa: LocalCapa,
         to: LocalCapa,
     ) -> Result<LocalCapa, CapaError> {
-        self.send_with_flags(domain, capa, to, None, None)
+        self.send_with_flags(domain, capa, to, None, None, None)
+    }
+
+    /// Sends a region to another domain, the region is revoked once the lease expires.
+    pub fn send_with_lease(
+        &mut self,
+        domain: Handle<Domain>,
+        capa: LocalCapa,
+        to: LocalCapa,
+        lease: Lease,
+    ) -> Result<LocalCapa, CapaError> {
+        self.send_with_flags(domain, capa, to, None, None, Some(lease))
     }
 
     pub fn send_with_flags(
@@ -292,6 +511,7 @@ impl CapaEngine {
         to: LocalCapa,
         flags: Option<MemOps>,
         hash: Option<RegionHash>,
+        lease: Option<Lease>,
     ) -> Result<LocalCapa, CapaError> {
         // Enforce permissions
         domain::has_permission(
@@ -303,17 +523,59 @@ impl CapaEngine {
 
         //TODO(all) as some code might fail below, we should not remove the capa
         // first.
-        let to = self.domains[domain].get(to)?.as_channel()?;
+        let to = match self.domains[domain].get(to)? {
+            Capa::Management(_, rights) if !rights.contains(MgmtRights::SEND) => {
+                return Err(CapaError::InsufficientPermissions);
+            }
+            to => to.as_channel()?,
+        };
         domain::has_capacity_for(to, 1, &mut self.regions, &mut self.domains)?;
+        if let Capa::Management(managed, rights) = self.domains[domain].get(capa)? {
+            if rights.contains(MgmtRights::MANAGE) {
+                domain::check_send_management(managed, &self.domains, to)?;
+            }
+        }
+        let labels = flow::domain_labels(&self.domains[to]);
+        match self.domains[domain].get(capa)? {
+            Capa::Region(region) => {
+                let ops = self.regions[region].access.ops;
+                self.check_flow(
+                    flow::transfer::SEND_REGION,
+                    domain,
+                    labels,
+                    Some((region, ops)),
+                )?
+            }
+            _ => self.check_flow(flow::transfer::SEND_CAPA, domain, labels, None)?,
+        }
+        self.transaction.reserve()?;
+        if let Some(lease) = lease {
+            // Leases can't be undone
+            self.transaction.forbid()?;
+            // Only regions can be lent, and root regions can't be revoked
+            let region = self.domains[domain].get(capa)?.as_region()?;
+            if self.regions[region].is_root() {
+                return Err(CapaError::InvalidOperation);
+            }
+            lease.check()?;
+            self.leases.has_capacity()?;
+        }
+        let local = capa;
         let capa = remove_capa(domain, capa, &mut self.domains)?;
+        let mut previous_region = None;
         match capa {
             // No side effect for those capas
             Capa::None => (),
             Capa::Channel(_) => (),
             Capa::Switch { .. } => (),
             Capa::RegionRevoke(_) => (),
+            Capa::Management(_, rights) if !rights.contains(MgmtRights::MANAGE) => (),
 
             Capa::Region(region) => {
+                let previous = &self.regions[region];
+                previous_region = Some((previous.access, previous.hash, previous.labels));
+                quota::mark(domain, &mut self.domains);
+                quota::mark(to, &mut self.domains);
                 segment::send(
                     region,
                     &mut self.regions,
@@ -322,6 +584,22 @@ impl CapaEngine {
                     &mut self.updates,
                     to,
                 )?;
+                let within_quota = quota::check_growth(to, &self.domains)
+                    .and_then(|_| quota::check_growth(domain, &self.domains));
+                if let Err(err) = within_quota {
+                    // Give the region back to the sender
+                    segment::send(
+                        region,
+                        &mut self.regions,
+                        &mut self.domains,
+                        &mut self.tracker,
+                        &mut self.updates,
+                        domain,
+                    )?;
+                    self.restore_capa(domain, local, capa)?;
+                    return Err(err);
+                }
+                self.regions[region].labels |= self.domains[domain].labels();
 
                 // Set or unset the hash when sending the region
                 if let Some(flags) = flags {
@@ -339,15 +617,52 @@ impl CapaEngine {
                         None => self.regions[region].reset_hash(),
                     }
                 }
+
+                if let Some(lease) = lease {
+                    // Can't fail as we checked for capacity already
+                    self.leases.insert(ActiveLease {
+                        region,
+                        lender: domain,
+                        holder: to,
+                        lease,
+                        expired: false,
+                    })?;
+                }
             }
-            Capa::Management(domain) => {
-                // TODO: check that no cycles are created
-                domain::send_management(domain, &mut self.domains, to)?;
+            Capa::Management(managed, _) => {
+                quota::mark(to, &mut self.domains);
+                domain::send_management(managed, &mut self.domains, to);
+                if let Err(err) = quota::check_growth(to, &self.domains) {
+                    domain::send_management(managed, &mut self.domains, domain);
+                    self.restore_capa(domain, local, capa)?;
+                    return Err(err);
+                }
             }
         }
 
         // Move the capa to the new domain, can't fail as we checked for capacity already.
-        insert_capa(to, capa, &mut self.regions, &mut self.domains)
+        let received = insert_capa(to, capa, &mut self.regions, &mut self.domains)?;
+        self.transaction.record(Undo::Send {
+            from: domain,
+            local,
+            to,
+            received,
+            region: previous_region,
+        });
+        Ok(received)
+    }
+
+    /// Puts back a capability that was just removed from a domain, at the same index.
+    fn restore_capa(
+        &mut self,
+        domain: Handle<Domain>,
+        local: LocalCapa,
+        capa: Capa,
+    ) -> Result<(), CapaError> {
+        // The capability table allocates the last freed slot first.
+        let restored = insert_capa(domain, capa, &mut self.regions, &mut self.domains)?;
+        debug_assert_eq!(restored.as_usize(), local.as_usize());
+        Ok(())
     }
 
     // Mostly for debug.
@@ -379,11 +694,59 @@ impl CapaEngine {
         value: u64,
     ) -> Result<(), CapaError> {
         domain::has_permission(manager, &self.domains, bitmap, value)?;
-        let domain = self.domains[manager].get(capa)?.as_management()?;
+        let domain = self.domains[manager]
+            .get(capa)?
+            .as_management_with(MgmtRights::CONFIGURE)?;
+        self.transaction.reserve()?;
+        let previous = domain::get_permission(domain, &self.domains, bitmap);
         domain::set_permission(domain, &mut self.domains, bitmap, value)?;
+        self.transaction.record(Undo::Permission {
+            domain,
+            index: bitmap,
+            value: previous,
+        });
         Ok(())
     }
 
+    /// Adds information-flow labels to a child domain, which must not be sealed yet.
+    ///
+    /// Labels can't be removed, and the labels set by the engine (see [flow::labels]) can't be
+    /// set by managers.
+    pub fn set_child_labels(
+        &mut self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+        labels: u64,
+    ) -> Result<(), CapaError> {
+        if labels & flow::labels::IMPLICIT != 0 {
+            return Err(CapaError::InvalidValue);
+        }
+        let domain = self.domains[manager]
+            .get(capa)?
+            .as_management_with(MgmtRights::CONFIGURE)?;
+        if self.domains[domain].is_sealed() {
+            return Err(CapaError::AlreadySealed);
+        }
+        self.transaction.reserve()?;
+        let previous = self.domains[domain].labels();
+        self.domains[domain].set_labels(previous | labels);
+        self.transaction.record(Undo::Labels {
+            domain,
+            labels: previous,
+        });
+        Ok(())
+    }
+
+    /// Returns the labels of a child domain, including the ones set by the engine.
+    pub fn get_child_labels(
+        &self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+    ) -> Result<u64, CapaError> {
+        let domain = self.domains[manager].get(capa)?.as_management()?;
+        Ok(flow::domain_labels(&self.domains[domain]))
+    }
+
     pub fn get_child_permission(
         &mut self,
         manager: Handle<Domain>,
@@ -394,6 +757,83 @@ impl CapaEngine {
         Ok(domain::get_permission(domain, &mut self.domains, bitmap))
     }
 
+    /// Sets the cores a child domain is allowed to run on.
+    ///
+    /// The cores must be a subset of the cores the manager is allowed to run on.
+    pub fn set_child_core_map(
+        &mut self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+        cores: CoreSet,
+    ) -> Result<(), CapaError> {
+        if !cores.is_subset(&self.domains[manager].core_map()) {
+            return Err(CapaError::InsufficientPermissions);
+        }
+        let domain = self.domains[manager]
+            .get(capa)?
+            .as_management_with(MgmtRights::CONFIGURE)?;
+        self.transaction.reserve()?;
+        let previous = self.domains[domain].core_map();
+        domain::set_core_map(domain, &mut self.domains, cores)?;
+        self.transaction.record(Undo::CoreMap {
+            domain,
+            cores: previous,
+        });
+        Ok(())
+    }
+
+    /// Returns the cores a child domain is allowed to run on.
+    pub fn get_child_core_map(
+        &self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+    ) -> Result<CoreSet, CapaError> {
+        let domain = self.domains[manager].get(capa)?.as_management()?;
+        Ok(self.domains[domain].core_map())
+    }
+
+    /// Returns the cores a domain is allowed to run on.
+    pub fn get_domain_core_map(&self, domain: Handle<Domain>) -> CoreSet {
+        self.domains[domain].core_map()
+    }
+
+    /// Sets the resources a child domain and the domains it manages can use.
+    ///
+    /// The quota can not be above the manager's own quota, and is inherited by the domains
+    /// created by the child.
+    pub fn set_child_quota(
+        &mut self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+        quota: Quota,
+    ) -> Result<(), CapaError> {
+        if !quota.is_within(&self.domains[manager].quota()) {
+            return Err(CapaError::QuotaExceeded);
+        }
+        let domain = self.domains[manager]
+            .get(capa)?
+            .as_management_with(MgmtRights::CONFIGURE)?;
+        self.transaction.reserve()?;
+        let previous = self.domains[domain].quota();
+        domain::set_quota(domain, &mut self.domains, quota)?;
+        self.transaction.record(Undo::Quota {
+            domain,
+            quota: previous,
+        });
+        Ok(())
+    }
+
+    /// Returns the quota and resource usage of a child domain.
+    pub fn get_child_quota(
+        &self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+    ) -> Result<(Quota, Usage), CapaError> {
+        let domain = self.domains[manager].get(capa)?.as_management()?;
+        let domain = &self.domains[domain];
+        Ok((domain.quota(), domain.usage()))
+    }
+
     // Should only be used for the root domain.
     pub fn set_domain_permission(
         &mut self,
@@ -401,6 +841,7 @@ impl CapaEngine {
         bitmap: permission::PermissionIndex,
         value: u64,
     ) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
         domain::set_permission(domain, &mut self.domains, bitmap, value)?;
         Ok(())
     }
@@ -420,8 +861,12 @@ impl CapaEngine {
         core: usize,
         capa: LocalCapa,
     ) -> Result<LocalCapa, CapaError> {
-        let capa = self.domains[domain].get(capa)?.as_management()?;
+        let capa = self.domains[domain]
+            .get(capa)?
+            .as_management_with(MgmtRights::CONFIGURE)?;
+        self.transaction.reserve()?;
         self.domains[capa].seal()?;
+        self.transaction.record(Undo::Seal { domain: capa });
         //TODO(aghosn)(Charly) we should create a switch capa for all cores?
         /*let mut cores = domain::get_permission(
             capa,
@@ -471,36 +916,59 @@ impl CapaEngine {
         core: usize,
         capa: LocalCapa,
     ) -> Result<LocalCapa, CapaError> {
-        let capa = self.domains[domain].get(capa)?.as_management()?;
-        let cores = domain::get_permission(
-            capa,
-            &self.domains,
-            permission::PermissionIndex::AllowedCores,
-        );
+        let capa = self.domains[domain]
+            .get(capa)?
+            .as_management_with(MgmtRights::SWITCH)?;
         // HACK: remove check on this operation being allowed.
         //       related to change in monitor::do_init_child_context
         //       where we're making & sealing a domain for another core
         //       that does not yet have a context
-        // if (1 << core) & cores == 0 {
+        // if !self.domains[capa].core_map().contains(core) {
         //     return Err(CapaError::InvalidCore);
         // }
+        self.transaction.reserve()?;
         let capa = insert_capa(
             domain,
             Capa::Switch { to: capa, core },
             &mut self.regions,
             &mut self.domains,
         )?;
+        self.transaction.record(Undo::InsertCapa { domain, capa });
         Ok(capa)
     }
 
     pub fn revoke(&mut self, domain: Handle<Domain>, capa: LocalCapa) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
         match self.domains[domain].get(capa)? {
             // Root regions can't be revoked.
             Capa::Region(region) if self.regions[region].is_root() => {
                 Err(CapaError::InvalidOperation)
             }
+            // Without the revoke right only the capability itself is dropped.
+            Capa::Management(_, rights) if !rights.contains(MgmtRights::REVOKE) => {
+                remove_capa(domain, capa, &mut self.domains)?;
+                Ok(())
+            }
+            // Attenuated capabilities revoke the domain on behalf of its manager.
+            Capa::Management(dom, rights) if !rights.contains(MgmtRights::MANAGE) => {
+                let manager = self.domains[dom]
+                    .get_manager()
+                    .ok_or(CapaError::InvalidOperation)?;
+                let mgmt_capa = self.domains[manager]
+                    .find_capa(|capa| match capa {
+                        Capa::Management(h, r) => *h == dom && r.contains(MgmtRights::MANAGE),
+                        _ => false,
+                    })
+                    .ok_or(CapaError::InvalidCapa)?;
+                self.revoke(manager, mgmt_capa)
+            }
+            // Pairs are dissolved first, the peer only runs on the core along with its owner.
+            Capa::Management(dom, _) if self.domains[dom].fast_switch().is_some() => {
+                domain::dissolve_fast_switch(dom, &mut self.domains, &mut self.updates)?;
+                self.revoke(domain, capa)
+            }
             // If the domain is running, put an update rather than revoke.
-            Capa::Management(dom) if self.domains[dom].cores() != 0 => {
+            Capa::Management(dom, _) if !self.domains[dom].cores().is_empty() => {
                 self.updates.push(Update::RevokeDomain {
                     manager: domain,
                     mgmt_capa: capa,
@@ -519,13 +987,109 @@ impl CapaEngine {
         }
     }
 
+    /// Returns the oldest region revoked from under the domain it was not yet told about.
+    pub fn next_revocation(&mut self, domain: Handle<Domain>) -> Option<RevokedRegion> {
+        self.domains[domain].next_revocation()
+    }
+
+    /// Creates a channel to the domain managed through `capa`, or to the caller if `capa` is
+    /// `None`, so that it can be handed over to the domains that should be able to message it.
+    pub fn create_channel(
+        &mut self,
+        domain: Handle<Domain>,
+        capa: Option<LocalCapa>,
+    ) -> Result<LocalCapa, CapaError> {
+        let to = match capa {
+            None => domain,
+            Some(capa) => match self.domains[domain].get(capa)? {
+                Capa::Management(to, rights) if rights.contains(MgmtRights::SEND) => to,
+                Capa::Management(..) => return Err(CapaError::InsufficientPermissions),
+                _ => return Err(CapaError::WrongCapabilityType),
+            },
+        };
+        domain::has_capacity_for(domain, 1, &mut self.regions, &mut self.domains)?;
+        self.transaction.reserve()?;
+        let capa = insert_capa(
+            domain,
+            Capa::Channel(to),
+            &mut self.regions,
+            &mut self.domains,
+        )?;
+        self.transaction.record(Undo::InsertCapa { domain, capa });
+        Ok(capa)
+    }
+
+    /// Sends a message over a channel, optionally transferring a page-aligned region along.
+    ///
+    /// The message is queued by the receiver, which is notified if it allows for the
+    /// [trap_bits::CHANNEL_MESSAGE] trap.
+    pub fn channel_send(
+        &mut self,
+        domain: Handle<Domain>,
+        channel: LocalCapa,
+        words: [usize; config::NB_MESSAGE_WORDS],
+        region: Option<LocalCapa>,
+    ) -> Result<(), CapaError> {
+        domain::has_permission(
+            domain,
+            &self.domains,
+            permission::PermissionIndex::MonitorInterface,
+            permission::monitor_inter_perm::SEND,
+        )?;
+        let to = match self.domains[domain].get(channel)? {
+            Capa::Channel(to) => to,
+            _ => return Err(CapaError::WrongCapabilityType),
+        };
+        // Delivered messages can't be taken back
+        self.transaction.forbid()?;
+        self.domains
+            .get(to)
+            .ok_or(CapaError::CapabilityDoesNotExist)?
+            .can_receive_message()?;
+        let region = match region {
+            Some(capa) => {
+                let region = self.domains[domain].get(capa)?.as_region()?;
+                if !crate::channel::is_page_aligned(&self.regions[region].access) {
+                    return Err(CapaError::InvalidRegion);
+                }
+                Some(self.send(domain, capa, channel)?)
+            }
+            None => None,
+        };
+
+        // Can't fail as we checked for capacity already
+        let from = self.domains[domain].id();
+        self.domains[to].receive_message(Message {
+            from,
+            words,
+            region,
+        })?;
+        self.updates.push(Update::ChannelMessage {
+            domain: to,
+            pending: self.domains[to].pending_messages(),
+        })
+    }
+
+    /// Returns the oldest message received by the domain over a channel it did not poll yet.
+    pub fn next_message(&mut self, domain: Handle<Domain>) -> Option<Message> {
+        self.domains[domain].next_message()
+    }
+
+    /// The number of messages received by the domain it did not poll yet.
+    pub fn pending_messages(&self, domain: Handle<Domain>) -> usize {
+        self.domains[domain].pending_messages()
+    }
+
     /// Creates a new switch handle for the current domain.
     pub fn create_switch(
         &mut self,
         domain: Handle<Domain>,
         core: usize,
     ) -> Result<LocalCapa, CapaError> {
-        domain::create_switch(domain, core, &mut self.regions, &mut self.domains)
+        self.transaction.reserve()?;
+        let capa = domain::create_switch(domain, core, &mut self.regions, &mut self.domains)?;
+        self.transaction.record(Undo::InsertCapa { domain, capa });
+        Ok(capa)
     }
 
     /// Returns the new domain if the switch succeeds
@@ -536,15 +1100,30 @@ impl CapaEngine {
         delta: usize,
         capa: LocalCapa,
     ) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
         let mut quantum = delta;
         // Check the domain can be scheduled on the core.
         let (next_dom, _) = self.domains[domain].get(capa)?.as_switch()?;
-        if (1 << core) & self.domains[next_dom].core_map() == 0 {
+        if !self.domains[next_dom].core_map().contains(core) {
             log::error!("Attempt to schedule domain on unallowed core {}", core);
             log::error!("allowed: {:b}", self.domains[next_dom].core_map());
-            log::error!("request: {:b}", 1 << core);
             return Err(CapaError::InvalidCore);
         }
+        // Within a pair the domains switch without the monitor, and the peer only leaves the
+        // core through its owner.
+        let into_pair = self.domains[next_dom]
+            .fast_switch_on(core)
+            .is_some_and(|pair| !pair.is_owner || pair.peer == domain);
+        let from_peer = self.domains[domain]
+            .fast_switch_on(core)
+            .is_some_and(|pair| !pair.is_owner);
+        if into_pair || from_peer {
+            log::error!(
+                "Attempt to switch through a fast switch pair on core {}",
+                core
+            );
+            return Err(CapaError::InvalidSwitch);
+        }
         let return_capa = insert_capa(
             next_dom,
             Capa::Switch { to: domain, core },
@@ -552,9 +1131,8 @@ impl CapaEngine {
             &mut self.domains,
         )?;
         remove_capa(domain, capa, &mut self.domains).unwrap(); // We already checked the capa
-        self.domains[next_dom].execute_on_core(core);
-        self.domains[domain].remove_from_core(core);
-        self.cores[core].set_domain(next_dom);
+        self.enter_core(next_dom, core);
+        self.leave_core(domain, core);
 
         // Only allow delta quantums from manager to child.
         if let Some(m) = self.domains[domain].get_manager() {
@@ -571,6 +1149,78 @@ impl CapaEngine {
                 delta: quantum,
             })
             .unwrap();
+
+        // Remind the domain of the messages it did not poll yet
+        let pending = self.domains[next_dom].pending_messages();
+        if pending > 0 && self.domains[next_dom].traps() & trap_bits::CHANNEL_MESSAGE != 0 {
+            self.updates.push(Update::Trap {
+                manager: next_dom,
+                trap: trap_bits::CHANNEL_MESSAGE,
+                info: pending as u64,
+                core,
+            })?;
+        }
+
+        self.leases.on_switch(domain, next_dom);
+        self.revoke_expired_leases(core)
+    }
+
+    /// Revokes the regions whose lease deadline is past `now`, the timestamp counter value.
+    ///
+    /// Deadlines are only enforced when this is called, see [CapaEngine::next_lease_deadline] to
+    /// know when to call it next.
+    pub fn expire_leases(&mut self, now: u64, core: usize) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
+        self.leases.on_timestamp(now);
+        self.revoke_expired_leases(core)
+    }
+
+    /// The earliest deadline of the leases in effect, if any.
+    pub fn next_lease_deadline(&self) -> Option<u64> {
+        self.leases.next_deadline()
+    }
+
+    fn revoke_expired_leases(&mut self, core: usize) -> Result<(), CapaError> {
+        while let Some(expired) = self.leases.take_expired(&self.regions) {
+            let ActiveLease {
+                region,
+                lender,
+                holder,
+                lease,
+                ..
+            } = expired;
+            log::trace!("Lease on region {} expired", region);
+            let start = self.regions[region].access.start;
+
+            // The mapping must be removed before the permissions are updated
+            self.updates.push(Update::LeaseExpired {
+                holder,
+                alias: lease.alias,
+                size: lease.size,
+            })?;
+            segment::revoke(
+                region,
+                lender,
+                &mut self.regions,
+                &mut self.domains,
+                &mut self.tracker,
+                &mut self.updates,
+            )?;
+
+            // Notify the holder, if it is still alive and asked for it
+            let notify = self
+                .domains
+                .get(holder)
+                .is_some_and(|d| d.traps() & trap_bits::LEASE_EXPIRED != 0);
+            if notify {
+                self.updates.push(Update::Trap {
+                    manager: holder,
+                    trap: trap_bits::LEASE_EXPIRED,
+                    info: start as u64,
+                    core,
+                })?;
+            }
+        }
         Ok(())
     }
 
@@ -580,12 +1230,88 @@ impl CapaEngine {
         manager: Handle<Domain>,
         core: usize,
     ) -> Result<(), CapaError> {
-        self.domains[domain].remove_from_core(core);
-        self.domains[manager].execute_on_core(core);
-        self.cores[core].set_domain(manager);
+        self.transaction.forbid()?;
+        self.leave_core(domain, core);
+        self.enter_core(manager, core);
         Ok(())
     }
 
+    /// Pairs the domain with the one it can switch to through `capa`, so that the two can then
+    /// switch to each other on `core` without going through the monitor (e.g., with `vmfunc` on
+    /// x86). Returns the peer.
+    ///
+    /// Both domains must be sealed. The peer then runs on the core along with the domain, and
+    /// only leaves it by switching back to it. The pair lasts until disabled by the domain or
+    /// until one of the two domains is revoked.
+    pub fn enable_fast_switch(
+        &mut self,
+        domain: Handle<Domain>,
+        core: usize,
+        capa: LocalCapa,
+    ) -> Result<Handle<Domain>, CapaError> {
+        self.transaction.forbid()?;
+        let (peer, capa_core) = self.domains[domain].get(capa)?.as_switch()?;
+        if capa_core != core
+            || !self.domains[domain].cores().contains(core)
+            || !self.domains[peer].core_map().contains(core)
+        {
+            return Err(CapaError::InvalidCore);
+        }
+        // A manager could otherwise be revoked from under its running peer.
+        if peer == domain || self.domains[domain].get_manager() == Some(peer) {
+            return Err(CapaError::InvalidSwitch);
+        }
+        if !self.domains[domain].is_sealed() || !self.domains[peer].is_sealed() {
+            return Err(CapaError::InvalidOperation);
+        }
+        if self.domains[domain].fast_switch().is_some()
+            || self.domains[peer].fast_switch().is_some()
+        {
+            return Err(CapaError::InvalidSwitch);
+        }
+
+        self.domains[domain].set_fast_switch(Some(FastSwitch {
+            peer,
+            core,
+            is_owner: true,
+        }));
+        self.domains[peer].set_fast_switch(Some(FastSwitch {
+            peer: domain,
+            core,
+            is_owner: false,
+        }));
+        self.domains[peer].execute_on_core(core);
+        Ok(peer)
+    }
+
+    /// Dissolves the pair enabled by the domain, see [CapaEngine::enable_fast_switch].
+    pub fn disable_fast_switch(&mut self, domain: Handle<Domain>) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
+        match self.domains[domain].fast_switch() {
+            Some(pair) if pair.is_owner => {
+                domain::dissolve_fast_switch(domain, &mut self.domains, &mut self.updates)
+            }
+            _ => Err(CapaError::InvalidOperation),
+        }
+    }
+
+    /// Marks the domain as running on the core, along with its peer if it owns a pair there.
+    fn enter_core(&mut self, domain: Handle<Domain>, core: usize) {
+        self.domains[domain].execute_on_core(core);
+        if let Some(pair) = self.domains[domain].fast_switch_on(core) {
+            self.domains[pair.peer].execute_on_core(core);
+        }
+        self.cores[core].set_domain(domain);
+    }
+
+    /// Removes the domain from the core, along with its peer if it owns a pair there.
+    fn leave_core(&mut self, domain: Handle<Domain>, core: usize) {
+        self.domains[domain].remove_from_core(core);
+        if let Some(pair) = self.domains[domain].fast_switch_on(core) {
+            self.domains[pair.peer].remove_from_core(core);
+        }
+    }
+
     pub fn handle_trap(
         &mut self,
         domain: Handle<Domain>,
@@ -593,6 +1319,7 @@ impl CapaEngine {
         trap: u64,
         info: u64,
     ) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
         if self.domains[domain].can_handle(trap) {
             log::error!("The domain is able to handle its own trap, why did we exit?");
             return Err(CapaError::ValidTrapCausedExit);
@@ -690,6 +1417,16 @@ impl CapaEngine {
         self.domains[domain].get(capa)?.as_domain()
     }
 
+    /// Returns the domain managed through `capa`, if the capability holds all the `rights`.
+    pub fn get_child_domain(
+        &self,
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+        rights: MgmtRights,
+    ) -> Result<Handle<Domain>, CapaError> {
+        self.domains[manager].get(capa)?.as_management_with(rights)
+    }
+
     pub fn get_switch_capa(
         &self,
         domain: Handle<Domain>,
@@ -729,11 +1466,45 @@ impl CapaEngine {
         Ok(domain.regions().permissions(&self.tracker))
     }
 
-    pub fn get_domain_cores(&self, domain: Handle<Domain>) -> Result<u64, CapaError> {
+    /// Returns the changes to the permissions of the domain since they were last acknowledged, or
+    /// `None` if there were too many changes to track and the permissions must be applied in full.
+    pub fn get_domain_permission_deltas<'a>(
+        &'a self,
+        domain: Handle<Domain>,
+    ) -> Result<Option<DeltaIterator<'a>>, CapaError> {
+        let Some(domain) = self.domains.get(domain) else {
+            return Err(CapaError::InvalidValue);
+        };
+        if domain.deltas().has_overflowed() {
+            return Ok(None);
+        }
+        Ok(Some(
+            domain.deltas().deltas(domain.regions(), &self.tracker),
+        ))
+    }
+
+    /// Marks the permission changes of the domain as applied by the platform.
+    pub fn acknowledge_permission_deltas(
+        &mut self,
+        domain: Handle<Domain>,
+    ) -> Result<(), CapaError> {
+        let Some(domain) = self.domains.get_mut(domain) else {
+            return Err(CapaError::InvalidValue);
+        };
+        domain.deltas_mut().clear();
+        Ok(())
+    }
+
+    pub fn get_domain_cores(&self, domain: Handle<Domain>) -> Result<CoreSet, CapaError> {
         Ok(self.domains[domain].cores())
     }
 
+    /// Returns the next update to apply, updates are held back until the transaction in progress
+    /// (if any) commits.
     pub fn pop_update(&mut self) -> Option<Update> {
+        if self.transaction.is_active() {
+            return None;
+        }
         self.updates.pop()
     }
 
@@ -762,7 +1533,142 @@ impl CapaEngine {
     /// Returns the number of bytes written. Raises an out of memory error if buffer space is
     /// insufficient.
     pub fn serialize_attestation(&self, buff: &mut [u8]) -> Result<usize, CapaError> {
-        serializer::serialize(buff, &self.domains, &self.regions)
+        serializer::serialize(buff, &self.domains, &self.regions, &self.policy)
+    }
+
+    /// Returns the size of the engine pools, or `None` if the engine is not initialized.
+    pub fn config(&self) -> Option<EngineConfig> {
+        self.config
+    }
+
+    /// Saves the complete state of the engine, see [snapshot].
+    ///
+    /// Returns the number of bytes written, or an out of memory error if the buffer is too small,
+    /// see [CapaEngine::snapshot_size]. Snapshots can not be taken within a transaction.
+    pub fn snapshot(&self, buff: &mut [u8]) -> Result<usize, CapaError> {
+        self.transaction.forbid()?;
+        let mut writer = snapshot::Writer::new(Some(buff));
+        snapshot::save_engine(self, &mut writer)?;
+        Ok(writer.len())
+    }
+
+    /// Returns the number of bytes needed to snapshot the engine.
+    pub fn snapshot_size(&self) -> Result<usize, CapaError> {
+        let mut writer = snapshot::Writer::new(None);
+        snapshot::save_engine(self, &mut writer)?;
+        Ok(writer.len())
+    }
+
+    /// Restores a snapshot taken with [CapaEngine::snapshot].
+    ///
+    /// The engine must be initialized with the same configuration as the engine the snapshot was
+    /// taken from. If the snapshot can not be restored the engine is left in an inconsistent
+    /// state, and must not be used until a snapshot is successfully restored.
+    pub fn restore(&mut self, buff: &[u8]) -> Result<(), CapaError> {
+        self.transaction.forbid()?;
+        snapshot::restore_engine(self, &mut snapshot::Reader::new(buff))
+    }
+
+    /// Starts a transaction: the following operations are applied all-or-nothing, until
+    /// [CapaEngine::commit_transaction] or [CapaEngine::abort_transaction] is called.
+    ///
+    /// Only the operations that can be undone are allowed within a transaction (creating domains
+    /// and regions, sending, duplicating, configuring and sealing), the others return
+    /// `InvalidOperation`. Updates are held back until the transaction commits.
+    pub fn begin_transaction(&mut self) -> Result<(), CapaError> {
+        self.transaction.begin(self.updates.mark(), self.id_counter)
+    }
+
+    /// Commits the transaction in progress, its updates can now be popped.
+    pub fn commit_transaction(&mut self) -> Result<(), CapaError> {
+        if !self.transaction.is_active() {
+            return Err(CapaError::InvalidOperation);
+        }
+        self.transaction.end();
+        Ok(())
+    }
+
+    /// Rolls back all the operations of the transaction in progress and drops their updates.
+    pub fn abort_transaction(&mut self) -> Result<(), CapaError> {
+        if !self.transaction.is_active() {
+            return Err(CapaError::InvalidOperation);
+        }
+        let mut result = Ok(());
+        while let Some(undo) = self.transaction.pop() {
+            if let Err(err) = self.undo(undo) {
+                log::error!("Failed to undo {:?}: {:?}", undo, err);
+                result = Err(err);
+            }
+        }
+        // Undoing the operations pushes updates too, they cancel out the dropped ones.
+        self.updates.truncate(self.transaction.updates_mark);
+        self.id_counter = self.transaction.id_counter;
+        self.transaction.end();
+        result
+    }
+
+    /// Undoes an operation, assuming the operations performed after it are already undone.
+    fn undo(&mut self, undo: Undo) -> Result<(), CapaError> {
+        match undo {
+            Undo::CreateDomain {
+                manager,
+                capa,
+                domain,
+            } => {
+                remove_capa(manager, capa, &mut self.domains)?;
+                let usage = Usage::DOMAIN.plus(self.domains[domain].usage());
+                quota::release(manager, &mut self.domains, usage);
+                self.domains.free(domain);
+            }
+            Undo::CreateRegion { domain, capa } => self.remove_region(domain, capa)?,
+            Undo::InsertCapa { domain, capa } => {
+                remove_capa(domain, capa, &mut self.domains)?;
+            }
+            Undo::Send {
+                from,
+                local,
+                to,
+                received,
+                region,
+            } => {
+                let capa = remove_capa(to, received, &mut self.domains)?;
+                match capa {
+                    Capa::Region(handle) => {
+                        // The rights must be restored first, the tracker holds the previous ones
+                        if let Some((access, hash, labels)) = region {
+                            self.regions[handle].access = access;
+                            self.regions[handle].hash = hash;
+                            self.regions[handle].labels = labels;
+                        }
+                        segment::send(
+                            handle,
+                            &mut self.regions,
+                            &mut self.domains,
+                            &mut self.tracker,
+                            &mut self.updates,
+                            from,
+                        )?;
+                    }
+                    Capa::Management(managed, rights) if rights.contains(MgmtRights::MANAGE) => {
+                        domain::send_management(managed, &mut self.domains, from);
+                    }
+                    _ => (),
+                }
+                self.restore_capa(from, local, capa)?;
+            }
+            Undo::Permission {
+                domain,
+                index,
+                value,
+            } => domain::set_permission(domain, &mut self.domains, index, value)?,
+            Undo::CoreMap { domain, cores } => {
+                domain::set_core_map(domain, &mut self.domains, cores)?
+            }
+            Undo::Quota { domain, quota } => domain::set_quota(domain, &mut self.domains, quota)?,
+            Undo::Labels { domain, labels } => self.domains[domain].set_labels(labels),
+            Undo::Seal { domain } => self.domains[domain].unseal(),
+        }
+        Ok(())
     }
 
     /// creates a new domain
@@ -780,21 +1686,38 @@ impl CapaEngine {
             permission::PermissionIndex::MonitorInterface,
             permission::monitor_inter_perm::SPAWN,
         )?;
+        // The new domain inherits the labels of its manager
+        let labels = self.domains[manager].labels();
+        self.check_flow(flow::transfer::SPAWN, manager, labels, None)?;
+
+        // Check capacity (one local handle and one update) and quota, so that nothing fails once
+        // the domain is allocated.
+        domain::has_capacity_for(manager, 1, &mut self.regions, &mut self.domains)?;
+        self.updates.has_capacity_for(1)?;
+        self.transaction.reserve()?;
+        quota::check(manager, &self.domains, Usage::DOMAIN)?;
 
         let id = self.domain_id();
-        match self.domains.allocate(Domain::new(id, io)) {
+        let quota = self.domains[manager].quota();
+        match self.domains.allocate_with(|domain| domain.reset(id, io)) {
             Some(handle) => {
                 self.domains[handle].set_id(id)?;
                 self.domains[handle].set_manager(manager);
+                self.domains[handle].set_labels(labels);
+                domain::set_quota(handle, &mut self.domains, quota)?;
+                quota::charge(manager, &mut self.domains, Usage::DOMAIN);
                 let capa = insert_capa(
                     manager,
                     Capa::management(handle),
                     &mut self.regions,
                     &mut self.domains,
                 )?;
-                self.updates
-                    .push(Update::CreateDomain { domain: handle })
-                    .unwrap();
+                self.updates.push(Update::CreateDomain { domain: handle })?;
+                self.transaction.record(Undo::CreateDomain {
+                    manager,
+                    capa,
+                    domain: handle,
+                });
                 Ok(capa)
             }
             None => {
@@ -836,6 +1759,18 @@ impl CapaEngine {
     pub fn is_domain_sealed(&self, domain: Handle<Domain>) -> bool {
         self.domains[domain].is_sealed()
     }
+
+    /// Checks that the internal structures of the engine are consistent with each other.
+    ///
+    /// Panics if the region trackers do not match the effective regions of the capability tree,
+    /// if a child list or a manager link is malformed, if the core bitmaps of the domains do not
+    /// match the cores, or if the resource usage of a domain is wrong. This is expensive and meant for tests, fuzzing and debug builds.
+    pub fn check_invariants(&self) {
+        segment::check_invariants(&self.regions, &self.domains, &self.tracker);
+        domain::check_invariants(&self.domains);
+        cores::check_invariants(&self.cores, &self.domains);
+        quota::check_invariants(&self.regions, &self.domains, &self.tracker);
+    }
 }
 
 impl Default for CapaEngine {
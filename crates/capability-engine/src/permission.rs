@@ -1,8 +1,13 @@
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{CapaError, CoreSet};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
 #[repr(usize)]
 pub enum PermissionIndex {
     MonitorInterface = 0,
     AllowedTraps = 1,
+    /// The allowed cores are stored as a [CoreSet], the `u64` permission accessors only see the
+    /// first 64 cores.
     AllowedCores = 2,
     MgmtRead16 = 3,
     MgmtWrite16 = 4,
@@ -56,26 +61,44 @@ pub mod monitor_inter_perm {
     pub const NONE: u64 = 0;
 }
 
-pub mod core_bits {
-    /// No core.
-    pub const NONE: u64 = 0;
-
-    /// All cores.
-    pub const ALL: u64 = !(NONE);
-}
-
 pub mod trap_bits {
     /// No trap can be handled by the domain.
     pub const NONE: u64 = 0;
 
     /// All traps can be handled by the domain.
     pub const ALL: u64 = !(NONE);
+
+    /// Not an exception: a region lent to the domain was revoked because its lease expired.
+    pub const LEASE_EXPIRED: u64 = 1 << 32;
+
+    /// Not an exception: a region held by the domain was revoked by another domain.
+    pub const REGION_REVOKED: u64 = 1 << 33;
+
+    /// Not an exception: the domain has pending messages received over a channel.
+    pub const CHANNEL_MESSAGE: u64 = 1 << 34;
 }
 
 pub struct Permissions {
     pub perm: [u64; PermissionIndex::size()],
+    /// The cores the domain is allowed to run on.
+    pub cores: CoreSet,
 }
 
 pub const DEFAULT: Permissions = Permissions {
     perm: [0; PermissionIndex::size()],
+    cores: CoreSet::NONE,
 };
+
+impl Snapshot for Permissions {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.perm.save(w)?;
+        self.cores.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Permissions {
+            perm: Snapshot::load(r)?,
+            cores: CoreSet::load(r)?,
+        })
+    }
+}
@@ -0,0 +1,201 @@
+//! Memory Pools
+//!
+//! The engine objects (domains, regions, capabilities...) are allocated from pools whose size is
+//! only known at boot time. The pools are carved out of a range of memory provided by the
+//! platform, which is never given back.
+
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use crate::snapshot::{restore_slice, snapshot_slice, Reader, Restore, Writer};
+use crate::CapaError;
+
+// ——————————————————————————————— Pool Memory —————————————————————————————— //
+
+/// A range of memory from which pools can be carved.
+pub struct PoolMemory {
+    /// Start of the remaining memory.
+    start: usize,
+    /// End of the memory range.
+    end: usize,
+}
+
+impl PoolMemory {
+    /// Creates a pool memory from a range of memory.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid for reads and writes, and must not be accessed by anything else
+    /// than the pools carved from it for the rest of the execution.
+    pub const unsafe fn new(start: usize, size: usize) -> Self {
+        Self {
+            start,
+            end: start + size,
+        }
+    }
+
+    /// Creates a pool memory backed by a static buffer.
+    pub fn from_slice(buffer: &'static mut [u8]) -> Self {
+        // SAFETY: the buffer is borrowed for the rest of the execution.
+        unsafe { Self::new(buffer.as_mut_ptr() as usize, buffer.len()) }
+    }
+
+    /// Returns the remaining size, in bytes.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Carves a slice of `len` objects, initialized with `init(index)`.
+    pub fn carve<T>(
+        &mut self,
+        len: usize,
+        mut init: impl FnMut(usize) -> T,
+    ) -> Result<PoolSlice<T>, CapaError> {
+        let start = self
+            .start
+            .checked_next_multiple_of(align_of::<T>())
+            .ok_or(CapaError::OutOfMemory)?;
+        let end = size_of::<T>()
+            .checked_mul(len)
+            .and_then(|size| start.checked_add(size))
+            .ok_or(CapaError::OutOfMemory)?;
+        if end > self.end {
+            log::error!(
+                "Pool memory exhausted: need {:#x} bytes, {:#x} remaining",
+                end - self.start,
+                self.size()
+            );
+            return Err(CapaError::OutOfMemory);
+        }
+        self.start = end;
+
+        let ptr = start as *mut T;
+        for idx in 0..len {
+            // SAFETY: the object is aligned and within the memory range we own.
+            unsafe { ptr.add(idx).write(init(idx)) };
+        }
+
+        Ok(PoolSlice {
+            ptr: NonNull::new(ptr).unwrap_or(NonNull::dangling()),
+            len,
+        })
+    }
+
+    /// Splits off the next `size` bytes into a new pool memory.
+    pub fn split(&mut self, size: usize) -> Result<PoolMemory, CapaError> {
+        if size > self.size() {
+            return Err(CapaError::OutOfMemory);
+        }
+        let start = self.start;
+        self.start += size;
+        Ok(PoolMemory {
+            start,
+            end: self.start,
+        })
+    }
+}
+
+/// The worst-case number of bytes needed to carve `len` objects of type `T`, including padding.
+pub const fn pool_size<T>(len: usize) -> usize {
+    size_of::<T>() * len + align_of::<T>() - 1
+}
+
+// ——————————————————————————————— Pool Slice ——————————————————————————————— //
+
+/// A slice of objects carved from a [`PoolMemory`].
+///
+/// The slice owns its objects, but never frees them: pools live for the whole execution.
+pub struct PoolSlice<T> {
+    ptr: NonNull<T>,
+    len: usize,
+}
+
+// SAFETY: a pool slice behaves as a `&'static mut [T]`.
+unsafe impl<T: Send> Send for PoolSlice<T> {}
+unsafe impl<T: Sync> Sync for PoolSlice<T> {}
+
+impl<T> PoolSlice<T> {
+    /// An empty slice, used before the pools are initialized.
+    pub const fn empty() -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> Deref for PoolSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the pointer is valid and initialized for `len` objects by construction.
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for PoolSlice<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the pointer is valid and initialized for `len` objects by construction.
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl<T: Restore> Restore for PoolSlice<T> {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        snapshot_slice(self, w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        restore_slice(self, r)
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+pub(crate) fn leak_memory(size: usize) -> PoolMemory {
+    PoolMemory::from_slice(Box::leak(vec![0u8; size].into_boxed_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carve() {
+        let mut memory = leak_memory(0x100);
+        let bytes = memory.carve(3, |idx| idx as u8).unwrap();
+        assert_eq!(&*bytes, &[0, 1, 2]);
+
+        // Objects are aligned
+        let words = memory.carve(4, |_| 0xffu64).unwrap();
+        assert_eq!(words.as_ptr() as usize % align_of::<u64>(), 0);
+        assert_eq!(&*words, &[0xff; 4]);
+        assert!(memory.size() <= 0x100 - 3 - 4 * 8);
+
+        // Running out of memory is not fatal
+        assert_eq!(
+            memory.carve(0x100, |_| 0u8).err(),
+            Some(CapaError::OutOfMemory)
+        );
+        assert_eq!(
+            memory.carve(usize::MAX, |_| 0u64).err(),
+            Some(CapaError::OutOfMemory)
+        );
+        assert!(memory.carve(0x10, |_| 0u8).is_ok());
+    }
+
+    #[test]
+    fn split() {
+        let mut memory = leak_memory(0x100);
+        let mut sub = memory.split(0x40).unwrap();
+        assert_eq!(sub.size(), 0x40);
+        assert_eq!(memory.size(), 0xc0);
+        assert!(sub.carve(0x40, |_| 0u8).is_ok());
+        assert!(sub.carve(1, |_| 0u8).is_err());
+        assert!(memory.split(0x100).is_err());
+    }
+}
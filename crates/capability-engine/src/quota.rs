@@ -0,0 +1,262 @@
+//! Resource Quotas
+//!
+//! All domains share the engine pools. To prevent a domain from exhausting them, the resources
+//! used by a domain are accounted to the domain and to all of its (transitive) managers, and each
+//! of them can have a quota. A domain can therefore never use more than what any of its managers
+//! is allowed to.
+
+use crate::domain::DomainPool;
+use crate::region::TrackerPool;
+use crate::segment::{owned_memory, RegionPool};
+use crate::{CapaError, Domain, Handle};
+
+// ————————————————————————————————— Quota —————————————————————————————————— //
+
+/// The maximum amount of resources that a domain and the domains it manages can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum number of managed domains, including the indirectly managed ones.
+    pub max_domains: usize,
+    /// Maximum number of region capabilities.
+    pub max_regions: usize,
+    /// Maximum number of regions in the permission trackers.
+    pub max_trackers: usize,
+    /// Maximum number of bytes of memory owned, aliased memory is not accounted.
+    pub max_memory: usize,
+}
+
+impl Quota {
+    pub const UNLIMITED: Self = Self {
+        max_domains: usize::MAX,
+        max_regions: usize::MAX,
+        max_trackers: usize::MAX,
+        max_memory: usize::MAX,
+    };
+
+    /// Returns true if none of the limits is above the corresponding limit of `other`.
+    pub fn is_within(&self, other: &Quota) -> bool {
+        self.max_domains <= other.max_domains
+            && self.max_regions <= other.max_regions
+            && self.max_trackers <= other.max_trackers
+            && self.max_memory <= other.max_memory
+    }
+}
+
+// ————————————————————————————————— Usage —————————————————————————————————— //
+
+/// The resources used by a domain and the domains it manages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Number of managed domains, including the indirectly managed ones.
+    pub domains: usize,
+    /// Number of region capabilities.
+    pub regions: usize,
+    /// Number of regions in the permission trackers.
+    pub trackers: usize,
+    /// Number of bytes of memory owned, see [Quota::max_memory].
+    pub memory: usize,
+}
+
+impl Usage {
+    pub const NONE: Self = Self {
+        domains: 0,
+        regions: 0,
+        trackers: 0,
+        memory: 0,
+    };
+    pub const DOMAIN: Self = Self {
+        domains: 1,
+        ..Self::NONE
+    };
+
+    /// The usage of a region capability owning `memory` bytes.
+    pub const fn region(memory: usize) -> Self {
+        Self {
+            regions: 1,
+            memory,
+            ..Self::NONE
+        }
+    }
+
+    pub const fn memory(memory: usize) -> Self {
+        Self {
+            memory,
+            ..Self::NONE
+        }
+    }
+
+    pub const fn trackers(trackers: usize) -> Self {
+        Self {
+            trackers,
+            ..Self::NONE
+        }
+    }
+
+    /// Returns true if any of the resources is above the quota.
+    pub fn exceeds(&self, quota: &Quota) -> bool {
+        self.domains > quota.max_domains
+            || self.regions > quota.max_regions
+            || self.trackers > quota.max_trackers
+            || self.memory > quota.max_memory
+    }
+
+    /// Returns true if a resource grew since `previous` and is now above the quota.
+    fn grew_above(&self, previous: &Usage, quota: &Quota) -> bool {
+        (self.domains > previous.domains && self.domains > quota.max_domains)
+            || (self.regions > previous.regions && self.regions > quota.max_regions)
+            || (self.trackers > previous.trackers && self.trackers > quota.max_trackers)
+            || (self.memory > previous.memory && self.memory > quota.max_memory)
+    }
+
+    pub(crate) fn plus(self, other: Usage) -> Self {
+        Self {
+            domains: self.domains.saturating_add(other.domains),
+            regions: self.regions.saturating_add(other.regions),
+            trackers: self.trackers.saturating_add(other.trackers),
+            memory: self.memory.saturating_add(other.memory),
+        }
+    }
+
+    pub(crate) fn minus(self, other: Usage) -> Self {
+        Self {
+            domains: self.domains.saturating_sub(other.domains),
+            regions: self.regions.saturating_sub(other.regions),
+            trackers: self.trackers.saturating_sub(other.trackers),
+            memory: self.memory.saturating_sub(other.memory),
+        }
+    }
+}
+
+// ——————————————————————————————— Accounting ——————————————————————————————— //
+
+/// Calls `f` on the domain and all of its managers.
+fn for_each_manager(
+    domain: Handle<Domain>,
+    domains: &mut DomainPool,
+    mut f: impl FnMut(&mut Domain),
+) {
+    let mut cursor = Some(domain);
+    while let Some(handle) = cursor {
+        let Some(dom) = domains.get_mut(handle) else {
+            break;
+        };
+        f(dom);
+        cursor = dom.get_manager();
+    }
+}
+
+/// Accounts `usage` to the domain and its managers, regardless of their quotas.
+pub(crate) fn charge(domain: Handle<Domain>, domains: &mut DomainPool, usage: Usage) {
+    for_each_manager(domain, domains, |dom| dom.usage = dom.usage.plus(usage));
+}
+
+/// Gives `usage` back to the domain and its managers.
+pub(crate) fn release(domain: Handle<Domain>, domains: &mut DomainPool, usage: Usage) {
+    for_each_manager(domain, domains, |dom| dom.usage = dom.usage.minus(usage));
+}
+
+/// Accounts the regions allocated or freed from the tracker pool to update the permissions of
+/// the domain, given the capacity of the pool before and after the update.
+pub(crate) fn update_trackers(
+    domain: Handle<Domain>,
+    domains: &mut DomainPool,
+    capacity_before: usize,
+    capacity_after: usize,
+) {
+    if capacity_after < capacity_before {
+        let usage = Usage::trackers(capacity_before - capacity_after);
+        charge(domain, domains, usage);
+    } else if capacity_after > capacity_before {
+        let usage = Usage::trackers(capacity_after - capacity_before);
+        release(domain, domains, usage);
+    }
+}
+
+/// Returns `QuotaExceeded` if charging `usage` to the domain would exceed its quota or the quota
+/// of one of its managers.
+pub(crate) fn check(
+    domain: Handle<Domain>,
+    domains: &DomainPool,
+    usage: Usage,
+) -> Result<(), CapaError> {
+    let mut cursor = Some(domain);
+    while let Some(handle) = cursor {
+        let Some(dom) = domains.get(handle) else {
+            break;
+        };
+        if dom.usage.plus(usage).exceeds(&dom.quota) {
+            log::debug!("Domain {} would exceed its quota", handle);
+            return Err(CapaError::QuotaExceeded);
+        }
+        cursor = dom.get_manager();
+    }
+    Ok(())
+}
+
+/// Records the current usage of the domain and its managers, see [check_growth].
+pub(crate) fn mark(domain: Handle<Domain>, domains: &mut DomainPool) {
+    for_each_manager(domain, domains, |dom| dom.usage_mark = dom.usage);
+}
+
+/// Returns `QuotaExceeded` if a resource of the domain or one of its managers grew above its
+/// quota since the last call to [mark].
+///
+/// This is used for operations whose cost is only known once performed, such as the number of
+/// regions needed to track the new permissions.
+pub(crate) fn check_growth(domain: Handle<Domain>, domains: &DomainPool) -> Result<(), CapaError> {
+    let mut cursor = Some(domain);
+    while let Some(handle) = cursor {
+        let Some(dom) = domains.get(handle) else {
+            break;
+        };
+        if dom.usage.grew_above(&dom.usage_mark, &dom.quota) {
+            log::debug!("Domain {} exceeded its quota", handle);
+            return Err(CapaError::QuotaExceeded);
+        }
+        cursor = dom.get_manager();
+    }
+    Ok(())
+}
+
+// ——————————————————————————————— Invariants ——————————————————————————————— //
+
+/// Panics if the usage of a domain does not match the resources used by it and the domains it
+/// manages.
+pub(crate) fn check_invariants(regions: &RegionPool, domains: &DomainPool, tracker: &TrackerPool) {
+    let is_managed_by = |mut handle: Handle<Domain>, manager: Handle<Domain>| {
+        for _ in 0..=domains.size() {
+            if handle == manager {
+                return true;
+            }
+            match domains.get(handle).and_then(|d| d.get_manager()) {
+                Some(next) => handle = next,
+                None => return false,
+            }
+        }
+        false
+    };
+
+    for handle in domains {
+        let mut expected = Usage::NONE;
+        for managed in domains {
+            if !is_managed_by(managed, handle) {
+                continue;
+            }
+            if managed != handle {
+                expected.domains += 1;
+            }
+            for region in regions {
+                if regions[region].domain == managed {
+                    expected = expected.plus(Usage::region(owned_memory(region, regions)));
+                }
+            }
+            let nb_trackers = domains[managed].regions().iter(tracker).count();
+            expected = expected.plus(Usage::trackers(nb_trackers));
+        }
+        assert_eq!(
+            domains[handle].usage, expected,
+            "Invalid resource usage for domain {}",
+            handle
+        );
+    }
+}
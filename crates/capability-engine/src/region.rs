@@ -2,8 +2,8 @@ use core::fmt;
 
 use bitflags::bitflags;
 
-use crate::config::NB_TRACKER;
 use crate::gen_arena::{GenArena, Handle};
+use crate::snapshot::{Reader, Snapshot, Writer};
 use crate::CapaError;
 
 bitflags! {
@@ -107,7 +107,7 @@ impl PermissionChange {
 
 // ———————————————————————————————— Regions ————————————————————————————————— //
 
-pub(crate) type TrackerPool = GenArena<Region, NB_TRACKER>;
+pub(crate) type TrackerPool = GenArena<Region>;
 
 pub(crate) const EMPTY_REGION: Region = Region {
     start: 0,
@@ -118,6 +118,11 @@ pub(crate) const EMPTY_REGION: Region = Region {
     super_count: 0,
     ref_count: 0,
     next: None,
+    prev: None,
+    parent: None,
+    left: None,
+    right: None,
+    height: 0,
 };
 
 #[derive(Debug)]
@@ -129,7 +134,18 @@ pub struct Region {
     exec_count: usize,
     super_count: usize,
     ref_count: usize,
+    /// The next region in the list.
     next: Option<Handle<Region>>,
+    /// The previous region in the list.
+    prev: Option<Handle<Region>>,
+    /// The parent region in the tree.
+    parent: Option<Handle<Region>>,
+    /// The left child in the tree, starting at lower addresses.
+    left: Option<Handle<Region>>,
+    /// The right child in the tree, starting at higher addresses.
+    right: Option<Handle<Region>>,
+    /// The height of the subtree rooted at this region.
+    height: u8,
 }
 
 impl Region {
@@ -151,15 +167,10 @@ impl Region {
             exec_count: x,
             super_count: s,
             ref_count: 1,
-            next: None,
+            ..EMPTY_REGION
         }
     }
 
-    fn set_next(mut self, next: Option<Handle<Region>>) -> Self {
-        self.next = next;
-        self
-    }
-
     pub fn contains(&self, addr: usize) -> bool {
         self.start <= addr && addr < self.end
     }
@@ -196,23 +207,45 @@ impl Region {
     pub fn get_end(&self) -> usize {
         self.end
     }
+
+    /// Returns the reference count, followed by the read, write, exec and super counts.
+    pub(crate) fn counters(&self) -> (usize, (usize, usize, usize, usize)) {
+        let ops = (
+            self.read_count,
+            self.write_count,
+            self.exec_count,
+            self.super_count,
+        );
+        (self.ref_count, ops)
+    }
 }
 
 // ————————————————————————————— RegionTracker —————————————————————————————— //
 
+/// The regions of a domain, sorted by address.
+///
+/// Regions are kept both in a doubly linked list, for iterating in order, and in an AVL tree
+/// keyed by start address, so that the region containing an address is found in logarithmic time.
+/// Adding or removing a region thus costs O(log n + k), where k is the number of tracked regions
+/// overlapping the range.
 pub struct RegionTracker {
     head: Option<Handle<Region>>,
+    root: Option<Handle<Region>>,
 }
 
 impl RegionTracker {
     pub const fn new() -> Self {
-        Self { head: None }
+        Self {
+            head: None,
+            root: None,
+        }
     }
 
     pub fn get_refcount(&self, start: usize, end: usize, tracker: &TrackerPool) -> usize {
         let mut count = 0;
+        let first = self.find_lower_bound(start, tracker).or(self.head);
 
-        for (_, region) in self.iter(tracker) {
+        for (_, region) in self.iter_from(first, tracker) {
             if region.end <= start {
                 continue;
             } else if region.start >= end {
@@ -225,6 +258,88 @@ impl RegionTracker {
         count
     }
 
+    /// Returns the counters of the region containing `addr`, or zero if there is none.
+    pub(crate) fn counters_at(
+        &self,
+        addr: usize,
+        tracker: &TrackerPool,
+    ) -> (usize, (usize, usize, usize, usize)) {
+        self.find_lower_bound(addr, tracker)
+            .map(|handle| &tracker[handle])
+            .filter(|region| region.contains(addr))
+            .map_or((0, (0, 0, 0, 0)), |region| region.counters())
+    }
+
+    /// Panics if the regions are not sorted, overlap, are empty or are not referenced, or if the
+    /// tree is not consistent with the list.
+    pub(crate) fn validate(&self, tracker: &TrackerPool) {
+        let mut previous_end = 0;
+        let mut previous = None;
+        for (handle, region) in self.iter(tracker) {
+            assert!(
+                region.start < region.end,
+                "Empty region in tracker: {:?}",
+                region
+            );
+            assert!(
+                region.start >= previous_end,
+                "Tracker regions are not sorted or overlap: {:?}",
+                region
+            );
+            assert!(region.ref_count > 0, "Unreferenced region: {:?}", region);
+            assert!(
+                region.prev == previous,
+                "Invalid previous region: {:?}",
+                region
+            );
+            previous_end = region.end;
+            previous = Some(handle);
+        }
+
+        let mut cursor = self.head;
+        if let Some(root) = self.root {
+            assert!(tracker[root].parent.is_none(), "The tree root has a parent");
+        }
+        self.validate_subtree(self.root, &mut cursor, tracker);
+        assert!(cursor.is_none(), "Some regions are missing from the tree");
+    }
+
+    /// Checks the links and balance of a subtree, and that it visits the regions in the same order
+    /// as the list. Returns the height of the subtree.
+    fn validate_subtree(
+        &self,
+        node: Option<Handle<Region>>,
+        cursor: &mut Option<Handle<Region>>,
+        tracker: &TrackerPool,
+    ) -> u8 {
+        let Some(handle) = node else {
+            return 0;
+        };
+        let region = &tracker[handle];
+        for child in [region.left, region.right].into_iter().flatten() {
+            assert!(
+                tracker[child].parent == Some(handle),
+                "Invalid parent: {:?}",
+                tracker[child]
+            );
+        }
+        let left = self.validate_subtree(region.left, cursor, tracker);
+        assert!(
+            *cursor == Some(handle),
+            "The tree is not sorted like the list: {:?}",
+            region
+        );
+        *cursor = region.next;
+        let right = self.validate_subtree(region.right, cursor, tracker);
+        assert!(left.abs_diff(right) <= 1, "Unbalanced tree: {:?}", region);
+        assert!(
+            region.height == left.max(right) + 1,
+            "Invalid height: {:?}",
+            region
+        );
+        region.height
+    }
+
     pub fn remove_region(
         &mut self,
         start: usize,
@@ -240,20 +355,15 @@ impl RegionTracker {
             return Ok(PermissionChange::None);
         }
 
-        let (Some(mut bound), mut prev) = self.find_lower_bound(start, tracker) else {
+        let Some(mut bound) = self.find_lower_bound(start, tracker) else {
             log::trace!("Region does not exist");
             return Err(CapaError::InvalidRegion);
         };
 
         // Check if we need a split for the start.
         if tracker[bound].start < start {
-            prev = Some(bound);
             bound = self.split_region_at(bound, start, tracker)?;
         }
-        // Check if we need a split for the end.
-        if tracker[bound].end > end {
-            let _ = self.split_region_at(bound, end, tracker)?;
-        }
 
         assert_eq!(
             tracker[bound].start, start,
@@ -261,53 +371,29 @@ impl RegionTracker {
         );
 
         let mut change = PermissionChange::None;
-        let mut next = bound;
-        while tracker[next].start < end {
-            let mut update = self.decrease_refcount(next, tracker);
-            update.update(self.decrease_ops(next, ops, tracker));
+        let mut next = Some(bound);
+        while let Some(current) = next {
+            if tracker[current].start >= end {
+                break;
+            }
+            // Check if we need a split for the end.
+            if tracker[current].end > end {
+                self.split_region_at(current, end, tracker)?;
+            }
+
+            let mut update = self.decrease_refcount(current, tracker);
+            update.update(self.decrease_ops(current, ops, tracker));
             change.update(update);
 
             // Free regions with ref_count 0.
-            if tracker[next].ref_count == 0 {
-                // Remove the element from the list.
-                let to_visit = tracker[next].next;
-                match prev {
-                    Some(handle) => {
-                        tracker[handle].next = tracker[next].next;
-                    }
-                    None => {
-                        self.head = tracker[next].next;
-                    }
-                }
-                // Free the region.
-                tracker.free(next);
-
-                // Update next.
-                match to_visit {
-                    Some(handle) => {
-                        next = handle;
-                        continue;
-                    }
-                    None => {
-                        break;
-                    }
-                }
-                // End of free block.
-            }
-
-            match &tracker[next].next {
-                Some(handle) => {
-                    prev = Some(next);
-                    next = *handle;
-                }
-                None => {
-                    break;
-                }
+            next = tracker[current].next;
+            if tracker[current].ref_count == 0 {
+                self.unlink(current, tracker);
+                tracker.free(current);
             }
         }
 
-        // coalesce.
-        self.coalesce(tracker);
+        self.coalesce(start, end, tracker);
         Ok(change)
     }
 
@@ -328,13 +414,13 @@ impl RegionTracker {
 
         // There is no region yet, insert head and exit
         let Some(head) = self.head else {
-            self.insert_head(start, end, ops, tracker)?;
+            self.insert_after(start, end, ops, None, tracker)?;
             return Ok(PermissionChange::Some);
         };
 
         let mut change = PermissionChange::None;
         let (mut previous, mut cursor) =
-            if let (Some(lower_bound), _) = self.find_lower_bound(start, tracker) {
+            if let Some(lower_bound) = self.find_lower_bound(start, tracker) {
                 let region = &tracker[lower_bound];
                 if start == region.start {
                     // Regions have the same start
@@ -354,8 +440,8 @@ impl RegionTracker {
             } else {
                 let head = &tracker[head];
                 let cursor = core::cmp::min(end, head.start);
-                let previous = self.insert_head(start, cursor, ops, tracker)?;
-                change = PermissionChange::Some;
+                let (previous, update) = self.insert_after(start, cursor, ops, None, tracker)?;
+                change = update;
                 (previous, cursor)
             };
 
@@ -368,8 +454,7 @@ impl RegionTracker {
             cursor = tracker[previous].end;
         }
 
-        // Coalesce.
-        self.coalesce(tracker);
+        self.coalesce(start, end, tracker);
         Ok(change)
     }
 
@@ -398,7 +483,7 @@ impl RegionTracker {
                 end = next.start;
             }
         }
-        self.insert_after(start, end, ops, after, tracker)
+        self.insert_after(start, end, ops, Some(after), tracker)
     }
 
     fn partial_add_region_overlapping(
@@ -424,33 +509,19 @@ impl RegionTracker {
     }
 
     /// Returns a handle to the region with the closest (inferior or equal) start address.
-    /// First value is the closest, second is the previous element.
-    fn find_lower_bound(
-        &self,
-        start: usize,
-        tracker: &mut TrackerPool,
-    ) -> (Option<Handle<Region>>, Option<Handle<Region>>) {
-        let Some(mut closest) = self.head else {
-            return (None, None);
-        };
-
-        if tracker[closest].start > start {
-            // The first region already starts at a higher address
-            return (None, None);
-        }
-        let mut prev = None;
-        let mut iter = None;
-        for (handle, region) in self.iter(tracker) {
+    fn find_lower_bound(&self, start: usize, tracker: &TrackerPool) -> Option<Handle<Region>> {
+        let mut closest = None;
+        let mut node = self.root;
+        while let Some(handle) = node {
+            let region = &tracker[handle];
             if region.start <= start {
-                prev = iter;
-                closest = handle
+                closest = Some(handle);
+                node = region.right;
             } else {
-                break;
+                node = region.left;
             }
-            iter = Some(handle);
         }
-
-        (Some(closest), prev)
+        closest
     }
 
     /// Split the given region at the provided address. Returns a handle to the second half (the
@@ -476,7 +547,7 @@ impl RegionTracker {
             exec_count: region.exec_count,
             super_count: region.super_count,
             ref_count: region.ref_count,
-            next: region.next,
+            ..EMPTY_REGION
         };
         let second_half_handle = tracker.allocate(second_half).ok_or_else(|| {
             log::error!("Unable to allocate new region! Runned out of Tracker");
@@ -484,26 +555,33 @@ impl RegionTracker {
         })?;
 
         // Update the first half
-        let region = &mut tracker[handle];
-        region.end = at;
-        region.next = Some(second_half_handle);
+        tracker[handle].end = at;
+        self.link_after(second_half_handle, Some(handle), tracker);
 
         Ok(second_half_handle)
     }
 
-    /// Insert a fresh region after the region pointer by the `after` handle. Returns a handle
-    /// to the inserted region.
+    /// Insert a fresh region after the region pointer by the `after` handle, or as the new head if
+    /// `after` is `None`. Returns a handle to the inserted region.
     fn insert_after(
         &mut self,
         start: usize,
         end: usize,
         ops: MemOps,
-        after: Handle<Region>,
+        after: Option<Handle<Region>>,
         tracker: &mut TrackerPool,
     ) -> Result<(Handle<Region>, PermissionChange), CapaError> {
-        let region = &tracker[after];
-        assert!(start >= region.end, "Regions should be sorted by addresses");
-        if let Some(next) = region.next {
+        let next = match after {
+            Some(after) => {
+                assert!(
+                    start >= tracker[after].end,
+                    "Regions should be sorted by addresses"
+                );
+                tracker[after].next
+            }
+            None => self.head,
+        };
+        if let Some(next) = next {
             assert!(
                 end <= tracker[next].start,
                 "Regions should be sorted by addresses"
@@ -511,41 +589,17 @@ impl RegionTracker {
         }
 
         let handle = tracker
-            .allocate(Region::new(start, end, ops).set_next(region.next))
+            .allocate(Region::new(start, end, ops))
             .ok_or_else(|| {
                 log::error!("Unable to allocate new region! Increase number of Trackers");
                 CapaError::OutOfMemory
             })?;
-        let region = &mut tracker[after];
-        region.next = Some(handle);
+        self.link_after(handle, after, tracker);
 
         // There is alway a permission change in this case
         Ok((handle, PermissionChange::Some))
     }
 
-    fn insert_head(
-        &mut self,
-        start: usize,
-        end: usize,
-        ops: MemOps,
-        tracker: &mut TrackerPool,
-    ) -> Result<Handle<Region>, CapaError> {
-        if let Some(head) = self.head {
-            assert!(
-                tracker[head].start >= end,
-                "Region should be sorted by address"
-            );
-        }
-
-        let region = Region::new(start, end, ops).set_next(self.head);
-        let handle = tracker.allocate(region).ok_or_else(|| {
-            log::error!("Unable to allocate new region! Increase number of trackers");
-            CapaError::OutOfMemory
-        })?;
-        self.head = Some(handle);
-        Ok(handle)
-    }
-
     fn increase_refcount(
         &mut self,
         handle: Handle<Region>,
@@ -646,17 +700,26 @@ impl RegionTracker {
         return change;
     }
 
-    fn coalesce(&mut self, tracker: &mut TrackerPool) {
-        if self.head == None {
+    /// Merges the adjacent regions with the same counters around `[start, end]`.
+    ///
+    /// The regions outside of the range were coalesced by previous updates, so only the regions
+    /// within the range and their immediate neighbors need to be visited.
+    fn coalesce(&mut self, start: usize, end: usize, tracker: &mut TrackerPool) {
+        let first = self
+            .find_lower_bound(start, tracker)
+            .map(|handle| tracker[handle].prev.unwrap_or(handle))
+            .or(self.head);
+        let Some(mut prev) = first else {
             // Nothing to do.
             return;
-        }
-        let mut prev = self.head.unwrap();
+        };
         let mut curr = tracker[prev].next;
 
-        // Go through the list.
-        while curr != None {
-            let current = curr.unwrap();
+        // Go through the range.
+        while let Some(current) = curr {
+            if tracker[prev].start > end {
+                break;
+            }
             if tracker[prev].end == tracker[current].start
                 && (tracker[prev].same_counts(&tracker[current])
                     || tracker[current].start == tracker[current].end
@@ -666,13 +729,13 @@ impl RegionTracker {
                 if tracker[prev].start == tracker[prev].end {
                     tracker[prev].ref_count = tracker[current].ref_count;
                 }
-                tracker[prev].next = tracker[current].next;
                 tracker[prev].end = tracker[current].end;
-                tracker.free(curr.unwrap());
+                self.unlink(current, tracker);
+                tracker.free(current);
                 curr = tracker[prev].next;
                 continue;
             }
-            prev = curr.unwrap();
+            prev = current;
             curr = tracker[current].next;
         }
     }
@@ -694,11 +757,215 @@ impl RegionTracker {
 
     pub fn permissions<'a>(&'a self, pool: &'a TrackerPool) -> PermissionIterator<'a> {
         PermissionIterator {
-            tracker: self,
             pool,
             next: self.head,
         }
     }
+
+    /// Returns the permissions at `addr`, and the end of the range starting at `addr` that shares
+    /// the same region (or absence of region).
+    pub(crate) fn permission_at(&self, addr: usize, tracker: &TrackerPool) -> (MemOps, usize) {
+        let lower_bound = self.find_lower_bound(addr, tracker);
+        if let Some(handle) = lower_bound {
+            let region = &tracker[handle];
+            if region.contains(addr) {
+                return (region.get_ops(), region.end);
+            }
+        }
+        let next = match lower_bound {
+            Some(handle) => tracker[handle].next,
+            None => self.head,
+        };
+        let end = next.map_or(usize::MAX, |handle| tracker[handle].start);
+        (MemOps::NONE, end)
+    }
+}
+
+// —————————————————————————————— Region Tree ——————————————————————————————— //
+
+impl RegionTracker {
+    /// Links a region right after `after` in the list and the tree, or as the head if `after` is
+    /// `None`.
+    fn link_after(
+        &mut self,
+        handle: Handle<Region>,
+        after: Option<Handle<Region>>,
+        tracker: &mut TrackerPool,
+    ) {
+        // Insert in the list
+        let next = match after {
+            Some(after) => tracker[after].next.replace(handle),
+            None => self.head.replace(handle),
+        };
+        if let Some(next) = next {
+            tracker[next].prev = Some(handle);
+        }
+        let region = &mut tracker[handle];
+        region.prev = after;
+        region.next = next;
+        region.left = None;
+        region.right = None;
+        region.height = 1;
+
+        // Insert in the tree, as the leftmost node of the subtree following `after`
+        let parent = match (after, next) {
+            (Some(after), _) if tracker[after].right.is_none() => {
+                tracker[after].right = Some(handle);
+                Some(after)
+            }
+            (_, Some(next)) => {
+                // The next region is the leftmost node of the right subtree (or of the tree)
+                tracker[next].left = Some(handle);
+                Some(next)
+            }
+            (_, None) => {
+                self.root = Some(handle);
+                None
+            }
+        };
+        tracker[handle].parent = parent;
+        self.rebalance(parent, tracker);
+    }
+
+    /// Removes a region from the list and the tree, without freeing it.
+    fn unlink(&mut self, handle: Handle<Region>, tracker: &mut TrackerPool) {
+        // Remove from the list
+        let (prev, next) = (tracker[handle].prev, tracker[handle].next);
+        match prev {
+            Some(prev) => tracker[prev].next = next,
+            None => self.head = next,
+        }
+        if let Some(next) = next {
+            tracker[next].prev = prev;
+        }
+
+        // Remove from the tree
+        let region = &tracker[handle];
+        let (parent, left, right) = (region.parent, region.left, region.right);
+        let unbalanced = match (left, right) {
+            (Some(left), Some(right)) => {
+                // Replace the region with its successor, the leftmost node of the right subtree
+                let successor = next.unwrap();
+                let unbalanced = if successor == right {
+                    successor
+                } else {
+                    let successor_parent = tracker[successor].parent.unwrap();
+                    let successor_right = tracker[successor].right;
+                    tracker[successor_parent].left = successor_right;
+                    if let Some(successor_right) = successor_right {
+                        tracker[successor_right].parent = Some(successor_parent);
+                    }
+                    tracker[successor].right = Some(right);
+                    tracker[right].parent = Some(successor);
+                    successor_parent
+                };
+                tracker[successor].left = Some(left);
+                tracker[left].parent = Some(successor);
+                tracker[successor].height = tracker[handle].height;
+                self.replace_child(parent, handle, Some(successor), tracker);
+                Some(unbalanced)
+            }
+            (child, None) | (None, child) => {
+                self.replace_child(parent, handle, child, tracker);
+                parent
+            }
+        };
+        self.rebalance(unbalanced, tracker);
+    }
+
+    /// Replaces `old` by `new` in the children of `parent`, or as the root.
+    fn replace_child(
+        &mut self,
+        parent: Option<Handle<Region>>,
+        old: Handle<Region>,
+        new: Option<Handle<Region>>,
+        tracker: &mut TrackerPool,
+    ) {
+        match parent {
+            Some(parent) if tracker[parent].left == Some(old) => tracker[parent].left = new,
+            Some(parent) => tracker[parent].right = new,
+            None => self.root = new,
+        }
+        if let Some(new) = new {
+            tracker[new].parent = parent;
+        }
+    }
+
+    fn height(node: Option<Handle<Region>>, tracker: &TrackerPool) -> u8 {
+        node.map_or(0, |handle| tracker[handle].height)
+    }
+
+    /// Returns the height of the left subtree minus the one of the right subtree.
+    fn balance(handle: Handle<Region>, tracker: &TrackerPool) -> i16 {
+        let region = &tracker[handle];
+        Self::height(region.left, tracker) as i16 - Self::height(region.right, tracker) as i16
+    }
+
+    fn update_height(handle: Handle<Region>, tracker: &mut TrackerPool) {
+        let region = &tracker[handle];
+        let height = Self::height(region.left, tracker).max(Self::height(region.right, tracker));
+        tracker[handle].height = height + 1;
+    }
+
+    /// Restores the balance of the tree, from `node` up to the root.
+    fn rebalance(&mut self, mut node: Option<Handle<Region>>, tracker: &mut TrackerPool) {
+        while let Some(mut handle) = node {
+            Self::update_height(handle, tracker);
+            let balance = Self::balance(handle, tracker);
+            if balance > 1 {
+                let left = tracker[handle].left.unwrap();
+                if Self::balance(left, tracker) < 0 {
+                    self.rotate_left(left, tracker);
+                }
+                handle = self.rotate_right(handle, tracker);
+            } else if balance < -1 {
+                let right = tracker[handle].right.unwrap();
+                if Self::balance(right, tracker) > 0 {
+                    self.rotate_right(right, tracker);
+                }
+                handle = self.rotate_left(handle, tracker);
+            }
+            node = tracker[handle].parent;
+        }
+    }
+
+    /// Rotates the subtree rooted at `handle` to the left, returns the new root of the subtree.
+    fn rotate_left(&mut self, handle: Handle<Region>, tracker: &mut TrackerPool) -> Handle<Region> {
+        let pivot = tracker[handle].right.unwrap();
+        let inner = tracker[pivot].left;
+        tracker[handle].right = inner;
+        if let Some(inner) = inner {
+            tracker[inner].parent = Some(handle);
+        }
+        let parent = tracker[handle].parent;
+        self.replace_child(parent, handle, Some(pivot), tracker);
+        tracker[pivot].left = Some(handle);
+        tracker[handle].parent = Some(pivot);
+        Self::update_height(handle, tracker);
+        Self::update_height(pivot, tracker);
+        pivot
+    }
+
+    /// Rotates the subtree rooted at `handle` to the right, returns the new root of the subtree.
+    fn rotate_right(
+        &mut self,
+        handle: Handle<Region>,
+        tracker: &mut TrackerPool,
+    ) -> Handle<Region> {
+        let pivot = tracker[handle].left.unwrap();
+        let inner = tracker[pivot].right;
+        tracker[handle].left = inner;
+        if let Some(inner) = inner {
+            tracker[inner].parent = Some(handle);
+        }
+        let parent = tracker[handle].parent;
+        self.replace_child(parent, handle, Some(pivot), tracker);
+        tracker[pivot].right = Some(handle);
+        tracker[handle].parent = Some(pivot);
+        Self::update_height(handle, tracker);
+        Self::update_height(pivot, tracker);
+        pivot
+    }
 }
 
 // ———————————————————————————— Region Iterators ———————————————————————————— //
@@ -724,7 +991,6 @@ impl<'a> Iterator for RegionIterator<'a> {
 /// An iterator over a domain's memory access permissions.
 #[derive(Clone)]
 pub struct PermissionIterator<'a> {
-    tracker: &'a RegionTracker,
     pool: &'a TrackerPool,
     next: Option<Handle<Region>>,
 }
@@ -747,43 +1013,83 @@ impl<'a> Iterator for PermissionIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         // Get the first valid region
-        let mut handle = None;
-        let mut start = None;
-        for (h, region) in self.tracker.iter_from(self.next, self.pool) {
+        while let Some(handle) = self.next {
+            let region = &self.pool[handle];
+            self.next = region.next;
             if region.ref_count > 0 {
-                handle = Some(h);
-                start = Some(region.start);
-                break;
+                return Some(MemoryPermission {
+                    start: region.start,
+                    end: region.end,
+                    ops: region.get_ops(),
+                });
             }
         }
+        None
+    }
+}
 
-        let Some(start) = start else {
-            self.next = None; // makes next iteration faster
-            return None;
-        };
-        let (end, ops) = {
-            let reg = &self.pool[handle.unwrap()];
-            (reg.end, reg.get_ops())
-        };
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for Region {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.start.save(w)?;
+        self.end.save(w)?;
+        self.read_count.save(w)?;
+        self.write_count.save(w)?;
+        self.exec_count.save(w)?;
+        self.super_count.save(w)?;
+        self.ref_count.save(w)?;
+        self.next.save(w)?;
+        self.prev.save(w)?;
+        self.parent.save(w)?;
+        self.left.save(w)?;
+        self.right.save(w)?;
+        self.height.save(w)
+    }
 
-        let mut next = None;
-        for (handle, _region) in self.tracker.iter_from(handle, self.pool).skip(1) {
-            //TODO(aghosn) charly had some optimization here that I had to remove.
-            //We can put something correct here in the future.
-            next = Some(handle);
-            break;
-        }
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Region {
+            start: usize::load(r)?,
+            end: usize::load(r)?,
+            read_count: usize::load(r)?,
+            write_count: usize::load(r)?,
+            exec_count: usize::load(r)?,
+            super_count: usize::load(r)?,
+            ref_count: usize::load(r)?,
+            next: Option::load(r)?,
+            prev: Option::load(r)?,
+            parent: Option::load(r)?,
+            left: Option::load(r)?,
+            right: Option::load(r)?,
+            height: u8::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for RegionTracker {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.head.save(w)?;
+        self.root.save(w)
+    }
 
-        self.next = next;
-        Some(MemoryPermission { start, end, ops })
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(RegionTracker {
+            head: Option::load(r)?,
+            root: Option::load(r)?,
+        })
     }
 }
 
 // ————————————————————————————————— Tests —————————————————————————————————— //
 
+#[cfg(test)]
+mod legacy;
+
 #[cfg(test)]
 mod tests {
+    use super::legacy::ListTracker;
     use super::*;
+    use crate::config::NB_TRACKER;
     use crate::debug::snap;
 
     #[test]
@@ -796,7 +1102,7 @@ mod tests {
             exec_count: 0,
             super_count: 0,
             ref_count: 0,
-            next: None,
+            ..EMPTY_REGION
         };
 
         assert!(region.contains(0x100));
@@ -809,24 +1115,61 @@ mod tests {
     #[test]
     fn region_pool() {
         let mut tracker = RegionTracker::new();
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         tracker
             .add_region(0x100, 0x1000, MEMOPS_ALL, &mut pool)
             .unwrap();
 
         // Should return None if there is no lower bound region
-        assert_eq!(tracker.find_lower_bound(0x50, &mut pool), (None, None));
+        assert_eq!(tracker.find_lower_bound(0x50, &pool), None);
 
         let head = tracker.head;
-        assert_eq!(tracker.find_lower_bound(0x100, &mut pool), (head, None));
-        assert_eq!(tracker.find_lower_bound(0x200, &mut pool), (head, None));
+        assert_eq!(tracker.find_lower_bound(0x100, &pool), head);
+        assert_eq!(tracker.find_lower_bound(0x200, &pool), head);
+    }
+
+    #[test]
+    fn region_counters() {
+        let mut tracker = RegionTracker::new();
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
+        tracker
+            .add_region(0x100, 0x300, MEMOPS_ALL, &mut pool)
+            .unwrap();
+        tracker
+            .add_region(0x200, 0x400, MemOps::READ, &mut pool)
+            .unwrap();
+        tracker.validate(&pool);
+
+        assert_eq!(tracker.counters_at(0x50, &pool), (0, (0, 0, 0, 0)));
+        assert_eq!(tracker.counters_at(0x100, &pool), (1, (1, 1, 1, 1)));
+        assert_eq!(tracker.counters_at(0x2ff, &pool), (2, (2, 1, 1, 1)));
+        assert_eq!(tracker.counters_at(0x300, &pool), (1, (1, 0, 0, 0)));
+        assert_eq!(tracker.counters_at(0x400, &pool), (0, (0, 0, 0, 0)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn region_validate() {
+        let mut tracker = RegionTracker::new();
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
+        tracker
+            .add_region(0x100, 0x300, MEMOPS_ALL, &mut pool)
+            .unwrap();
+        tracker
+            .add_region(0x400, 0x500, MEMOPS_ALL, &mut pool)
+            .unwrap();
+
+        // Make the regions overlap
+        let head = tracker.head.unwrap();
+        pool[head].end = 0x450;
+        tracker.validate(&pool);
     }
 
     #[test]
     fn region_add() {
         // Region is added as head
         let mut tracker = RegionTracker::new();
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         tracker
             .add_region(0x300, 0x400, MEMOPS_ALL, &mut pool)
             .unwrap();
@@ -932,7 +1275,7 @@ mod tests {
     #[test]
     fn refcount() {
         let mut tracler = RegionTracker::new();
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         tracler
             .add_region(0x100, 0x300, MEMOPS_ALL, &mut pool)
             .unwrap();
@@ -981,6 +1324,172 @@ mod tests {
         assert!(!access.overlap(&dummy_access(2, 10)));
         assert!(!access.overlap(&dummy_access(20, 28)));
     }
+    /// A small deterministic pseudo-random generator.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    const OPS: [MemOps; 3] = [MEMOPS_ALL, MemOps::READ, MemOps::READ.union(MemOps::WRITE)];
+
+    /// Checks the tracker against a model keeping the counters of each page.
+    #[test]
+    fn random_updates() {
+        const NB_PAGES: usize = 64;
+        const PAGE: usize = 0x1000;
+
+        let mut tracker = RegionTracker::new();
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
+        let mut pages = [(0, (0, 0, 0, 0)); NB_PAGES];
+        let mut added = Vec::new();
+        let mut rng = Rng(0x5eed);
+
+        for _ in 0..4000 {
+            let before = pages;
+            let change = if added.is_empty() || rng.below(3) != 0 {
+                let start = rng.below(NB_PAGES - 8);
+                let end = start + rng.below(8) + 1;
+                let ops = OPS[rng.below(OPS.len())];
+                let (r, w, x, s) = ops.as_counters();
+                for (count, ops) in &mut pages[start..end] {
+                    *count += 1;
+                    *ops = (ops.0 + r, ops.1 + w, ops.2 + x, ops.3 + s);
+                }
+                added.push((start, end, ops));
+                tracker.add_region(start * PAGE, end * PAGE, ops, &mut pool)
+            } else {
+                let (start, end, ops) = added.swap_remove(rng.below(added.len()));
+                let (r, w, x, s) = ops.as_counters();
+                for (count, ops) in &mut pages[start..end] {
+                    *count -= 1;
+                    *ops = (ops.0 - r, ops.1 - w, ops.2 - x, ops.3 - s);
+                }
+                tracker.remove_region(start * PAGE, end * PAGE, ops, &mut pool)
+            };
+            tracker.validate(&pool);
+
+            // A change is reported if any counter went from or to zero
+            let toggled = |a: usize, b: usize| (a == 0) != (b == 0);
+            let expected = before.iter().zip(pages.iter()).any(|(a, b)| {
+                toggled(a.0, b.0)
+                    || toggled(a.1 .0, b.1 .0)
+                    || toggled(a.1 .1, b.1 .1)
+                    || toggled(a.1 .2, b.1 .2)
+                    || toggled(a.1 .3, b.1 .3)
+            });
+            assert!((change.unwrap() == PermissionChange::Some) == expected);
+
+            for (page, counters) in pages.iter().enumerate() {
+                assert_eq!(tracker.counters_at(page * PAGE, &pool), *counters);
+            }
+
+            // Adjacent regions are always coalesced
+            let mut previous: Option<&Region> = None;
+            for (_, region) in tracker.iter(&pool) {
+                if let Some(previous) = previous {
+                    assert!(previous.end != region.start || !previous.same_counts(region));
+                }
+                previous = Some(region);
+            }
+        }
+    }
+
+    /// Compares the tracker with the list-based tracker it replaced, run with:
+    /// `cargo test --release -p capa-engine -- --ignored --nocapture tracker_benchmark`
+    #[test]
+    #[ignore]
+    fn tracker_benchmark() {
+        use std::time::{Duration, Instant};
+
+        const PAGE: usize = 0x1000;
+
+        /// Adds page-granular regions, enumerates the permissions and removes the pages.
+        fn run<T>(
+            nb_pages: usize,
+            tracker: &mut T,
+            add: impl Fn(&mut T, usize, usize, MemOps, &mut TrackerPool),
+            remove: impl Fn(&mut T, usize, usize, MemOps, &mut TrackerPool),
+            permissions: impl Fn(&T, &TrackerPool) -> usize,
+        ) -> (Duration, Duration, Duration) {
+            let mut pool = TrackerPool::leak(nb_pages + 1, |_| EMPTY_REGION);
+            let mut order: Vec<usize> = (0..nb_pages).collect();
+            let mut rng = Rng(0x5eed);
+            for idx in (1..nb_pages).rev() {
+                order.swap(idx, rng.below(idx + 1));
+            }
+            // Alternate the permissions, so that pages are not coalesced
+            let ops = |page: usize| OPS[page % 2];
+
+            let now = Instant::now();
+            for &page in &order {
+                add(
+                    tracker,
+                    page * PAGE,
+                    (page + 1) * PAGE,
+                    ops(page),
+                    &mut pool,
+                );
+            }
+            let add_time = now.elapsed();
+
+            let now = Instant::now();
+            assert_eq!(permissions(tracker, &pool), nb_pages);
+            let permissions_time = now.elapsed();
+
+            let now = Instant::now();
+            for &page in order.iter().rev() {
+                remove(
+                    tracker,
+                    page * PAGE,
+                    (page + 1) * PAGE,
+                    ops(page),
+                    &mut pool,
+                );
+            }
+            let remove_time = now.elapsed();
+            assert_eq!(permissions(tracker, &pool), 0);
+
+            (add_time, permissions_time, remove_time)
+        }
+
+        println!(
+            "pages      | tracker (add / permissions / remove) | list (add / permissions / remove)"
+        );
+        for nb_pages in [256, 1024, 4096, 16384] {
+            let tree = run(
+                nb_pages,
+                &mut RegionTracker::new(),
+                |t, start, end, ops, pool| {
+                    t.add_region(start, end, ops, pool).unwrap();
+                },
+                |t, start, end, ops, pool| {
+                    t.remove_region(start, end, ops, pool).unwrap();
+                },
+                |t, pool| t.permissions(pool).count(),
+            );
+            let list = run(
+                nb_pages,
+                &mut ListTracker::new(),
+                |t, start, end, ops, pool| {
+                    t.add_region(start, end, ops, pool).unwrap();
+                },
+                |t, start, end, ops, pool| {
+                    t.remove_region(start, end, ops, pool).unwrap();
+                },
+                |t, pool| t.permissions(pool).count(),
+            );
+            println!(
+                "{:<10} | {:>10.2?} / {:>10.2?} / {:>10.2?} | {:>10.2?} / {:>10.2?} / {:>10.2?}",
+                nb_pages, tree.0, tree.1, tree.2, list.0, list.1, list.2
+            );
+        }
+    }
 }
 
 // ———————————————————————————————— Display ————————————————————————————————— //
@@ -0,0 +1,493 @@
+//! List-based Region Tracker
+//!
+//! The tracker used before regions were indexed by a tree: regions are kept in a singly linked
+//! list, which is walked linearly on every update. It is kept as a reference for testing and
+//! benchmarking the tree-based [RegionTracker](super::RegionTracker).
+
+use super::{
+    PermissionChange, PermissionIterator, Region, RegionIterator, TrackerPool, EMPTY_REGION,
+};
+use crate::gen_arena::Handle;
+use crate::{CapaError, MemOps};
+
+fn with_next(mut region: Region, next: Option<Handle<Region>>) -> Region {
+    region.next = next;
+    region
+}
+
+pub(super) struct ListTracker {
+    head: Option<Handle<Region>>,
+}
+
+impl ListTracker {
+    pub const fn new() -> Self {
+        Self { head: None }
+    }
+
+    pub fn remove_region(
+        &mut self,
+        start: usize,
+        end: usize,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> Result<PermissionChange, CapaError> {
+        log::trace!("Removing region [0x{:x}, 0x{:x}]", start, end);
+
+        assert!(start <= end);
+        if start == end {
+            // Empty region: nothing to do
+            return Ok(PermissionChange::None);
+        }
+
+        let (Some(mut bound), mut prev) = self.find_lower_bound(start, tracker) else {
+            log::trace!("Region does not exist");
+            return Err(CapaError::InvalidRegion);
+        };
+
+        // Check if we need a split for the start.
+        if tracker[bound].start < start {
+            prev = Some(bound);
+            bound = self.split_region_at(bound, start, tracker)?;
+        }
+        // Check if we need a split for the end.
+        if tracker[bound].end > end {
+            let _ = self.split_region_at(bound, end, tracker)?;
+        }
+
+        assert_eq!(
+            tracker[bound].start, start,
+            "Remove region must specify exact boundaries"
+        );
+
+        let mut change = PermissionChange::None;
+        let mut next = bound;
+        while tracker[next].start < end {
+            let mut update = self.decrease_refcount(next, tracker);
+            update.update(self.decrease_ops(next, ops, tracker));
+            change.update(update);
+
+            // Free regions with ref_count 0.
+            if tracker[next].ref_count == 0 {
+                // Remove the element from the list.
+                let to_visit = tracker[next].next;
+                match prev {
+                    Some(handle) => {
+                        tracker[handle].next = tracker[next].next;
+                    }
+                    None => {
+                        self.head = tracker[next].next;
+                    }
+                }
+                // Free the region.
+                tracker.free(next);
+
+                // Update next.
+                match to_visit {
+                    Some(handle) => {
+                        next = handle;
+                        continue;
+                    }
+                    None => {
+                        break;
+                    }
+                }
+                // End of free block.
+            }
+
+            match &tracker[next].next {
+                Some(handle) => {
+                    prev = Some(next);
+                    next = *handle;
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+
+        // coalesce.
+        self.coalesce(tracker);
+        Ok(change)
+    }
+
+    pub fn add_region(
+        &mut self,
+        start: usize,
+        end: usize,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> Result<PermissionChange, CapaError> {
+        log::trace!("Adding region [0x{:x}, 0x{:x}]", start, end);
+
+        assert!(start <= end);
+        if start == end {
+            // return immediately, nothing to do
+            return Ok(PermissionChange::None);
+        }
+
+        // There is no region yet, insert head and exit
+        let Some(head) = self.head else {
+            self.insert_head(start, end, ops, tracker)?;
+            return Ok(PermissionChange::Some);
+        };
+
+        let mut change = PermissionChange::None;
+        let (mut previous, mut cursor) =
+            if let (Some(lower_bound), _) = self.find_lower_bound(start, tracker) {
+                let region = &tracker[lower_bound];
+                if start == region.start {
+                    // Regions have the same start
+                    let (previous, update) =
+                        self.partial_add_region_overlapping(start, end, lower_bound, ops, tracker)?;
+                    change.update(update);
+                    let cursor = tracker[previous].end;
+                    (previous, cursor)
+                } else if region.contains(start) {
+                    // Region start in the middle of the lower bound region
+                    self.split_region_at(lower_bound, start, tracker)?;
+                    (lower_bound, start)
+                } else {
+                    // Region starts after lower bound region
+                    (lower_bound, start)
+                }
+            } else {
+                let head = &tracker[head];
+                let cursor = core::cmp::min(end, head.start);
+                let previous = self.insert_head(start, cursor, ops, tracker)?;
+                change = PermissionChange::Some;
+                (previous, cursor)
+            };
+
+        // Add the remaining portions of the region
+        while cursor < end {
+            let (next, update) =
+                self.partial_add_region_after(cursor, end, previous, ops, tracker)?;
+            previous = next;
+            change.update(update);
+            cursor = tracker[previous].end;
+        }
+
+        // Coalesce.
+        self.coalesce(tracker);
+        Ok(change)
+    }
+
+    fn partial_add_region_after(
+        &mut self,
+        start: usize,
+        end: usize,
+        after: Handle<Region>,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> Result<(Handle<Region>, PermissionChange), CapaError> {
+        let region = &mut tracker[after];
+
+        assert!(start < end, "Tried to add invalid region");
+        assert!(region.end <= start, "Invalid add_region_after");
+
+        // Check how much of the region can fit before the next one
+        let mut end = end;
+        if let Some(next_handle) = region.next {
+            let next = &mut tracker[next_handle];
+            if start == next.start {
+                // Overlapping
+                return self.partial_add_region_overlapping(start, end, next_handle, ops, tracker);
+            } else if end > next.start {
+                // Fit as much as possible
+                end = next.start;
+            }
+        }
+        self.insert_after(start, end, ops, after, tracker)
+    }
+
+    fn partial_add_region_overlapping(
+        &mut self,
+        start: usize,
+        end: usize,
+        overlapping: Handle<Region>,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> Result<(Handle<Region>, PermissionChange), CapaError> {
+        let region = &tracker[overlapping];
+        assert!(
+            region.start == start,
+            "Region is not overlapping from the start"
+        );
+
+        if end < region.end {
+            self.split_region_at(overlapping, end, tracker)?;
+        }
+        let mut change = self.increase_refcount(overlapping, tracker);
+        change.update(self.increase_ops(overlapping, ops, tracker));
+        Ok((overlapping, change))
+    }
+
+    /// Returns a handle to the region with the closest (inferior or equal) start address.
+    /// First value is the closest, second is the previous element.
+    fn find_lower_bound(
+        &self,
+        start: usize,
+        tracker: &mut TrackerPool,
+    ) -> (Option<Handle<Region>>, Option<Handle<Region>>) {
+        let Some(mut closest) = self.head else {
+            return (None, None);
+        };
+
+        if tracker[closest].start > start {
+            // The first region already starts at a higher address
+            return (None, None);
+        }
+        let mut prev = None;
+        let mut iter = None;
+        for (handle, region) in self.iter(tracker) {
+            if region.start <= start {
+                prev = iter;
+                closest = handle
+            } else {
+                break;
+            }
+            iter = Some(handle);
+        }
+
+        (Some(closest), prev)
+    }
+
+    /// Split the given region at the provided address. Returns a handle to the second half (the
+    /// first hald keeps the same handle).
+    fn split_region_at(
+        &mut self,
+        handle: Handle<Region>,
+        at: usize,
+        tracker: &mut TrackerPool,
+    ) -> Result<Handle<Region>, CapaError> {
+        let region = &tracker[handle];
+        assert!(
+            region.contains(at),
+            "Tried to split at an address that is not contained in the region"
+        );
+
+        // Allocate the second half
+        let second_half = Region {
+            start: at,
+            end: region.end,
+            read_count: region.read_count,
+            write_count: region.write_count,
+            exec_count: region.exec_count,
+            super_count: region.super_count,
+            ref_count: region.ref_count,
+            next: region.next,
+            ..EMPTY_REGION
+        };
+        let second_half_handle = tracker.allocate(second_half).ok_or_else(|| {
+            log::error!("Unable to allocate new region! Runned out of Tracker");
+            CapaError::OutOfMemory
+        })?;
+
+        // Update the first half
+        let region = &mut tracker[handle];
+        region.end = at;
+        region.next = Some(second_half_handle);
+
+        Ok(second_half_handle)
+    }
+
+    /// Insert a fresh region after the region pointer by the `after` handle. Returns a handle
+    /// to the inserted region.
+    fn insert_after(
+        &mut self,
+        start: usize,
+        end: usize,
+        ops: MemOps,
+        after: Handle<Region>,
+        tracker: &mut TrackerPool,
+    ) -> Result<(Handle<Region>, PermissionChange), CapaError> {
+        let region = &tracker[after];
+        assert!(start >= region.end, "Regions should be sorted by addresses");
+        if let Some(next) = region.next {
+            assert!(
+                end <= tracker[next].start,
+                "Regions should be sorted by addresses"
+            );
+        }
+
+        let handle = tracker
+            .allocate(with_next(Region::new(start, end, ops), region.next))
+            .ok_or_else(|| {
+                log::error!("Unable to allocate new region! Increase number of Trackers");
+                CapaError::OutOfMemory
+            })?;
+        let region = &mut tracker[after];
+        region.next = Some(handle);
+
+        // There is alway a permission change in this case
+        Ok((handle, PermissionChange::Some))
+    }
+
+    fn insert_head(
+        &mut self,
+        start: usize,
+        end: usize,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> Result<Handle<Region>, CapaError> {
+        if let Some(head) = self.head {
+            assert!(
+                tracker[head].start >= end,
+                "Region should be sorted by address"
+            );
+        }
+
+        let region = with_next(Region::new(start, end, ops), self.head);
+        let handle = tracker.allocate(region).ok_or_else(|| {
+            log::error!("Unable to allocate new region! Increase number of trackers");
+            CapaError::OutOfMemory
+        })?;
+        self.head = Some(handle);
+        Ok(handle)
+    }
+
+    fn increase_refcount(
+        &mut self,
+        handle: Handle<Region>,
+        tracker: &mut TrackerPool,
+    ) -> PermissionChange {
+        let region = &mut tracker[handle];
+        region.ref_count += 1;
+
+        if region.ref_count == 1 {
+            PermissionChange::Some
+        } else {
+            PermissionChange::None
+        }
+    }
+
+    fn increase_ops(
+        &mut self,
+        handle: Handle<Region>,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> PermissionChange {
+        let region = &mut tracker[handle];
+        let mut change = PermissionChange::None;
+        if ops.contains(MemOps::READ) {
+            region.read_count += 1;
+            if region.read_count == 1 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::WRITE) {
+            region.write_count += 1;
+            if region.write_count == 1 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::EXEC) {
+            region.exec_count += 1;
+            if region.exec_count == 1 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::SUPER) {
+            region.super_count += 1;
+            if region.super_count == 1 {
+                change = PermissionChange::Some;
+            }
+        }
+        return change;
+    }
+
+    fn decrease_refcount(
+        &mut self,
+        handle: Handle<Region>,
+        tracker: &mut TrackerPool,
+    ) -> PermissionChange {
+        let region = &mut tracker[handle];
+        region.ref_count = region.ref_count.checked_sub(1).unwrap();
+
+        if region.ref_count == 0 {
+            PermissionChange::Some
+        } else {
+            PermissionChange::None
+        }
+    }
+
+    fn decrease_ops(
+        &mut self,
+        handle: Handle<Region>,
+        ops: MemOps,
+        tracker: &mut TrackerPool,
+    ) -> PermissionChange {
+        let region = &mut tracker[handle];
+        let mut change = PermissionChange::None;
+        if ops.contains(MemOps::READ) {
+            region.read_count = region.read_count.checked_sub(1).unwrap();
+            if region.read_count == 0 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::WRITE) {
+            region.write_count = region.write_count.checked_sub(1).unwrap();
+            if region.write_count == 0 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::EXEC) {
+            region.exec_count = region.exec_count.checked_sub(1).unwrap();
+            if region.exec_count == 0 {
+                change = PermissionChange::Some;
+            }
+        }
+        if ops.contains(MemOps::SUPER) {
+            region.super_count = region.super_count.checked_sub(1).unwrap();
+            if region.super_count == 0 {
+                change = PermissionChange::Some;
+            }
+        }
+        return change;
+    }
+
+    fn coalesce(&mut self, tracker: &mut TrackerPool) {
+        if self.head == None {
+            // Nothing to do.
+            return;
+        }
+        let mut prev = self.head.unwrap();
+        let mut curr = tracker[prev].next;
+
+        // Go through the list.
+        while curr != None {
+            let current = curr.unwrap();
+            if tracker[prev].end == tracker[current].start
+                && (tracker[prev].same_counts(&tracker[current])
+                    || tracker[current].start == tracker[current].end
+                    || tracker[prev].start == tracker[prev].end)
+            {
+                // Coalesce
+                if tracker[prev].start == tracker[prev].end {
+                    tracker[prev].ref_count = tracker[current].ref_count;
+                }
+                tracker[prev].next = tracker[current].next;
+                tracker[prev].end = tracker[current].end;
+                tracker.free(curr.unwrap());
+                curr = tracker[prev].next;
+                continue;
+            }
+            prev = curr.unwrap();
+            curr = tracker[current].next;
+        }
+    }
+
+    pub fn iter<'a>(&'a self, pool: &'a TrackerPool) -> RegionIterator<'a> {
+        RegionIterator {
+            pool,
+            next: self.head,
+        }
+    }
+
+    pub fn permissions<'a>(&'a self, pool: &'a TrackerPool) -> PermissionIterator<'a> {
+        PermissionIterator {
+            pool,
+            next: self.head,
+        }
+    }
+}
@@ -5,11 +5,12 @@
 
 use core::{cmp, fmt};
 
+use crate::pool::PoolMemory;
 use crate::region::{MemoryPermission, PermissionIterator};
 use crate::{CapaError, GenArena, Handle, MemOps};
 
-pub struct Remapper<const N: usize> {
-    segments: GenArena<Segment, N>,
+pub struct Remapper {
+    segments: GenArena<Segment>,
     head: Option<Handle<Segment>>,
 }
 
@@ -61,15 +62,34 @@ impl Segment {
     }
 }
 
-impl<const N: usize> Remapper<N> {
-    pub const fn new() -> Self {
+impl Remapper {
+    /// An empty remapper, which can not hold any segment.
+    pub const fn empty() -> Self {
         Remapper {
-            segments: GenArena::new([EMPTY_SEGMENT; N]),
+            segments: GenArena::empty(),
             head: None,
         }
     }
 
-    pub fn remap<'a>(&'a self, regions: PermissionIterator<'a>) -> RemapIterator<'a, N> {
+    /// Creates a remapper that can hold up to `nb_segments` segments.
+    pub fn new(memory: &mut PoolMemory, nb_segments: usize) -> Result<Self, CapaError> {
+        Ok(Remapper {
+            segments: GenArena::new(memory, nb_segments, |_| EMPTY_SEGMENT)?,
+            head: None,
+        })
+    }
+
+    /// Number of bytes needed to create a remapper with `nb_segments` segments.
+    pub const fn memory_size(nb_segments: usize) -> usize {
+        GenArena::<Segment>::memory_size(nb_segments)
+    }
+
+    /// Return OK if `count` more segments can be mapped, Err otherwise.
+    pub fn has_capacity_for(&self, count: usize) -> Result<(), CapaError> {
+        self.segments.has_capacity_for(count)
+    }
+
+    pub fn remap<'a>(&'a self, regions: PermissionIterator<'a>) -> RemapIterator<'a> {
         RemapIterator {
             regions,
             next_region_start: None,
@@ -81,7 +101,7 @@ impl<const N: usize> Remapper<N> {
         }
     }
 
-    pub fn iter_segments(&self) -> RemapperSegmentIterator<'_, N> {
+    pub fn iter_segments(&self) -> RemapperSegmentIterator<'_> {
         RemapperSegmentIterator {
             remapper: self,
             next_segment: self.head,
@@ -303,9 +323,9 @@ impl<const N: usize> Remapper<N> {
 // ——————————————————————————————— Iterators ———————————————————————————————— //
 
 #[derive(Clone)]
-pub struct RemapIterator<'a, const N: usize> {
+pub struct RemapIterator<'a> {
     regions: PermissionIterator<'a>,
-    segments: RemapperSegmentIterator<'a, N>,
+    segments: RemapperSegmentIterator<'a>,
     cursor: usize,
     next_region_start: Option<usize>,
     next_segment_start: Option<usize>,
@@ -313,7 +333,7 @@ pub struct RemapIterator<'a, const N: usize> {
     ongoing_segment: Option<SingleSegmentIterator<'a>>,
 }
 
-impl<'a, const N: usize> Iterator for RemapIterator<'a, N> {
+impl<'a> Iterator for RemapIterator<'a> {
     type Item = Mapping;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -507,12 +527,12 @@ impl<'a> SingleSegmentIterator<'a> {
 
 /// An iterator over the remapper segments.
 #[derive(Clone)]
-pub struct RemapperSegmentIterator<'a, const N: usize> {
-    remapper: &'a Remapper<N>,
+pub struct RemapperSegmentIterator<'a> {
+    remapper: &'a Remapper,
     next_segment: Option<Handle<Segment>>,
 }
 
-impl<'a, const N: usize> Iterator for RemapperSegmentIterator<'a, N> {
+impl<'a> Iterator for RemapperSegmentIterator<'a> {
     type Item = &'a Segment;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -528,6 +548,14 @@ impl<'a, const N: usize> Iterator for RemapperSegmentIterator<'a, N> {
 
 // ————————————————————————————————— Tests —————————————————————————————————— //
 
+#[cfg(test)]
+impl Remapper {
+    fn leak(nb_segments: usize) -> Self {
+        let mut memory = crate::pool::leak_memory(Self::memory_size(nb_segments));
+        Self::new(&mut memory, nb_segments).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -564,9 +592,9 @@ mod tests {
 
     #[test]
     fn remap() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         // Add a first region
         tracker
@@ -655,9 +683,9 @@ mod tests {
 
     #[test]
     fn cross_regions() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         // Add two regions with hole
         tracker
@@ -685,9 +713,9 @@ mod tests {
 
     #[test]
     fn backward_overlap() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         tracker
             .add_region(0x10, 0x40, MEMOPS_ALL, &mut pool)
@@ -716,9 +744,9 @@ mod tests {
 
     #[test]
     fn forward_overlap() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         tracker
             .add_region(0x10, 0x40, MEMOPS_ALL, &mut pool)
@@ -741,9 +769,9 @@ mod tests {
 
     #[test]
     fn update_region() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         // Add one region
         tracker
@@ -784,9 +812,9 @@ mod tests {
 
     #[test]
     fn split_region() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         tracker
             .add_region(0x12fcb6000, 0x12fcf6000, MEMOPS_ALL, &mut pool)
@@ -806,9 +834,9 @@ mod tests {
 
     #[test]
     fn gpa_overlap() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         tracker
             .add_region(0x20, 0x80, MEMOPS_ALL, &mut pool)
@@ -832,7 +860,7 @@ mod tests {
 
     #[test]
     fn debug_iterator() {
-        let mut remapper: Remapper<32> = Remapper::new();
+        let mut remapper = Remapper::leak(32);
 
         remapper.map_range(0x10, 0x100, 0x20, 2).unwrap();
         snap("{[0x10, 0x30 at 0x100, rep 2]}", &remapper.iter_segments());
@@ -847,7 +875,7 @@ mod tests {
 
     #[test]
     fn single_segment_iterator() {
-        let mut pool = TrackerPool::new([EMPTY_REGION; NB_TRACKER]);
+        let mut pool = TrackerPool::leak(NB_TRACKER, |_| EMPTY_REGION);
         let mut tracker = RegionTracker::new();
 
         // Create a single region
@@ -942,7 +970,7 @@ impl fmt::Display for Mapping {
     }
 }
 
-impl<'a, const N: usize> fmt::Display for RemapIterator<'a, N> {
+impl<'a> fmt::Display for RemapIterator<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut first = true;
         write!(f, "{{")?;
@@ -958,7 +986,7 @@ impl<'a, const N: usize> fmt::Display for RemapIterator<'a, N> {
     }
 }
 
-impl<'a, const N: usize> fmt::Display for RemapperSegmentIterator<'a, N> {
+impl<'a> fmt::Display for RemapperSegmentIterator<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut first = true;
         write!(f, "{{")?;
@@ -0,0 +1,88 @@
+//! Revocation Notifications
+//!
+//! When a region is revoked from under a domain, for instance by the domain that sent it, the
+//! holder loses access to the memory without being asked. To let the holder unmap the region
+//! gracefully rather than fault on its next access, each revocation is recorded in a small queue
+//! of pending notifications that the holder can poll.
+
+use core::fmt;
+
+use crate::config::NB_PENDING_REVOCATIONS;
+use crate::snapshot::{Reader, Snapshot, Writer};
+use crate::{CapaError, LocalCapa};
+
+/// A region capability that was revoked from a domain.
+#[derive(Clone, Copy, Debug)]
+pub struct RevokedRegion {
+    /// The capability of the domain that referred to the region.
+    pub capa: LocalCapa,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The revocation notifications not yet polled by a domain.
+///
+/// Notifications are dropped once the queue is full, the holder is expected to poll often enough.
+pub(crate) struct RevocationQueue {
+    entries: [Option<RevokedRegion>; NB_PENDING_REVOCATIONS],
+    read: usize,
+    len: usize,
+}
+
+impl RevocationQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [None; NB_PENDING_REVOCATIONS],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, revoked: RevokedRegion) {
+        if self.len == NB_PENDING_REVOCATIONS {
+            log::warn!("Revocation queue is full, dropping {}", revoked);
+            return;
+        }
+        let write = (self.read + self.len) % NB_PENDING_REVOCATIONS;
+        self.entries[write] = Some(revoked);
+        self.len += 1;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<RevokedRegion> {
+        if self.len == 0 {
+            return None;
+        }
+        let revoked = self.entries[self.read].take();
+        self.read = (self.read + 1) % NB_PENDING_REVOCATIONS;
+        self.len -= 1;
+        revoked
+    }
+}
+
+impl Snapshot for RevocationQueue {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.entries.save(w)?;
+        self.read.save(w)?;
+        self.len.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Self {
+            entries: Snapshot::load(r)?,
+            read: usize::load(r)?,
+            len: usize::load(r)?,
+        })
+    }
+}
+
+impl fmt::Display for RevokedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Revoked({}, [0x{:x}, 0x{:x}])",
+            self.capa.as_usize(),
+            self.start,
+            self.end
+        )
+    }
+}
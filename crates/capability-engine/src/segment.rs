@@ -2,17 +2,20 @@
 
 use core::cell::Cell;
 
-use crate::config::NB_REGIONS;
+use crate::capa::Capa;
 use crate::debug::debug_check;
 use crate::domain::{activate_region, deactivate_region, insert_capa, DomainPool};
+use crate::quota::{self, Usage};
 use crate::region::TrackerPool;
+use crate::revocation::RevokedRegion;
+use crate::snapshot::{self, Reader, Snapshot, Writer};
 use crate::update::{Update, UpdateBuffer};
 use crate::{
     domain, AccessRights, CapaError, Domain, GenArena, Handle, LocalCapa, MemOps, MEMOPS_ALL,
 };
 
 pub type RegionHash = [u8; 32];
-pub(crate) type RegionPool = GenArena<RegionCapa, NB_REGIONS>;
+pub(crate) type RegionPool = GenArena<RegionCapa>;
 pub const EMPTY_REGION_CAPA: RegionCapa = RegionCapa::new_invalid();
 
 pub enum RegionKind {
@@ -22,12 +25,14 @@ pub enum RegionKind {
 }
 
 pub struct RegionCapa {
-    domain: Handle<Domain>,
+    pub(crate) domain: Handle<Domain>,
     pub(crate) child_list_head: Option<Handle<RegionCapa>>,
     next_sibling: Option<Handle<RegionCapa>>,
     pub(crate) kind: RegionKind,
     pub(crate) is_confidential: bool,
     pub(crate) access: AccessRights,
+    /// The labels of the domains the region went through, see [crate::flow].
+    pub(crate) labels: u64,
     /// A temporary ID used when building an attestation
     pub(crate) temporary_id: Cell<u32>,
     pub(crate) hash: Option<RegionHash>,
@@ -42,6 +47,7 @@ impl RegionCapa {
             next_sibling: None,
             is_confidential: false,
             access: AccessRights::none(),
+            labels: 0,
             temporary_id: Cell::new(0),
             hash: None,
         }
@@ -55,6 +61,7 @@ impl RegionCapa {
             next_sibling: None,
             is_confidential: false,
             access,
+            labels: 0,
             temporary_id: Cell::new(0),
             hash: None,
         }
@@ -74,6 +81,12 @@ impl RegionCapa {
         }
     }
 
+    /// Sets the labels of the region.
+    pub fn labeled(mut self, labels: u64) -> Self {
+        self.labels = labels;
+        self
+    }
+
     /// Update the confidential attripute.
     pub fn confidential(mut self, confidential: bool) -> Self {
         self.is_confidential = confidential;
@@ -106,6 +119,27 @@ impl RegionCapa {
     }
 }
 
+/// Returns the amount of memory owned by a region, that is the memory that is not carved out of it.
+///
+/// Aliases, and the regions carved out of them, do not own memory, so that each byte of memory is
+/// owned by a single region.
+pub(crate) fn owned_memory(handle: Handle<RegionCapa>, regions: &RegionPool) -> usize {
+    let mut cursor = handle;
+    loop {
+        match regions[cursor].kind {
+            RegionKind::Root => break,
+            RegionKind::Alias(_) => return 0,
+            RegionKind::Carve(parent) => cursor = parent,
+        }
+    }
+    let region = &regions[handle];
+    let carved: usize = RegionIterator::child_list(handle, regions)
+        .filter(|child| child.is_carved())
+        .map(|child| child.access.end - child.access.start)
+        .sum();
+    region.access.end - region.access.start - carved
+}
+
 pub(crate) fn create_root_region(
     domain: Handle<Domain>,
     regions: &mut RegionPool,
@@ -127,6 +161,7 @@ pub(crate) fn create_root_region(
     let region = regions
         .allocate(RegionCapa::new(domain, RegionKind::Root, access).confidential(true))
         .unwrap();
+    quota::charge(domain, domains, Usage::region(access.end - access.start));
     let local_capa = insert_capa(domain, region, regions, domains)?;
     activate_region(domain, access, domains, updates, tracker)?;
 
@@ -150,6 +185,10 @@ pub(crate) fn send(
         old_domain
     };
 
+    let usage = Usage::region(owned_memory(handle, regions));
+    quota::release(old_domain, domains, usage);
+    quota::charge(domain, domains, usage);
+
     for reg in EffectiveRegionIterator::active_regions(handle, regions) {
         deactivate_region(old_domain, reg, domains, updates, tracker)?;
         activate_region(domain, reg, domains, updates, tracker)?;
@@ -175,6 +214,7 @@ pub(crate) fn alias(
 
     let new_handle = alias_region(handle, regions, access)?;
     debug_check!(validate_child_list(handle, regions));
+    quota::charge(domain, domains, Usage::region(0));
     let local_capa = insert_capa(domain, new_handle, regions, domains)?;
     activate_region(domain, access, domains, updates, tracker)?;
 
@@ -189,13 +229,15 @@ fn alias_region(
 ) -> Result<Handle<RegionCapa>, CapaError> {
     let region = regions.get(handle).ok_or(CapaError::InvalidCapa)?;
     let domain_handle = region.domain;
+    let labels = region.labels;
 
     if !access.is_valid() || !check_alias(handle, &access, regions) {
         return Err(CapaError::InvalidOperation);
     }
 
-    let new_region =
-        RegionCapa::new(domain_handle, RegionKind::Alias(handle), access).confidential(false);
+    let new_region = RegionCapa::new(domain_handle, RegionKind::Alias(handle), access)
+        .confidential(false)
+        .labeled(labels);
     let new_handle = regions.allocate(new_region).ok_or_else(|| {
         log::error!("Unable to allocate new region! Increase number of regions");
         CapaError::OutOfMemory
@@ -223,6 +265,7 @@ pub(crate) fn carve(
 
     let new_handle = carve_region(handle, regions, access)?;
     debug_check!(validate_child_list(handle, regions));
+    quota::charge(domain, domains, Usage::region(0));
     let local_capa = insert_capa(domain, new_handle, regions, domains)?;
     // Update the tracker here if permissions were reduced.
     if access.ops != region_access.ops {
@@ -247,13 +290,15 @@ fn carve_region(
     let region = regions.get(handle).ok_or(CapaError::InvalidCapa)?;
     let domain_handle = region.domain;
     let is_confidential = region.is_confidential;
+    let labels = region.labels;
 
     if !access.is_valid() || !check_carve(handle, &access, regions) {
         return Err(CapaError::InvalidOperation);
     }
 
     let new_region = RegionCapa::new(domain_handle, RegionKind::Carve(handle), access)
-        .confidential(is_confidential);
+        .confidential(is_confidential)
+        .labeled(labels);
     let new_handle = regions.allocate(new_region).ok_or_else(|| {
         log::error!("Unable to allocate new region! Increase the number of regions");
         CapaError::OutOfMemory
@@ -262,8 +307,10 @@ fn carve_region(
     Ok(new_handle)
 }
 
+/// Revokes a region and all of its children, on behalf of the `revoker` domain.
 pub(crate) fn revoke(
     handle: Handle<RegionCapa>,
+    revoker: Handle<Domain>,
     regions: &mut RegionPool,
     domains: &mut DomainPool,
     tracker: &mut TrackerPool,
@@ -272,26 +319,96 @@ pub(crate) fn revoke(
     let region = &regions[handle];
     //let region_owner = region.domain;
     let region_access = region.access;
-    let parent = match region.kind {
-        RegionKind::Root => panic!("Trying to revoke a root region"),
-        RegionKind::Alias(h) => h,
-        RegionKind::Carve(h) => h,
-    };
+    if region.is_root() {
+        panic!("Trying to revoke a root region");
+    }
 
     // Recursively free all of this regions's children
     while let Some(child) = regions[handle].child_list_head {
         // Remove the first child from the linked list until we exhaust it
-        revoke(child, regions, domains, tracker, updates)?;
+        revoke(child, revoker, regions, domains, tracker, updates)?;
     }
 
+    notify_revocation(handle, revoker, regions, domains, updates)?;
+    remove(handle, regions, domains, tracker, updates)?;
+
+    // Apply side effetcs, if any
+    if region_access.ops.contains(MemOps::VITAL) {
+        // This was a vital region, we need to revoke the current domain
+        //TODO(aghosn): disabled for now
+        //domain::revoke(region_owner, regions, domains, tracker, updates)?;
+    }
+    if region_access.ops.contains(MemOps::CLEANUP)
+    /*&& region_access.ops.contains(MemOps::WRITE)*/
+    {
+        // We need to zero-out this region, emmit an update
+        updates.push(Update::Cleanup {
+            start: region_access.start,
+            end: region_access.end,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Tells the holder of a region revoked by another domain which capability and range went away.
+fn notify_revocation(
+    handle: Handle<RegionCapa>,
+    revoker: Handle<Domain>,
+    regions: &RegionPool,
+    domains: &mut DomainPool,
+    updates: &mut UpdateBuffer,
+) -> Result<(), CapaError> {
+    let region = &regions[handle];
+    let holder = region.domain;
+    if holder == revoker || domains[holder].is_being_revoked() {
+        return Ok(());
+    }
+    let capa = domains[holder].find_capa(|capa| match capa {
+        Capa::Region(h) => *h == handle,
+        _ => false,
+    });
+    let Some(capa) = capa else {
+        return Ok(());
+    };
+
+    let (start, end) = (region.access.start, region.access.end);
+    domains[holder].notify_revocation(RevokedRegion { capa, start, end });
+    updates.push(Update::RegionRevoked {
+        domain: holder,
+        capa,
+        start,
+        end,
+    })
+}
+
+/// Removes a region without children and frees it, without applying the revocation side effects.
+///
+/// This is also used to undo the creation of a region.
+pub(crate) fn remove(
+    handle: Handle<RegionCapa>,
+    regions: &mut RegionPool,
+    domains: &mut DomainPool,
+    tracker: &mut TrackerPool,
+    updates: &mut UpdateBuffer,
+) -> Result<(), CapaError> {
+    let parent = match regions[handle].kind {
+        RegionKind::Root => panic!("Trying to remove a root region"),
+        RegionKind::Alias(h) => h,
+        RegionKind::Carve(h) => h,
+    };
+    assert!(regions[handle].child_list_head.is_none());
+
     // Remove capability
     remove_child(parent, handle, regions);
 
     // Update permissions
     let region = &regions[handle];
+    let memory = owned_memory(handle, regions);
     deactivate_region(region.domain, region.access, domains, updates, tracker)?;
+    quota::release(region.domain, domains, Usage::region(memory));
     if region.is_carved() {
-        // Also update parent's permissions
+        // Also update parent's permissions, the memory goes back to the parent
         let parent_region = &regions[parent];
         let access = AccessRights {
             start: region.access.start,
@@ -299,28 +416,12 @@ pub(crate) fn revoke(
             ops: parent_region.access.ops,
         };
         activate_region(parent_region.domain, access, domains, updates, tracker)?;
+        quota::charge(parent_region.domain, domains, Usage::memory(memory));
     }
 
     // Definitively free the handle
     regions.free(handle);
     debug_check!(validate_child_list(parent, regions));
-
-    // Apply side effetcs, if any
-    if region_access.ops.contains(MemOps::VITAL) {
-        // This was a vital region, we need to revoke the current domain
-        //TODO(aghosn): disabled for now
-        //domain::revoke(region_owner, regions, domains, tracker, updates)?;
-    }
-    if region_access.ops.contains(MemOps::CLEANUP)
-    /*&& region_access.ops.contains(MemOps::WRITE)*/
-    {
-        // We need to zero-out this region, emmit an update
-        updates.push(Update::Cleanup {
-            start: region_access.start,
-            end: region_access.end,
-        })?;
-    }
-
     Ok(())
 }
 
@@ -456,6 +557,95 @@ fn validate_child_list(region: Handle<RegionCapa>, regions: &RegionPool) {
     }
 }
 
+/// Panics if the region tree is malformed, or if the trackers of the domains do not match the
+/// effective regions they own.
+pub(crate) fn check_invariants(regions: &RegionPool, domains: &DomainPool, tracker: &TrackerPool) {
+    for handle in regions {
+        let region = &regions[handle];
+        assert!(
+            HandleIterator::child_list(handle, regions)
+                .nth(regions.size())
+                .is_none(),
+            "Child list contains a cycle"
+        );
+        validate_child_list(handle, regions);
+
+        // The region must be in its parent's child list
+        match region.kind {
+            RegionKind::Root => (),
+            RegionKind::Alias(parent) | RegionKind::Carve(parent) => {
+                assert!(regions.get(parent).is_some(), "Invalid parent");
+                assert!(
+                    HandleIterator::child_list(parent, regions).any(|h| h == handle),
+                    "Region is missing from its parent's child list"
+                );
+            }
+        }
+
+        // The owner, and only the owner, must hold the region capability
+        assert!(
+            domains.get(region.domain).is_some(),
+            "Region owned by an invalid domain"
+        );
+        for domain in domains {
+            let nb_capas = domains[domain]
+                .iter_capa()
+                .filter(|capa| matches!(capa, Capa::Region(h) if *h == handle))
+                .count();
+            let expected = if domain == region.domain { 1 } else { 0 };
+            assert_eq!(
+                nb_capas, expected,
+                "Domain {} holds {} capabilities to a region owned by {}",
+                domain, nb_capas, region.domain
+            );
+        }
+    }
+
+    // Both the trackers and the effective regions are piecewise constant, so it is enough to
+    // compare them at the boundaries.
+    for domain in domains {
+        let domain_tracker = domains[domain].regions();
+        domain_tracker.validate(tracker);
+        let check_at = |addr: usize| {
+            let mut expected = (0, (0, 0, 0, 0));
+            for handle in regions {
+                if regions[handle].domain != domain {
+                    continue;
+                }
+                for access in EffectiveRegionIterator::active_regions(handle, regions) {
+                    if access.start <= addr && addr < access.end {
+                        let (r, w, x, s) = access.ops.as_counters();
+                        let ops = &mut expected.1;
+                        expected.0 += 1;
+                        *ops = (ops.0 + r, ops.1 + w, ops.2 + x, ops.3 + s);
+                    }
+                }
+            }
+            assert_eq!(
+                domain_tracker.counters_at(addr, tracker),
+                expected,
+                "Tracker of domain {} does not match its regions at 0x{:x}",
+                domain,
+                addr
+            );
+        };
+
+        for (_, region) in domain_tracker.iter(tracker) {
+            check_at(region.get_start());
+            check_at(region.get_end());
+        }
+        for handle in regions {
+            if regions[handle].domain != domain {
+                continue;
+            }
+            for access in EffectiveRegionIterator::active_regions(handle, regions) {
+                check_at(access.start);
+                check_at(access.end);
+            }
+        }
+    }
+}
+
 /// Checks that a region with the provided access rights can be carved from the parent.
 fn check_alias(parent: Handle<RegionCapa>, access: &AccessRights, regions: &RegionPool) -> bool {
     let region = &regions[parent];
@@ -463,6 +653,11 @@ fn check_alias(parent: Handle<RegionCapa>, access: &AccessRights, regions: &Regi
         return false;
     }
 
+    // Empty regions do not overlap anything, and would break the ordering of the child list
+    if access.start == access.end {
+        return false;
+    }
+
     for child in RegionIterator::child_list(parent, regions) {
         if child.is_carved() && access.overlap(&child.access) {
             return false;
@@ -479,6 +674,11 @@ fn check_carve(parent: Handle<RegionCapa>, access: &AccessRights, regions: &Regi
         return false;
     }
 
+    // Empty regions do not overlap anything, and would break the ordering of the child list
+    if access.start == access.end {
+        return false;
+    }
+
     for child in RegionIterator::child_list(parent, regions) {
         if access.overlap(&child.access) {
             return false;
@@ -608,11 +808,67 @@ impl<'a> Iterator for EffectiveRegionIterator<'a> {
     }
 }
 
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl Snapshot for RegionKind {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match *self {
+            RegionKind::Root => 0u8.save(w),
+            RegionKind::Alias(parent) => {
+                1u8.save(w)?;
+                parent.save(w)
+            }
+            RegionKind::Carve(parent) => {
+                2u8.save(w)?;
+                parent.save(w)
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        match u8::load(r)? {
+            0 => Ok(RegionKind::Root),
+            1 => Ok(RegionKind::Alias(Handle::load(r)?)),
+            2 => Ok(RegionKind::Carve(Handle::load(r)?)),
+            tag => snapshot::invalid("region kind", tag as u64),
+        }
+    }
+}
+
+/// The temporary ID is only used while serializing attestations, it is not part of snapshots.
+impl Snapshot for RegionCapa {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.domain.save(w)?;
+        self.child_list_head.save(w)?;
+        self.next_sibling.save(w)?;
+        self.kind.save(w)?;
+        self.is_confidential.save(w)?;
+        self.access.save(w)?;
+        self.labels.save(w)?;
+        self.hash.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(RegionCapa {
+            domain: Handle::load(r)?,
+            child_list_head: Option::load(r)?,
+            next_sibling: Option::load(r)?,
+            kind: RegionKind::load(r)?,
+            is_confidential: bool::load(r)?,
+            access: AccessRights::load(r)?,
+            labels: u64::load(r)?,
+            temporary_id: Cell::new(0),
+            hash: Option::load(r)?,
+        })
+    }
+}
+
 // ————————————————————————————————— Tests —————————————————————————————————— //
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::NB_REGIONS;
     use crate::MEMOPS_ALL;
 
     fn dummy_region(start: usize, end: usize) -> RegionCapa {
@@ -637,7 +893,7 @@ mod tests {
 
     #[test]
     fn alias() {
-        let mut pool = GenArena::new([EMPTY_REGION_CAPA; NB_REGIONS]);
+        let mut pool = GenArena::leak(NB_REGIONS, |_| EMPTY_REGION_CAPA);
         let root = pool.allocate(dummy_region(0x10, 0x100)).unwrap();
         validate_child_list(root, &pool);
 
@@ -657,11 +913,12 @@ mod tests {
         assert!(alias_region(root, &mut pool, dummy_access(0x5, 0x30)).is_err());
         assert!(alias_region(root, &mut pool, dummy_access(0x20, 0x120)).is_err());
         assert!(alias_region(root, &mut pool, dummy_access(0x30, 0x20)).is_err());
+        assert!(alias_region(root, &mut pool, dummy_access(0x80, 0x80)).is_err());
     }
 
     #[test]
     fn carve() {
-        let mut pool = GenArena::new([EMPTY_REGION_CAPA; NB_REGIONS]);
+        let mut pool = GenArena::leak(NB_REGIONS, |_| EMPTY_REGION_CAPA);
         let root = pool.allocate(dummy_region(0x10, 0x100)).unwrap();
         validate_child_list(root, &pool);
 
@@ -678,6 +935,7 @@ mod tests {
         assert!(carve_region(root, &mut pool, dummy_access(0x20, 0x30)).is_err());
         assert!(carve_region(root, &mut pool, dummy_access(0x22, 0x28)).is_err());
         assert!(carve_region(root, &mut pool, dummy_access(0x30, 0x20)).is_err());
+        assert!(carve_region(root, &mut pool, dummy_access(0x80, 0x80)).is_err());
         assert!(alias_region(root, &mut pool, dummy_access(0x20, 0x25)).is_err());
     }
 }
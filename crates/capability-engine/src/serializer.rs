@@ -4,6 +4,7 @@
 //! inter-operable attestation format.
 
 use crate::domain::DomainPool;
+use crate::flow::{self, Policy};
 use crate::segment::{HandleIterator, RegionCapa, RegionPool};
 use crate::{Capa, CapaError, Domain, Handle};
 
@@ -13,6 +14,7 @@ pub mod serde {
     pub const MAGIC: [u8; 4] = *b"capa";
     pub const REGION_HEADER: u8 = 0b00000001;
     pub const DOMAIN_HEADER: u8 = 0b00000010;
+    pub const POLICY_HEADER: u8 = 0b00000011;
     pub const END_MARKER:    u8 = 0b11111111;
 
     pub const REGION_ROOT:     u8 = 0b10000000;
@@ -21,10 +23,14 @@ pub mod serde {
     pub const REGION_HAS_HASH: u8 = 0b10000101;
     pub const REGION_NO_HASH:  u8 = 0b10000100;
 
-    pub const DOMAIN_CAPA_START: u8 = 0b01000000;
-    pub const DOMAIN_CAPA_END:   u8 = 0b01000001;
+    pub const DOMAIN_CAPA_START:      u8 = 0b01000000;
+    pub const DOMAIN_CAPA_END:        u8 = 0b01000001;
+    pub const DOMAIN_HAS_MEASUREMENT: u8 = 0b01000010;
+    pub const DOMAIN_NO_MEASUREMENT:  u8 = 0b01000011;
     pub const CAPA_REGION:       u8 = 0b00100000;
     pub const CAPA_DOMAIN:       u8 = 0b00100001;
+
+    pub const POLICY_RULE: u8 = 0b00010000;
 }
 
 // ————————————————————————————————— Buffer ————————————————————————————————— //
@@ -72,14 +78,16 @@ pub(crate) fn serialize(
     buff: &mut [u8],
     domains: &DomainPool,
     regions: &RegionPool,
+    policy: &Policy,
 ) -> Result<usize, CapaError> {
     let mut buff = Buffer::new(buff);
     buff.write_bytes(serde::MAGIC)?;
     serialize_regions(&mut buff, regions)?;
     serialize_domains(&mut buff, domains, regions)?;
+    serialize_policy(&mut buff, policy)?;
     buff.u8(serde::END_MARKER)?;
 
-    Ok(buff.idx + 1)
+    Ok(buff.idx)
 }
 
 fn serialize_regions(buff: &mut Buffer, regions: &RegionPool) -> Result<(), CapaError> {
@@ -172,6 +180,15 @@ fn serialize_domain(
 ) -> Result<(), CapaError> {
     buff.u64(td.temporary_id.get())?;
     buff.u64(td.monitor_interface())?;
+    buff.u64(flow::domain_labels(td))?;
+    if let Some(measurement) = td.measurement() {
+        let mut bytes = [0; 32];
+        measurement.to_byte_arr(&mut bytes, 0);
+        buff.u8(serde::DOMAIN_HAS_MEASUREMENT)?;
+        buff.write_bytes(bytes)?;
+    } else {
+        buff.u8(serde::DOMAIN_NO_MEASUREMENT)?;
+    }
     buff.u8(serde::DOMAIN_CAPA_START)?;
     for capa in td.iter_capa() {
         match capa {
@@ -180,7 +197,7 @@ fn serialize_domain(
                 buff.u8(serde::CAPA_REGION)?;
                 buff.u64(regions[h].temporary_id.get() as u64)?;
             }
-            Capa::Management(h) => {
+            Capa::Management(h, _) => {
                 buff.u8(serde::CAPA_DOMAIN)?;
                 buff.u64(domains[h].temporary_id.get())?;
             }
@@ -189,3 +206,18 @@ fn serialize_domain(
     buff.u8(serde::DOMAIN_CAPA_END)?;
     Ok(())
 }
+
+/// Serialize the information-flow policy, so that verifiers know which transfers were denied.
+fn serialize_policy(buff: &mut Buffer, policy: &Policy) -> Result<(), CapaError> {
+    buff.u8(serde::POLICY_HEADER)?;
+    for rule in policy.rules() {
+        buff.u8(serde::POLICY_RULE)?;
+        buff.u64(rule.transfers)?;
+        buff.u64(rule.from)?;
+        buff.u64(rule.to)?;
+        buff.u64(rule.region)?;
+        buff.u64(rule.ops)?;
+    }
+    buff.u8(serde::END_MARKER)?;
+    Ok(())
+}
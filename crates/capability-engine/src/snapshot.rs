@@ -0,0 +1,695 @@
+//! Snapshots
+//!
+//! A snapshot is a complete copy of the engine state, which can be restored into another engine,
+//! for instance to reproduce a bug on the host from the state of a monitor that crashed. Unlike
+//! the attestation [serializer](crate::serializer), which only exposes what verifiers need,
+//! snapshots keep the pools slot by slot, including the generations and free lists, so that the
+//! handles held outside of the engine remain valid once restored.
+//!
+//! Snapshots are restored in place: the engine must be initialized with the same configuration
+//! as the engine the snapshot was taken from. Snapshots are not meant to be exchanged with
+//! untrusted parties, the content is not checked beyond what is needed to restore it.
+
+use attestation::hashing::HashEnclave;
+use attestation::signature::{
+    AttestationKeyCertificate, AttestationPublicKey, AttestationSignature, EnclaveReport,
+    MAX_DEVICE_SIGNATURE_SZ,
+};
+
+use crate::config::{
+    NB_CORES, NB_FLOW_RULES, NB_LEASES, NB_MESSAGE_WORDS, NB_PENDING_MESSAGES,
+    NB_PENDING_REVOCATIONS, NB_PERMISSION_DELTAS, NB_TRANSACTION_OPS, NB_UPDATES,
+};
+use crate::lease::ActiveLease;
+use crate::{
+    AccessRights, CapaEngine, CapaError, CoreSet, EngineConfig, FastSwitch, Lease, LeaseCondition,
+    LocalCapa, MemOps, Message, Quota, RevokedRegion, Update, Usage,
+};
+
+// ————————————————————————————————— Header ————————————————————————————————— //
+
+pub const MAGIC: [u8; 4] = *b"snap";
+
+/// Version of the snapshot format, to bump whenever the engine state changes.
+pub const VERSION: u32 = 4;
+
+/// The compile-time sizes of the engine, which must match to restore a snapshot.
+const LAYOUT: [usize; 9] = [
+    NB_CORES,
+    NB_UPDATES,
+    NB_LEASES,
+    NB_PENDING_REVOCATIONS,
+    NB_PENDING_MESSAGES,
+    NB_MESSAGE_WORDS,
+    NB_TRANSACTION_OPS,
+    NB_FLOW_RULES,
+    NB_PERMISSION_DELTAS,
+];
+
+// ————————————————————————————————— Writer ————————————————————————————————— //
+
+pub(crate) struct Writer<'a> {
+    /// The output buffer, or `None` to only compute the size of the snapshot.
+    buff: Option<&'a mut [u8]>,
+    idx: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(buff: Option<&'a mut [u8]>) -> Self {
+        Self { buff, idx: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.idx
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CapaError> {
+        let end = self.idx + bytes.len();
+        if let Some(buff) = &mut self.buff {
+            if end > buff.len() {
+                log::error!("Snapshot buffer is full");
+                return Err(CapaError::OutOfMemory);
+            }
+            buff[self.idx..end].copy_from_slice(bytes);
+        }
+        self.idx = end;
+        Ok(())
+    }
+}
+
+// ————————————————————————————————— Reader ————————————————————————————————— //
+
+pub(crate) struct Reader<'a> {
+    buff: &'a [u8],
+    idx: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buff: &'a [u8]) -> Self {
+        Self { buff, idx: 0 }
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], CapaError> {
+        let Some(bytes) = self.buff.get(self.idx..(self.idx + N)) else {
+            log::error!("Snapshot is truncated");
+            return Err(CapaError::CouldNotDeserializeInfo);
+        };
+        self.idx += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    /// Returns an error if some bytes were not consumed.
+    pub(crate) fn finish(&self) -> Result<(), CapaError> {
+        if self.idx != self.buff.len() {
+            log::error!("Trailing bytes in snapshot: {}", self.buff.len() - self.idx);
+            return Err(CapaError::CouldNotDeserializeInfo);
+        }
+        Ok(())
+    }
+}
+
+// ————————————————————————————————— Traits ————————————————————————————————— //
+
+/// Values that can be saved in a snapshot and loaded back.
+pub(crate) trait Snapshot: Sized {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError>;
+    fn load(r: &mut Reader) -> Result<Self, CapaError>;
+}
+
+/// Objects restored in place, such as the pools whose memory is allocated at boot.
+pub(crate) trait Restore {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError>;
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError>;
+}
+
+impl<T: Snapshot> Restore for T {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.save(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        *self = T::load(r)?;
+        Ok(())
+    }
+}
+
+/// Returns an error for unexpected values, such as an unknown enum tag.
+pub(crate) fn invalid<T>(what: &str, value: u64) -> Result<T, CapaError> {
+    log::error!("Invalid {} in snapshot: 0x{:x}", what, value);
+    Err(CapaError::CouldNotDeserializeInfo)
+}
+
+/// Restores a slice in place, its length must match the one of the snapshot.
+pub(crate) fn restore_slice<T: Restore>(slice: &mut [T], r: &mut Reader) -> Result<(), CapaError> {
+    let len = usize::load(r)?;
+    if len != slice.len() {
+        log::error!("Snapshot has {} objects, expected {}", len, slice.len());
+        return Err(CapaError::InvalidValue);
+    }
+    slice.iter_mut().try_for_each(|item| item.restore(r))
+}
+
+pub(crate) fn snapshot_slice<T: Restore>(slice: &[T], w: &mut Writer) -> Result<(), CapaError> {
+    slice.len().save(w)?;
+    slice.iter().try_for_each(|item| item.snapshot(w))
+}
+
+// ——————————————————————————————— Primitives ——————————————————————————————— //
+
+macro_rules! snapshot_int {
+    ($($ty:ty),*) => {$(
+        impl Snapshot for $ty {
+            fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+                w.write_bytes(&self.to_le_bytes())
+            }
+
+            fn load(r: &mut Reader) -> Result<Self, CapaError> {
+                Ok(<$ty>::from_le_bytes(r.read_bytes()?))
+            }
+        }
+    )*};
+}
+
+snapshot_int!(u8, u32, u64, u128);
+
+/// Sizes are saved as 64 bits values, so that host-side tools can read snapshots of any target.
+impl Snapshot for usize {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        (*self as u64).save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let value = u64::load(r)?;
+        usize::try_from(value).or_else(|_| invalid("size", value))
+    }
+}
+
+impl Snapshot for bool {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        (*self as u8).save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        match u8::load(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => invalid("bool", value as u64),
+        }
+    }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match self {
+            None => false.save(w),
+            Some(value) => {
+                true.save(w)?;
+                value.save(w)
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        match bool::load(r)? {
+            false => Ok(None),
+            true => Ok(Some(T::load(r)?)),
+        }
+    }
+}
+
+impl<T: Snapshot + Copy + Default, const N: usize> Snapshot for [T; N] {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.iter().try_for_each(|item| item.save(w))
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let mut array = [T::default(); N];
+        for item in array.iter_mut() {
+            *item = T::load(r)?;
+        }
+        Ok(array)
+    }
+}
+
+// —————————————————————————————— Engine Types —————————————————————————————— //
+
+impl Snapshot for LocalCapa {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.as_usize().save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(LocalCapa::new(usize::load(r)?))
+    }
+}
+
+impl Snapshot for MemOps {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.bits().save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let bits = u8::load(r)?;
+        MemOps::from_bits(bits).map_or_else(|| invalid("memory operations", bits as u64), Ok)
+    }
+}
+
+impl Snapshot for AccessRights {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.start.save(w)?;
+        self.end.save(w)?;
+        self.ops.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(AccessRights {
+            start: usize::load(r)?,
+            end: usize::load(r)?,
+            ops: MemOps::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for CoreSet {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        (0..CoreSet::NB_WORDS).try_for_each(|idx| self.word(idx).unwrap().save(w))
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let mut cores = CoreSet::NONE;
+        for idx in 0..CoreSet::NB_WORDS {
+            cores.set_word(idx, u64::load(r)?)?;
+        }
+        Ok(cores)
+    }
+}
+
+impl Snapshot for FastSwitch {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.peer.save(w)?;
+        self.core.save(w)?;
+        self.is_owner.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(FastSwitch {
+            peer: Snapshot::load(r)?,
+            core: usize::load(r)?,
+            is_owner: bool::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for Quota {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.max_domains.save(w)?;
+        self.max_regions.save(w)?;
+        self.max_trackers.save(w)?;
+        self.max_memory.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Quota {
+            max_domains: usize::load(r)?,
+            max_regions: usize::load(r)?,
+            max_trackers: usize::load(r)?,
+            max_memory: usize::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for Usage {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.domains.save(w)?;
+        self.regions.save(w)?;
+        self.trackers.save(w)?;
+        self.memory.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Usage {
+            domains: usize::load(r)?,
+            regions: usize::load(r)?,
+            trackers: usize::load(r)?,
+            memory: usize::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for RevokedRegion {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.capa.save(w)?;
+        self.start.save(w)?;
+        self.end.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(RevokedRegion {
+            capa: LocalCapa::load(r)?,
+            start: usize::load(r)?,
+            end: usize::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for Message {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.from.save(w)?;
+        self.words.save(w)?;
+        self.region.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(Message {
+            from: usize::load(r)?,
+            words: Snapshot::load(r)?,
+            region: Option::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for LeaseCondition {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match self {
+            LeaseCondition::OnReturn => 0u8.save(w),
+            LeaseCondition::Switches(count) => {
+                1u8.save(w)?;
+                count.save(w)
+            }
+            LeaseCondition::Deadline(deadline) => {
+                2u8.save(w)?;
+                deadline.save(w)
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        match u8::load(r)? {
+            0 => Ok(LeaseCondition::OnReturn),
+            1 => Ok(LeaseCondition::Switches(usize::load(r)?)),
+            2 => Ok(LeaseCondition::Deadline(u64::load(r)?)),
+            tag => invalid("lease condition", tag as u64),
+        }
+    }
+}
+
+impl Snapshot for ActiveLease {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.region.save(w)?;
+        self.lender.save(w)?;
+        self.holder.save(w)?;
+        self.lease.condition.save(w)?;
+        self.lease.alias.save(w)?;
+        self.lease.size.save(w)?;
+        self.expired.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(ActiveLease {
+            region: Snapshot::load(r)?,
+            lender: Snapshot::load(r)?,
+            holder: Snapshot::load(r)?,
+            lease: Lease {
+                condition: LeaseCondition::load(r)?,
+                alias: usize::load(r)?,
+                size: usize::load(r)?,
+            },
+            expired: bool::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for Update {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        match *self {
+            Update::PermissionUpdate { domain, core_map } => {
+                0u8.save(w)?;
+                domain.save(w)?;
+                core_map.save(w)
+            }
+            Update::RevokeDomain {
+                manager,
+                mgmt_capa,
+                domain,
+            } => {
+                1u8.save(w)?;
+                manager.save(w)?;
+                mgmt_capa.save(w)?;
+                domain.save(w)
+            }
+            Update::CreateDomain { domain } => {
+                2u8.save(w)?;
+                domain.save(w)
+            }
+            Update::Switch {
+                domain,
+                return_capa,
+                core,
+                delta,
+            } => {
+                3u8.save(w)?;
+                domain.save(w)?;
+                return_capa.save(w)?;
+                core.save(w)?;
+                delta.save(w)
+            }
+            Update::Trap {
+                manager,
+                trap,
+                info,
+                core,
+            } => {
+                4u8.save(w)?;
+                manager.save(w)?;
+                trap.save(w)?;
+                info.save(w)?;
+                core.save(w)
+            }
+            Update::Cleanup { start, end } => {
+                5u8.save(w)?;
+                start.save(w)?;
+                end.save(w)
+            }
+            Update::LeaseExpired {
+                holder,
+                alias,
+                size,
+            } => {
+                6u8.save(w)?;
+                holder.save(w)?;
+                alias.save(w)?;
+                size.save(w)
+            }
+            Update::RegionRevoked {
+                domain,
+                capa,
+                start,
+                end,
+            } => {
+                7u8.save(w)?;
+                domain.save(w)?;
+                capa.save(w)?;
+                start.save(w)?;
+                end.save(w)
+            }
+            Update::ChannelMessage { domain, pending } => {
+                8u8.save(w)?;
+                domain.save(w)?;
+                pending.save(w)
+            }
+            Update::FastSwitchDisabled { owner, peer, core } => {
+                9u8.save(w)?;
+                owner.save(w)?;
+                peer.save(w)?;
+                core.save(w)
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let update = match u8::load(r)? {
+            0 => Update::PermissionUpdate {
+                domain: Snapshot::load(r)?,
+                core_map: CoreSet::load(r)?,
+            },
+            1 => Update::RevokeDomain {
+                manager: Snapshot::load(r)?,
+                mgmt_capa: LocalCapa::load(r)?,
+                domain: Snapshot::load(r)?,
+            },
+            2 => Update::CreateDomain {
+                domain: Snapshot::load(r)?,
+            },
+            3 => Update::Switch {
+                domain: Snapshot::load(r)?,
+                return_capa: LocalCapa::load(r)?,
+                core: usize::load(r)?,
+                delta: usize::load(r)?,
+            },
+            4 => Update::Trap {
+                manager: Snapshot::load(r)?,
+                trap: u64::load(r)?,
+                info: u64::load(r)?,
+                core: usize::load(r)?,
+            },
+            5 => Update::Cleanup {
+                start: usize::load(r)?,
+                end: usize::load(r)?,
+            },
+            6 => Update::LeaseExpired {
+                holder: Snapshot::load(r)?,
+                alias: usize::load(r)?,
+                size: usize::load(r)?,
+            },
+            7 => Update::RegionRevoked {
+                domain: Snapshot::load(r)?,
+                capa: LocalCapa::load(r)?,
+                start: usize::load(r)?,
+                end: usize::load(r)?,
+            },
+            8 => Update::ChannelMessage {
+                domain: Snapshot::load(r)?,
+                pending: usize::load(r)?,
+            },
+            9 => Update::FastSwitchDisabled {
+                owner: Snapshot::load(r)?,
+                peer: Snapshot::load(r)?,
+                core: usize::load(r)?,
+            },
+            tag => return invalid("update", tag as u64),
+        };
+        Ok(update)
+    }
+}
+
+// —————————————————————————————— Attestation ——————————————————————————————— //
+
+impl Snapshot for HashEnclave {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.low.save(w)?;
+        self.high.save(w)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        Ok(HashEnclave {
+            low: u128::load(r)?,
+            high: u128::load(r)?,
+        })
+    }
+}
+
+impl Snapshot for EnclaveReport {
+    fn save(&self, w: &mut Writer) -> Result<(), CapaError> {
+        w.write_bytes(&*self.public_key)?;
+        w.write_bytes(&*self.signed_enclave_data)?;
+        let certificate = self.certificate.signature();
+        certificate.len().save(w)?;
+        w.write_bytes(certificate)
+    }
+
+    fn load(r: &mut Reader) -> Result<Self, CapaError> {
+        let public_key = AttestationPublicKey::new(r.read_bytes()?);
+        let signed_enclave_data = AttestationSignature::new(r.read_bytes()?);
+        let len = usize::load(r)?;
+        if len > MAX_DEVICE_SIGNATURE_SZ {
+            return invalid("certificate size", len as u64);
+        }
+        let mut signature = [0; MAX_DEVICE_SIGNATURE_SZ];
+        for byte in signature[..len].iter_mut() {
+            *byte = u8::load(r)?;
+        }
+        let certificate = AttestationKeyCertificate::from_signature(&signature[..len])
+            .unwrap_or(AttestationKeyCertificate::empty());
+        Ok(EnclaveReport {
+            public_key,
+            signed_enclave_data,
+            certificate,
+        })
+    }
+}
+
+// ————————————————————————————————— Engine ————————————————————————————————— //
+
+fn save_config(config: &EngineConfig, w: &mut Writer) -> Result<(), CapaError> {
+    config.nb_domains.save(w)?;
+    config.nb_capas_per_domain.save(w)?;
+    config.nb_regions.save(w)?;
+    config.nb_tracker.save(w)
+}
+
+fn load_config(r: &mut Reader) -> Result<EngineConfig, CapaError> {
+    Ok(EngineConfig {
+        nb_domains: usize::load(r)?,
+        nb_capas_per_domain: usize::load(r)?,
+        nb_regions: usize::load(r)?,
+        nb_tracker: usize::load(r)?,
+    })
+}
+
+pub(crate) fn save_engine(engine: &CapaEngine, w: &mut Writer) -> Result<(), CapaError> {
+    let Some(config) = &engine.config else {
+        log::error!("Can not snapshot an engine that is not initialized");
+        return Err(CapaError::InvalidOperation);
+    };
+    w.write_bytes(&MAGIC)?;
+    VERSION.save(w)?;
+    LAYOUT.iter().try_for_each(|size| size.save(w))?;
+    save_config(config, w)?;
+
+    engine.id_counter.save(w)?;
+    engine.policy.snapshot(w)?;
+    engine.cores.iter().try_for_each(|core| core.snapshot(w))?;
+    engine.domains.snapshot(w)?;
+    engine.regions.snapshot(w)?;
+    engine.tracker.snapshot(w)?;
+    engine.updates.snapshot(w)?;
+    engine.leases.snapshot(w)
+}
+
+pub(crate) fn restore_engine(engine: &mut CapaEngine, r: &mut Reader) -> Result<(), CapaError> {
+    let Some(config) = engine.config else {
+        log::error!("The engine must be initialized before restoring a snapshot");
+        return Err(CapaError::InvalidOperation);
+    };
+    if r.read_bytes()? != MAGIC {
+        log::error!("Invalid snapshot magic");
+        return Err(CapaError::CouldNotDeserializeInfo);
+    }
+    let version = u32::load(r)?;
+    if version != VERSION {
+        log::error!(
+            "Unsupported snapshot version {}, expected {}",
+            version,
+            VERSION
+        );
+        return Err(CapaError::CouldNotDeserializeInfo);
+    }
+    for expected in LAYOUT {
+        let size = usize::load(r)?;
+        if size != expected {
+            log::error!("Snapshot from an engine of a different size: {}", size);
+            return Err(CapaError::InvalidValue);
+        }
+    }
+    let snapshot_config = load_config(r)?;
+    if snapshot_config != config {
+        log::error!(
+            "Snapshot configuration {:?} does not match {:?}",
+            snapshot_config,
+            config
+        );
+        return Err(CapaError::InvalidValue);
+    }
+
+    engine.id_counter = usize::load(r)?;
+    engine.policy.restore(r)?;
+    for core in engine.cores.iter_mut() {
+        core.restore(r)?;
+    }
+    engine.domains.restore(r)?;
+    engine.regions.restore(r)?;
+    engine.tracker.restore(r)?;
+    engine.updates.restore(r)?;
+    engine.leases.restore(r)?;
+    r.finish()
+}
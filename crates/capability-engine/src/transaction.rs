@@ -0,0 +1,148 @@
+//! Transactions
+//!
+//! Building a domain takes many operations (creating it, carving and sending regions,
+//! configuring and sealing it), and a failure halfway leaves the manager with a half-constructed
+//! domain. Within a transaction each operation records how to undo itself, so that the whole
+//! sequence can be rolled back: the undo log is replayed in reverse order, and the updates queued
+//! since the beginning of the transaction are dropped.
+
+use crate::config::NB_TRANSACTION_OPS;
+use crate::permission::PermissionIndex;
+use crate::segment::RegionHash;
+use crate::{AccessRights, CapaError, CoreSet, Domain, Handle, LocalCapa, Quota};
+
+/// How to undo an operation performed within a transaction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Undo {
+    /// A domain was created, `capa` is the management capability held by the manager.
+    CreateDomain {
+        manager: Handle<Domain>,
+        capa: LocalCapa,
+        domain: Handle<Domain>,
+    },
+    /// A region was created by aliasing or carving.
+    CreateRegion {
+        domain: Handle<Domain>,
+        capa: LocalCapa,
+    },
+    /// A capability without side effects was added to a domain.
+    InsertCapa {
+        domain: Handle<Domain>,
+        capa: LocalCapa,
+    },
+    /// A capability was sent from `local` in `from` to `received` in `to`. The access rights, hash
+    /// and labels are the ones of the region before sending, if the capability is a region.
+    Send {
+        from: Handle<Domain>,
+        local: LocalCapa,
+        to: Handle<Domain>,
+        received: LocalCapa,
+        region: Option<(AccessRights, Option<RegionHash>, u64)>,
+    },
+    /// A permission was changed, `value` is the previous one.
+    Permission {
+        domain: Handle<Domain>,
+        index: PermissionIndex,
+        value: u64,
+    },
+    /// The core map was changed, `cores` is the previous one.
+    CoreMap {
+        domain: Handle<Domain>,
+        cores: CoreSet,
+    },
+    /// The quota was changed, `quota` is the previous one.
+    Quota {
+        domain: Handle<Domain>,
+        quota: Quota,
+    },
+    /// The labels were changed, `labels` are the previous ones.
+    Labels { domain: Handle<Domain>, labels: u64 },
+    /// The domain was sealed.
+    Seal { domain: Handle<Domain> },
+}
+
+/// The undo log of the transaction in progress, if any.
+pub(crate) struct Transaction {
+    log: [Option<Undo>; NB_TRANSACTION_OPS],
+    len: usize,
+    is_active: bool,
+    /// Mark of the update buffer when the transaction began.
+    pub(crate) updates_mark: usize,
+    /// The domain ID counter when the transaction began.
+    pub(crate) id_counter: usize,
+}
+
+impl Transaction {
+    pub(crate) const fn new() -> Self {
+        Self {
+            log: [None; NB_TRANSACTION_OPS],
+            len: 0,
+            is_active: false,
+            updates_mark: 0,
+            id_counter: 0,
+        }
+    }
+
+    pub(crate) fn begin(
+        &mut self,
+        updates_mark: usize,
+        id_counter: usize,
+    ) -> Result<(), CapaError> {
+        if self.is_active {
+            log::error!("Transactions can not be nested");
+            return Err(CapaError::InvalidOperation);
+        }
+        self.is_active = true;
+        self.len = 0;
+        self.updates_mark = updates_mark;
+        self.id_counter = id_counter;
+        Ok(())
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.is_active = false;
+        self.log = [None; NB_TRANSACTION_OPS];
+        self.len = 0;
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// Returns an error if the operation can not be part of a transaction.
+    pub(crate) fn forbid(&self) -> Result<(), CapaError> {
+        if self.is_active {
+            log::error!("Operation not supported within a transaction");
+            return Err(CapaError::InvalidOperation);
+        }
+        Ok(())
+    }
+
+    /// Returns `OutOfMemory` if the next operation could not be recorded.
+    ///
+    /// Must be checked before performing an operation, so that recording it can't fail.
+    pub(crate) fn reserve(&self) -> Result<(), CapaError> {
+        if self.is_active && self.len == NB_TRANSACTION_OPS {
+            log::error!("Transaction log is full");
+            return Err(CapaError::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    /// Records how to undo an operation, if a transaction is in progress.
+    pub(crate) fn record(&mut self, undo: Undo) {
+        if self.is_active {
+            self.log[self.len] = Some(undo);
+            self.len += 1;
+        }
+    }
+
+    /// Removes the last operation recorded.
+    pub(crate) fn pop(&mut self) -> Option<Undo> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.log[self.len].take()
+    }
+}
@@ -3,7 +3,8 @@
 use core::fmt;
 
 use crate::config::NB_UPDATES;
-use crate::{CapaError, Domain, Handle, LocalCapa};
+use crate::snapshot::{Reader, Restore, Snapshot, Writer};
+use crate::{CapaError, CoreSet, Domain, Handle, LocalCapa};
 
 pub type UpdateBuffer = Buffer<Update>;
 
@@ -11,7 +12,7 @@ pub type UpdateBuffer = Buffer<Update>;
 pub enum Update {
     PermissionUpdate {
         domain: Handle<Domain>,
-        core_map: u64,
+        core_map: CoreSet,
     },
     RevokeDomain {
         manager: Handle<Domain>,
@@ -41,6 +42,36 @@ pub enum Update {
         start: usize,
         end: usize,
     },
+    LeaseExpired {
+        /// The domain the region was lent to
+        holder: Handle<Domain>,
+        /// Guest physical address of the mapping in the holder
+        alias: usize,
+        /// Size of the mapping
+        size: usize,
+    },
+    RegionRevoked {
+        /// The domain that lost access to the region
+        domain: Handle<Domain>,
+        /// The capability of the domain that referred to the region
+        capa: LocalCapa,
+        start: usize,
+        end: usize,
+    },
+    ChannelMessage {
+        /// The domain that received a message
+        domain: Handle<Domain>,
+        /// The number of messages the domain did not poll yet
+        pending: usize,
+    },
+    FastSwitchDisabled {
+        /// The domain that enabled the pair
+        owner: Handle<Domain>,
+        /// The other domain of the pair
+        peer: Handle<Domain>,
+        /// The core shared by the pair
+        core: usize,
+    },
 }
 
 pub struct Buffer<U> {
@@ -98,6 +129,32 @@ where
         return false;
     }
 
+    /// Returns a mark of the current end of the buffer, see [Buffer::truncate].
+    pub(crate) fn mark(&self) -> usize {
+        self.write
+    }
+
+    /// Drops the updates pushed since `mark`, none of them must have been popped.
+    pub(crate) fn truncate(&mut self, mark: usize) {
+        while self.write != mark {
+            self.write = (self.write + self.buff.len() - 1) % self.buff.len();
+            self.buff[self.write] = None;
+        }
+    }
+
+    /// Return OK if the buffer has enough capacity for `count` updates, Err otherwise.
+    pub fn has_capacity_for(&self, count: usize) -> Result<(), CapaError> {
+        if self.capacity() >= count {
+            Ok(())
+        } else {
+            log::error!(
+                "Update buffer does not have enough capacity for {} updates",
+                count
+            );
+            Err(CapaError::OutOfMemory)
+        }
+    }
+
     pub fn capacity(&self) -> usize {
         let available = if self.write >= self.read {
             // Write pointer is ahead of or equal to read pointer
@@ -112,6 +169,22 @@ where
     }
 }
 
+// ———————————————————————————————— Snapshot ———————————————————————————————— //
+
+impl<U: Snapshot + Copy> Restore for Buffer<U> {
+    fn snapshot(&self, w: &mut Writer) -> Result<(), CapaError> {
+        self.buff.save(w)?;
+        self.read.save(w)?;
+        self.write.save(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), CapaError> {
+        self.buff.restore(r)?;
+        self.read.restore(r)?;
+        self.write.restore(r)
+    }
+}
+
 // ———————————————————————————————— Display ————————————————————————————————— //
 
 impl fmt::Display for Update {
@@ -130,6 +203,36 @@ impl fmt::Display for Update {
             Update::CreateDomain { domain } => write!(f, "CreateDomain({})", domain),
             Update::Switch { domain, core, .. } => write!(f, "Switch({}, core {})", domain, core),
             Update::Cleanup { start, end } => write!(f, "Cleanup([0x{:x}, 0x{:x}])", start, end),
+            Update::LeaseExpired {
+                holder,
+                alias,
+                size,
+            } => write!(
+                f,
+                "LeaseExpired({}, [0x{:x}, 0x{:x}])",
+                holder,
+                alias,
+                alias + size
+            ),
+            Update::RegionRevoked {
+                domain,
+                capa,
+                start,
+                end,
+            } => write!(
+                f,
+                "RegionRevoked({}, {}, [0x{:x}, 0x{:x}])",
+                domain,
+                capa.as_usize(),
+                start,
+                end
+            ),
+            Update::ChannelMessage { domain, pending } => {
+                write!(f, "ChannelMessage({}, {} pending)", domain, pending)
+            }
+            Update::FastSwitchDisabled { owner, peer, core } => {
+                write!(f, "FastSwitchDisabled({}, {}, core {})", owner, peer, core)
+            }
             Update::Trap {
                 manager,
                 trap,
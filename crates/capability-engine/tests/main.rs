@@ -1,9 +1,12 @@
 use std::fmt::Write;
 
-use capa_engine::config::NB_UPDATES;
+use capa_engine::config::{self, NB_CORES, NB_UPDATES};
+use capa_engine::flow::{labels, transfer, Policy, Rule};
+use capa_engine::pool::PoolMemory;
 use capa_engine::{
-    permission, AccessRights, Buffer, CapaEngine, CapaError, Domain, Handle, LocalCapa, MemOps,
-    NextCapaToken, RegionIterator, MEMOPS_ALL,
+    permission, AccessRights, Buffer, CapaEngine, CapaError, CapaInfo, CoreSet, Domain,
+    EngineConfig, Handle, Lease, LeaseCondition, LocalCapa, MemOps, MgmtRights, NextCapaToken,
+    Quota, RegionIterator, MEMOPS_ALL,
 };
 
 /// Snapshot testing
@@ -18,18 +21,29 @@ macro_rules! snap {
 /// Creates a static CapaEngine and returns a mutable reference
 ///
 /// This is required to avoid stack overflow when creating a new engine on the stack, due to the
-/// size of the engine.
+/// size of the engine. The engine pools are backed by leaked memory and sized with the given
+/// config, or the default one.
 ///
 /// # SAFETY:
 /// The macro returns a mutable reference to a global static, thus the usual rules applies.
 /// The macro can be used multiple functions to define multiple engines in the .data section.
 macro_rules! static_engine {
-    () => {{
+    () => {
+        static_engine!(EngineConfig::DEFAULT)
+    };
+    ($config:expr) => {{
         static mut ENGINE: CapaEngine = CapaEngine::new();
+        ENGINE
+            .init($config, &mut leak_memory($config.memory_size()))
+            .unwrap();
         &mut ENGINE
     }};
 }
 
+fn leak_memory(size: usize) -> PoolMemory {
+    PoolMemory::from_slice(Box::leak(vec![0u8; size].into_boxed_slice()))
+}
+
 // ———————————————————————————— Test our Buffer ————————————————————————————— //
 
 #[test]
@@ -2148,18 +2162,1041 @@ fn new_capa() {
     );
 }
 
+#[test]
+fn send_management_cycle() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+
+    // Create initial domain
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let _ctx = engine.start_domain_on_core(d0, core).unwrap();
+
+    // Create two children
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    snap!("{Management(2 | _), Management(3 | _)}", capas(d0, engine));
+
+    // A domain can't manage itself
+    assert_eq!(
+        engine.send(d0, d1_mgmt, d1_mgmt).err(),
+        Some(CapaError::InvalidOperation)
+    );
+    snap!("{Management(2 | _), Management(3 | _)}", capas(d0, engine));
+
+    // But it can manage another domain
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine.send(d0, d2_mgmt, d1_mgmt).unwrap();
+    snap!("{Management(2 | _)}", capas(d0, engine));
+    snap!("{Management(3 | _)}", capas(d1, engine));
+}
+
+#[test]
+fn runtime_pools() {
+    let config = EngineConfig {
+        nb_domains: 3,
+        nb_capas_per_domain: 4,
+        nb_regions: 8,
+        nb_tracker: 16,
+    };
+
+    // Not enough memory for the pools
+    let mut engine = CapaEngine::new();
+    assert_eq!(
+        engine
+            .init(config, &mut leak_memory(config.memory_size() / 2))
+            .err(),
+        Some(CapaError::OutOfMemory)
+    );
+
+    let engine = unsafe { static_engine!(config) };
+    let core = 0;
+    assert_eq!(engine.get_capacity().0, 3);
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let _ctx = engine.start_domain_on_core(d0, core).unwrap();
+
+    // Only two more domains fit in the pool
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    assert_eq!(engine.create_domain(d0).err(), Some(CapaError::OutOfMemory));
+
+    // The capability tables are sized by the config too
+    engine
+        .create_root_region(d0, dummy_access(0x0, 0x1000))
+        .unwrap();
+    engine
+        .create_root_region(d0, dummy_access(0x1000, 0x2000))
+        .unwrap();
+    assert_eq!(
+        engine
+            .create_root_region(d0, dummy_access(0x2000, 0x3000))
+            .err(),
+        Some(CapaError::OutOfMemory)
+    );
+
+    // Revoked domains free their slot, and new domains start with an empty capability table
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine.send(d0, d2_mgmt, d1_mgmt).unwrap();
+    engine.revoke(d0, d1_mgmt).unwrap();
+    assert_eq!(engine.get_capacity().0, 2);
+    let d3_mgmt = engine.create_domain(d0).unwrap();
+    let d3 = engine.get_domain_capa(d0, d3_mgmt).unwrap();
+    assert!(d3 != d1);
+    snap!("{}", capas(d3, engine));
+}
+
+#[test]
+fn core_set() {
+    let engine = unsafe { static_engine!() };
+    let core = NB_CORES - 1;
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let _ctx = engine.start_domain_on_core(d0, core).unwrap();
+    assert_eq!(engine.get_domain_cores(d0), Ok(CoreSet::single(core)));
+
+    // Allow the child on the last core only
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine
+        .set_child_core_map(d0, d1_mgmt, CoreSet::single(core))
+        .unwrap();
+    assert_eq!(
+        engine.get_child_core_map(d0, d1_mgmt),
+        Ok(CoreSet::single(core))
+    );
+
+    // The u64 permission only covers the first 64 cores
+    let allowed = permission::PermissionIndex::AllowedCores;
+    engine
+        .set_child_permission(d0, d1_mgmt, allowed, 0b1)
+        .unwrap();
+    assert_eq!(engine.get_child_permission(d0, d1_mgmt, allowed), Ok(0b1));
+    let core_map = engine.get_domain_core_map(d1);
+    assert_eq!(core_map.iter().collect::<Vec<_>>(), vec![0, core]);
+
+    // A domain can't be managed by a domain with less cores
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    engine
+        .set_child_core_map(d0, d2_mgmt, CoreSet::ALL)
+        .unwrap();
+    assert_eq!(
+        engine.send(d0, d2_mgmt, d1_mgmt).err(),
+        Some(CapaError::InsufficientPermissions)
+    );
+
+    // Switch to the child on the last core
+    engine.create_switch_on_core(d0, core, d1_mgmt).unwrap();
+    let switch = engine.seal(d0, core, d1_mgmt).unwrap();
+    assert_eq!(
+        engine.set_child_core_map(d0, d1_mgmt, CoreSet::NONE),
+        Err(CapaError::AlreadySealed)
+    );
+    engine.switch(d0, core, 0, switch).unwrap();
+    assert_eq!(engine.get_domain_cores(d0), Ok(CoreSet::NONE));
+    assert_eq!(engine.get_domain_cores(d1), Ok(CoreSet::single(core)));
+    engine.check_invariants();
+}
+
+#[test]
+fn quotas() {
+    let engine = unsafe { static_engine!() };
+    let interface = permission::PermissionIndex::MonitorInterface;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    assert_eq!(engine[d0].quota(), Quota::UNLIMITED);
+
+    // Create a child with a quota
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let quota = Quota {
+        max_domains: 1,
+        max_regions: 2,
+        max_memory: 0x2000,
+        ..Quota::UNLIMITED
+    };
+    engine
+        .set_child_permission(d0, d1_mgmt, interface, permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.set_child_quota(d0, d1_mgmt, quota).unwrap();
+    assert_eq!(engine[d0].usage().domains, 1);
+
+    // Memory quota
+    let r1 = engine
+        .carve_region(d0, r0, dummy_access(0, 0x2000))
+        .unwrap();
+    engine.send(d0, r1, d1_mgmt).unwrap();
+    let r2 = engine
+        .carve_region(d0, r0, dummy_access(0x2000, 0x3000))
+        .unwrap();
+    assert_eq!(
+        engine.send(d0, r2, d1_mgmt).err(),
+        Some(CapaError::QuotaExceeded)
+    );
+    snap!("{[0x0, 0x2000 | 1 (1 - 1 - 1 - 1)]}", regions(d1, engine));
+    snap!(
+        "{Region([0x0, 0x10000 | PURWXS]), Management(2 | _), Region([0x2000, 0x3000 | _URWXS])}",
+        capas(d0, engine)
+    );
+
+    // Region quota
+    let r1 = LocalCapa::new(0);
+    let alias = engine
+        .alias_region(d1, r1, dummy_access(0, 0x1000))
+        .unwrap();
+    assert_eq!(
+        engine.alias_region(d1, r1, dummy_access(0, 0x1000)).err(),
+        Some(CapaError::QuotaExceeded)
+    );
+    assert_eq!(engine[d1].usage().regions, 2);
+    snap!(
+        "{[0x0, 0x1000 | 2 (2 - 2 - 2 - 2)] -> [0x1000, 0x2000 | 1 (1 - 1 - 1 - 1)]}",
+        regions(d1, engine)
+    );
+
+    // Domain quota, the quota is inherited and accounted to all the managers
+    let d2_mgmt = engine.create_domain(d1).unwrap();
+    let d2 = engine.get_domain_capa(d1, d2_mgmt).unwrap();
+    assert_eq!(engine[d2].quota(), quota);
+    assert_eq!(
+        engine.create_domain(d1).err(),
+        Some(CapaError::QuotaExceeded)
+    );
+    assert_eq!(engine[d0].usage().domains, 2);
+    assert_eq!(
+        engine.set_child_quota(d1, d2_mgmt, Quota::UNLIMITED),
+        Err(CapaError::QuotaExceeded)
+    );
+
+    // Moving resources within the managed domains is fine
+    engine.send(d1, alias, d2_mgmt).unwrap();
+    assert_eq!(engine[d1].usage().regions, 2);
+    assert_eq!(engine[d2].usage().regions, 1);
+
+    // Revoking gives the resources back
+    engine.revoke(d1, d2_mgmt).unwrap();
+    assert_eq!(engine[d1].usage().domains, 0);
+    assert_eq!(engine[d1].usage().regions, 1);
+    engine.create_domain(d1).unwrap();
+    engine.revoke(d0, d1_mgmt).unwrap();
+    assert_eq!(engine[d0].usage().domains, 0);
+    engine.check_invariants();
+}
+
+#[test]
+fn attenuated_management() {
+    let engine = unsafe { static_engine!() };
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let sched_mgmt = engine.create_domain(d0).unwrap();
+    let sched = engine.get_domain_capa(d0, sched_mgmt).unwrap();
+
+    // Delegate running d1 to the scheduler
+    let switch_only = engine
+        .duplicate_with_rights(d0, d1_mgmt, MgmtRights::SWITCH)
+        .unwrap();
+    assert_eq!(engine.get_domain_capa(d0, switch_only), Ok(d1));
+    engine.send(d0, switch_only, sched_mgmt).unwrap();
+    snap!("{Management(2 | _ | 0x2)}", capas(sched, engine));
+    let delegated = LocalCapa::new(0);
+    engine.create_switch_on_core(sched, 0, delegated).unwrap();
+    assert_eq!(
+        engine.seal(sched, 0, delegated).err(),
+        Some(CapaError::InsufficientPermissions)
+    );
+    assert_eq!(
+        engine.set_child_core_map(sched, delegated, CoreSet::NONE),
+        Err(CapaError::InsufficientPermissions)
+    );
+    engine.check_invariants();
+
+    // Without the revoke right, only the capability is dropped
+    engine.revoke(sched, delegated).unwrap();
+    snap!("{Switch(2 on core 0)}", capas(sched, engine));
+
+    // Duplicates never manage the domain
+    let all_rights = engine
+        .duplicate_with_rights(d0, d1_mgmt, MgmtRights::all())
+        .unwrap();
+    engine.send(d0, all_rights, sched_mgmt).unwrap();
+    assert_eq!(engine[sched].usage().domains, 0);
+    snap!(
+        "{Management(2 | _ | 0x3f), Switch(2 on core 0)}",
+        capas(sched, engine)
+    );
+    engine.check_invariants();
+
+    // Capabilities can only be sent with the send right
+    let no_send = engine
+        .duplicate_with_rights(d0, d1_mgmt, MgmtRights::CONFIGURE)
+        .unwrap();
+    let region = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    assert_eq!(
+        engine.send(d0, region, no_send).err(),
+        Some(CapaError::InsufficientPermissions)
+    );
+
+    // With the revoke right, the domain is revoked on behalf of its manager
+    engine.revoke(sched, delegated).unwrap();
+    snap!("{}", capas(sched, engine));
+    assert_eq!(engine[d0].usage().domains, 1);
+    engine.check_invariants();
+}
+
+#[test]
+fn leases() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine
+        .set_child_core_map(d0, d1_mgmt, CoreSet::ALL)
+        .unwrap();
+    let lease = |condition| Lease {
+        condition,
+        alias: 0x1000,
+        size: 0x1000,
+    };
+    let switch_from = |engine: &mut CapaEngine, domain| {
+        let mut token = NextCapaToken::new();
+        while let Some((info, next, idx)) = engine.enumerate(domain, token) {
+            if matches!(info, CapaInfo::Switch { .. }) {
+                return engine.switch(domain, core, 0, LocalCapa::new(idx)).unwrap();
+            }
+            token = next;
+        }
+        panic!("No switch capability");
+    };
+
+    // Root regions and leases expiring right away are refused
+    assert_eq!(
+        engine
+            .send_with_lease(d0, r0, d1_mgmt, lease(LeaseCondition::OnReturn))
+            .err(),
+        Some(CapaError::InvalidOperation)
+    );
+    let lent = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    assert_eq!(
+        engine
+            .send_with_lease(d0, lent, d1_mgmt, lease(LeaseCondition::Switches(0)))
+            .err(),
+        Some(CapaError::InvalidValue)
+    );
+
+    // The region is revoked when switching back to the sender
+    engine
+        .send_with_lease(d0, lent, d1_mgmt, lease(LeaseCondition::OnReturn))
+        .unwrap();
+    engine.create_switch_on_core(d0, core, d1_mgmt).unwrap();
+    switch_from(engine, d0);
+    snap!("{[0x0, 0x1000 | 1 (1 - 1 - 1 - 1)]}", regions(d1, engine));
+    snap!(
+        "{Region([0x0, 0x1000 | _URWXS]), Switch(1 on core 0)}",
+        capas(d1, engine)
+    );
+    updates(engine);
+    switch_from(engine, d1);
+    snap!("{}", regions(d1, engine));
+    snap!(
+        "{Switch(H(0, gen 0), core 0), LeaseExpired(H(1, gen 0), [0x1000, 0x2000]), RegionRevoked(H(1, gen 0), 0, [0x0, 0x1000]), PermissionUpdate(H(1, gen 0)), PermissionUpdate(H(0, gen 0))}",
+        updates(engine)
+    );
+
+    // The region is revoked after a number of switches out of the holder, which is notified
+    engine
+        .set_domain_permission(
+            d1,
+            permission::PermissionIndex::AllowedTraps,
+            permission::trap_bits::LEASE_EXPIRED,
+        )
+        .unwrap();
+    let lent = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine
+        .send_with_lease(d0, lent, d1_mgmt, lease(LeaseCondition::Switches(2)))
+        .unwrap();
+    switch_from(engine, d0);
+    switch_from(engine, d1);
+    snap!("{[0x0, 0x1000 | 1 (1 - 1 - 1 - 1)]}", regions(d1, engine));
+    switch_from(engine, d0);
+    updates(engine);
+    switch_from(engine, d1);
+    snap!("{}", regions(d1, engine));
+    snap!(
+        "{Switch(H(0, gen 0), core 0), LeaseExpired(H(1, gen 0), [0x1000, 0x2000]), RegionRevoked(H(1, gen 0), 1, [0x0, 0x1000]), PermissionUpdate(H(1, gen 0)), PermissionUpdate(H(0, gen 0)), Trap(manager: H(1, gen 0), trap: 4294967296, core: 0)}",
+        updates(engine)
+    );
+
+    // The region is revoked at the deadline
+    let lent = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine
+        .send_with_lease(d0, lent, d1_mgmt, lease(LeaseCondition::Deadline(100)))
+        .unwrap();
+    assert_eq!(engine.next_lease_deadline(), Some(100));
+    engine.expire_leases(99, core).unwrap();
+    snap!("{[0x0, 0x1000 | 1 (1 - 1 - 1 - 1)]}", regions(d1, engine));
+    engine.expire_leases(100, core).unwrap();
+    snap!("{}", regions(d1, engine));
+    assert_eq!(engine.next_lease_deadline(), None);
+
+    // Leases on regions revoked in the meantime are dropped
+    let lent = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine
+        .send_with_lease(d0, lent, d1_mgmt, lease(LeaseCondition::Deadline(200)))
+        .unwrap();
+    engine.revoke(d0, d1_mgmt).unwrap();
+    updates(engine);
+    engine.expire_leases(200, core).unwrap();
+    snap!("{}", updates(engine));
+    snap!("{[0x0, 0x10000 | 1 (1 - 1 - 1 - 1)]}", regions(d0, engine));
+}
+
+#[test]
+fn revocation_notifications() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    updates(engine);
+
+    // The holder is told which capability and range were revoked from under it
+    let sent = engine
+        .alias_region(d0, r0, dummy_access(0x1000, 0x2000))
+        .unwrap();
+    let revoke = engine.create_revoke_capa(d0, sent).unwrap();
+    engine.send(d0, sent, d1_mgmt).unwrap();
+    updates(engine);
+    engine.revoke(d0, revoke).unwrap();
+    snap!(
+        "{RegionRevoked(H(1, gen 0), 0, [0x1000, 0x2000]), PermissionUpdate(H(1, gen 0))}",
+        updates(engine)
+    );
+    let revoked = engine.next_revocation(d1).unwrap();
+    assert_eq!(revoked.capa.as_usize(), 0);
+    assert_eq!((revoked.start, revoked.end), (0x1000, 0x2000));
+    assert!(engine.next_revocation(d1).is_none());
+
+    // Domains are not notified of the regions they revoke themselves
+    let carved = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine.revoke(d0, carved).unwrap();
+    assert!(engine.next_revocation(d0).is_none());
+
+    // Notifications are dropped once the queue is full
+    for _ in 0..config::NB_PENDING_REVOCATIONS + 1 {
+        let sent = engine
+            .alias_region(d0, r0, dummy_access(0x1000, 0x2000))
+            .unwrap();
+        let revoke = engine.create_revoke_capa(d0, sent).unwrap();
+        engine.send(d0, sent, d1_mgmt).unwrap();
+        engine.revoke(d0, revoke).unwrap();
+    }
+    let mut pending = 0;
+    while engine.next_revocation(d1).is_some() {
+        pending += 1;
+    }
+    assert_eq!(pending, config::NB_PENDING_REVOCATIONS);
+}
+
+#[test]
+fn transactions() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    updates(engine);
+    let initial_capas = capas(d0, engine);
+    let initial_capacity = engine.get_capacity();
+
+    // Build a domain, but fail to configure it once sealed
+    engine.begin_transaction().unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let carved = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine.create_revoke_capa(d0, carved).unwrap();
+    let aliased = engine
+        .alias_region(d0, r0, dummy_access(0x2000, 0x3000))
+        .unwrap();
+    engine.send(d0, carved, d1_mgmt).unwrap();
+    engine
+        .send_with_flags(d0, aliased, d1_mgmt, Some(MemOps::CLEANUP), None, None)
+        .unwrap();
+    engine
+        .set_child_core_map(d0, d1_mgmt, CoreSet::single(core))
+        .unwrap();
+    engine.create_switch_on_core(d0, core, d1_mgmt).unwrap();
+    engine.seal(d0, core, d1_mgmt).unwrap();
+    snap!("{}", updates(engine));
+    let err =
+        engine.set_child_permission(d0, d1_mgmt, permission::PermissionIndex::AllowedTraps, 0);
+    assert_eq!(err, Err(CapaError::AlreadySealed));
+
+    // Revocation can't be undone
+    assert_eq!(engine.revoke(d0, d1_mgmt), Err(CapaError::InvalidOperation));
+
+    // Rolling back leaves no trace of the domain
+    engine.abort_transaction().unwrap();
+    snap!("{}", updates(engine));
+    snap!("{[0x0, 0x10000 | 1 (1 - 1 - 1 - 1)]}", regions(d0, engine));
+    assert_eq!(capas(d0, engine), initial_capas);
+    assert_eq!(engine.get_capacity(), initial_capacity);
+    assert!(engine.get_domain_regions(d1).is_err());
+
+    // Committed transactions are kept, and updates are released
+    engine.begin_transaction().unwrap();
+    assert_eq!(engine.begin_transaction(), Err(CapaError::InvalidOperation));
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let carved = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine.send(d0, carved, d1_mgmt).unwrap();
+    snap!("{}", updates(engine));
+    engine.commit_transaction().unwrap();
+    snap!(
+        "{CreateDomain(H(1, gen 1)), PermissionUpdate(H(0, gen 0)), PermissionUpdate(H(1, gen 1))}",
+        updates(engine)
+    );
+    snap!("{[0x0, 0x1000 | 1 (1 - 1 - 1 - 1)]}", regions(d1, engine));
+    assert_eq!(engine.abort_transaction(), Err(CapaError::InvalidOperation));
+}
+
+#[test]
+fn channels() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    let d2 = engine.get_domain_capa(d0, d2_mgmt).unwrap();
+    engine
+        .set_child_permission(
+            d0,
+            d1_mgmt,
+            permission::PermissionIndex::MonitorInterface,
+            permission::monitor_inter_perm::SEND,
+        )
+        .unwrap();
+
+    // The manager hands over a channel to d2 to d1
+    let channel = engine.create_channel(d0, Some(d2_mgmt)).unwrap();
+    let channel = engine.send(d0, channel, d1_mgmt).unwrap();
+    let region = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    let region = engine.send(d0, region, d1_mgmt).unwrap();
+    let unaligned = engine
+        .carve_region(d0, r0, dummy_access(0x1000, 0x1800))
+        .unwrap();
+    let unaligned = engine.send(d0, unaligned, d1_mgmt).unwrap();
+    snap!(
+        "{Channel(3), Region([0x0, 0x1000 | _URWXS]), Region([0x1000, 0x1800 | _URWXS])}",
+        capas(d1, engine)
+    );
+    updates(engine);
+
+    // Only channels can be sent over, and only page-aligned regions transferred
+    assert_eq!(
+        engine.channel_send(d1, region, [0; 3], None),
+        Err(CapaError::WrongCapabilityType)
+    );
+    assert_eq!(
+        engine.channel_send(d1, channel, [0; 3], Some(unaligned)),
+        Err(CapaError::InvalidRegion)
+    );
+    assert!(engine.next_message(d2).is_none());
+
+    // d1 messages d2 directly, transferring the region along
+    engine
+        .channel_send(d1, channel, [1, 2, 3], Some(region))
+        .unwrap();
+    snap!(
+        "{PermissionUpdate(H(1, gen 0)), PermissionUpdate(H(2, gen 0)), ChannelMessage(H(2, gen 0), 1 pending)}",
+        updates(engine)
+    );
+    let message = engine.next_message(d2).unwrap();
+    assert_eq!(message.from, engine[d1].id());
+    assert_eq!(message.words, [1, 2, 3]);
+    snap!("{Region([0x0, 0x1000 | _URWXS])}", capas(d2, engine));
+    assert_eq!(message.region.map(|r| r.as_usize()), Some(0));
+    snap!("{[0x0, 0x1000 | 1 (1 - 1 - 1 - 1)]}", regions(d2, engine));
+    assert!(engine.next_message(d2).is_none());
+
+    // Sending fails once the receiver's queue is full
+    for i in 0..config::NB_PENDING_MESSAGES {
+        engine.channel_send(d1, channel, [i; 3], None).unwrap();
+    }
+    assert_eq!(
+        engine.channel_send(d1, channel, [0; 3], None),
+        Err(CapaError::ChannelFull)
+    );
+    assert_eq!(engine.next_message(d2).unwrap().words, [0; 3]);
+    engine.channel_send(d1, channel, [0; 3], None).unwrap();
+
+    // Domains can create channels to themselves, to hand them over to their peers
+    let channel = engine.create_channel(d2, None).unwrap();
+    snap!(
+        "{Region([0x0, 0x1000 | _URWXS]), Channel(3)}",
+        capas(d2, engine)
+    );
+    assert_eq!(
+        engine.create_channel(d2, Some(channel)).err(),
+        Some(CapaError::WrongCapabilityType)
+    );
+}
+
+#[test]
+fn flow_policy() {
+    const CONFIDENTIAL: u64 = 1 << 0;
+    const NET: u64 = 1 << 1;
+
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+    let policy = Policy::new(&[
+        // Confidential regions never reach the initial domain
+        Rule {
+            transfers: transfer::SEND_REGION | transfer::ALIAS,
+            to: labels::INITIAL,
+            region: CONFIDENTIAL,
+            ..Rule::EMPTY
+        },
+        // Network domains can't receive executable memory
+        Rule {
+            transfers: transfer::SEND_REGION,
+            to: NET,
+            ops: MemOps::EXEC.bits() as u64,
+            ..Rule::EMPTY
+        },
+        // Sealed domains can't spawn children
+        Rule {
+            transfers: transfer::SPAWN,
+            from: labels::SEALED,
+            ..Rule::EMPTY
+        },
+    ])
+    .unwrap();
+    engine.load_flow_policy(policy).unwrap();
+    assert_eq!(engine.flow_policy().rules().len(), 3);
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    assert_eq!(
+        engine.load_flow_policy(Policy::empty()),
+        Err(CapaError::InvalidOperation)
+    );
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    let d2 = engine.get_domain_capa(d0, d2_mgmt).unwrap();
+    for mgmt in [d1_mgmt, d2_mgmt] {
+        engine
+            .set_child_permission(
+                d0,
+                mgmt,
+                permission::PermissionIndex::MonitorInterface,
+                permission::monitor_inter_perm::SEND | permission::monitor_inter_perm::SPAWN,
+            )
+            .unwrap();
+    }
+
+    // Managers label their children, but can't set the labels maintained by the engine
+    engine.set_child_labels(d0, d1_mgmt, CONFIDENTIAL).unwrap();
+    engine.set_child_labels(d0, d2_mgmt, NET).unwrap();
+    assert_eq!(
+        engine.set_child_labels(d0, d2_mgmt, labels::SEALED),
+        Err(CapaError::InvalidValue)
+    );
+    assert_eq!(engine.get_child_labels(d0, d1_mgmt), Ok(CONFIDENTIAL));
+
+    // Regions are tainted by the domains they go through
+    let region = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    let region = engine.send(d0, region, d1_mgmt).unwrap();
+    let d0_in_d1 = engine.create_channel(d0, None).unwrap();
+    let d0_in_d1 = engine.send(d0, d0_in_d1, d1_mgmt).unwrap();
+    updates(engine);
+    assert_eq!(
+        engine.channel_send(d1, d0_in_d1, [0; 3], Some(region)),
+        Err(CapaError::DeniedByPolicy)
+    );
+    snap!(
+        "{Region([0x0, 0x1000 | _URWXS]), Channel(1)}",
+        capas(d1, engine)
+    );
+    snap!("{}", updates(engine));
+
+    // Executable regions can't be sent to network domains
+    let exec = engine
+        .carve_region(d0, r0, dummy_access(0x1000, 0x2000))
+        .unwrap();
+    assert_eq!(
+        engine.send(d0, exec, d2_mgmt).err(),
+        Some(CapaError::DeniedByPolicy)
+    );
+    let read_only = AccessRights {
+        start: 0x2000,
+        end: 0x3000,
+        ops: MemOps::READ,
+    };
+    let read_only = engine.carve_region(d0, r0, read_only).unwrap();
+    engine.send(d0, read_only, d2_mgmt).unwrap();
+
+    // Children inherit the labels of their creator
+    let d3_mgmt = engine.create_domain(d2).unwrap();
+    assert_eq!(engine.get_child_labels(d2, d3_mgmt), Ok(NET));
+
+    // Sealed domains can't spawn children, nor be labeled anymore
+    engine.create_switch_on_core(d0, core, d1_mgmt).unwrap();
+    engine.seal(d0, core, d1_mgmt).unwrap();
+    assert_eq!(
+        engine.get_child_labels(d0, d1_mgmt),
+        Ok(CONFIDENTIAL | labels::SEALED)
+    );
+    assert_eq!(
+        engine.set_child_labels(d0, d1_mgmt, NET),
+        Err(CapaError::AlreadySealed)
+    );
+    assert_eq!(
+        engine.create_domain(d1).err(),
+        Some(CapaError::DeniedByPolicy)
+    );
+    engine.create_domain(d0).unwrap();
+}
+
+#[test]
+fn snapshot_restore() {
+    let config = EngineConfig {
+        nb_domains: 4,
+        nb_capas_per_domain: 8,
+        nb_regions: 16,
+        nb_tracker: 32,
+    };
+    let engine = unsafe { static_engine!(config) };
+    let core = 0;
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine.set_child_labels(d0, d1_mgmt, 1 << 3).unwrap();
+    let region = engine
+        .carve_region(d0, r0, dummy_access(0, 0x1000))
+        .unwrap();
+    engine.send(d0, region, d1_mgmt).unwrap();
+    let channel = engine.create_channel(d0, Some(d1_mgmt)).unwrap();
+    engine.channel_send(d0, channel, [1, 2, 3], None).unwrap();
+
+    // Snapshots need a large enough buffer
+    let mut snapshot = vec![0; engine.snapshot_size().unwrap()];
+    assert_eq!(
+        engine.snapshot(&mut snapshot[..16]),
+        Err(CapaError::OutOfMemory)
+    );
+    assert_eq!(engine.snapshot(&mut snapshot), Ok(snapshot.len()));
+
+    // The restored engine has the same state, including the pending updates
+    let restored = unsafe { static_engine!(config) };
+    restored.restore(&snapshot).unwrap();
+    let mut copy = vec![0; snapshot.len()];
+    restored.snapshot(&mut copy).unwrap();
+    assert_eq!(snapshot, copy);
+    for domain in [d0, d1] {
+        assert_eq!(capas(domain, engine), capas(domain, restored));
+        assert_eq!(
+            format!("{}", regions(domain, engine)),
+            format!("{}", regions(domain, restored))
+        );
+    }
+    assert_eq!(updates(engine), updates(restored));
+    let mut attestation = [0; 0x1000];
+    let mut restored_attestation = [0; 0x1000];
+    assert_eq!(
+        engine.serialize_attestation(&mut attestation),
+        restored.serialize_attestation(&mut restored_attestation)
+    );
+    assert_eq!(attestation, restored_attestation);
+
+    // Handles remain valid, and both engines keep behaving the same
+    assert_eq!(restored.next_message(d1).unwrap().words, [1, 2, 3]);
+    assert_eq!(engine.next_message(d1).unwrap().words, [1, 2, 3]);
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    assert_eq!(
+        restored.create_domain(d0).unwrap().as_usize(),
+        d2_mgmt.as_usize()
+    );
+    assert_eq!(
+        engine.get_domain_capa(d0, d2_mgmt),
+        restored.get_domain_capa(d0, d2_mgmt)
+    );
+    assert_eq!(updates(engine), updates(restored));
+
+    // Snapshots can only be restored in engines of the same size
+    let other = unsafe { static_engine!() };
+    assert_eq!(other.restore(&snapshot), Err(CapaError::InvalidValue));
+    assert_eq!(
+        restored.restore(&snapshot[..snapshot.len() - 1]),
+        Err(CapaError::CouldNotDeserializeInfo)
+    );
+    let mut corrupted = snapshot.clone();
+    corrupted[0] = 0;
+    assert_eq!(
+        restored.restore(&corrupted),
+        Err(CapaError::CouldNotDeserializeInfo)
+    );
+
+    // Nor during a transaction
+    restored.restore(&snapshot).unwrap();
+    restored.begin_transaction().unwrap();
+    assert_eq!(
+        restored.snapshot(&mut copy),
+        Err(CapaError::InvalidOperation)
+    );
+    assert_eq!(
+        restored.restore(&snapshot),
+        Err(CapaError::InvalidOperation)
+    );
+    restored.abort_transaction().unwrap();
+    restored.snapshot(&mut copy).unwrap();
+    assert_eq!(snapshot, copy);
+}
+
+#[test]
+fn permission_deltas() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    let _ctx = engine.start_domain_on_core(d0, core).unwrap();
+    let r0 = engine
+        .create_root_region(d0, dummy_access(0, 0x10000))
+        .unwrap();
+    snap!("{Added([0x0, 0x10000 | RWXS])}", deltas(d0, engine));
+    snap!("{}", deltas(d0, engine));
+
+    // Losing permissions on part of the region only changes that part.
+    let r1 = engine
+        .carve_region(
+            d0,
+            r0,
+            AccessRights {
+                start: 0x1000,
+                end: 0x2000,
+                ops: MemOps::READ,
+            },
+        )
+        .unwrap();
+    snap!("{Changed([0x1000, 0x2000 | R___])}", deltas(d0, engine));
+
+    // Sending the region to another domain removes it from the sender.
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine.send(d0, r1, d1_mgmt).unwrap();
+    snap!("{Removed([0x1000, 0x2000])}", deltas(d0, engine));
+    snap!("{Added([0x1000, 0x2000 | R___])}", deltas(d1, engine));
+
+    // Changes that cancel out before being acknowledged are not reported.
+    let r2 = engine
+        .carve_region(
+            d0,
+            r0,
+            AccessRights {
+                start: 0x4000,
+                end: 0x5000,
+                ops: MemOps::READ,
+            },
+        )
+        .unwrap();
+    engine
+        .carve_region(
+            d0,
+            r0,
+            AccessRights {
+                start: 0x8000,
+                end: 0x9000,
+                ops: MemOps::READ,
+            },
+        )
+        .unwrap();
+    engine.revoke(d0, r2).unwrap();
+    snap!("{Changed([0x8000, 0x9000 | R___])}", deltas(d0, engine));
+
+    // Too many changes can not be tracked, the permissions must be applied in full.
+    let mut carved = [LocalCapa::new(0); config::NB_PERMISSION_DELTAS + 1];
+    for (idx, capa) in carved.iter_mut().enumerate() {
+        let start = 0x10 * idx;
+        *capa = engine
+            .carve_region(
+                d0,
+                r0,
+                AccessRights {
+                    start,
+                    end: start + 0x8,
+                    ops: MemOps::READ,
+                },
+            )
+            .unwrap();
+    }
+    assert!(engine.get_domain_permission_deltas(d0).unwrap().is_none());
+    engine.acknowledge_permission_deltas(d0).unwrap();
+    snap!("{}", deltas(d0, engine));
+
+    // Up to the size of the log, each change is reported.
+    for capa in &carved[..config::NB_PERMISSION_DELTAS] {
+        engine.revoke(d0, *capa).unwrap();
+    }
+    let mut deltas = engine.get_domain_permission_deltas(d0).unwrap().unwrap();
+    snap!("Changed([0x0, 0x8 | RWXS])", deltas.next().unwrap());
+    assert_eq!(deltas.count(), config::NB_PERMISSION_DELTAS - 1);
+}
+
+#[test]
+fn fast_switch() {
+    let engine = unsafe { static_engine!() };
+    let core = 0;
+
+    let d0 = engine
+        .create_manager_domain(permission::monitor_inter_perm::ALL)
+        .unwrap();
+    engine.start_domain_on_core(d0, core).unwrap();
+    let d1_mgmt = engine.create_domain(d0).unwrap();
+    let d1 = engine.get_domain_capa(d0, d1_mgmt).unwrap();
+    engine
+        .set_child_core_map(d0, d1_mgmt, CoreSet::single(core))
+        .unwrap();
+    engine.create_switch_on_core(d0, core, d1_mgmt).unwrap();
+    let switch = engine.seal(d0, core, d1_mgmt).unwrap();
+    let d2_mgmt = engine.create_domain(d0).unwrap();
+    engine
+        .set_child_core_map(d0, d2_mgmt, CoreSet::single(core))
+        .unwrap();
+    let unsealed = engine.create_switch_on_core(d0, core, d2_mgmt).unwrap();
+    updates(engine);
+
+    // Both domains must be sealed and share the core
+    assert_eq!(
+        engine.enable_fast_switch(d0, core, unsealed),
+        Err(CapaError::InvalidOperation)
+    );
+    assert_eq!(
+        engine.enable_fast_switch(d0, core + 1, switch),
+        Err(CapaError::InvalidCore)
+    );
+
+    // The peer runs on the core along with its owner
+    assert_eq!(engine.enable_fast_switch(d0, core, switch), Ok(d1));
+    assert_eq!(engine.get_domain_cores(d1), Ok(CoreSet::single(core)));
+    assert_eq!(
+        engine.enable_fast_switch(d0, core, switch),
+        Err(CapaError::InvalidSwitch)
+    );
+    engine.check_invariants();
+
+    // Switches within the pair do not go through the engine
+    assert_eq!(
+        engine.switch(d0, core, 0, switch),
+        Err(CapaError::InvalidSwitch)
+    );
+
+    // Only the owner can disable the pair
+    assert_eq!(
+        engine.disable_fast_switch(d1),
+        Err(CapaError::InvalidOperation)
+    );
+    engine.disable_fast_switch(d0).unwrap();
+    snap!(
+        "{FastSwitchDisabled(H(0, gen 0), H(1, gen 0), core 0)}",
+        updates(engine)
+    );
+    assert_eq!(engine.get_domain_cores(d1), Ok(CoreSet::NONE));
+
+    // Revoking the peer dissolves the pair first
+    engine.enable_fast_switch(d0, core, switch).unwrap();
+    engine.revoke(d0, d1_mgmt).unwrap();
+    snap!(
+        "{FastSwitchDisabled(H(0, gen 0), H(1, gen 0), core 0)}",
+        updates(engine)
+    );
+    assert_eq!(engine.get_domain_cores(d0), Ok(CoreSet::single(core)));
+}
+
 // ————————————————————————————————— Utils —————————————————————————————————— //
 
 fn regions(domain: Handle<Domain>, engine: &CapaEngine) -> RegionIterator {
+    engine.check_invariants();
     engine.get_domain_regions(domain).expect("Invalid domain")
 }
 
 fn capas(domain: Handle<Domain>, engine: &mut CapaEngine) -> String {
+    engine.check_invariants();
     let mut token = NextCapaToken::new();
     let mut buff = String::from("{");
     let mut is_first = true;
 
-    while let Some((capa, new_token)) = engine.enumerate(domain, token) {
+    while let Some((capa, new_token, _)) = engine.enumerate(domain, token) {
         if is_first {
             is_first = false;
         } else {
@@ -2174,6 +3211,7 @@ fn capas(domain: Handle<Domain>, engine: &mut CapaEngine) -> String {
 }
 
 fn updates(engine: &mut CapaEngine) -> String {
+    engine.check_invariants();
     let mut buff = String::from("{");
     let mut is_first = true;
 
@@ -2190,6 +3228,30 @@ fn updates(engine: &mut CapaEngine) -> String {
     buff
 }
 
+/// Returns the permission changes of the domain, and acknowledges them.
+fn deltas(domain: Handle<Domain>, engine: &mut CapaEngine) -> String {
+    engine.check_invariants();
+    let deltas = engine
+        .get_domain_permission_deltas(domain)
+        .expect("Invalid domain")
+        .expect("Too many permission changes");
+    let mut buff = String::from("{");
+    let mut is_first = true;
+
+    for delta in deltas {
+        if is_first {
+            is_first = false;
+        } else {
+            buff.write_str(", ").unwrap();
+        }
+        buff.write_str(&format!("{}", delta)).unwrap();
+    }
+
+    buff.write_str("}").unwrap();
+    engine.acknowledge_permission_deltas(domain).unwrap();
+    buff
+}
+
 fn dummy_access(start: usize, end: usize) -> AccessRights {
     AccessRights {
         start,
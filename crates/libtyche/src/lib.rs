@@ -1,46 +1,82 @@
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use capa_engine::CapaInfo;
+use capa_engine::{CapaInfo, MgmtRights};
+use monitor_abi::channel::{NB_WORDS, NO_REGION};
+use monitor_abi::error::{Error as MonitorError, ErrorCode, ERROR_VERSION};
+use monitor_abi::ring::{Completion, Header, Submission, MAX_ENTRIES};
+use monitor_abi::{calls, status, Args, Results};
 
-// ——————————————————————————————— Hypercalls ——————————————————————————————— //
+// ————————————————————————————————— Errors ————————————————————————————————— //
 
-#[derive(Debug)]
-#[repr(usize)]
-#[rustfmt::skip]
-pub enum VmCalls {
-    DomainCreate      = 0x1,
-    SealDomain        = 0x2,
-    Share             = 0x3,
-    Send             = 0x4,
-    SegmentRegion     = 0x5,
-    Revoke            = 0x6,
-    Duplicate         = 0x7,
-    Enumerate         = 0x8,
-    Switch            = 0x9,
-    Exit              = 0xA,
-    Debug             = 0xB,
-}
-
-// —————————————————————————————— Error Codes ——————————————————————————————— //
-
-#[derive(Debug, Clone, Copy)]
-#[repr(usize)]
-pub enum ErrorCode {
-    Success = 0,
-    Failure = 1,
-    UnknownVmCall = 2,
-    OutOfMemory = 3,
-    DomainOutOfBound = 4,
-    RegionOutOfBound = 5,
-    RegionCapaOutOfBound = 6,
-    InvalidRegionCapa = 7,
-    RegionNotOwned = 8,
-    InvalidAddress = 9,
-    InvalidDomain = 10,
-    DomainIsSealed = 11,
-    StoreAccessOutOfBound = 12,
-    BadParameters = 13,
-    RegionIsShared = 14,
+/// An error returned by a monitor call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// An error defined by the monitor ABI.
+    Monitor(MonitorError),
+    /// An error code unknown to this version of the monitor ABI.
+    Unknown { code: usize, details: usize },
+    /// The monitor uses another version of the error space, its codes can not be decoded.
+    Version {
+        monitor: usize,
+        code: usize,
+        details: usize,
+    },
+}
+
+/// The version of the error space of the running monitor, queried on the first failed call.
+static MONITOR_ERROR_VERSION: AtomicUsize = AtomicUsize::new(VERSION_NOT_QUERIED);
+const VERSION_NOT_QUERIED: usize = usize::MAX;
+
+impl Error {
+    /// Decodes the error from the result registers of a failed call.
+    fn decode(res: &Results) -> Self {
+        let monitor = error_version();
+        if monitor != ERROR_VERSION {
+            return Error::Version {
+                monitor,
+                code: res[0],
+                details: res[1],
+            };
+        }
+        match MonitorError::decode(res) {
+            Some(err) => Error::Monitor(err),
+            None => Error::Unknown {
+                code: res[0],
+                details: res[1],
+            },
+        }
+    }
+
+    /// Returns the error code, if known.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Monitor(err) => Some(err.code),
+            Error::Unknown { .. } | Error::Version { .. } => None,
+        }
+    }
+}
+
+/// Returns the version of the error space of the running monitor, 0 if it does not report one.
+pub fn error_version() -> usize {
+    let version = MONITOR_ERROR_VERSION.load(Ordering::Relaxed);
+    if version != VERSION_NOT_QUERIED {
+        return version;
+    }
+    // The error is not decoded, as decoding relies on the version.
+    let (result, res) = raw_vmcall(calls::GET_ERROR_VERSION, [0; 6]);
+    let version = if result == status::SUCCESS { res[0] } else { 0 };
+    MONITOR_ERROR_VERSION.store(version, Ordering::Relaxed);
+    version
+}
+
+/// Checks that the running monitor uses the error space of this library, returns the version of
+/// the monitor otherwise.
+pub fn check_error_version() -> Result<(), usize> {
+    match error_version() {
+        ERROR_VERSION => Ok(()),
+        monitor => Err(monitor),
+    }
 }
 
 // ———————————————————————————— Data Structures ————————————————————————————— //
@@ -61,133 +97,286 @@ pub struct RevokHandle(pub usize);
 
 pub struct RegionHandle(pub usize);
 
-pub fn domain_create() -> Result<usize, ErrorCode> {
-    do_vmcall(VmCalls::DomainCreate, 0, 0, 0, 0, 0, 0, 0)
-        .map(|(managment, _, _, _, _, _, _)| managment)
+pub fn domain_create() -> Result<usize, Error> {
+    do_vmcall(calls::CREATE_DOMAIN, [0; 6]).map(|res| res[0])
 }
 
-pub fn seal_domain(
-    domain: usize,
-    core_map: usize,
-    arg1: usize,
-    arg2: usize,
-    arg3: usize,
-) -> Result<DomainId, ErrorCode> {
-    do_vmcall(
-        VmCalls::SealDomain,
-        domain,
-        core_map,
-        arg1,
-        arg2,
-        arg3,
-        0,
-        0,
-    )
-    .map(|(domain, _, _, _, _, _, _)| DomainId(domain))
+pub fn seal_domain(domain: usize) -> Result<usize, Error> {
+    do_vmcall(calls::SEAL_DOMAIN, [domain, 0, 0, 0, 0, 0]).map(|res| res[0])
 }
 
-// pub fn share(
-//     target: usize,
-//     capa: usize,
-//     arg1: usize,
-//     arg2: usize,
-//     arg3: usize,
-// ) -> Result<usize, ErrorCode> {
-//     do_vmcall(VmCalls::Share, target, capa, arg1, arg2, arg3, 0, 0)
-//         .map(|(left, _, _, _, _, _, _)| left)
-// }
+/// Adds information-flow labels to a child domain, returns all of its labels.
+pub fn configure_labels(domain: usize, labels: usize) -> Result<usize, Error> {
+    do_vmcall(calls::CONFIGURE_LABELS, [domain, labels, 0, 0, 0, 0]).map(|res| res[0])
+}
 
-pub fn send(target: usize, capa: usize) -> Result<(), ErrorCode> {
-    do_vmcall(VmCalls::Send, capa, target, 0, 0, 0, 0, 0).map(|_| ())
+pub fn send(capa: usize, target: usize) -> Result<(), Error> {
+    do_vmcall(calls::SEND, [capa, target, 0, 0, 0, 0]).map(|_| ())
 }
 
 pub fn segment_region(
     capa: usize,
-    start_1: usize,
-    end_1: usize,
-    prot_1: usize,
-    start_2: usize,
-    end_2: usize,
-    prot_2: usize,
-) -> Result<(usize, usize), ErrorCode> {
+    is_shared: bool,
+    start: usize,
+    end: usize,
+    prot: usize,
+) -> Result<(usize, usize), Error> {
     do_vmcall(
-        VmCalls::SegmentRegion,
-        capa,
-        start_1,
-        end_1,
-        prot_1,
-        start_2,
-        end_2,
-        prot_2,
+        calls::SEGMENT_REGION,
+        [capa, is_shared as usize, start, end, prot, 0],
     )
-    .map(|(left, right, _, _, _, _, _)| (left, right))
+    .map(|res| (res[0], res[1]))
 }
 
-pub fn revoke(capa: usize) -> Result<(), ErrorCode> {
-    do_vmcall(VmCalls::Revoke, capa, 0, 0, 0, 0, 0, 0).map(|(_, _, _, _, _, _, _)| ())
+pub fn revoke(capa: usize) -> Result<(), Error> {
+    do_vmcall(calls::REVOKE, [capa, 0, 0, 0, 0, 0]).map(|_| ())
 }
 
-pub fn duplicate(capa: usize) -> Result<usize, ErrorCode> {
-    do_vmcall(VmCalls::Duplicate, capa, 0, 0, 0, 0, 0, 0).map(|(capa, _, _, _, _, _, _)| capa)
+/// Duplicates a capability. Duplicated management capabilities only keep `rights`, which are
+/// ignored for other capabilities.
+pub fn duplicate(capa: usize, rights: MgmtRights) -> Result<usize, Error> {
+    let rights = rights.bits() as usize;
+    do_vmcall(calls::DUPLICATE, [capa, rights, 0, 0, 0, 0]).map(|res| res[0])
 }
 
-pub fn enumerate(next_token: usize) -> Result<Option<(CapaInfo, usize)>, ErrorCode> {
-    let (v1, v2, v3, next, _, _, _) = do_vmcall(VmCalls::Enumerate, next_token, 0, 0, 0, 0, 0, 0)?;
+pub fn enumerate(next_token: usize) -> Result<Option<(CapaInfo, usize)>, Error> {
+    let [v1, v2, v3, next, _, _] = do_vmcall(calls::ENUMERATE, [next_token, 0, 0, 0, 0, 0])?;
+    if next == 0 {
+        // No more capabilities
+        return Ok(None);
+    }
     let info = CapaInfo::deserialize(v1, v2, v3 as u16).expect("Deserialization should not fail");
     Ok(Some((info, next)))
 }
 
-pub fn switch(handle: usize, cpu: usize) -> Result<usize, ErrorCode> {
-    do_vmcall(VmCalls::Switch, handle, cpu, 0, 0, 0, 0, 0)
-        .map(|(return_handle, _, _, _, _, _, _)| return_handle)
+pub fn switch(handle: usize, delta: usize) -> Result<usize, Error> {
+    do_vmcall(calls::SWITCH, [handle, delta, 0, 0, 0, 0]).map(|res| res[0])
+}
+
+/// Pairs the caller with the domain `switch` leads to, mapping the trampoline used to switch
+/// between the two at `trampoline`. The owner is entered at `entry` with `stack`.
+pub fn enable_fast_switch(
+    switch: usize,
+    trampoline: usize,
+    entry: usize,
+    stack: usize,
+) -> Result<(), Error> {
+    do_vmcall(
+        calls::ENABLE_FAST_SWITCH,
+        [switch, trampoline, entry, stack, 0, 0],
+    )
+    .map(|_| ())
+}
+
+/// Dissolves the pair enabled by the caller.
+pub fn disable_fast_switch() -> Result<(), Error> {
+    do_vmcall(calls::DISABLE_FAST_SWITCH, [0; 6]).map(|_| ())
+}
+
+pub fn exit() -> Result<(), Error> {
+    do_vmcall(calls::EXIT, [0; 6]).map(|_| ())
+}
+
+pub fn debug() -> Result<(), Error> {
+    do_vmcall(calls::DEBUG, [0; 6]).map(|_| ())
 }
 
-pub fn exit() -> Result<(), ErrorCode> {
-    do_vmcall(VmCalls::Exit, 0, 0, 0, 0, 0, 0, 0).map(|_| ())
+/// A call that does nothing, to measure the cost of a monitor call.
+pub fn test_call() -> Result<(), Error> {
+    do_vmcall(calls::TEST_CALL, [0; 6]).map(|_| ())
+}
+
+/// Creates a channel to the domain managed through `domain`, or to the caller if `None`.
+pub fn create_channel(domain: Option<usize>) -> Result<usize, Error> {
+    let args = match domain {
+        Some(domain) => [domain, 0, 0, 0, 0, 0],
+        None => [0, 1, 0, 0, 0, 0],
+    };
+    do_vmcall(calls::CREATE_CHANNEL, args).map(|res| res[0])
+}
+
+/// Sends a message over a channel, with an optional `(region, alias)` to transfer the region
+/// along, mapped at `alias` in the receiver.
+pub fn channel_send(
+    channel: usize,
+    words: [usize; NB_WORDS],
+    region: Option<(usize, usize)>,
+) -> Result<(), Error> {
+    let (region, alias) = region.unwrap_or((NO_REGION, 0));
+    let [w0, w1, w2] = words;
+    do_vmcall(calls::CHANNEL_SEND, [channel, region, w0, w1, w2, alias]).map(|_| ())
+}
+
+/// A message received over a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    /// The id of the sending domain.
+    pub from: usize,
+    /// The region transferred along the message, if any.
+    pub region: Option<usize>,
+    pub words: [usize; NB_WORDS],
+}
+
+/// Polls the oldest message received over a channel, if any.
+pub fn channel_receive() -> Result<Option<Message>, Error> {
+    let [pending, from, region, w0, w1, w2] = do_vmcall(calls::CHANNEL_RECEIVE, [0; 6])?;
+    if pending == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Message {
+        from,
+        region: (region != NO_REGION).then_some(region),
+        words: [w0, w1, w2],
+    }))
 }
 
-pub fn debug() -> Result<(), ErrorCode> {
-    do_vmcall(VmCalls::Debug, 0, 0, 0, 0, 0, 0, 0).map(|_| ())
+/// The notifications pending for the caller, see [poll_notifications].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notifications {
+    /// Trap bits of the pending notifications.
+    pub pending: usize,
+    /// Start of the last region whose lease expired.
+    pub lease: usize,
+    /// Start of the last region revoked from under the caller.
+    pub revoked: usize,
+    /// Number of messages pending for the caller when last notified.
+    pub messages: usize,
 }
 
-fn do_vmcall(
-    vmcall: VmCalls,
-    arg_1: usize,
-    arg_2: usize,
-    arg_3: usize,
-    arg_4: usize,
-    arg_5: usize,
-    arg_6: usize,
-    arg_7: usize,
-) -> Result<(usize, usize, usize, usize, usize, usize, usize), ErrorCode> {
-    let result: ErrorCode;
-    let val_1: usize;
-    let val_2: usize;
-    let val_3: usize;
-    let val_4: usize;
-    let val_5: usize;
-    let val_6: usize;
-    let val_7: usize;
+/// Sets the interrupt vector signaling new notifications, or `notification::NO_VECTOR` to only
+/// poll them.
+pub fn set_notification_vector(vector: usize) -> Result<(), Error> {
+    do_vmcall(calls::SET_NOTIFICATION_VECTOR, [vector, 0, 0, 0, 0, 0]).map(|_| ())
+}
+
+/// Polls and clears the notifications pending for the caller.
+pub fn poll_notifications() -> Result<Notifications, Error> {
+    let [pending, lease, revoked, messages, ..] = do_vmcall(calls::POLL_NOTIFICATIONS, [0; 6])?;
+    Ok(Notifications {
+        pending,
+        lease,
+        revoked,
+        messages,
+    })
+}
+
+pub fn register_ring(addr: usize, entries: usize) -> Result<(), Error> {
+    do_vmcall(calls::REGISTER_RING, [addr, entries, 1, 0, 0, 0]).map(|_| ())
+}
+
+pub fn ring_enter(max: usize) -> Result<usize, Error> {
+    do_vmcall(calls::RING_ENTER, [max, 0, 0, 0, 0, 0]).map(|res| res[0])
+}
+
+// ————————————————————————————————— Rings —————————————————————————————————— //
+
+/// A submission ring, to issue many monitor calls with a single exit.
+///
+/// The ring must not move while it is registered.
+#[repr(C)]
+pub struct Ring<const N: usize> {
+    header: Header,
+    submissions: [Submission; N],
+    completions: [Completion; N],
+}
+
+impl<const N: usize> Ring<N> {
+    const VALID_SIZE: () = assert!(N.is_power_of_two() && N <= MAX_ENTRIES);
+
+    pub const fn new() -> Self {
+        let () = Self::VALID_SIZE;
+        const SUBMISSION: Submission = Submission {
+            call: 0,
+            user_data: 0,
+            args: [0; 6],
+        };
+        const COMPLETION: Completion = Completion {
+            user_data: 0,
+            status: 0,
+            res: [0; 6],
+        };
+        Ring {
+            header: Header {
+                sq_head: 0,
+                sq_tail: 0,
+                cq_head: 0,
+                cq_tail: 0,
+            },
+            submissions: [SUBMISSION; N],
+            completions: [COMPLETION; N],
+        }
+    }
+
+    pub fn register(&mut self) -> Result<(), Error> {
+        register_ring(self as *mut Self as usize, N)
+    }
+
+    /// Queues a call, returns false if the ring is full.
+    pub fn submit(&mut self, call: usize, args: Args, user_data: usize) -> bool {
+        // The monitor updates the header behind our back.
+        let head = unsafe { core::ptr::read_volatile(&self.header.sq_head) };
+        let tail = self.header.sq_tail;
+        if tail.wrapping_sub(head) >= N {
+            return false;
+        }
+        self.submissions[tail % N] = Submission {
+            call,
+            user_data,
+            args,
+        };
+        unsafe { core::ptr::write_volatile(&mut self.header.sq_tail, tail.wrapping_add(1)) };
+        true
+    }
+
+    /// Processes the queued calls, returns how many were processed.
+    pub fn enter(&mut self) -> Result<usize, Error> {
+        ring_enter(0)
+    }
+
+    /// Pops the oldest completion, with the `user_data` of its submission.
+    pub fn complete(&mut self) -> Option<(usize, Result<Results, Error>)> {
+        let head = self.header.cq_head;
+        if head == unsafe { core::ptr::read_volatile(&self.header.cq_tail) } {
+            return None;
+        }
+        let completion = unsafe { core::ptr::read_volatile(&self.completions[head % N]) };
+        unsafe { core::ptr::write_volatile(&mut self.header.cq_head, head.wrapping_add(1)) };
+        let result = match completion.status {
+            status::SUCCESS => Ok(completion.res),
+            _ => Err(Error::decode(&completion.res)),
+        };
+        Some((completion.user_data, result))
+    }
+}
+
+impl<const N: usize> Default for Ring<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn do_vmcall(vmcall: usize, args: Args) -> Result<Results, Error> {
+    match raw_vmcall(vmcall, args) {
+        (status::SUCCESS, res) => Ok(res),
+        (_, res) => Err(Error::decode(&res)),
+    }
+}
+
+/// Issues a monitor call, returns its status and result registers.
+fn raw_vmcall(vmcall: usize, args: Args) -> (usize, Results) {
+    let mut res: Results = args;
+    let result: usize;
     unsafe {
-        let res: usize;
         asm!(
             "vmcall",
-            inout("eax") vmcall as usize => res,
-            inout("edi") arg_1 => val_1,
-            inout("esi") arg_2 => val_2,
-            inout("edx") arg_3 => val_3,
-            inout("ecx") arg_4 => val_4,
-            inout("r8") arg_5 => val_5,
-            inout("r9") arg_6 => val_6,
-            inout("r10") arg_7 => val_7,
+            inout("rax") vmcall => result,
+            inout("rdi") res[0],
+            inout("rsi") res[1],
+            inout("rdx") res[2],
+            inout("rcx") res[3],
+            inout("r8") res[4],
+            inout("r9") res[5],
         );
-        result = match res {
-            0..=14 => core::mem::transmute(res),
-            _ => ErrorCode::Failure,
-        };
-    }
-    match result {
-        ErrorCode::Success => Ok((val_1, val_2, val_3, val_4, val_5, val_6, val_7)),
-        _ => Err(result),
     }
+    (result, res)
 }
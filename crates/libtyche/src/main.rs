@@ -1,3 +1,4 @@
+use capa_engine::MgmtRights;
 use clap::Parser;
 use clap_num::maybe_hex;
 use libtyche::{
@@ -16,10 +17,6 @@ enum Subcommand {
     CreateDomain,
     SealDomain {
         domain: usize,
-        core_map: usize,
-        arg1: usize,
-        arg2: usize,
-        arg3: usize,
     },
     Send {
         capa: usize,
@@ -27,31 +24,30 @@ enum Subcommand {
     },
     SegmentRegion {
         capa: usize,
+        #[clap(long)]
+        shared: bool,
         #[clap(value_parser=maybe_hex::<usize>)]
-        start_1: usize,
+        start: usize,
         #[clap(value_parser=maybe_hex::<usize>)]
-        end_1: usize,
+        end: usize,
         #[clap(value_parser=maybe_hex::<usize>)]
-        prot_1: usize,
-        #[clap(value_parser=maybe_hex::<usize>)]
-        start_2: usize,
-        #[clap(value_parser=maybe_hex::<usize>)]
-        end_2: usize,
-        #[clap(value_parser=maybe_hex::<usize>)]
-        prot_2: usize,
+        prot: usize,
     },
     Revoke {
         capa: usize,
     },
     Duplicate {
         capa: usize,
+        /// Rights kept by a duplicated management capability, all by default.
+        #[clap(long, value_parser=maybe_hex::<u8>)]
+        rights: Option<u8>,
     },
     Enumerate {
         capa: usize,
     },
     Switch {
         handle: usize,
-        cpu: usize,
+        delta: usize,
     },
     Exit,
     List,
@@ -62,43 +58,36 @@ pub fn main() {
     let args = Args::parse();
     match args.subcommand {
         Subcommand::Send { target, capa } => {
-            send(target, capa).unwrap();
+            send(capa, target).unwrap();
         }
         Subcommand::SegmentRegion {
             capa,
-            start_1,
-            end_1,
-            prot_1,
-            start_2,
-            end_2,
-            prot_2,
+            shared,
+            start,
+            end,
+            prot,
         } => {
-            segment_region(capa, start_1, end_1, prot_1, start_2, end_2, prot_2).unwrap();
+            segment_region(capa, shared, start, end, prot).unwrap();
         }
         Subcommand::Revoke { capa } => {
             revoke(capa).unwrap();
         }
-        Subcommand::Duplicate { capa } => {
-            duplicate(capa).unwrap();
+        Subcommand::Duplicate { capa, rights } => {
+            let rights = rights.map_or(MgmtRights::all(), MgmtRights::from_bits_truncate);
+            duplicate(capa, rights).unwrap();
         }
         Subcommand::Enumerate { capa } => {
             enumerate(capa).unwrap();
         }
-        Subcommand::Switch { handle, cpu } => {
-            switch(handle, cpu).unwrap();
+        Subcommand::Switch { handle, delta } => {
+            switch(handle, delta).unwrap();
         }
 
         Subcommand::CreateDomain => {
             domain_create().unwrap();
         }
-        Subcommand::SealDomain {
-            domain,
-            core_map,
-            arg1,
-            arg2,
-            arg3,
-        } => {
-            seal_domain(domain, core_map, arg1, arg2, arg3).unwrap();
+        Subcommand::SealDomain { domain } => {
+            seal_domain(domain).unwrap();
         }
         Subcommand::Exit => {
             exit().unwrap();
@@ -111,10 +100,6 @@ pub fn main() {
 fn list_all_capas() {
     let mut token = 0;
     while let Some((capa, next)) = enumerate(token).unwrap() {
-        if next == 0 {
-            break;
-        }
-
         println!("{}: {}", next - 1, capa);
         token = next;
     }
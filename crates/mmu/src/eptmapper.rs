@@ -1,11 +1,11 @@
 //! EPT mapper implementation
 
 use utils::{GuestPhysAddr, HostPhysAddr, HostVirtAddr};
-use vmx::bitmaps::{EptEntryFlags, EptMemoryType};
-use vmx::ept::{GIANT_PAGE_SIZE, HUGE_PAGE_SIZE, PAGE_SIZE};
+use vmx::bitmaps::{EptCapability, EptEntryFlags, EptMemoryType};
+use vmx::ept::PAGE_SIZE;
 
 use crate::frame_allocator::FrameAllocator;
-use crate::walker::{Address, Level, WalkNext, Walker};
+use crate::walker::{Address, LargePages, Level, WalkNext, Walker};
 
 pub const ADDRESS_MASK: u64 = 0x7fffffffff000;
 
@@ -13,6 +13,7 @@ pub struct EptMapper {
     host_offset: usize,
     root: HostPhysAddr,
     level: Level,
+    large_pages: LargePages,
 }
 
 pub const EPT_PRESENT: EptEntryFlags = EptEntryFlags::READ
@@ -51,6 +52,7 @@ impl EptMapper {
             host_offset,
             root,
             level: Level::L4,
+            large_pages: LargePages::empty(),
         }
     }
     /*
@@ -66,9 +68,29 @@ impl EptMapper {
             host_offset,
             root,
             level,
+            large_pages: LargePages::empty(),
         }
     }
 
+    /// Allows the mapper to use the given large pages, see [EptMapper::supported_large_pages].
+    pub fn with_large_pages(mut self, large_pages: LargePages) -> Self {
+        self.large_pages = large_pages;
+        self
+    }
+
+    /// Returns the large pages supported by the processor for EPT mappings.
+    pub fn supported_large_pages() -> LargePages {
+        let capabilities = vmx::ept_capabilities().unwrap_or(EptCapability::empty());
+        let mut large_pages = LargePages::empty();
+        if capabilities.contains(EptCapability::PAGE_2MB) {
+            large_pages |= LargePages::HUGE;
+        }
+        if capabilities.contains(EptCapability::PAGE_1GB) {
+            large_pages |= LargePages::GIANT;
+        }
+        large_pages
+    }
+
     pub fn debug_range(&mut self, gpa: GuestPhysAddr, size: usize) {
         let (phys_addr, _) = self.root();
         log::info!("EPT root: 0x{:x}", phys_addr.as_usize());
@@ -92,6 +114,10 @@ impl EptMapper {
     }
 
     /// Maps a range of physical memory to the given virtual memory.
+    ///
+    /// Large pages are used whenever the alignment of the addresses and the size of the range
+    /// allow it. Existing mappings within the range are replaced, large pages partially in the
+    /// range are split.
     pub fn map_range(
         &mut self,
         allocator: &impl FrameAllocator,
@@ -100,45 +126,40 @@ impl EptMapper {
         size: usize,
         prot: EptEntryFlags,
     ) {
+        let large_pages = self.large_pages;
         unsafe {
             self.walk_range(
                 gpa,
                 GuestPhysAddr::new(gpa.as_usize() + size),
                 &mut |addr, entry, level| {
-                    if (*entry & EPT_PRESENT.bits()) != 0 {
-                        if (level == Level::L3 || level == Level::L2)
-                            && ((*entry & EptEntryFlags::PAGE.bits()) != 0)
-                        {
-                            return WalkNext::Leaf;
-                        }
-                        return WalkNext::Continue;
-                    }
-
                     let end = gpa.as_usize() + size;
                     let hphys = hpa.as_usize() + (addr.as_usize() - gpa.as_usize());
-                    if level == Level::L3 {
-                        if (addr.as_usize() + GIANT_PAGE_SIZE <= end)
-                            && (hphys % GIANT_PAGE_SIZE == 0)
-                        {
-                            *entry = hphys as u64
-                                | EptEntryFlags::PAGE.bits()
-                                | prot.bits()
-                                | EptMemoryType::WB.bits()
-                                | (1 << 7);
-                            return WalkNext::Leaf;
+                    let area_size = level.area_size() as usize;
+                    let is_page = level != Level::L1
+                        && large_pages.is_leaf_level(level)
+                        && addr.as_usize() % area_size == 0
+                        && hphys % area_size == 0
+                        && addr.as_usize() + area_size <= end;
+
+                    if (*entry & EPT_PRESENT.bits()) != 0 {
+                        // Page tables are kept, the range is mapped within them.
+                        if !is_leaf(*entry, level) {
+                            return WalkNext::Continue;
                         }
-                    }
-                    if level == Level::L2 {
-                        if (addr.as_usize() + HUGE_PAGE_SIZE <= end)
-                            && (hphys % HUGE_PAGE_SIZE == 0)
-                        {
-                            *entry = hphys as u64
-                                | EptEntryFlags::PAGE.bits()
-                                | prot.bits()
-                                | EptMemoryType::WB.bits();
-                            return WalkNext::Leaf;
+                        // Large pages partially in the range are split, other pages are replaced.
+                        if level != Level::L1 && !is_page {
+                            split_page(entry, level, allocator);
+                            return WalkNext::Continue;
                         }
                     }
+
+                    if is_page {
+                        *entry = hphys as u64
+                            | EptEntryFlags::PAGE.bits()
+                            | prot.bits()
+                            | EptMemoryType::WB.bits();
+                        return WalkNext::Leaf;
+                    }
                     if level == Level::L1 {
                         assert!(hphys % PAGE_SIZE == 0);
                         *entry = hphys as u64 | prot.bits() | EptMemoryType::WB.bits();
@@ -189,103 +210,41 @@ impl EptMapper {
         }
     }
 
+    /// Unmaps a range of guest physical memory, large pages partially in the range are split.
     pub fn unmap_range(
         &mut self,
         allocator: &impl FrameAllocator,
         gpa: GuestPhysAddr,
         size: usize,
-        root: HostPhysAddr,
-        offset: usize,
     ) {
         let host_offset = self.host_offset;
+        let end = gpa.as_usize() + size;
+        let mut cleanup = |page_virt_addr: HostVirtAddr| unsafe {
+            let page_phys = HostPhysAddr::new(page_virt_addr.as_usize() - host_offset);
+            allocator
+                .free_frame(page_phys)
+                .expect("failed to free EPT page");
+        };
+        let mut callback = |addr: GuestPhysAddr, entry: &mut u64, level: Level| {
+            if (*entry & EPT_PRESENT.bits()) == 0 {
+                return WalkNext::Leaf;
+            }
+            if !is_leaf(*entry, level) {
+                return WalkNext::Continue;
+            }
+            // Easy case, the entire page is to be removed.
+            let area_size = level.area_size() as usize;
+            if addr.as_usize() % area_size == 0 && addr.as_usize() + area_size <= end {
+                *entry = 0;
+                return WalkNext::Leaf;
+            }
+            // Harder case, the large page must be split first.
+            split_page(entry, level, allocator);
+            WalkNext::Continue
+        };
         unsafe {
-            let mut cleanup = |page_virt_addr: HostVirtAddr| {
-                let page_phys = HostPhysAddr::new(page_virt_addr.as_usize() - host_offset);
-                allocator
-                    .free_frame(page_phys)
-                    .expect("failed to free EPT page");
-            };
-            let mut callback = |addr: GuestPhysAddr, entry: &mut u64, level: Level| {
-                if (*entry & EPT_PRESENT.bits()) == 0 {
-                    return WalkNext::Leaf;
-                }
-
-                let end = gpa.as_usize() + size;
-                let mut needs_remap = false;
-                let mut big_size: usize = 0;
-                let mut aligned_addr = addr.as_usize();
-
-                // We have a big entry
-                if level == Level::L3 && (*entry & EptEntryFlags::PAGE.bits()) != 0 {
-                    aligned_addr = addr.as_usize() & (level.mask() as usize);
-                    // Easy case, the entire entry is to be removed.
-                    if gpa.as_usize() <= aligned_addr && (aligned_addr + GIANT_PAGE_SIZE <= end) {
-                        *entry = 0;
-                        return WalkNext::Leaf;
-                    }
-                    // Harder case, we need to break the entry.
-                    *entry = 0;
-                    needs_remap = true;
-                    big_size = GIANT_PAGE_SIZE;
-                }
-                if level == Level::L2 && (*entry & EptEntryFlags::PAGE.bits()) != 0 {
-                    aligned_addr = addr.as_usize() & (level.mask() as usize);
-                    // Easy case, the entire entry is to be removed.
-                    if gpa.as_usize() <= aligned_addr && (aligned_addr + GIANT_PAGE_SIZE <= end) {
-                        *entry = 0;
-                        return WalkNext::Leaf;
-                    }
-                    // Harder case, we need to break the entry.
-                    *entry = 0;
-                    needs_remap = true;
-                    big_size = HUGE_PAGE_SIZE;
-                }
-                if needs_remap {
-                    // Harder case for huge entries.
-                    let mut mapper = EptMapper::new(offset, root);
-                    // Some mapping on the left.
-                    if aligned_addr < gpa.as_usize() {
-                        let n_size = gpa.as_usize() - aligned_addr;
-                        mapper.map_range(
-                            allocator,
-                            GuestPhysAddr::new(aligned_addr),
-                            HostPhysAddr::new(aligned_addr),
-                            n_size,
-                            EptEntryFlags::READ
-                                | EptEntryFlags::WRITE
-                                | EptEntryFlags::USER_EXECUTE
-                                | EPT_PRESENT,
-                        );
-                    }
-                    // Some mapping on the left.
-                    if gpa.as_usize() + size < aligned_addr + big_size {
-                        let n_size = aligned_addr + big_size - gpa.as_usize() - size;
-                        mapper.map_range(
-                            allocator,
-                            gpa + size,
-                            HostPhysAddr::new(gpa.as_usize() + size),
-                            n_size,
-                            EptEntryFlags::READ
-                                | EptEntryFlags::WRITE
-                                | EptEntryFlags::USER_EXECUTE
-                                | EPT_PRESENT,
-                        );
-                    }
-                    return WalkNext::Leaf;
-                }
-                if level == Level::L1 {
-                    *entry = 0;
-                    return WalkNext::Leaf;
-                }
-                WalkNext::Continue
-            };
-            self.cleanup_range(
-                gpa,
-                GuestPhysAddr::new(gpa.as_usize() + size),
-                &mut callback,
-                &mut cleanup,
-            )
-            .expect("Failed to unmap EPTs");
+            self.cleanup_range(gpa, GuestPhysAddr::new(end), &mut callback, &mut cleanup)
+                .expect("Failed to unmap EPTs");
         }
     }
 
@@ -293,3 +252,29 @@ impl EptMapper {
         HostPhysAddr::new(self.root.as_usize() | EPT_ROOT_FLAGS)
     }
 }
+
+/// Returns whether the entry maps a page, rather than pointing to a page table.
+fn is_leaf(entry: u64, level: Level) -> bool {
+    level == Level::L1 || (entry & EptEntryFlags::PAGE.bits()) != 0
+}
+
+/// Replaces a large page by a page table mapping the same memory with pages of the next level,
+/// preserving the permissions and memory type of the large page.
+fn split_page(entry: &mut u64, level: Level, allocator: &impl FrameAllocator) {
+    let next_level = level
+        .next()
+        .expect("split_page: 4 KiB pages can not be split");
+    let mut frame = allocator
+        .allocate_frame()
+        .expect("split_page: unable to allocate page table entry");
+    let mut flags = *entry & !ADDRESS_MASK;
+    if next_level == Level::L1 {
+        flags &= !EptEntryFlags::PAGE.bits();
+    }
+    let base = *entry & ADDRESS_MASK;
+    let page_size = next_level.area_size();
+    for (idx, child) in frame.as_array_page().iter_mut().enumerate() {
+        *child = (base + idx as u64 * page_size) | flags;
+    }
+    *entry = frame.phys_addr.as_u64() | EPT_PRESENT.bits();
+}
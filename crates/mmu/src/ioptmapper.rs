@@ -2,13 +2,14 @@ use bitflags::bitflags;
 use utils::{GuestPhysAddr, HostPhysAddr, HostVirtAddr};
 
 use crate::frame_allocator::FrameAllocator;
-use crate::walker::{Address, Level, WalkNext, Walker};
+use crate::walker::{Address, LargePages, Level, WalkNext, Walker};
 
 pub const ADDRESS_MASK: u64 = 0x7fffffffff000;
 
 pub struct IoPtMapper {
     host_offset: usize,
     root: HostPhysAddr,
+    large_pages: LargePages,
 }
 
 bitflags! {
@@ -23,7 +24,6 @@ bitflags! {
     }
 }
 
-pub const HUGE_PAGE_SIZE: usize = 1 << 21;
 pub const PAGE_SIZE: usize = 1 << 12;
 
 pub const DEFAULT_PROTS: IoPtFlag = IoPtFlag::READ
@@ -59,9 +59,24 @@ unsafe impl Walker for IoPtMapper {
 
 impl IoPtMapper {
     pub fn new(host_offset: usize, root: HostPhysAddr) -> Self {
-        Self { host_offset, root }
+        Self {
+            host_offset,
+            root,
+            large_pages: LargePages::empty(),
+        }
+    }
+
+    /// Allows the mapper to use the given large pages, which must be supported by the I/O MMU.
+    pub fn with_large_pages(mut self, large_pages: LargePages) -> Self {
+        self.large_pages = large_pages;
+        self
     }
 
+    /// Maps a range of physical memory to the given virtual memory.
+    ///
+    /// Large pages are used whenever the alignment of the addresses and the size of the range
+    /// allow it. Existing mappings within the range are replaced, large pages partially in the
+    /// range are split.
     pub fn map_range(
         &mut self,
         allocator: &impl FrameAllocator,
@@ -70,26 +85,37 @@ impl IoPtMapper {
         size: usize,
         prot: IoPtFlag,
     ) {
+        let large_pages = self.large_pages;
         unsafe {
             self.walk_range(
                 gpa,
                 GuestPhysAddr::new(gpa.as_usize() + size),
                 &mut |addr, entry, level| {
-                    if (*entry & PRESENT.bits()) != 0 {
-                        return WalkNext::Continue;
-                    }
-
                     let end = gpa.as_usize() + size;
                     let hphys = hpa.as_usize() + (addr.as_usize() - gpa.as_usize());
+                    let area_size = level.area_size() as usize;
+                    let is_page = level != Level::L1
+                        && large_pages.is_leaf_level(level)
+                        && addr.as_usize() % area_size == 0
+                        && hphys % area_size == 0
+                        && addr.as_usize() + area_size <= end;
 
-                    if level == Level::L2 {
-                        if (addr.as_usize() + HUGE_PAGE_SIZE <= end)
-                            && (hphys % HUGE_PAGE_SIZE == 0)
-                        {
-                            *entry = hphys as u64 | IoPtFlag::PAGE_SIZE.bits() | prot.bits();
-                            return WalkNext::Leaf;
+                    if (*entry & PRESENT.bits()) != 0 {
+                        // Page tables are kept, the range is mapped within them.
+                        if !is_leaf(*entry, level) {
+                            return WalkNext::Continue;
+                        }
+                        // Large pages partially in the range are split, other pages are replaced.
+                        if level != Level::L1 && !is_page {
+                            split_page(entry, level, allocator);
+                            return WalkNext::Continue;
                         }
                     }
+
+                    if is_page {
+                        *entry = hphys as u64 | IoPtFlag::PAGE_SIZE.bits() | prot.bits();
+                        return WalkNext::Leaf;
+                    }
                     if level == Level::L1 {
                         assert!(hphys % PAGE_SIZE == 0);
                         *entry = hphys as u64 | prot.bits();
@@ -140,7 +166,71 @@ impl IoPtMapper {
         }
     }
 
+    /// Unmaps a range of guest physical memory, large pages partially in the range are split.
+    pub fn unmap_range(
+        &mut self,
+        allocator: &impl FrameAllocator,
+        gpa: GuestPhysAddr,
+        size: usize,
+    ) {
+        let host_offset = self.host_offset;
+        let end = gpa.as_usize() + size;
+        let mut cleanup = |page_virt_addr: HostVirtAddr| unsafe {
+            let page_phys = HostPhysAddr::new(page_virt_addr.as_usize() - host_offset);
+            allocator
+                .free_frame(page_phys)
+                .expect("failed to free I/O PT page");
+        };
+        let mut callback = |addr: GuestPhysAddr, entry: &mut u64, level: Level| {
+            if (*entry & PRESENT.bits()) == 0 {
+                return WalkNext::Leaf;
+            }
+            if !is_leaf(*entry, level) {
+                return WalkNext::Continue;
+            }
+            // Easy case, the entire page is to be removed.
+            let area_size = level.area_size() as usize;
+            if addr.as_usize() % area_size == 0 && addr.as_usize() + area_size <= end {
+                *entry = 0;
+                return WalkNext::Leaf;
+            }
+            // Harder case, the large page must be split first.
+            split_page(entry, level, allocator);
+            WalkNext::Continue
+        };
+        unsafe {
+            self.cleanup_range(gpa, GuestPhysAddr::new(end), &mut callback, &mut cleanup)
+                .expect("Failed to unmap I/O PTs");
+        }
+    }
+
     pub fn get_root(&self) -> HostPhysAddr {
         HostPhysAddr::new(self.root.as_usize())
     }
 }
+
+/// Returns whether the entry maps a page, rather than pointing to a page table.
+fn is_leaf(entry: u64, level: Level) -> bool {
+    level == Level::L1 || (entry & IoPtFlag::PAGE_SIZE.bits()) != 0
+}
+
+/// Replaces a large page by a page table mapping the same memory with pages of the next level,
+/// preserving the permissions of the large page.
+fn split_page(entry: &mut u64, level: Level, allocator: &impl FrameAllocator) {
+    let next_level = level
+        .next()
+        .expect("split_page: 4 KiB pages can not be split");
+    let mut frame = allocator
+        .allocate_frame()
+        .expect("split_page: unable to allocate page table entry");
+    let mut flags = *entry & !ADDRESS_MASK;
+    if next_level == Level::L1 {
+        flags &= !IoPtFlag::PAGE_SIZE.bits();
+    }
+    let base = *entry & ADDRESS_MASK;
+    let page_size = next_level.area_size();
+    for (idx, child) in frame.as_array_page().iter_mut().enumerate() {
+        *child = (base + idx as u64 * page_size) | flags;
+    }
+    *entry = frame.phys_addr.as_u64() | DEFAULT_PROTS.bits();
+}
@@ -11,6 +11,7 @@ pub use frame_allocator::{FrameAllocator, RangeAllocator};
 pub use ioptmapper::{IoPtFlag, IoPtMapper};
 pub use ptmapper::{PtFlag, PtMapper};
 pub use riscv_ptmapper::{RVPtFlag, RVPtMapper};
+pub use walker::LargePages;
 
 // ————————————————————————————————— x86_64 ————————————————————————————————— //
 
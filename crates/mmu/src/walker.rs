@@ -5,6 +5,7 @@
 
 use core::slice;
 
+use bitflags::bitflags;
 use utils::{GuestPhysAddr, GuestVirtAddr, HostPhysAddr, HostVirtAddr};
 
 /// Number of entries per page.
@@ -61,6 +62,28 @@ impl Level {
     }
 }
 
+bitflags! {
+    /// Sizes of the large pages that can be mapped, in addition to 4 KiB pages.
+    pub struct LargePages: u8 {
+        /// 2 MiB pages, mapped by L2 entries.
+        const HUGE  = 1 << 0;
+        /// 1 GiB pages, mapped by L3 entries.
+        const GIANT = 1 << 1;
+    }
+}
+
+impl LargePages {
+    /// Returns whether entries of the given level can map a page.
+    pub fn is_leaf_level(self, level: Level) -> bool {
+        match level {
+            Level::L4 => false,
+            Level::L3 => self.contains(LargePages::GIANT),
+            Level::L2 => self.contains(LargePages::HUGE),
+            Level::L1 => true,
+        }
+    }
+}
+
 /* #[cfg(feature = "visionfive2")]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Level {
@@ -270,7 +293,7 @@ pub unsafe trait Walker {
     {
         let (phys_addr, level) = self.root();
         let page = as_page(self, self.translate(phys_addr));
-        walk_range_rec(self, page, level, start, end, callback, &mut |_| {})
+        walk_range_rec(self, page, level, start, end, callback, None::<&mut fn(_)>)
     }
 
     /// Walk the page tables entries spanning the range between `start` and `end`. Call the cleanup
@@ -289,7 +312,7 @@ pub unsafe trait Walker {
     {
         let (phys_addr, level) = self.root();
         let page = as_page(self, self.translate(phys_addr));
-        walk_range_rec(self, page, level, start, end, callback, cleanup)
+        walk_range_rec(self, page, level, start, end, callback, Some(cleanup))
     }
 
     unsafe fn as_page(&mut self, addr: HostVirtAddr) -> &mut [u64] {
@@ -305,7 +328,7 @@ unsafe fn walk_range_rec<VirtAddr, PhysAddr, W, F, C>(
     start: VirtAddr,
     end: VirtAddr,
     callback: &mut F,
-    cleanup: &mut C,
+    mut cleanup: Option<&mut C>,
 ) -> Result<(), ()>
 where
     VirtAddr: Address,
@@ -331,14 +354,29 @@ where
                     let phys_addr = W::get_phys_addr(*entry);
                     let host_virt_addr = walker.translate(phys_addr);
                     let page = as_page(walker, host_virt_addr);
-                    walk_range_rec(walker, page, next, addr, end, callback, cleanup)?;
-
-                    // If the whole page is used, call the cleanup function after the page has been
-                    // walked.
-                    let use_index_zero = addr.index(next) == 0;
-                    let use_whole_area = end.as_u64() - start.as_u64() >= next.area_size();
-                    if use_index_zero && use_whole_area {
-                        cleanup(host_virt_addr);
+                    walk_range_rec(
+                        walker,
+                        page,
+                        next,
+                        addr,
+                        end,
+                        callback,
+                        cleanup.as_deref_mut(),
+                    )?;
+
+                    // When cleaning up, if the whole area of the entry is in the range, the page
+                    // is not used anymore: clear the entry and call the cleanup function after the
+                    // page has been walked.
+                    if let Some(cleanup) = cleanup.as_deref_mut() {
+                        let is_aligned = addr.mask(level_mask) == addr;
+                        let use_whole_area = match addr.add(level_offset) {
+                            Some(area_end) => area_end <= end,
+                            None => true,
+                        };
+                        if is_aligned && use_whole_area {
+                            *entry = 0;
+                            cleanup(host_virt_addr);
+                        }
                     }
                 }
             }
@@ -0,0 +1,15 @@
+//! Regenerates the C header of the monitor ABI.
+
+use std::path::Path;
+
+use monitor_abi::header::{write_c_header, HEADER_PATH};
+
+fn main() {
+    let mut header = String::new();
+    write_c_header(&mut header).expect("Failed to generate the header");
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    let path = root.join(HEADER_PATH);
+    std::fs::write(&path, header).expect("Failed to write the header");
+    println!("Wrote {}", path.display());
+}
@@ -0,0 +1,290 @@
+//! List of valid monitor calls.
+//!
+//! Each call documents its argument layout as `args[i]`/`res[i]`, the index of the argument or
+//! result register as described in the crate documentation.
+
+/// Description of a monitor call, used to generate bindings for other languages.
+#[derive(Debug, Clone, Copy)]
+pub struct CallInfo {
+    /// Name of the call, without prefix.
+    pub name: &'static str,
+    /// The call number.
+    pub number: usize,
+    /// Documentation of the call, one entry per line.
+    pub doc: &'static [&'static str],
+}
+
+/// Declares the monitor calls, the corresponding constants and the list of all calls.
+macro_rules! monitor_calls {
+    ($($(#[doc = $doc:literal])* $name:ident = $number:literal;)*) => {
+        $(
+            $(#[doc = $doc])*
+            pub const $name: usize = $number;
+        )*
+
+        /// All monitor calls, sorted by call number.
+        pub const ALL: &[CallInfo] = &[
+            $(CallInfo {
+                name: stringify!($name),
+                number: $number,
+                doc: &[$($doc),*],
+            },)*
+        ];
+    };
+}
+
+monitor_calls! {
+    /// Create a new domain managed by the caller.
+    /// res[0]: management capability.
+    CREATE_DOMAIN = 1;
+    /// Seal a domain, its configuration can not be modified afterward.
+    /// args[0]: management capability.
+    /// res[0]: switch capability for the current core.
+    SEAL_DOMAIN = 2;
+    /// Send a non-region capability.
+    /// args[0]: capability, args[1]: management or channel capability of the destination.
+    SEND = 3;
+    /// Alias or carve a region capability.
+    /// args[0]: region capability, args[1]: alias if non-zero, carve otherwise,
+    /// args[2]: start, args[3]: end, args[4]: memory access rights.
+    /// res[0]: new region capability, res[1]: revocation capability for the new region.
+    SEGMENT_REGION = 4;
+    /// Revoke a capability.
+    /// args[0]: capability.
+    REVOKE = 5;
+    /// Duplicate a capability.
+    /// args[0]: capability, args[1]: rights kept by a duplicated management capability, among
+    /// configure (1 << 0), switch (1 << 1), revoke (1 << 2), read registers (1 << 3), write
+    /// registers (1 << 4) and send (1 << 5).
+    /// res[0]: new capability.
+    DUPLICATE = 6;
+    /// Enumerate the capabilities of the caller.
+    /// args[0]: token, 0 to start the enumeration.
+    /// res[0..3]: serialized capability, res[3]: next token, 0 once done.
+    ENUMERATE = 7;
+    /// Switch to another domain, the call returns once switching back.
+    /// args[0]: switch capability, args[1]: quantum delta.
+    /// res[0]: return switch capability, as seen by the target domain.
+    SWITCH = 8;
+    /// Not implemented.
+    EXIT = 9;
+    /// Log a value from the monitor.
+    /// args[0]: value.
+    DEBUG = 10;
+    /// Configure a permission of a child domain.
+    /// args[0]: permission index, args[1]: management capability, args[2]: value.
+    /// res[0]: 0 on success, 1 otherwise.
+    CONFIGURE = 11;
+    /// Send a region capability and map it in the destination.
+    /// args[0]: region capability, args[1]: management capability of the destination,
+    /// args[2]: guest physical address, args[3]: repeat the mapping if non-zero,
+    /// args[4]: size of the mapping, args[5]: extra memory operations (hash, cleanup, vital).
+    /// res[0]: the region capability.
+    SEND_REGION = 12;
+    /// Write a field of a child's core context.
+    /// args[0]: management capability, args[1]: core, args[2]: field, args[3]: value.
+    CONFIGURE_CORE = 13;
+    /// Read a field of a child's core context.
+    /// args[0]: management capability, args[1]: core, args[2]: field.
+    /// res[0]: value.
+    GET_CONFIG_CORE = 14;
+    /// Allocate a core context for a child domain.
+    /// args[0]: management capability, args[1]: core.
+    /// res[0]: switch capability for that core.
+    ALLOC_CORE_CONTEXT = 15;
+    /// Read all general purpose registers of a child's core context into the caller's registers.
+    /// args[0]: management capability, args[1]: core.
+    READ_ALL_GP = 16;
+    /// Not implemented.
+    WRITE_ALL_GP = 17;
+    /// Write up to 6 fields of a child's core context, passed as (field, value) pairs in the
+    /// caller's general purpose registers.
+    /// args[0]: management capability, args[1]: core.
+    WRITE_FIELDS = 18;
+    /// Not implemented.
+    SELF_CONFIG = 19;
+    /// Attest the caller with the attestation key of this boot: the report is the signature of
+    /// the caller's measurement followed by the nonce, see the `report` modes.
+    /// args[0]: nonce, args[1]: mode.
+    /// Create report: res[0..4]: attestation public key, res[4..6]: signature bytes 0 to 15.
+    /// Read report: res[0..6]: signature bytes 16 to 63.
+    /// Read certificate: args[2]: buffer address, args[3]: buffer size, args[4]: address is
+    /// virtual if non-zero. res[0]: number of bytes written, i.e. the device (TPM) signature of
+    /// "tyche-attest-key" followed by the public key.
+    ENCLAVE_ATTESTATION = 20;
+    /// Unmap and revoke a region previously sent with `SEND_REGION`.
+    /// args[0]: revocation capability, args[1]: management capability of the holder,
+    /// args[2]: guest physical address, args[3]: size of the mapping.
+    REVOKE_ALIASED_REGION = 21;
+    /// Serialize the capability graph into a buffer of the caller.
+    /// args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero.
+    /// res[0]: number of bytes written.
+    SERIALIZE_ATTESTATION = 22;
+    /// Return to the manager, as if a fault happened.
+    RETURN_TO_MANAGER = 23;
+    /// Translate a guest physical address of the caller.
+    /// args[0]: guest physical address, args[1]: size.
+    /// res[0]: host physical address, res[1]: size of the contiguous mapping.
+    GET_HPA = 24;
+    /// Install a CPUID entry for a child domain (x86_64 only).
+    /// args[0]: management capability, args[1]: function, args[2]: index | flags << 32,
+    /// args[3]: eax | ebx << 32, args[4]: ecx | edx << 32.
+    SET_CPUID_ENTRY = 26;
+    /// Configure the cores a child domain can run on, 64 cores at a time. `CONFIGURE` with the
+    /// allowed cores permission is equivalent to using the group 0.
+    /// args[0]: management capability, args[1]: group of cores, covering cores 64 * args[1] to
+    /// 64 * args[1] + 63, args[2]: bitmap of the allowed cores within the group.
+    CONFIGURE_CORE_MAP = 27;
+    /// Configure the resources a child domain and the domains it manages can use. The quota is
+    /// inherited by the domains the child creates, and can not exceed the caller's own quota.
+    /// args[0]: management capability, args[1]: maximum number of managed domains,
+    /// args[2]: maximum number of region capabilities, args[3]: maximum number of tracked
+    /// regions, args[4]: maximum number of bytes of accessible memory.
+    CONFIGURE_QUOTA = 28;
+    /// Lend a region to another domain and map it in the destination, the region is revoked
+    /// automatically once the lease expires. Deadlines are checked on every monitor entry, i.e.
+    /// monitor call, interrupt or fault of any domain on any core.
+    /// args[0]: region capability, args[1]: management capability of the destination,
+    /// args[2]: guest physical address, args[3]: lease condition, args[4]: number of switches
+    /// or timestamp deadline, args[5]: extra memory operations (hash, cleanup, vital).
+    /// res[0]: the region capability.
+    SEND_REGION_LEASED = 29;
+    /// For benchmarks to measure the cost of communication with tyche.
+    TEST_CALL = 30;
+    /// Run the TPM self test.
+    /// args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero.
+    /// res[0]: number of bytes of manufacturer info written, res[1]: self test result.
+    TPM_SELFTEST = 31;
+    /// Serialize the capability graph followed by a nonce, and sign both with the TPM.
+    /// args[0]: attestation address, args[1]: attestation size, args[2]: signature address,
+    /// args[3]: signature size, args[4]: addresses are virtual if non-zero, args[5]: nonce.
+    /// res[0]: number of bytes of signature written, res[1]: number of bytes of attestation
+    /// written, including the 8 bytes of nonce.
+    SIGNED_ATTESTATION = 32;
+    /// Read the TPM signing key.
+    /// args[0]: buffer address, args[1]: buffer size, args[2]: address is virtual if non-zero.
+    /// res[0]: number of bytes written.
+    GET_SIGNING_KEY = 33;
+    /// Sign a digest with the TPM.
+    /// args[0]: digest address, args[1]: digest size, args[2]: signature address,
+    /// args[3]: signature size, args[4]: addresses are virtual if non-zero.
+    /// res[0]: number of bytes of signature written.
+    TPM_SIGN = 34;
+    /// Sign a digest with the monitor's virtual TPM key.
+    /// args[0]: digest address, args[1]: digest size, args[2]: signature address,
+    /// args[3]: signature size, args[4]: addresses are virtual if non-zero.
+    /// res[0]: number of bytes of signature written.
+    VTPM_SIGN = 35;
+    /// Append data to the caller's Argos transcript.
+    /// args[0]: buffer address, args[1]: buffer size, args[2]: hash the data if non-zero,
+    /// args[3]: address is virtual if non-zero.
+    ARGOS_APPEND_TRANSCRIPT = 36;
+    /// Finalize the caller's Argos transcript and sign it with the TPM.
+    /// args[0]: transcript address, args[1]: transcript size, args[2]: signature address,
+    /// args[3]: signature size, args[4]: addresses are virtual if non-zero.
+    ARGOS_GET_SIGNED_TRANSCRIPT = 37;
+    /// Poll the oldest region revoked from under the caller by another domain. A bounded number
+    /// of revocations are kept pending, see `TYCHE_REVOCATION_TRAP` to be notified of new ones.
+    /// res[0]: revoked region capability, res[1]: start address, res[2]: end address, res[3]: 1
+    /// if a revocation was pending, 0 otherwise.
+    POLL_REVOCATION = 38;
+    /// Apply a list of operations all-or-nothing: if one of them fails the previous ones are rolled
+    /// back. The operations are laid out as in `transaction::Operation`, and their results are
+    /// written back once all of them succeed. On failure the error details hold the index of the
+    /// failed operation.
+    /// args[0]: operations address, args[1]: number of operations, args[2]: address is virtual
+    /// if non-zero.
+    TRANSACTION = 39;
+    /// Register the caller's submission ring, replacing the previous one. The ring is laid out as
+    /// described in the `ring` module and must stay accessible to the caller while registered.
+    /// args[0]: ring address, or 0 to unregister, args[1]: number of entries, a power of two,
+    /// args[2]: address is virtual if non-zero.
+    REGISTER_RING = 40;
+    /// Process the calls submitted to the caller's ring, until the submission ring is empty, the
+    /// completion ring is full, or the given number of calls is reached. Calls that switch domain
+    /// or access the caller's registers are completed with an error.
+    /// args[0]: maximum number of calls to process, 0 for one ring's worth.
+    /// res[0]: number of calls processed.
+    RING_ENTER = 41;
+    /// Create a channel to a domain, over which messages can be sent to it with `CHANNEL_SEND`.
+    /// The channel can be handed over to other domains with `SEND`.
+    /// args[0]: management capability with the send right, args[1]: if non-zero, create a channel
+    /// to the caller instead. res[0]: channel capability.
+    CREATE_CHANNEL = 42;
+    /// Send a message over a channel, optionally transferring a page-aligned region along. The
+    /// message is queued by the receiver, see `TYCHE_CHANNEL_TRAP` to be notified of new ones.
+    /// args[0]: channel capability, args[1]: region capability, or `TYCHE_CHANNEL_NO_REGION`,
+    /// args[2..5]: message words.
+    CHANNEL_SEND = 43;
+    /// Poll the oldest message received over a channel.
+    /// res[0]: number of pending messages, including this one, 0 if no message was pending,
+    /// res[1]: id of the sending domain, res[2]: received region capability, or
+    /// `TYCHE_CHANNEL_NO_REGION`, res[3..6]: message words.
+    CHANNEL_RECEIVE = 44;
+    /// Add information-flow labels to a child domain, checked against the policy loaded at boot on
+    /// capability transfers. Labels can't be removed, and can only be added before sealing.
+    /// args[0]: management capability, args[1]: labels, within `TYCHE_LABELS_USER`.
+    /// res[0]: labels of the child, including the ones set by the monitor.
+    CONFIGURE_LABELS = 45;
+    /// Pair the caller with a sealed domain, so that both switch to each other on the current
+    /// core with `vmfunc` rather than with `SWITCH`. The monitor maps a trampoline of
+    /// `TYCHE_FAST_SWITCH_SIZE` bytes at the same address in both domains, which enters the peer
+    /// at its entry point (`TYCHE_FAST_SWITCH_TO_PEER`) or the owner at the given one
+    /// (`TYCHE_FAST_SWITCH_TO_OWNER`). The domains must map the trampoline at the same virtual
+    /// address. Only the trampoline is executable right after `vmfunc`, the monitor checks that
+    /// a domain is entered at its entry point, with its stack and page tables. The monitor serves
+    /// the calls of the peer, its other exits and invalid entries abort it: the owner is then
+    /// entered with a `TYCHE_ERROR_FAST_SWITCH_ABORTED` error.
+    /// args[0]: switch capability for the current core, args[1]: guest physical address of the
+    /// trampoline, args[2]: entry point of the owner, args[3]: stack of the owner.
+    ENABLE_FAST_SWITCH = 46;
+    /// Dissolve the pair enabled by the caller, see `ENABLE_FAST_SWITCH`.
+    DISABLE_FAST_SWITCH = 47;
+    /// Query the version of the error space of the monitor, clients must not decode error codes
+    /// if it differs from their `ERROR_VERSION`.
+    /// res[0]: version of the error space.
+    GET_ERROR_VERSION = 48;
+    /// Set the interrupt the caller is signaled with when a notification becomes pending, see
+    /// `POLL_NOTIFICATIONS`. The interrupt is raised on the cores running the caller, or on its
+    /// next switch in.
+    /// args[0]: vector within [`TYCHE_NOTIFICATION_MIN_VECTOR`, `TYCHE_NOTIFICATION_MAX_VECTOR`],
+    /// or `TYCHE_NOTIFICATION_NO_VECTOR` to only poll the notifications.
+    SET_NOTIFICATION_VECTOR = 49;
+    /// Poll and clear the notifications pending for the caller, raised for the traps its manager
+    /// allowed (see `CONFIGURE`).
+    /// res[0]: trap bits of the pending notifications, res[1]: start of the last region whose
+    /// lease expired (`TYCHE_LEASE_TRAP`), res[2]: start of the last revoked region
+    /// (`TYCHE_REVOCATION_TRAP`), res[3]: number of pending messages when last notified
+    /// (`TYCHE_CHANNEL_TRAP`).
+    POLL_NOTIFICATIONS = 50;
+}
+
+/// Returns the name of a monitor call, if it exists.
+pub fn name(call: usize) -> Option<&'static str> {
+    ALL.iter().find(|c| c.number == call).map(|c| c.name)
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_sorted() {
+        for pair in ALL.windows(2) {
+            assert!(
+                pair[0].number < pair[1].number,
+                "{} and {} are not sorted or collide",
+                pair[0].name,
+                pair[1].name
+            );
+        }
+    }
+
+    #[test]
+    fn call_names() {
+        assert_eq!(name(SWITCH), Some("SWITCH"));
+        assert_eq!(name(25), None);
+    }
+}
@@ -0,0 +1,287 @@
+//! Error codes
+//!
+//! When a monitor call fails the status is set to [FAILURE](crate::status::FAILURE), the first
+//! result register holds an [ErrorCode] and the second one holds additional details whose meaning
+//! depends on the error code (zero unless documented otherwise).
+//!
+//! Error codes are stable: a code is never renumbered nor re-purposed, new codes can be added
+//! within a version. Incompatible changes to the error space bump [ERROR_VERSION], which the
+//! monitor reports through [GET_ERROR_VERSION](crate::calls::GET_ERROR_VERSION).
+//!
+//! Codes are grouped by origin:
+//!
+//! | Range   | Origin                                |
+//! |---------|---------------------------------------|
+//! | 0x1XX   | Monitor, independently of the engine  |
+//! | 0x2XX   | Capability engine, see `CapaError`    |
+//! | 0x3XX   | Platform (VT-x), see `VmxError`       |
+
+use crate::Results;
+
+/// Version of the error space.
+pub const ERROR_VERSION: usize = 1;
+
+/// Description of an error code, used to generate bindings for other languages.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorInfo {
+    /// Name of the error code.
+    pub name: &'static str,
+    /// The code.
+    pub code: usize,
+    /// Documentation of the error code, one entry per line.
+    pub doc: &'static [&'static str],
+}
+
+/// Declares the error codes, with a conversion from raw values and the list of all codes.
+macro_rules! error_codes {
+    ($($(#[doc = $doc:literal])* $name:ident = $code:literal;)*) => {
+        /// An error code returned by the monitor.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(usize)]
+        pub enum ErrorCode {
+            $(
+                $(#[doc = $doc])*
+                $name = $code,
+            )*
+        }
+
+        impl ErrorCode {
+            /// Decodes a raw error code, returns `None` for unknown codes.
+            pub fn from_raw(code: usize) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+        }
+
+        /// All error codes, sorted by code.
+        pub const ALL: &[ErrorInfo] = &[
+            $(ErrorInfo {
+                name: stringify!($name),
+                code: $code,
+                doc: &[$($doc),*],
+            },)*
+        ];
+    };
+}
+
+error_codes! {
+    // Monitor
+    /// The call number does not correspond to any monitor call.
+    UnknownCall = 0x101;
+    /// The domain the caller switched to has been revoked.
+    DomainRevoked = 0x102;
+    /// The peer of a fast switch pair exited for something else than a monitor call, a domain of
+    /// the pair was not entered through the trampoline, or the pair was disabled: the owner is
+    /// back at its entry point, see `ENABLE_FAST_SWITCH`.
+    FastSwitchAborted = 0x103;
+    /// The monitor has no attestation key, as it found no entropy source at boot.
+    AttestationUnavailable = 0x104;
+
+    // Capability engine
+    /// The capability can not be duplicated.
+    CannotDuplicate = 0x201;
+    /// Invalid duplicate operation.
+    InvalidDuplicate = 0x202;
+    /// The capability can not be installed in the target domain.
+    InvalidInstall = 0x203;
+    /// Internal error of the region tracker.
+    InternalRegionError = 0x204;
+    /// The region is invalid (e.g. empty, or not contained in the parent).
+    InvalidRegion = 0x205;
+    /// The capability is invalid.
+    InvalidCapa = 0x206;
+    /// The capability is not of the expected type.
+    WrongCapabilityType = 0x207;
+    /// The capability does not exist.
+    CapabilityDoesNotExist = 0x208;
+    /// The domain is sealed and can not be modified.
+    AlreadySealed = 0x209;
+    /// The caller lacks the permissions for the operation.
+    InsufficientPermissions = 0x20a;
+    /// The requested permissions are invalid.
+    InvalidPermissions = 0x20b;
+    /// The monitor ran out of memory, i.e. one of the engine's pools is full.
+    OutOfMemory = 0x20c;
+    /// The capability information can not be deserialized.
+    CouldNotDeserializeInfo = 0x20d;
+    /// The core is invalid or not allowed.
+    InvalidCore = 0x20e;
+    /// No domain could handle the trap.
+    CouldNotHandleTrap = 0x20f;
+    /// A trap was handled by exiting the domain.
+    ValidTrapCausedExit = 0x210;
+    /// The switch is invalid.
+    InvalidSwitch = 0x211;
+    /// The core context is not of the expected type.
+    InvalidVcpuType = 0x212;
+    /// The operation is not valid in the current state.
+    InvalidOperation = 0x213;
+    /// An argument has an invalid value.
+    InvalidValue = 0x214;
+    /// The memory operations are invalid.
+    InvalidMemOps = 0x215;
+    /// The region is already aliased.
+    AlreadyAliased = 0x216;
+    /// Unspecified platform error.
+    PlatformError = 0x217;
+    /// The domain, or one of its managers, would exceed its resource quota.
+    QuotaExceeded = 0x218;
+    /// The receiver of a message has too many pending messages.
+    ChannelFull = 0x219;
+    /// The transfer is denied by the information-flow policy loaded at boot.
+    DeniedByPolicy = 0x21a;
+
+    // Platform
+    /// A VMX instruction failed with a valid VMCS.
+    /// Details: the VM-instruction error number.
+    VmFailValid = 0x301;
+    /// A VMX instruction failed with an invalid VMCS pointer.
+    VmFailInvalid = 0x302;
+    /// VMX is not supported by the CPU.
+    VmxNotSupported = 0x303;
+    /// VMX is supported by the CPU but not enabled.
+    VmxNotEnabled = 0x304;
+    /// A VMX feature is not supported.
+    FeatureNotSupported = 0x305;
+    /// A configuration bit can not be set.
+    /// Details: the VMX field index, and the bit index in bits 8 to 15.
+    Disallowed1 = 0x306;
+    /// A configuration bit can not be cleared.
+    /// Details: the VMX field index, and the bit index in bits 8 to 15.
+    Disallowed0 = 0x307;
+    /// A configuration bit has an invalid value.
+    /// Details: the VMX field index, and the bit index in bits 8 to 15.
+    MisconfiguredBit = 0x308;
+    /// A VMX field has an invalid value.
+    /// Details: the VMX field index.
+    Misconfigured = 0x309;
+}
+
+impl ErrorCode {
+    /// Returns the name of the error code.
+    pub fn name(self) -> &'static str {
+        ALL.iter()
+            .find(|info| info.code == self as usize)
+            .map(|info| info.name)
+            .unwrap_or("Unknown")
+    }
+}
+
+/// An error returned by a monitor call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub details: usize,
+}
+
+impl Error {
+    pub const fn new(code: ErrorCode) -> Self {
+        Error { code, details: 0 }
+    }
+
+    pub const fn with_details(code: ErrorCode, details: usize) -> Self {
+        Error { code, details }
+    }
+
+    /// Encodes the error into the result registers.
+    pub fn encode(self, res: &mut Results) {
+        res[0] = self.code as usize;
+        res[1] = self.details;
+    }
+
+    /// Decodes an error from the result registers of a failed call, returns `None` for unknown
+    /// error codes.
+    pub fn decode(res: &Results) -> Option<Self> {
+        ErrorCode::from_raw(res[0]).map(|code| Error::with_details(code, res[1]))
+    }
+}
+
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Error::new(code)
+    }
+}
+
+#[cfg(feature = "vmx")]
+impl From<vmx::VmxError> for Error {
+    fn from(err: vmx::VmxError) -> Self {
+        use vmx::{VmxError, VmxFieldError};
+
+        let field_bit = |field: VmxFieldError, bit: u8| field as usize | (bit as usize) << 8;
+        match err {
+            VmxError::VmFailValid(err) => {
+                Error::with_details(ErrorCode::VmFailValid, err.as_u64() as usize)
+            }
+            VmxError::VmFailInvalid => Error::new(ErrorCode::VmFailInvalid),
+            VmxError::VmxNotSupported => Error::new(ErrorCode::VmxNotSupported),
+            VmxError::VmxNotEnabled => Error::new(ErrorCode::VmxNotEnabled),
+            VmxError::FeatureNotSupported => Error::new(ErrorCode::FeatureNotSupported),
+            VmxError::Disallowed1(field, bit) => {
+                Error::with_details(ErrorCode::Disallowed1, field_bit(field, bit))
+            }
+            VmxError::Disallowed0(field, bit) => {
+                Error::with_details(ErrorCode::Disallowed0, field_bit(field, bit))
+            }
+            VmxError::MisconfiguredBit(field, bit) => {
+                Error::with_details(ErrorCode::MisconfiguredBit, field_bit(field, bit))
+            }
+            VmxError::Misconfigured(field) => {
+                Error::with_details(ErrorCode::Misconfigured, field as usize)
+            }
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_sorted() {
+        for pair in ALL.windows(2) {
+            assert!(
+                pair[0].code < pair[1].code,
+                "{} and {} are not sorted or collide",
+                pair[0].name,
+                pair[1].name
+            );
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        for info in ALL {
+            let code = ErrorCode::from_raw(info.code).unwrap();
+            assert_eq!(code as usize, info.code);
+            assert_eq!(code.name(), info.name);
+        }
+        assert_eq!(ErrorCode::from_raw(0), None);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let err = Error::with_details(ErrorCode::VmFailValid, 7);
+        let mut res = [0; 6];
+        err.encode(&mut res);
+        assert_eq!(Error::decode(&res), Some(err));
+    }
+
+    #[cfg(feature = "vmx")]
+    #[test]
+    fn vmx_details() {
+        use vmx::errors::VmxInstructionError;
+        use vmx::{VmxError, VmxFieldError};
+
+        let err = Error::from(VmxError::VmFailValid(
+            VmxInstructionError::VmWriteToReadOnly,
+        ));
+        assert_eq!(err, Error::with_details(ErrorCode::VmFailValid, 13));
+        let err = Error::from(VmxError::Disallowed1(VmxFieldError::HostCr4, 5));
+        assert_eq!(err.code, ErrorCode::Disallowed1);
+        assert_eq!(err.details, VmxFieldError::HostCr4 as usize | 5 << 8);
+    }
+}
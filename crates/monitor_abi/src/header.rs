@@ -0,0 +1,290 @@
+//! C Header
+//!
+//! Generates the C header exposing the monitor ABI to the C SDK. The generated header is checked
+//! in at [HEADER_PATH], and regenerated with `cargo run --package monitor_abi --bin c-header`.
+
+use core::fmt::{self, Write};
+
+use crate::{
+    calls, channel, error, fast_switch, labels, lease, notification, report, revocation, ring,
+    status, transaction, NB_ARGS, NB_RESULTS,
+};
+
+/// Path of the generated header, relative to the root of the repository.
+pub const HEADER_PATH: &str = "C/libraries/sdktyche/include/tyche_monitor_abi.h";
+
+/// Prefix of the monitor call constants in C.
+const CALL_PREFIX: &str = "TYCHE_CALL_";
+
+/// Prefix of the status constants in C.
+const STATUS_PREFIX: &str = "TYCHE_STATUS_";
+
+/// Prefix of the report modes in C.
+const REPORT_PREFIX: &str = "TYCHE_REPORT_";
+
+/// Prefix of the lease conditions in C.
+const LEASE_PREFIX: &str = "TYCHE_LEASE_";
+
+/// Prefix of the notification constants in C.
+const NOTIFICATION_PREFIX: &str = "TYCHE_NOTIFICATION_";
+
+/// Prefix of the error codes in C.
+const ERROR_PREFIX: &str = "TYCHE_ERROR_";
+
+/// Guards the C declarations of the header, so that assembly files can include it for the
+/// constants.
+const C_ONLY: &str = "#ifndef __ASSEMBLER__";
+
+/// Writes the C header into `out`.
+pub fn write_c_header<W: Write>(out: &mut W) -> fmt::Result {
+    writeln!(
+        out,
+        "/* Generated by the monitor_abi crate, do not edit by hand. */"
+    )?;
+    writeln!(out, "#pragma once")?;
+    writeln!(out)?;
+    writeln!(out, "#define TYCHE_NB_ARGS    {}", NB_ARGS)?;
+    writeln!(out, "#define TYCHE_NB_RESULTS {}", NB_RESULTS)?;
+    writeln!(out)?;
+
+    writeln!(out, "/* Status */")?;
+    define(out, STATUS_PREFIX, "SUCCESS", status::SUCCESS)?;
+    define(out, STATUS_PREFIX, "FAILURE", status::FAILURE)?;
+    define(out, STATUS_PREFIX, "DOMAIN_REVOKED", status::DOMAIN_REVOKED)?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Report modes */")?;
+    define(out, REPORT_PREFIX, "CREATE", report::CREATE)?;
+    define(out, REPORT_PREFIX, "READ", report::READ)?;
+    define(
+        out,
+        REPORT_PREFIX,
+        "READ_CERTIFICATE",
+        report::READ_CERTIFICATE,
+    )?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Lease conditions */")?;
+    define(out, LEASE_PREFIX, "ON_RETURN", lease::ON_RETURN)?;
+    define(out, LEASE_PREFIX, "SWITCHES", lease::SWITCHES)?;
+    define(out, LEASE_PREFIX, "DEADLINE", lease::DEADLINE)?;
+    writeln!(out, "#define {}TRAP {:#x}UL", LEASE_PREFIX, lease::TRAP)?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Notifications */")?;
+    define(
+        out,
+        NOTIFICATION_PREFIX,
+        "NO_VECTOR",
+        notification::NO_VECTOR,
+    )?;
+    define(
+        out,
+        NOTIFICATION_PREFIX,
+        "MIN_VECTOR",
+        notification::MIN_VECTOR,
+    )?;
+    define(
+        out,
+        NOTIFICATION_PREFIX,
+        "MAX_VECTOR",
+        notification::MAX_VECTOR,
+    )?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Revocation notifications */")?;
+    writeln!(
+        out,
+        "#define TYCHE_REVOCATION_TRAP {:#x}UL",
+        revocation::TRAP
+    )?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Channels */")?;
+    writeln!(out, "#define TYCHE_CHANNEL_NB_WORDS {}", channel::NB_WORDS)?;
+    writeln!(
+        out,
+        "#define TYCHE_CHANNEL_NO_REGION {:#x}UL",
+        channel::NO_REGION
+    )?;
+    writeln!(out, "#define TYCHE_CHANNEL_TRAP {:#x}UL", channel::TRAP)?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Information-flow labels */")?;
+    writeln!(out, "#define TYCHE_LABELS_INITIAL {:#x}UL", labels::INITIAL)?;
+    writeln!(out, "#define TYCHE_LABELS_SEALED {:#x}UL", labels::SEALED)?;
+    writeln!(out, "#define TYCHE_LABELS_USER {:#x}UL", labels::USER)?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Fast switches */")?;
+    writeln!(
+        out,
+        "#define TYCHE_FAST_SWITCH_SIZE {:#x}",
+        fast_switch::SIZE
+    )?;
+    writeln!(
+        out,
+        "#define TYCHE_FAST_SWITCH_TO_OWNER {:#x}",
+        fast_switch::TO_OWNER
+    )?;
+    writeln!(
+        out,
+        "#define TYCHE_FAST_SWITCH_TO_PEER {:#x}",
+        fast_switch::TO_PEER
+    )?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Transactions */")?;
+    writeln!(
+        out,
+        "#define TYCHE_TRANSACTION_MAX_OPERATIONS {}",
+        transaction::MAX_OPERATIONS
+    )?;
+    writeln!(
+        out,
+        "#define TYCHE_TRANSACTION_LINK(op, result) ((op) * TYCHE_NB_RESULTS + (result))"
+    )?;
+    writeln!(out, "{}", C_ONLY)?;
+    writeln!(out, "typedef struct tyche_operation_t {{")?;
+    writeln!(out, "  unsigned long call;")?;
+    writeln!(out, "  unsigned long links;")?;
+    writeln!(out, "  unsigned long args[TYCHE_NB_ARGS];")?;
+    writeln!(out, "  unsigned long res[TYCHE_NB_RESULTS];")?;
+    writeln!(out, "}} tyche_operation_t;")?;
+    writeln!(out, "#endif")?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Submission rings */")?;
+    writeln!(out, "#define TYCHE_RING_MAX_ENTRIES {}", ring::MAX_ENTRIES)?;
+    writeln!(out, "{}", C_ONLY)?;
+    writeln!(out, "typedef struct tyche_ring_header_t {{")?;
+    writeln!(out, "  unsigned long sq_head;")?;
+    writeln!(out, "  unsigned long sq_tail;")?;
+    writeln!(out, "  unsigned long cq_head;")?;
+    writeln!(out, "  unsigned long cq_tail;")?;
+    writeln!(out, "}} tyche_ring_header_t;")?;
+    writeln!(out, "typedef struct tyche_ring_submission_t {{")?;
+    writeln!(out, "  unsigned long call;")?;
+    writeln!(out, "  unsigned long user_data;")?;
+    writeln!(out, "  unsigned long args[TYCHE_NB_ARGS];")?;
+    writeln!(out, "}} tyche_ring_submission_t;")?;
+    writeln!(out, "typedef struct tyche_ring_completion_t {{")?;
+    writeln!(out, "  unsigned long user_data;")?;
+    writeln!(out, "  unsigned long status;")?;
+    writeln!(out, "  unsigned long res[TYCHE_NB_RESULTS];")?;
+    writeln!(out, "}} tyche_ring_completion_t;")?;
+    writeln!(out, "#endif")?;
+
+    writeln!(out)?;
+    writeln!(out, "/* Monitor calls */")?;
+    for call in calls::ALL {
+        writeln!(out)?;
+        for line in call.doc {
+            writeln!(out, "/*{} */", line)?;
+        }
+        define(out, CALL_PREFIX, call.name, call.number)?;
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/* Error codes, returned in res[0] on failure, details in res[1] */"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "#define TYCHE_ERROR_VERSION {}", error::ERROR_VERSION)?;
+    for err in error::ALL {
+        writeln!(out)?;
+        for line in err.doc {
+            writeln!(out, "/*{} */", line)?;
+        }
+        write!(out, "#define {}", ERROR_PREFIX)?;
+        write_screaming_snake_case(out, err.name)?;
+        writeln!(out, " {:#x}", err.code)?;
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/* Returns the name of an error code, or NULL if unknown. */"
+    )?;
+    writeln!(out, "{}", C_ONLY)?;
+    writeln!(
+        out,
+        "static inline const char* tyche_error_name(unsigned long code) {{"
+    )?;
+    writeln!(out, "  switch (code) {{")?;
+    for err in error::ALL {
+        write!(out, "    case {}", ERROR_PREFIX)?;
+        write_screaming_snake_case(out, err.name)?;
+        writeln!(out, ": return \"{}\";", err.name)?;
+    }
+    writeln!(out, "    default: return (const char*) 0;")?;
+    writeln!(out, "  }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "#endif")?;
+    Ok(())
+}
+
+fn define<W: Write>(out: &mut W, prefix: &str, name: &str, value: usize) -> fmt::Result {
+    writeln!(out, "#define {}{} {}", prefix, name, value)
+}
+
+/// Writes a CamelCase name in SCREAMING_SNAKE_CASE.
+fn write_screaming_snake_case<W: Write>(out: &mut W, name: &str) -> fmt::Result {
+    for (idx, c) in name.chars().enumerate() {
+        if idx != 0 && c.is_ascii_uppercase() {
+            out.write_char('_')?;
+        }
+        out.write_char(c.to_ascii_uppercase())?;
+    }
+    Ok(())
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the checked-in header is up to date.
+    #[test]
+    fn header_is_up_to_date() {
+        let checked_in = include_str!("../../../C/libraries/sdktyche/include/tyche_monitor_abi.h");
+        let mut generated = String::new();
+        write_c_header(&mut generated).unwrap();
+        assert!(
+            checked_in == generated,
+            "{} is outdated, run `cargo run --package monitor_abi --bin c-header`",
+            HEADER_PATH
+        );
+    }
+
+    /// Checks that assembly files can include the header, as the C SDK runtime does.
+    #[test]
+    fn header_assembles() {
+        let include = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../C/libraries/sdktyche/include"
+        );
+        let dir = std::env::temp_dir().join(format!("tyche-abi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("header.S");
+        std::fs::write(
+            &source,
+            "#include \"tyche_monitor_abi.h\"\n.globl f\nf:\n  .quad TYCHE_CALL_SWITCH\n",
+        )
+        .unwrap();
+        let cc = std::env::var("CC").unwrap_or_else(|_| String::from("cc"));
+        let status = std::process::Command::new(cc)
+            .arg("-c")
+            .arg("-I")
+            .arg(include)
+            .arg(&source)
+            .arg("-o")
+            .arg(dir.join("header.o"))
+            .status()
+            .expect("Failed to run the C compiler");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(status.success(), "{} does not assemble", HEADER_PATH);
+    }
+}
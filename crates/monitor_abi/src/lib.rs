@@ -0,0 +1,210 @@
+//! Monitor ABI
+//!
+//! This crate defines the ABI of monitor calls, it is intended to be consumed by the monitor and
+//! by all of its clients (libtyche, bricks, and the C SDK through a generated header) so that they
+//! agree on call numbers, register layout and return codes.
+//!
+//! A monitor call takes a call number and up to [NB_ARGS] arguments, and returns a status and up
+//! to [NB_RESULTS] values. Arguments and results are passed in the same registers:
+//!
+//! | Arch    | Call number & status | Arguments & results          |
+//! |---------|----------------------|------------------------------|
+//! | x86_64  | rax                  | rdi, rsi, rdx, rcx, r8, r9   |
+//! | riscv64 | a0                   | a1, a2, a3, a4, a5, a6       |
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod calls;
+pub mod error;
+pub mod header;
+
+/// Number of arguments of a monitor call, not counting the call number.
+pub const NB_ARGS: usize = 6;
+
+/// Number of values returned by a monitor call, not counting the status.
+pub const NB_RESULTS: usize = 6;
+
+/// Arguments of a monitor call, in register order.
+pub type Args = [usize; NB_ARGS];
+
+/// Results of a monitor call, in register order.
+pub type Results = [usize; NB_RESULTS];
+
+/// Status returned in the call number register once the monitor call completes.
+#[rustfmt::skip]
+pub mod status {
+    /// The call succeeded, results are valid.
+    pub const SUCCESS:        usize = 0;
+    /// The call failed, the first two results hold an error, see [error](crate::error).
+    pub const FAILURE:        usize = 1;
+    /// Set in the fifth result register, together with a `FAILURE` status, when the domain the
+    /// caller switched to got revoked and the caller was preempted back.
+    pub const DOMAIN_REVOKED: usize = 66;
+}
+
+/// Modes of `ENCLAVE_ATTESTATION`.
+#[rustfmt::skip]
+pub mod report {
+    /// Sign the caller's measurement and nonce, return the start of the report.
+    pub const CREATE:           usize = 0;
+    /// Return the end of the last report of the caller.
+    pub const READ:             usize = 1;
+    /// Copy the certificate of the attestation key into a buffer of the caller.
+    pub const READ_CERTIFICATE: usize = 2;
+}
+
+/// Conditions under which a region sent with `SEND_REGION_LEASED` is revoked.
+#[rustfmt::skip]
+pub mod lease {
+    /// Revoke on the next switch back to the caller.
+    pub const ON_RETURN: usize = 1;
+    /// Revoke once the destination has been switched out the given number of times.
+    pub const SWITCHES:  usize = 2;
+    /// Revoke on the first monitor entry, on any core, once the timestamp counter (TSC on x86)
+    /// reaches the given deadline. The holder is not interrupted to enforce the deadline.
+    pub const DEADLINE:  usize = 3;
+    /// Trap bit to allow for the holder to be notified of the revocation, see `CONFIGURE` and
+    /// `POLL_NOTIFICATIONS`.
+    pub const TRAP:      usize = 1 << 32;
+}
+
+/// Interrupts signaling pending notifications, see `SET_NOTIFICATION_VECTOR`.
+#[rustfmt::skip]
+pub mod notification {
+    /// The notifications are only polled, no interrupt is raised.
+    pub const NO_VECTOR:  usize = 0;
+    /// Lowest valid vector, the ones below are reserved for exceptions on x86.
+    pub const MIN_VECTOR: usize = 32;
+    /// Highest valid vector.
+    pub const MAX_VECTOR: usize = 255;
+}
+
+/// Notifications of the regions revoked from under a domain, see `POLL_REVOCATION`.
+pub mod revocation {
+    /// Trap bit to allow for a domain to be notified of new revocations, see `CONFIGURE` and
+    /// `POLL_NOTIFICATIONS`.
+    pub const TRAP: usize = 1 << 33;
+}
+
+/// Messages sent over channels, see `CHANNEL_SEND` and `CHANNEL_RECEIVE`.
+#[rustfmt::skip]
+pub mod channel {
+    /// Number of words carried by a message.
+    pub const NB_WORDS:  usize = 3;
+    /// Stands for the absence of region transferred along a message.
+    pub const NO_REGION: usize = usize::MAX;
+    /// Trap bit to allow for a domain to be notified of incoming messages, see `CONFIGURE` and
+    /// `POLL_NOTIFICATIONS`.
+    pub const TRAP:      usize = 1 << 34;
+}
+
+/// Information-flow labels of the domains, see `CONFIGURE_LABELS`.
+#[rustfmt::skip]
+pub mod labels {
+    /// Set by the monitor on the domains created at boot.
+    pub const INITIAL:  usize = 1 << 63;
+    /// Set by the monitor on the sealed domains.
+    pub const SEALED:   usize = 1 << 62;
+    /// The labels managers can set on their children.
+    pub const USER:     usize = !(INITIAL | SEALED);
+}
+
+/// Trampolines of the domain pairs switching without exits, see `ENABLE_FAST_SWITCH`.
+#[rustfmt::skip]
+pub mod fast_switch {
+    /// Size in bytes of a trampoline, a code page followed by a read-only data page.
+    pub const SIZE:     usize = 0x2000;
+    /// Offset of the code entering the owner of the pair, at the entry point it registered.
+    pub const TO_OWNER: usize = 0;
+    /// Offset of the code entering the peer of the pair, at its entry point.
+    pub const TO_PEER:  usize = 0x40;
+}
+
+/// Operations applied all-or-nothing, see `TRANSACTION`.
+pub mod transaction {
+    use core::mem::size_of;
+
+    use crate::{Args, Results, NB_RESULTS};
+
+    /// Maximum number of operations in a transaction.
+    pub const MAX_OPERATIONS: usize = 32;
+
+    /// An operation of a transaction, as laid out in the caller's memory.
+    ///
+    /// Only the calls whose effects can be rolled back are allowed: `CREATE_DOMAIN`,
+    /// `SEGMENT_REGION`, `SEND`, `SEND_REGION`, `DUPLICATE`, `CONFIGURE`, `CONFIGURE_CORE_MAP`,
+    /// `CONFIGURE_QUOTA`, `CONFIGURE_LABELS`, `ALLOC_CORE_CONTEXT`, `SEAL_DOMAIN` and
+    /// `CREATE_CHANNEL`.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    #[repr(C)]
+    pub struct Operation {
+        /// The call number.
+        pub call: usize,
+        /// If bit `i` is set, `args[i]` is a [link] to the result of a previous operation.
+        pub links: usize,
+        pub args: Args,
+        pub res: Results,
+    }
+
+    /// Refers to the result `result` of the operation at index `op`, e.g. to seal a domain
+    /// created earlier in the transaction.
+    pub const fn link(op: usize, result: usize) -> usize {
+        op * NB_RESULTS + result
+    }
+
+    // The layout must match `tyche_operation_t` in the C header.
+    const _: () = assert!(size_of::<Operation>() == 14 * size_of::<usize>());
+}
+
+/// Submission and completion rings, to issue many monitor calls per exit, see `REGISTER_RING`.
+///
+/// A ring is a [Header](ring::Header) followed by `entries` submission slots and `entries`
+/// completion slots. The indices of the header only grow (wrapping around), index `i` refers to
+/// slot `i % entries`. The domain pushes submissions at `sq_tail` and pops completions at
+/// `cq_head`, the monitor consumes submissions at `sq_head` and pushes completions at `cq_tail`.
+pub mod ring {
+    use core::mem::size_of;
+
+    use crate::{Args, Results};
+
+    /// Maximum number of entries of a ring.
+    pub const MAX_ENTRIES: usize = 256;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    #[repr(C)]
+    pub struct Header {
+        pub sq_head: usize,
+        pub sq_tail: usize,
+        pub cq_head: usize,
+        pub cq_tail: usize,
+    }
+
+    /// A monitor call submitted to the ring.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    #[repr(C)]
+    pub struct Submission {
+        pub call: usize,
+        /// Copied as is in the completion, to match it with its submission.
+        pub user_data: usize,
+        pub args: Args,
+    }
+
+    /// The outcome of a submitted call, as it would have been returned in registers.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    #[repr(C)]
+    pub struct Completion {
+        pub user_data: usize,
+        /// One of the [status](crate::status) codes.
+        pub status: usize,
+        pub res: Results,
+    }
+
+    /// Size in bytes of a ring with `entries` entries.
+    pub const fn size(entries: usize) -> usize {
+        size_of::<Header>() + entries * (size_of::<Submission>() + size_of::<Completion>())
+    }
+
+    // The layout must match the `tyche_ring_*_t` structs in the C header.
+    const _: () = assert!(size_of::<Submission>() == size_of::<[usize; 8]>());
+    const _: () = assert!(size_of::<Completion>() == size_of::<[usize; 8]>());
+}
@@ -46,6 +46,12 @@ pub const DOM0_ROOT_REGION_2_END: usize = 0xffffffffffffffff;
 
 // --------------------------------- TYCHE Manifest --------------------------------------- //
 
+/// Value of [RVManifest::seed_magic] when stage 1 provides an attestation seed.
+pub const SEED_MAGIC: usize = usize::from_le_bytes(*b"tycheSED");
+
+/// Size of the attestation seed provided by stage 1.
+pub const SEED_SIZE: usize = 32;
+
 #[repr(C)]
 pub struct RVManifest {
     pub next_arg1: usize,
@@ -53,4 +59,8 @@ pub struct RVManifest {
     pub next_mode: usize,
     pub coldboot_hartid: usize,
     pub num_harts: usize,
+    /// [SEED_MAGIC] if `attestation_seed` holds a seed, for harts without an entropy source.
+    /// Stage 1 derives it from its own entropy or a provisioned secret.
+    pub seed_magic: usize,
+    pub attestation_seed: [u8; SEED_SIZE],
 }
@@ -87,6 +87,10 @@ pub struct Manifest {
     pub iommu: u64,
     /// SMP info:
     pub smp: Smp,
+    /// Memory reserved for the monitor pools.
+    pub pools: Pools,
+    /// Information-flow policy enforced by the monitor on capability transfers.
+    pub flow: FlowPolicy,
 }
 
 /// Suport for x86_64 SMP
@@ -100,6 +104,84 @@ pub struct Smp {
     pub wakeup_cr3: u64,
 }
 
+/// Memory reserved by stage 1 for the monitor pools, and the size of those pools.
+///
+/// The monitor fails to boot if the pools do not fit in the reserved memory.
+#[repr(C)]
+pub struct Pools {
+    /// Virtual address of the pool memory in stage 2.
+    pub start: u64,
+    /// Size of the pool memory, in bytes.
+    pub size: u64,
+    /// Maximum number of domains.
+    pub nb_domains: u64,
+    /// Maximum number of capabilities per domain.
+    pub nb_capas_per_domain: u64,
+    /// Maximum number of region capabilities.
+    pub nb_regions: u64,
+    /// Maximum number of regions in the domains permission trackers.
+    pub nb_tracker: u64,
+}
+
+impl Pools {
+    pub const fn no_pools() -> Self {
+        Self {
+            start: 0,
+            size: 0,
+            nb_domains: 0,
+            nb_capas_per_domain: 0,
+            nb_regions: 0,
+            nb_tracker: 0,
+        }
+    }
+}
+
+/// Maximum number of rules of the information-flow policy.
+pub const MAX_FLOW_RULES: usize = 16;
+
+/// A rule of the information-flow policy, denying the transfers that match all of its fields.
+///
+/// See the `flow` module of the capability engine for the meaning of the fields.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlowRule {
+    /// Bitmap of the kinds of transfers denied by the rule.
+    pub transfers: u64,
+    /// Labels of the domain performing the transfer.
+    pub from: u64,
+    /// Labels of the domain receiving the capability.
+    pub to: u64,
+    /// Labels of the region being transferred.
+    pub region: u64,
+    /// Memory operations of the region, any region matches if 0.
+    pub ops: u64,
+}
+
+/// The information-flow policy loaded by the monitor at boot.
+#[repr(C)]
+pub struct FlowPolicy {
+    /// Number of valid rules.
+    pub nb_rules: u64,
+    pub rules: [FlowRule; MAX_FLOW_RULES],
+}
+
+impl FlowPolicy {
+    /// A policy allowing all transfers.
+    pub const fn no_policy() -> Self {
+        const EMPTY: FlowRule = FlowRule {
+            transfers: 0,
+            from: 0,
+            to: 0,
+            region: 0,
+            ops: 0,
+        };
+        Self {
+            nb_rules: 0,
+            rules: [EMPTY; MAX_FLOW_RULES],
+        }
+    }
+}
+
 impl Manifest {
     /// Find the symbol corresponding to the manifest and fill up the references to other
     /// static objects.
@@ -145,6 +227,8 @@ macro_rules! make_manifest {
                     mailbox: 0,
                     wakeup_cr3: 0,
                 },
+                pools: $crate::Pools::no_pools(),
+                flow: $crate::FlowPolicy::no_policy(),
             };
             static TAKEN: AtomicBool = AtomicBool::new(false);
 
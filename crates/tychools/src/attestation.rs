@@ -1,25 +1,18 @@
 use std::fs::read_to_string;
 use std::path::PathBuf;
 
+use ::attestation::measurement::{MeasuredRegion, Measurement, Measurer};
 use ed25519_compact::{PublicKey, Signature};
 use object::elf::{PF_R, PF_W, PF_X};
 use object::read::elf::ProgramHeader;
-use sha2::{Digest, Sha256};
 
 use crate::elf_modifier::TychePF::PfH;
 use crate::elf_modifier::{ModifiedELF, ModifiedSegment, TychePhdrTypes, DENDIAN};
 
-fn hash_acc_rights(hasher: &mut Sha256, flags: u32, mask: u32) {
-    if flags & mask != 0 {
-        log::trace!("1");
-        hasher.input(&u8::to_le_bytes(1 as u8));
-    } else {
-        log::trace!("0");
-        hasher.input(&u8::to_le_bytes(0 as u8));
-    }
-}
+/// Size of the chunks of zero padding fed to the measurer.
+const PADDING_CHUNK_SZ: usize = 0x1000;
 
-fn hash_segments_info(enclave: &Box<ModifiedELF>, hasher: &mut Sha256, offset: u64) {
+fn measure_segments_info(enclave: &Box<ModifiedELF>, measurer: &mut Measurer, offset: u64) {
     let mut segment_off = offset;
     for seg in &enclave.segments {
         if ModifiedSegment::is_loadable(seg.program_header.p_type(DENDIAN)) {
@@ -28,43 +21,27 @@ fn hash_segments_info(enclave: &Box<ModifiedELF>, hasher: &mut Sha256, offset: u
                 let memsz = seg.program_header.p_memsz(DENDIAN);
                 let align = seg.program_header.p_align(DENDIAN);
                 let sz = (memsz + align - 1) / align * align;
-                log::trace!("Region start {:#x}", start);
-                log::trace!("Region end {:#x}", start + sz);
                 let flags = seg.program_header.p_flags(DENDIAN);
-                let mut diff = (memsz + align - 1) / align * align;
-                diff = diff - (seg.data.len() as u64);
-                log::trace!("Attestation right");
-                let should_hash = (flags & (PfH as u32)) != 0;
-                log::trace!("{}", should_hash);
-                if should_hash {
-                    //hashing start - end of segment
-                    hasher.input(&u64::to_le_bytes(start));
-                    hasher.input(&u64::to_le_bytes(start + sz));
-                    //hashing access rights
-                    hash_acc_rights(hasher, flags, PfH as u32);
-                    log::trace!("X right");
-                    hash_acc_rights(hasher, flags, PF_X);
-                    log::trace!("W right");
-                    hash_acc_rights(hasher, flags, PF_W);
-                    log::trace!("R right");
-                    hash_acc_rights(hasher, flags, PF_R);
-                    //hashing confidential info
-                    if tpe.is_confidential() {
-                        log::trace!("Conf 1");
-                        hasher.input(&u8::to_le_bytes(1 as u8));
-                    } else {
-                        log::trace!("Conf 0");
-                        hasher.input(&u8::to_le_bytes(0 as u8));
-                    }
-                    //hashing data
-                    for u8_data in &seg.data {
-                        let arr_u8: [u8; 1] = [*u8_data];
-                        hasher.input(&arr_u8);
-                    }
+                let region = MeasuredRegion {
+                    start,
+                    end: start + sz,
+                    hash: flags & (PfH as u32) != 0,
+                    exec: flags & PF_X != 0,
+                    write: flags & PF_W != 0,
+                    read: flags & PF_R != 0,
+                    confidential: tpe.is_confidential(),
+                };
+                log::trace!("Measuring region {:x?}", region);
+                measurer.region(&region);
+                if region.hash {
+                    measurer.content(&seg.data);
                     //padding (allignment) which loader does
-                    for _ in 0..diff {
-                        let arr_u8: [u8; 1] = [0];
-                        hasher.input(&arr_u8);
+                    let mut diff = sz - (seg.data.len() as u64);
+                    let padding = [0; PADDING_CHUNK_SZ];
+                    while diff > 0 {
+                        let len = u64::min(diff, PADDING_CHUNK_SZ as u64);
+                        measurer.content(&padding[..len as usize]);
+                        diff -= len;
                     }
                 }
                 segment_off += sz;
@@ -73,20 +50,25 @@ fn hash_segments_info(enclave: &Box<ModifiedELF>, hasher: &mut Sha256, offset: u
     }
 }
 
-pub fn attest(src: &PathBuf, offset: u64, riscv_enabled: bool) -> (u128, u128) {
+/// Computes the measurement the monitor will report for the enclave.
+pub fn attest(src: &PathBuf, offset: u64, riscv_enabled: bool) -> Measurement {
     let data = std::fs::read(src).expect("Unable to read source file");
-    let mut hasher = Sha256::default();
+    let mut measurer = Measurer::new();
     let mut enclave = ModifiedELF::new(&data);
     enclave.fix_page_tables(offset, riscv_enabled);
 
-    hash_segments_info(&enclave, &mut hasher, offset);
+    measure_segments_info(&enclave, &mut measurer, offset);
 
-    let result = hasher.result();
+    let measurement = measurer.finalize();
     log::info!("Computed hash:");
-    log::info!("{}", format!("{:x}", result));
-    let hash_low: u128 = u128::from_be_bytes(result.as_slice()[0..16].try_into().unwrap());
-    let hash_high: u128 = u128::from_be_bytes(result.as_slice()[16..32].try_into().unwrap());
-    (hash_high, hash_low)
+    log::info!(
+        "{}",
+        measurement
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+    measurement
 }
 
 const MSG_SZ: usize = 32 + 8;
@@ -137,11 +119,10 @@ pub fn attestation_check(
 
     let mut message: [u8; MSG_SZ] = [0; MSG_SZ];
 
-    let (hash_high, hash_low) = attest(src_bin, offset, riscv_enabled);
+    let measurement = attest(src_bin, offset, riscv_enabled);
     //fill the bytes of the message to be checked
-    copy_arr(&mut message, &u128::to_le_bytes(hash_low), 0);
-    copy_arr(&mut message, &u128::to_le_bytes(hash_high), 16);
-    copy_arr(&mut message, &u64::to_le_bytes(nonce), 32);
+    copy_arr(&mut message, &measurement, 0);
+    copy_arr(&mut message, &u64::to_le_bytes(nonce), measurement.len());
     {
         let mut data_file = File::create("tychools_response.txt").expect("creation failed");
         if let Ok(_r) = pkey.verify(message, &sig) {
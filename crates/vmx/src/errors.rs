@@ -139,6 +139,38 @@ impl VmxInstructionError {
             _ => Self::Unknown,
         }
     }
+
+    /// Returns the VM-instruction error number, `0` for unknown errors.
+    pub fn as_u64(self) -> u64 {
+        match self {
+            Self::VmCallRoot => 1,
+            Self::VmClearInvalid => 2,
+            Self::VmClearVmxon => 3,
+            Self::VmLaunchNonClear => 4,
+            Self::VmResumeNonLaunched => 5,
+            Self::VmResumeAfterVmxoff => 6,
+            Self::VmEntryInvalidCtrlFields => 7,
+            Self::VmEntryInvalidHostState => 8,
+            Self::VmPtrldInvalidPhysAddr => 9,
+            Self::VmPtrldVmxon => 10,
+            Self::VmPtrldInvalidRevId => 11,
+            Self::VmAccessUnsupportedField => 12,
+            Self::VmWriteToReadOnly => 13,
+            Self::VmxonDuringVmxRoot => 15,
+            Self::VmEntryInvalidVmcs => 16,
+            Self::VmEntryNonLaunched => 17,
+            Self::VmEntryVmcsNotVmxon => 18,
+            Self::VmCallNonClearVmcs => 19,
+            Self::VmCallInvalidExitCtrlFields => 20,
+            Self::VmCallInvalidRevId => 22,
+            Self::VmxoffDualMonitor => 23,
+            Self::VmCallInvalidSmmFeatures => 24,
+            Self::VmEntryInvalidExecCtrlFields => 25,
+            Self::VmEntryBlockedMovSS => 26,
+            Self::InvalidInvEptInvPid => 28,
+            Self::Unknown => 0,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -241,6 +241,39 @@ impl Vmxon {
         })
     }
 
+    /// Invalidates the cached mappings derived from the EPT with the given root on this core.
+    ///
+    /// Required after modifying a live EPT in place, unless the modification only adds mappings.
+    pub fn invept_single_context(&self, eptp: HostPhysAddr) -> Result<(), VmxError> {
+        // SAFETY: we are in VMX operation, and invalidating mappings only costs performance.
+        unsafe { raw::invept(raw::INVEPT_SINGLE_CONTEXT, eptp.as_u64()) }
+    }
+
+    /// Invalidates the cached mappings derived from all EPTs on this core.
+    pub fn invept_all_contexts(&self) -> Result<(), VmxError> {
+        // SAFETY: we are in VMX operation, and invalidating mappings only costs performance.
+        unsafe { raw::invept(raw::INVEPT_ALL_CONTEXTS, 0) }
+    }
+
+    /// Invalidates the cached linear mappings tagged with `vpid` on this core.
+    pub fn invvpid_single_context(&self, vpid: u16) -> Result<(), VmxError> {
+        // SAFETY: we are in VMX operation, and invalidating mappings only costs performance.
+        unsafe { raw::invvpid(raw::INVVPID_SINGLE_CONTEXT, vpid, 0) }
+    }
+
+    /// Invalidates the cached mappings of the linear address `addr` tagged with `vpid` on this
+    /// core.
+    pub fn invvpid_individual_address(&self, vpid: u16, addr: u64) -> Result<(), VmxError> {
+        // SAFETY: we are in VMX operation, and invalidating mappings only costs performance.
+        unsafe { raw::invvpid(raw::INVVPID_INDIVIDUAL_ADDRESS, vpid, addr) }
+    }
+
+    /// Invalidates the cached linear mappings of all VPIDs on this core.
+    pub fn invvpid_all_contexts(&self) -> Result<(), VmxError> {
+        // SAFETY: we are in VMX operation, and invalidating mappings only costs performance.
+        unsafe { raw::invvpid(raw::INVVPID_ALL_CONTEXTS, 0, 0) }
+    }
+
     pub fn init_frame(&self, mut frame: Frame) {
         unsafe {
             let vmcs_info = get_vmx_info();
@@ -669,7 +702,7 @@ impl<'vmx> ActiveVmcs<'vmx> {
         let allowed_vmfuncs = available_vmfuncs()?;
         Self::validate_flags_allowed(flags.bits(), allowed_vmfuncs.bits())
             .map_err(|err| err.set_field(VmxFieldError::VmFuncControls))?;
-        self.set(VmcsField::VmFunctionControl, 1)
+        self.set(VmcsField::VmFunctionControl, flags.bits() as usize)
     }
 
     /// Sets a control setting for the current VMCS.
@@ -63,6 +63,41 @@ pub unsafe fn vmptrld(addr: u64) -> Result<(), VmxError> {
     vmx_capture_status()
 }
 
+/// INVEPT type invalidating the mappings of a single EPT root.
+pub const INVEPT_SINGLE_CONTEXT: u64 = 1;
+
+/// INVEPT type invalidating the mappings of all EPT roots.
+pub const INVEPT_ALL_CONTEXTS: u64 = 2;
+
+/// Executes INVEPT.
+///
+/// This invalidates the cached mappings derived from the EPT pointer `eptp`, with the scope
+/// selected by `kind` (see `INVEPT_SINGLE_CONTEXT` and `INVEPT_ALL_CONTEXTS`).
+pub unsafe fn invept(kind: u64, eptp: u64) -> Result<(), VmxError> {
+    let descriptor: [u64; 2] = [eptp, 0];
+    asm!("invept ({1}), {0}", in(reg) kind, in(reg) &descriptor, options(att_syntax));
+    vmx_capture_status()
+}
+
+/// INVVPID type invalidating the mappings of a single linear address of a VPID.
+pub const INVVPID_INDIVIDUAL_ADDRESS: u64 = 0;
+
+/// INVVPID type invalidating the mappings of a single VPID.
+pub const INVVPID_SINGLE_CONTEXT: u64 = 1;
+
+/// INVVPID type invalidating the mappings of all VPIDs but 0.
+pub const INVVPID_ALL_CONTEXTS: u64 = 2;
+
+/// Executes INVVPID.
+///
+/// This invalidates the cached linear mappings tagged with `vpid`, with the scope selected by
+/// `kind` (see the `INVVPID_*` types). `addr` is only used by individual-address invalidations.
+pub unsafe fn invvpid(kind: u64, vpid: u16, addr: u64) -> Result<(), VmxError> {
+    let descriptor: [u64; 2] = [vpid as u64, addr];
+    asm!("invvpid ({1}), {0}", in(reg) kind, in(reg) &descriptor, options(att_syntax));
+    vmx_capture_status()
+}
+
 pub unsafe fn vmptrst() -> Result<u64, VmxError> {
     let value: u64 = 0;
     asm!(
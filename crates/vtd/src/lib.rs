@@ -6,7 +6,8 @@ use core::arch::x86_64;
 use core::{ptr, slice};
 
 use bitflags::bitflags;
-use mmu::FrameAllocator;
+use mmu::ioptmapper::PAGE_SIZE;
+use mmu::{FrameAllocator, LargePages};
 use vmx::{HostPhysAddr, HostVirtAddr};
 
 /// Command bits that have an effect when set to 1 (e.g. update internal I/O MMU state).
@@ -14,6 +15,9 @@ const ONE_SHOOT_COMMAND_BITS: Command = Command::SET_ROOT_PTR
     .union(Command::WRITE_FLUSH_BUFFER)
     .union(Command::SET_INT_REMAP_PTR);
 
+/// The domain identifier of the context entries, all the devices share the same I/O page tables.
+pub const DOMAIN_ID: u16 = 0;
+
 /// A device identifier, in the form bus:device.function (BDF).
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(C)]
@@ -102,6 +106,78 @@ impl Iommu {
         self.execute_toggle_command(Command::TRANSLATION_ENABLE, true);
     }
 
+    /// Invalidates all the context entries cached by the I/O MMU, required after updating the
+    /// root table.
+    pub fn invalidate_context_cache(&mut self) {
+        self.set_context_command((ContextCommand::INVALIDATE | ContextCommand::GLOBAL).bits());
+        while self.get_context_command() & ContextCommand::INVALIDATE.bits() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Invalidates the IOTLB and paging-structure cache entries of the domain translating
+    /// `[addr, addr + size)`, and waits for the invalidation to complete.
+    ///
+    /// Page-selective invalidations cover a naturally aligned block of pages, the whole domain is
+    /// invalidated if the I/O MMU does not support them or the block would be too large.
+    pub fn invalidate_iotlb_range(&mut self, domain: u16, addr: usize, size: usize) {
+        let capability = self.get_capability();
+        let first = addr / PAGE_SIZE;
+        let last = (addr + size).div_ceil(PAGE_SIZE) - 1;
+        // The smallest block of pages containing both the first and last page of the range.
+        let mask = (usize::BITS - (first ^ last).leading_zeros()) as u64;
+        let max_mask = (capability & Capability::MAX_ADDR_MASK_VALUE).bits() >> 48;
+        if capability.contains(Capability::PAGE_SELECTIVE_INVAL) && mask <= max_mask {
+            let block = ((first as u64) >> mask << mask) * PAGE_SIZE as u64;
+            self.invalidate_iotlb(IotlbCommand::PAGE_SELECTIVE, domain, block | mask);
+        } else {
+            self.invalidate_iotlb(IotlbCommand::DOMAIN_SELECTIVE, domain, 0);
+        }
+    }
+
+    /// Invalidates all the IOTLB and paging-structure cache entries, and waits for the
+    /// invalidation to complete.
+    pub fn invalidate_iotlb_all(&mut self) {
+        self.invalidate_iotlb(IotlbCommand::GLOBAL, 0, 0);
+    }
+
+    /// Submits an IOTLB invalidation through the registers, `address` is only used by
+    /// page-selective invalidations.
+    fn invalidate_iotlb(&mut self, granularity: IotlbCommand, domain: u16, address: u64) {
+        let capability = self.get_capability();
+        // Page table updates might be buffered by the I/O MMU.
+        if capability.contains(Capability::WRITE_BUFFER_FLUSH) {
+            self.flush_write_buffer();
+        }
+        let mut command = IotlbCommand::INVALIDATE | granularity;
+        if capability.contains(Capability::READ_DRAINING) {
+            command |= IotlbCommand::DRAIN_READS;
+        }
+        if capability.contains(Capability::WRITE_DRAINING) {
+            command |= IotlbCommand::DRAIN_WRITES;
+        }
+        let offset =
+            (self.get_extended_capability() & ExtendedCapability::IOTLB_REG_OFFSET).bits() >> 8;
+        unsafe {
+            let address_reg = self.addr.offset(offset as isize * 16) as *mut u64;
+            let iotlb_reg = address_reg.offset(1);
+            ptr::write_volatile(address_reg, address);
+            ptr::write_volatile(iotlb_reg, command.bits() | (domain as u64) << 32);
+            // The I/O MMU clears the bit once the invalidation completed.
+            while ptr::read_volatile(iotlb_reg) & IotlbCommand::INVALIDATE.bits() != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Flushes the write buffer of the I/O MMU.
+    fn flush_write_buffer(&mut self) {
+        let status = self.get_global_status() & !ONE_SHOOT_COMMAND_BITS;
+        self.set_global_command((status | Command::WRITE_FLUSH_BUFFER).bits());
+        // The status bit is cleared once the buffer is flushed.
+        self.wait_on_global_status(Command::WRITE_FLUSH_BUFFER, false);
+    }
+
     pub fn iter_fault(&mut self) -> FaultIterator {
         let capability = self.get_capability().bits();
         let fault_reg_offset = ((capability >> 24) & 0b1111111111) * 16;
@@ -292,7 +368,7 @@ pub fn setup_iommu_context(
         .expect("I/O MMU root frame")
         .zeroed();
     let ctx_entry = ContextEntry {
-        upper: 0b010, // 4 lvl pages
+        upper: 0b010 | (DOMAIN_ID as u64) << 8, // 4 lvl pages
         lower: iopt_root.as_u64() | 0b0001,
     };
     let root_entry = RootEntry {
@@ -390,6 +466,20 @@ bitflags! {
         const TRANSLATION_ENABLE       = 1 << 31;
     }
 
+    pub struct ContextCommand: u64 {
+        const INVALIDATE = 1 << 63;
+        const GLOBAL     = 0b01 << 61;
+    }
+
+    pub struct IotlbCommand: u64 {
+        const INVALIDATE       = 1 << 63;
+        const GLOBAL           = 0b01 << 60;
+        const DOMAIN_SELECTIVE = 0b10 << 60;
+        const PAGE_SELECTIVE   = 0b11 << 60;
+        const DRAIN_READS      = 1 << 49;
+        const DRAIN_WRITES     = 1 << 48;
+    }
+
     pub struct FaultStatus: u32 {
         const PRIMARY_FAULT_OVERFLOW        = 1 << 0;
         const PRIMARY_PENDING_FAULT         = 1 << 1;
@@ -413,6 +503,20 @@ bitflags! {
     }
 }
 
+impl Capability {
+    /// Returns the large pages supported for second-stage translations.
+    pub fn large_pages(self) -> LargePages {
+        let mut large_pages = LargePages::empty();
+        if self.contains(Capability::SECOND_STAGE_2MB) {
+            large_pages |= LargePages::HUGE;
+        }
+        if self.contains(Capability::SECOND_STAGE_1GB) {
+            large_pages |= LargePages::GIANT;
+        }
+        large_pages
+    }
+}
+
 impl FaultRecording {
     pub fn reason(self) -> u8 {
         ((self.bits() >> 32) & 0b11111111) as u8
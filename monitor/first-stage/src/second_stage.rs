@@ -6,7 +6,7 @@ use core::sync::atomic::{AtomicBool, Ordering};
 
 use mmu::frame_allocator::PhysRange;
 use mmu::{PtFlag, PtMapper, RangeAllocator};
-use stage_two_abi::{EntryPoint, Manifest, Smp};
+use stage_two_abi::{EntryPoint, FlowPolicy, FlowRule, Manifest, Pools, Smp};
 
 use crate::cpu::MAX_CPU_NUM;
 use crate::elf::{Elf64PhdrType, ElfProgram};
@@ -22,12 +22,23 @@ const SECOND_STAGE: &'static [u8] =
 const SECOND_STAGE: &'static [u8] = &[0; 10];
 
 /// Size of memory allocated by the second stage.
-const SECOND_STAGE_SIZE: usize = 0x1000 * 16384;
+const SECOND_STAGE_SIZE: usize = 0x1000 * 32768;
 /// Virtual address to which the guest is loaded. Defined by our linker script.
 const LOAD_VIRT_ADDR: HostVirtAddr = HostVirtAddr::new(0x80000000000);
 //  Stack definitions
 const STACK_VIRT_ADDR: HostVirtAddr = HostVirtAddr::new(0x90000000000);
 const STACK_SIZE: usize = 0x1000 * 5;
+//  Monitor pools definitions, the pools must fit in `POOLS_SIZE` bytes. Most of it goes to the
+//  per-core contexts of each domain.
+const POOLS_VIRT_ADDR: HostVirtAddr = HostVirtAddr::new(0xa0000000000);
+const POOLS_SIZE: usize = 0x1000 * 10240;
+const NB_DOMAINS: u64 = 64;
+const NB_CAPAS_PER_DOMAIN: u64 = 128;
+const NB_REGIONS: u64 = 2048;
+const NB_TRACKER: u64 = 2048;
+//  Information-flow policy enforced by the monitor on capability transfers, at most
+//  `stage_two_abi::MAX_FLOW_RULES` rules. Empty by default: all transfers are allowed.
+const FLOW_RULES: &[FlowRule] = &[];
 
 /// Second stage jump structures
 static mut SECOND_STAGE_ENTRIES: [Option<Stage2>; MAX_CPU_NUM] = [None; MAX_CPU_NUM];
@@ -224,6 +235,18 @@ pub fn load(
         PtFlag::PRESENT | PtFlag::WRITE,
     );
 
+    // Reserve and map the memory for the monitor pools
+    let pools_range = stage2_allocator
+        .allocate_range(POOLS_SIZE)
+        .expect("Failed to allocate the monitor pools");
+    loaded_elf.pt_mapper.map_range(
+        stage2_allocator,
+        POOLS_VIRT_ADDR,
+        pools_range.start,
+        POOLS_SIZE,
+        PtFlag::PRESENT | PtFlag::WRITE | PtFlag::EXEC_DISABLE,
+    );
+
     // Map the MP wakeup mailbox page into stage 2
     loaded_elf.pt_mapper.map_range(
         stage2_allocator,
@@ -273,6 +296,17 @@ pub fn load(
     manifest.voffset = LOAD_VIRT_ADDR.as_u64();
     manifest.vga = info.vga_info.clone();
     manifest.smp = smp;
+    manifest.pools = Pools {
+        start: POOLS_VIRT_ADDR.as_u64(),
+        size: POOLS_SIZE as u64,
+        nb_domains: NB_DOMAINS,
+        nb_capas_per_domain: NB_CAPAS_PER_DOMAIN,
+        nb_regions: NB_REGIONS,
+        nb_tracker: NB_TRACKER,
+    };
+    manifest.flow = FlowPolicy::no_policy();
+    manifest.flow.rules[..FLOW_RULES.len()].copy_from_slice(FLOW_RULES);
+    manifest.flow.nb_rules = FLOW_RULES.len() as u64;
 
     debug::hook_stage2_offsets(manifest.poffset, manifest.voffset);
     debug::tyche_hook_stage1(1);
@@ -0,0 +1,159 @@
+#![no_main]
+
+use capa_engine::MEMOPS_ALL;
+use libfuzzer_sys::arbitrary::Arbitrary;
+use libfuzzer_sys::{arbitrary, fuzz_target};
+use monitor_abi::calls;
+use tyche::allocator::PAGE_SIZE;
+use tyche::mock::{Simulation, NB_MEMORY_PAGES};
+
+/// Number of simulated cores.
+const NB_CORES: usize = 3;
+
+#[derive(Arbitrary, Debug)]
+pub struct Call {
+    core: u8,
+    vmcall: Vmcall,
+    args: [Arg; 6],
+}
+
+/// The monitor calls exercised by the fuzzer.
+///
+/// `EXIT`, `WRITE_ALL_GP` and `SELF_CONFIG` are not implemented, and the TPM and signing calls
+/// require a TPM and an attestation key, which are not simulated.
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Vmcall {
+    CreateDomain,
+    SealDomain,
+    Send,
+    SegmentRegion,
+    Revoke,
+    Duplicate,
+    Enumerate,
+    Switch,
+    Debug,
+    Configure,
+    SendRegion,
+    ConfigureCore,
+    GetConfigCore,
+    AllocCoreContext,
+    ReadAllGp,
+    WriteFields,
+    RevokeAliasedRegion,
+    SerializeAttestation,
+    ReturnToManager,
+    GetHpa,
+    ConfigureCoreMap,
+    ConfigureQuota,
+    SendRegionLeased,
+    PollRevocation,
+    Transaction,
+    RegisterRing,
+    RingEnter,
+    CreateChannel,
+    ChannelSend,
+    ChannelReceive,
+    ConfigureLabels,
+    SetNotificationVector,
+    PollNotifications,
+    Unknown,
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Arg {
+    /// A small value, such as a capability, core, flag or permission.
+    Value(u8),
+    /// A page of the simulated memory, or its end.
+    Page(u8),
+}
+
+fuzz_target!(|calls: Vec<Call>| {
+    fuzz(calls);
+});
+
+fn fuzz(calls: Vec<Call>) {
+    let sim = Simulation::new(NB_CORES);
+    let initial_domain = sim.initial_domain();
+    let (mem_start, mem_end) = sim.memory();
+
+    // The root regions span the whole address space, but only the simulated memory is backed. We
+    // carve it out of the roots, and keep the roots out of reach of the fuzzed calls.
+    let roots = [
+        sim.find_region(initial_domain, 0, 1).unwrap(),
+        sim.find_region(initial_domain, mem_start, mem_end).unwrap(),
+    ];
+    sim.call(
+        0,
+        calls::SEGMENT_REGION,
+        [
+            roots[1].as_usize(),
+            0,
+            mem_start,
+            mem_end,
+            MEMOPS_ALL.bits() as usize,
+            0,
+        ],
+    )
+    .unwrap();
+
+    for (time, call) in calls.iter().enumerate() {
+        // Let time flow so that lease deadlines expire.
+        sim.set_timestamp(time as u64);
+        let core = call.core as usize % NB_CORES;
+        let args = call.args.map(|arg| match arg {
+            Arg::Value(value) => value as usize,
+            Arg::Page(page) => {
+                mem_start + (page as usize % (NB_MEMORY_PAGES + 1)) * PAGE_SIZE as usize
+            }
+        });
+        let uses_root = roots.iter().any(|root| args.contains(&root.as_usize()));
+        if uses_root && sim.current(core) == initial_domain {
+            continue;
+        }
+
+        // Errors are expected, only panics and broken invariants are bugs.
+        let _ = sim.call(core, call.vmcall.number(), args);
+        sim.check_invariants();
+    }
+}
+
+impl Vmcall {
+    fn number(self) -> usize {
+        match self {
+            Vmcall::CreateDomain => calls::CREATE_DOMAIN,
+            Vmcall::SealDomain => calls::SEAL_DOMAIN,
+            Vmcall::Send => calls::SEND,
+            Vmcall::SegmentRegion => calls::SEGMENT_REGION,
+            Vmcall::Revoke => calls::REVOKE,
+            Vmcall::Duplicate => calls::DUPLICATE,
+            Vmcall::Enumerate => calls::ENUMERATE,
+            Vmcall::Switch => calls::SWITCH,
+            Vmcall::Debug => calls::DEBUG,
+            Vmcall::Configure => calls::CONFIGURE,
+            Vmcall::SendRegion => calls::SEND_REGION,
+            Vmcall::ConfigureCore => calls::CONFIGURE_CORE,
+            Vmcall::GetConfigCore => calls::GET_CONFIG_CORE,
+            Vmcall::AllocCoreContext => calls::ALLOC_CORE_CONTEXT,
+            Vmcall::ReadAllGp => calls::READ_ALL_GP,
+            Vmcall::WriteFields => calls::WRITE_FIELDS,
+            Vmcall::RevokeAliasedRegion => calls::REVOKE_ALIASED_REGION,
+            Vmcall::SerializeAttestation => calls::SERIALIZE_ATTESTATION,
+            Vmcall::ReturnToManager => calls::RETURN_TO_MANAGER,
+            Vmcall::GetHpa => calls::GET_HPA,
+            Vmcall::ConfigureCoreMap => calls::CONFIGURE_CORE_MAP,
+            Vmcall::ConfigureQuota => calls::CONFIGURE_QUOTA,
+            Vmcall::SendRegionLeased => calls::SEND_REGION_LEASED,
+            Vmcall::PollRevocation => calls::POLL_REVOCATION,
+            Vmcall::Transaction => calls::TRANSACTION,
+            Vmcall::RegisterRing => calls::REGISTER_RING,
+            Vmcall::RingEnter => calls::RING_ENTER,
+            Vmcall::CreateChannel => calls::CREATE_CHANNEL,
+            Vmcall::ChannelSend => calls::CHANNEL_SEND,
+            Vmcall::ChannelReceive => calls::CHANNEL_RECEIVE,
+            Vmcall::ConfigureLabels => calls::CONFIGURE_LABELS,
+            Vmcall::SetNotificationVector => calls::SET_NOTIFICATION_VECTOR,
+            Vmcall::PollNotifications => calls::POLL_NOTIFICATIONS,
+            Vmcall::Unknown => 0,
+        }
+    }
+}
@@ -1,143 +1,131 @@
-use attestation::hashing::{self, TycheHasher};
-use attestation::signature::{self, get_attestation_keys, EnclaveReport, ATTESTATION_DATA_SZ};
-use capa_engine::{CapaEngine, CapaInfo, Domain, Handle, MemOps, NextCapaToken};
-use spin::MutexGuard;
+use attestation::hashing::HashEnclave;
+use attestation::measurement::{MeasuredRegion, Measurer};
+use attestation::signature::{
+    AttestationIdentity, AttestationKeyCertificate, EnclaveReport, ATTESTATION_DATA_SZ,
+    ATTESTATION_SEED_SZ,
+};
+use capa_engine::{CapaEngine, CapaError, CapaInfo, Domain, Handle, MemOps, NextCapaToken};
+use monitor_abi::report;
+use spin::{MutexGuard, Once};
+
+use crate::error::{Error, ErrorCode};
 
 // —————————————————————— Initial measurement —————————————————————— //
 
-fn hash_access_right(hasher: &mut TycheHasher, access_rights: u8, mask: u8) {
-    if access_rights & mask != 0 {
-        hashing::hash_segment(hasher, &u8::to_le_bytes(1 as u8));
-    } else {
-        hashing::hash_segment(hasher, &u8::to_le_bytes(0 as u8));
-    }
-}
+/// Size of the blocks in which the content of regions is measured.
+const BLOCK_SIZE: usize = 0x4000; // 16KB
 
-fn hash_capa_info(
-    hasher: &mut TycheHasher,
+fn measure_capa_info(
+    measurer: &mut Measurer,
     engine: &mut MutexGuard<'_, CapaEngine>,
     domain: Handle<Domain>,
 ) {
     let mut next_capa = NextCapaToken::new();
     while let Some((info, next_next_capa, _)) = engine.enumerate(domain, next_capa) {
         next_capa = next_next_capa;
-        match info {
-            CapaInfo::Region {
-                start,
-                end,
-                unique,
-                children: _,
-                ops,
-            } => {
-                if ops.contains(MemOps::HASH) {
-                    log::trace!("Hashing region at {:x}->{:x} with ops {:?}", start, end, ops);
-
-                    // Hashing start - end of region
-                    hashing::hash_segment(hasher, &(usize::to_le_bytes(start)));
-                    hashing::hash_segment(hasher, &(usize::to_le_bytes(end)));
-
-                    // Hashing access rights
-                    let access_rights = ops.bits();
-                    hash_access_right(hasher, access_rights, MemOps::HASH.bits());
-                    hash_access_right(hasher, access_rights, MemOps::EXEC.bits());
-                    hash_access_right(hasher, access_rights, MemOps::WRITE.bits());
-                    hash_access_right(hasher, access_rights, MemOps::READ.bits());
-
-                    // Hash conf/shared info
-                    let conf_info = if unique { 1 as u8 } else { 0 as u8 };
-                    hashing::hash_segment(hasher, &(u8::to_le_bytes(conf_info)));
-
-                    // Hashing region data info
-                    let mut addr = start;
-                    const BLOCK_SIZE: usize = 0x4000; // 16KB
-                    
-                    while addr < end {
-                        let len = if (addr + BLOCK_SIZE < end) { BLOCK_SIZE } else { end - addr };
-                        let data = unsafe { 
-                            core::slice::from_raw_parts(
-                                addr as *const u8,
-                                len
-                            )
-                        };
-                        hashing::hash_segment(hasher, data);
-                        addr = addr + len;
-                    }
-                } else {
-                    log::trace!("NOT hashing region at {:x}->{:x} with ops {:?}", start, end, ops);
-
-                    // Hash capa but set region to zero.
-                    // Hashing start - end of region
-                    hashing::hash_segment(hasher, &(usize::to_le_bytes(start)));
-                    hashing::hash_segment(hasher, &(usize::to_le_bytes(end)));
-
-                    // Hashing access rights
-                    let access_rights = ops.bits();
-                    hash_access_right(hasher, access_rights, MemOps::HASH.bits());
-                    hash_access_right(hasher, access_rights, MemOps::EXEC.bits());
-                    hash_access_right(hasher, access_rights, MemOps::WRITE.bits());
-                    hash_access_right(hasher, access_rights, MemOps::READ.bits());
-
-                    // Hash conf/shared info
-                    let conf_info = if unique { 1 as u8 } else { 0 as u8 };
-                    hashing::hash_segment(hasher, &(u8::to_le_bytes(conf_info)));
+        let CapaInfo::Region {
+            start,
+            end,
+            unique,
+            children: _,
+            ops,
+        } = info
+        else {
+            continue;
+        };
 
-                    // Set region to zero
-                    let data = unsafe {
-                        core::slice::from_raw_parts_mut(
-                            start as *mut u8,
-                            end - start,
-                        )
-                    };
-                    data.fill(0);
-                }
+        let region = MeasuredRegion {
+            start: start as u64,
+            end: end as u64,
+            hash: ops.contains(MemOps::HASH),
+            exec: ops.contains(MemOps::EXEC),
+            write: ops.contains(MemOps::WRITE),
+            read: ops.contains(MemOps::READ),
+            confidential: unique,
+        };
+        measurer.region(&region);
+
+        if region.hash {
+            log::trace!("Hashing region at {:x}->{:x} with ops {:?}", start, end, ops);
+            let mut addr = start;
+            while addr < end {
+                let len = usize::min(BLOCK_SIZE, end - addr);
+                let data = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+                measurer.content(data);
+                addr += len;
             }
-            _ => {}
+        } else {
+            log::trace!("NOT hashing region at {:x}->{:x} with ops {:?}", start, end, ops);
+            // Set region to zero
+            let data = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, end - start) };
+            data.fill(0);
         }
     }
 }
 
 pub fn calculate_attestation_hash(engine: &mut MutexGuard<'_, CapaEngine>, domain: Handle<Domain>) {
-    let mut hasher = hashing::get_hasher();
+    let mut measurer = Measurer::new();
 
-    hash_capa_info(&mut hasher, engine, domain);
+    measure_capa_info(&mut measurer, engine, domain);
 
     log::trace!("Finished calculating the hash!");
-    engine.set_hash(domain, hashing::get_hash(hasher));
+    let measurement = measurer.finalize();
+    engine.set_hash(domain, HashEnclave::from_bytes(&measurement));
 }
 
 // —————————————————————— Attestation —————————————————————— //
 
-fn copy_array(dst: &mut [u8], src: &[u8], index: usize) {
-    let mut ind_help = index;
-    for x in src {
-        dst[ind_help] = *x;
-        ind_help += 1;
-    }
+/// The attestation key of this boot, certified by the device key.
+static ATTESTATION_IDENTITY: Once<AttestationIdentity> = Once::new();
+
+/// Generates the attestation key of this boot from `seed`, and certifies it with the device key.
+///
+/// Must be called once on the BSP, before any domain is attested. See
+/// [AttestationIdentity::new] for the semantic of `device_sign`.
+pub fn init_attestation_identity<F>(seed: [u8; ATTESTATION_SEED_SZ], device_sign: F)
+where
+    F: FnOnce(&[u8], &mut [u8]) -> usize,
+{
+    ATTESTATION_IDENTITY.call_once(|| AttestationIdentity::new(seed, device_sign));
+}
+
+/// The certificate of the attestation key of this boot, if attestation is enabled.
+pub fn attestation_certificate() -> Option<&'static AttestationKeyCertificate> {
+    ATTESTATION_IDENTITY
+        .get()
+        .map(|identity| identity.certificate())
 }
 
+/// Creates or reads the report of `current`, depending on the `ENCLAVE_ATTESTATION` mode.
+///
+/// A new report signs the measurement of the domain followed by `nonce` and is kept in the
+/// domain until the next one, so that it can be read back in several calls.
 pub fn attest_domain(
     engine: &mut MutexGuard<CapaEngine>,
     current: Handle<Domain>,
     nonce: usize,
     mode: usize,
-) -> Option<EnclaveReport> {
-    if mode == 0 {
-        let enc_hash = engine[current].get_hash();
-        let mut sign_data: [u8; ATTESTATION_DATA_SZ] = [0; ATTESTATION_DATA_SZ];
-        enc_hash.to_byte_arr(&mut sign_data, 0);
-        copy_array(&mut sign_data, &usize::to_le_bytes(nonce), 32);
-        let (pb_key, priv_key) = get_attestation_keys();
-        let signed_enc_data = signature::sign_attestation_data(&sign_data, priv_key);
-        let rep = EnclaveReport {
-            public_key: pb_key,
-            signed_enclave_data: signed_enc_data,
-        };
-        engine.set_report(current, rep);
-        Some(rep)
-    } else if mode == 1 {
-        engine[current].get_report()
-    } else {
-        log::trace!("Wrong mode");
-        None
+) -> Result<EnclaveReport, Error> {
+    match mode {
+        report::CREATE => {
+            let Some(identity) = ATTESTATION_IDENTITY.get() else {
+                log::error!("No attestation key, can not attest domain");
+                return Err(ErrorCode::AttestationUnavailable.into());
+            };
+            let mut sign_data: [u8; ATTESTATION_DATA_SZ] = [0; ATTESTATION_DATA_SZ];
+            engine[current].get_hash().to_byte_arr(&mut sign_data, 0);
+            sign_data[32..].copy_from_slice(&usize::to_le_bytes(nonce));
+            let rep = identity.report(&sign_data);
+            engine.set_report(current, rep);
+            Ok(rep)
+        }
+        report::READ => engine[current].get_report().ok_or_else(|| {
+            log::trace!("No report to read, create one first");
+            CapaError::InvalidOperation.into()
+        }),
+        _ => {
+            log::trace!("Wrong mode");
+            Err(CapaError::InvalidValue.into())
+        }
     }
 }
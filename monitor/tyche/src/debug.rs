@@ -36,10 +36,7 @@ macro_rules! println {
 pub mod qemu {
     pub use qemu::ExitCode;
 
-    #[cfg(target_arch = "riscv64")]
-    use crate::riscv::hlt;
-    #[cfg(target_arch = "x86_64")]
-    use crate::x86_64::hlt;
+    use crate::arch::hlt;
 
     pub fn exit(exit_code: ExitCode) -> ! {
         println!("========= Exiting Second Stage =========");
@@ -1,5 +1,5 @@
 //! Second-stage
-#![no_std]
+#![cfg_attr(not(any(test, feature = "mock")), no_std)]
 #![feature(fn_align)]
 #![feature(naked_functions)]
 
@@ -13,23 +13,25 @@ mod rcframe;
 pub mod statics;
 mod sync;
 
-#[cfg(target_arch = "riscv64")]
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(all(target_arch = "riscv64", not(any(test, feature = "mock"))))]
 pub mod riscv;
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(target_arch = "x86_64", not(any(test, feature = "mock"))))]
 pub mod x86_64;
 
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(target_arch = "x86_64", not(any(test, feature = "mock"))))]
 pub mod arch {
     pub use crate::x86_64::*;
 }
 
-#[cfg(target_arch = "riscv64")]
+#[cfg(all(target_arch = "riscv64", not(any(test, feature = "mock"))))]
 pub mod arch {
     pub use crate::riscv::*;
 }
 
-/// Special return values supplied by the monitor.
-#[repr(usize)]
-pub enum MonitorErrors {
-    DomainRevoked = 66,
+/// Unit tests and fuzzing run on the host, against a simulated platform.
+#[cfg(any(test, feature = "mock"))]
+pub mod arch {
+    pub use crate::mock::{cpuid, hlt};
 }
@@ -0,0 +1,1024 @@
+//! Mock platform
+//!
+//! A software implementation of [PlatformState], used to run the monitor logic as ordinary unit
+//! tests on the host. Physical memory is a heap allocated array, EPT/PMP are replaced by the list
+//! of permissions last installed for each domain, and each core is simulated by a thread.
+//!
+//! Simulated cores are blocked until the test sends them a step: either a command to execute, or
+//! the delivery of their pending IPI. The IPIs sent by a core are reported to the test, which
+//! delivers them to the target cores in increasing order before waiting for the sender, and a core
+//! never runs otherwise. The interleaving of cores is thus entirely decided by the test, including
+//! for the multi-core shootdown and revocation paths.
+//!
+//! The mock is also available with the `mock` feature, for the fuzzer in `monitor/tyche/fuzz`.
+
+use std::any::Any;
+use std::boxed::Box;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::vec::Vec;
+
+use capa_engine::config::{NB_CORES, NB_DOMAINS};
+use capa_engine::pool::PoolMemory;
+use capa_engine::{
+    AccessRights, CapaEngine, CapaError, CapaInfo, CoreSet, Domain, EngineConfig, Handle,
+    LocalCapa, MemOps, NextCapaToken, PermissionDelta,
+};
+use monitor_abi::fast_switch::SIZE as FAST_SWITCH_SIZE;
+use monitor_abi::status;
+use spin::{Mutex, MutexGuard};
+use stage_two_abi::{FlowPolicy, FlowRule, GuestInfo, Manifest, Pools, Smp, VgaInfo};
+
+use crate::allocator::{Page, EMPTY_PAGE, PAGE_SIZE};
+use crate::error::{Error, ErrorCode};
+use crate::monitor::{
+    CoreUpdate, Monitor, Notifications, PlatformState, Ring, CAPA_ENGINE, CORE_UPDATES,
+    INITIAL_DOMAIN, IO_DOMAIN, LEASE_DEADLINE, NOTIFY,
+};
+use crate::sync::Barrier;
+
+/// Size of the simulated physical memory, in pages.
+pub const NB_MEMORY_PAGES: usize = 64;
+
+/// Number of general purpose registers in a simulated context.
+pub const NB_GP: usize = 15;
+
+/// Number of regions that can be mapped in a simulated domain, like the remapper segments on x86.
+pub const NB_ALIASES: usize = 8;
+
+/// Stack size of simulated cores, the capability engine is too large for the default stacks.
+const CORE_STACK_SIZE: usize = 64 << 20;
+
+// ——————————————————————————————— Arch Stubs ——————————————————————————————— //
+
+thread_local! {
+    static CORE_ID: Cell<usize> = const { Cell::new(0) };
+    /// Reports the events of the simulated core running on this thread to the test.
+    static EVENTS: RefCell<Option<Sender<Event>>> = const { RefCell::new(None) };
+}
+
+/// The ID of the simulated core running on this thread.
+pub fn cpuid() -> usize {
+    CORE_ID.with(|id| id.get())
+}
+
+pub fn hlt() -> ! {
+    panic!("Simulated core {} halted", cpuid());
+}
+
+// ———————————————————————————— Platform State ————————————————————————————— //
+
+/// Platform data of a domain.
+pub struct MockDomain {
+    /// The permissions installed for the domain, i.e. what the EPT or PMP would contain.
+    pub permissions: Vec<(usize, usize, MemOps)>,
+    /// Incremented each time new permissions are installed.
+    pub version: usize,
+    /// Regions sent with an alias: (hpa, gpa, size, repeat).
+    pub aliases: Vec<(usize, usize, usize, usize)>,
+    /// Traps delivered to the domain: (trap, info).
+    pub traps: Vec<(u64, u64)>,
+    /// The owner of the fast switch pair the domain belongs to, and the address of its trampoline.
+    pub fast_switch: Option<(Handle<Domain>, usize)>,
+    /// The submission ring registered by the domain.
+    pub ring: Option<Ring>,
+    /// The notifications pending for the domain.
+    pub notifications: Option<Notifications>,
+    /// Vectors of the notification interrupts raised in the domain.
+    pub interrupts: Vec<u8>,
+}
+
+/// Platform data of a domain on a given core, i.e. what the VMCS or saved registers would contain.
+pub struct MockContext {
+    pub regs: BTreeMap<usize, usize>,
+    pub gp: [usize; NB_GP],
+    /// The version of the domain permissions used by the core (i.e. cached in its TLB).
+    pub tlb_version: usize,
+    pub interrupted: bool,
+}
+
+impl MockDomain {
+    const fn new() -> Self {
+        MockDomain {
+            permissions: Vec::new(),
+            version: 0,
+            aliases: Vec::new(),
+            traps: Vec::new(),
+            fast_switch: None,
+            ring: None,
+            notifications: None,
+            interrupts: Vec::new(),
+        }
+    }
+}
+
+impl MockContext {
+    const fn new() -> Self {
+        MockContext {
+            regs: BTreeMap::new(),
+            gp: [0; NB_GP],
+            tlb_version: 0,
+            interrupted: false,
+        }
+    }
+}
+
+const EMPTY_DOMAIN: Mutex<MockDomain> = Mutex::new(MockDomain::new());
+const EMPTY_CONTEXT: Mutex<MockContext> = Mutex::new(MockContext::new());
+const EMPTY_CONTEXTS: [Mutex<MockContext>; NB_CORES] = [EMPTY_CONTEXT; NB_CORES];
+const NO_IPI: AtomicBool = AtomicBool::new(false);
+const NO_FLUSH: AtomicBool = AtomicBool::new(false);
+
+static DOMAINS: [Mutex<MockDomain>; NB_DOMAINS] = [EMPTY_DOMAIN; NB_DOMAINS];
+static CONTEXTS: [[Mutex<MockContext>; NB_CORES]; NB_DOMAINS] = [EMPTY_CONTEXTS; NB_DOMAINS];
+static TLB_FLUSH_BARRIERS: [Barrier; NB_DOMAINS] = [Barrier::NEW; NB_DOMAINS];
+static TLB_FLUSH: [AtomicBool; NB_DOMAINS] = [NO_FLUSH; NB_DOMAINS];
+static IPIS: [AtomicBool; NB_CORES] = [NO_IPI; NB_CORES];
+static IPI_ROUNDS: AtomicUsize = AtomicUsize::new(0);
+static NB_SIMULATED_CORES: AtomicUsize = AtomicUsize::new(0);
+static MEMORY_START: AtomicUsize = AtomicUsize::new(0);
+static MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+static TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+/// Memory for the engine pools, allocated once and re-used by all simulations.
+static POOLS: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+
+/// Serializes the simulations, as the monitor relies on global state.
+static SIMULATION_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+pub struct MockState {}
+
+pub struct MockMonitor {}
+
+impl Monitor<MockState> for MockMonitor {}
+
+impl PlatformState for MockState {
+    type DomainData = MockDomain;
+    type Context = MockContext;
+
+    fn find_buff(
+        &mut self,
+        _engine: &MutexGuard<CapaEngine>,
+        domain: Handle<Domain>,
+        addr: usize,
+        len: usize,
+        is_gva: bool,
+    ) -> Option<usize> {
+        if is_gva {
+            // There are no guest page tables to walk.
+            return None;
+        }
+        let end = addr.checked_add(len)?;
+        // Only the simulated memory is backed, the rest of the root regions is not.
+        if addr < MEMORY_START.load(Ordering::SeqCst) || MEMORY_END.load(Ordering::SeqCst) < end {
+            return None;
+        }
+        let domain = Self::get_domain(domain);
+        domain
+            .permissions
+            .iter()
+            .any(|&(start, stop, ops)| start <= addr && end <= stop && ops.contains(MemOps::WRITE))
+            .then_some(addr)
+    }
+
+    fn remap_core(core: usize) -> usize {
+        core
+    }
+
+    fn max_cpus() -> usize {
+        NB_SIMULATED_CORES.load(Ordering::SeqCst)
+    }
+
+    fn timestamp() -> u64 {
+        TIMESTAMP.load(Ordering::SeqCst)
+    }
+
+    fn create_context(
+        &mut self,
+        _engine: MutexGuard<CapaEngine>,
+        _current: Handle<Domain>,
+        domain: Handle<Domain>,
+        core: usize,
+    ) -> Result<(), CapaError> {
+        *Self::get_context(domain, core) = MockContext::new();
+        Ok(())
+    }
+
+    fn check_context(&mut self, _domain: Handle<Domain>, _core: usize) -> Result<(), CapaError> {
+        Ok(())
+    }
+
+    fn platform_init_io_mmu(&self, _addr: usize) {}
+
+    fn platform_init_pools(
+        &self,
+        config: &EngineConfig,
+        _memory: &mut PoolMemory,
+    ) -> Result<(), CapaError> {
+        // The simulated platform state is static
+        if config.nb_domains > NB_DOMAINS {
+            return Err(CapaError::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    fn get_domain(domain: Handle<Domain>) -> MutexGuard<'static, Self::DomainData> {
+        DOMAINS[domain.idx()].lock()
+    }
+
+    fn get_context(domain: Handle<Domain>, core: usize) -> MutexGuard<'static, Self::Context> {
+        CONTEXTS[domain.idx()][core].lock()
+    }
+
+    fn get_ring(domain: Handle<Domain>) -> Option<Ring> {
+        Self::get_domain(domain).ring
+    }
+
+    fn set_ring(domain: Handle<Domain>, ring: Option<Ring>) {
+        Self::get_domain(domain).ring = ring;
+    }
+
+    fn with_notifications<R>(
+        domain: Handle<Domain>,
+        f: impl FnOnce(&mut Option<Notifications>) -> R,
+    ) -> R {
+        f(&mut Self::get_domain(domain).notifications)
+    }
+
+    fn inject_notification(&mut self, domain: Handle<Domain>, _core: usize, vector: u8) -> bool {
+        // Simulated domains always take the interrupt right away.
+        Self::get_domain(domain).interrupts.push(vector);
+        true
+    }
+
+    fn update_permission(
+        domain_handle: Handle<Domain>,
+        engine: &mut MutexGuard<CapaEngine>,
+    ) -> bool {
+        // Apply the deltas in place, as the EPT would, unless there were too many to track.
+        let mut permissions = Self::get_domain(domain_handle).permissions.clone();
+        let mut changed = false;
+        match engine.get_domain_permission_deltas(domain_handle).unwrap() {
+            Some(deltas) => {
+                for delta in deltas {
+                    apply_delta(&mut permissions, delta);
+                    changed = true;
+                }
+            }
+            None => {
+                permissions = engine
+                    .get_domain_permissions(domain_handle)
+                    .unwrap()
+                    .filter(|p| !p.ops.is_empty())
+                    .map(|p| (p.start, p.end, p.ops))
+                    .collect();
+                changed = true;
+            }
+        }
+        engine.acknowledge_permission_deltas(domain_handle).unwrap();
+        if !changed {
+            return false;
+        }
+        while TLB_FLUSH[domain_handle.idx()]
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let mut domain = Self::get_domain(domain_handle);
+        domain.permissions = permissions;
+        domain.version += 1;
+        true
+    }
+
+    fn create_domain(domain: Handle<Domain>) {
+        *Self::get_domain(domain) = MockDomain::new();
+    }
+
+    fn revoke_domain(_domain: Handle<Domain>) {}
+
+    fn apply_core_update(
+        &mut self,
+        current_domain: &mut Handle<Domain>,
+        core: usize,
+        update: &CoreUpdate,
+    ) {
+        log::trace!("Core Update: {} on core {}", update, core);
+        match update {
+            CoreUpdate::TlbShootdown {
+                src_core: _,
+                domain,
+            } => {
+                self.platform_shootdown(domain, core, false);
+                TLB_FLUSH_BARRIERS[domain.idx()].wait();
+            }
+            CoreUpdate::Switch {
+                domain,
+                return_capa,
+                delta: _,
+            } => {
+                let mut next_ctx = Self::get_context(*domain, core);
+                next_ctx.gp[0] = return_capa.as_usize();
+                next_ctx.tlb_version = Self::get_domain(*domain).version;
+                *current_domain = *domain;
+            }
+            CoreUpdate::Trap {
+                manager,
+                trap,
+                info,
+            } => {
+                log::trace!("Trap {} on core {}", trap, core);
+                Self::get_domain(*manager).traps.push((*trap, *info));
+            }
+            CoreUpdate::DomainRevocation { revok, next } => {
+                self.context_interrupted(current_domain, core);
+                {
+                    let mut next_ctx = Self::get_context(*next, core);
+                    next_ctx.gp[0] = status::FAILURE;
+                    next_ctx.gp[1] = ErrorCode::DomainRevoked as usize;
+                    next_ctx.tlb_version = Self::get_domain(*next).version;
+                }
+                *current_domain = *next;
+                TLB_FLUSH_BARRIERS[revok.idx()].wait();
+                // Wait for the main thread to finish updating the engine.
+                TLB_FLUSH_BARRIERS[next.idx()].wait();
+            }
+            CoreUpdate::FastSwitchDisabled {
+                src_core,
+                owner,
+                peer,
+            } => {
+                if current_domain == peer {
+                    self.fast_switch_abort(current_domain, core);
+                }
+                Self::get_domain(*owner).fast_switch = None;
+                Self::get_domain(*peer).fast_switch = None;
+                if *src_core != core {
+                    TLB_FLUSH_BARRIERS[peer.idx()].wait();
+                }
+            }
+        }
+    }
+
+    fn platform_shootdown(&mut self, domain: &Handle<Domain>, core: usize, _trigger: bool) {
+        let version = Self::get_domain(*domain).version;
+        Self::get_context(*domain, core).tlb_version = version;
+    }
+
+    fn set_core(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: &Handle<Domain>,
+        core: usize,
+        idx: usize,
+        value: usize,
+    ) -> Result<(), Error> {
+        Self::get_context(*domain, core).regs.insert(idx, value);
+        Ok(())
+    }
+
+    fn get_core(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: &Handle<Domain>,
+        core: usize,
+        idx: usize,
+    ) -> Result<usize, Error> {
+        let ctx = Self::get_context(*domain, core);
+        Ok(ctx.regs.get(&idx).copied().ok_or(CapaError::InvalidValue)?)
+    }
+
+    fn get_core_gp(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: &Handle<Domain>,
+        core: usize,
+        result: &mut [usize],
+    ) -> Result<(), CapaError> {
+        let ctx = Self::get_context(*domain, core);
+        let len = usize::min(result.len(), NB_GP);
+        result[..len].copy_from_slice(&ctx.gp[..len]);
+        Ok(())
+    }
+
+    fn dump_in_gp(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: &mut Handle<Domain>,
+        core: usize,
+        src: &[usize],
+    ) -> Result<(), CapaError> {
+        let mut ctx = Self::get_context(*domain, core);
+        let len = usize::min(src.len(), NB_GP);
+        ctx.gp[..len].copy_from_slice(&src[..len]);
+        Ok(())
+    }
+
+    fn extract_from_gp(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: &Handle<Domain>,
+        core: usize,
+        res: &mut [(usize, usize); 6],
+    ) -> Result<(), CapaError> {
+        // Fields are passed as (field, value) pairs, starting from the third register.
+        let ctx = Self::get_context(*domain, core);
+        for (idx, pair) in res.iter_mut().enumerate() {
+            *pair = (ctx.gp[2 + 2 * idx], ctx.gp[3 + 2 * idx]);
+        }
+        Ok(())
+    }
+
+    fn check_overlaps(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: Handle<Domain>,
+        alias: usize,
+        repeat: usize,
+        region: &AccessRights,
+    ) -> bool {
+        let end = alias + repeat * (region.end - region.start);
+        Self::get_domain(domain)
+            .aliases
+            .iter()
+            .any(|&(_, gpa, size, repeat)| gpa < end && alias < gpa + size * repeat)
+    }
+
+    fn check_mappings(
+        &mut self,
+        domain: Handle<Domain>,
+        count: usize,
+        fresh: bool,
+    ) -> Result<(), CapaError> {
+        let mapped = if fresh {
+            0
+        } else {
+            Self::get_domain(domain).aliases.len()
+        };
+        if mapped + count > NB_ALIASES {
+            return Err(CapaError::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    fn map_region(
+        &mut self,
+        engine: &mut MutexGuard<CapaEngine>,
+        domain: Handle<Domain>,
+        alias: usize,
+        repeat: usize,
+        region: &AccessRights,
+    ) -> Result<(), CapaError> {
+        Self::get_domain(domain).aliases.push((
+            region.start,
+            alias,
+            region.end - region.start,
+            repeat,
+        ));
+        engine.conditional_permission_update(domain);
+        Ok(())
+    }
+
+    fn unmap_region(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: Handle<Domain>,
+        alias: usize,
+        size: usize,
+    ) -> Result<(), CapaError> {
+        Self::get_domain(domain)
+            .aliases
+            .retain(|&(_, gpa, s, repeat)| !(gpa == alias && s * repeat == size));
+        Ok(())
+    }
+
+    fn prepare_notify(domain: &Handle<Domain>, core_count: usize) {
+        TLB_FLUSH_BARRIERS[domain.idx()].set_count(core_count);
+    }
+
+    fn notify_cores(_domain: &Handle<Domain>, core_id: usize, core_map: CoreSet) {
+        IPI_ROUNDS.fetch_add(1, Ordering::SeqCst);
+        let targets: Vec<usize> = core_map.iter().filter(|&core| core != core_id).collect();
+        for &core in &targets {
+            IPIS[core].store(true, Ordering::SeqCst);
+        }
+        // The test delivers the IPIs, while this core waits for their acknowledgment.
+        EVENTS.with(|events| {
+            let events = events.borrow();
+            let events = events
+                .as_ref()
+                .expect("IPI sent outside of a simulated core");
+            events.send(Event::Ipi(targets)).unwrap();
+        });
+    }
+
+    fn acknowledge_notify(domain: &Handle<Domain>) {
+        TLB_FLUSH_BARRIERS[domain.idx()].wait();
+    }
+
+    fn finish_notify(domain: &Handle<Domain>) {
+        TLB_FLUSH[domain.idx()].store(false, Ordering::SeqCst);
+    }
+
+    fn context_interrupted(&mut self, domain: &Handle<Domain>, core: usize) {
+        Self::get_context(*domain, core).interrupted = true;
+    }
+
+    fn find_hpa(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        domain: Handle<Domain>,
+        gpa: usize,
+        size: usize,
+    ) -> Result<(usize, usize), CapaError> {
+        let domain = Self::get_domain(domain);
+        for &(hpa, seg_gpa, seg_size, repeat) in &domain.aliases {
+            if seg_gpa <= gpa && gpa < seg_gpa + seg_size * repeat {
+                let hpa = hpa + (gpa - seg_gpa) % seg_size;
+                return Ok((hpa, usize::min(size, seg_size)));
+            }
+        }
+        // Not found, we assume the result is Identity mapped.
+        Ok((gpa, size))
+    }
+
+    fn measure(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        _current_handle: Handle<Domain>,
+        _domain_handle: Handle<Domain>,
+        _core: usize,
+        _measurement: &mut [u8; 32],
+    ) -> Result<u64, CapaError> {
+        Ok(0)
+    }
+
+    fn enable_fast_switch(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        owner: Handle<Domain>,
+        peer: Handle<Domain>,
+        trampoline: usize,
+        _entry: usize,
+        _stack: usize,
+    ) -> Result<(), CapaError> {
+        if trampoline % PAGE_SIZE as usize != 0 {
+            return Err(CapaError::InvalidValue);
+        }
+        let end = trampoline + FAST_SWITCH_SIZE;
+        for domain in [owner, peer] {
+            let overlaps = Self::get_domain(domain)
+                .aliases
+                .iter()
+                .any(|&(_, gpa, size, repeat)| gpa < end && trampoline < gpa + size * repeat);
+            if overlaps {
+                return Err(CapaError::InvalidValue);
+            }
+        }
+        Self::get_domain(owner).fast_switch = Some((owner, trampoline));
+        Self::get_domain(peer).fast_switch = Some((owner, trampoline));
+        Ok(())
+    }
+
+    fn fast_switch_abort(&mut self, current: &mut Handle<Domain>, core: usize) {
+        let (owner, _) = Self::get_domain(*current)
+            .fast_switch
+            .expect("The domain is not part of a pair");
+        let mut owner_ctx = Self::get_context(owner, core);
+        owner_ctx.gp[0] = status::FAILURE;
+        owner_ctx.gp[1] = ErrorCode::FastSwitchAborted as usize;
+        *current = owner;
+    }
+}
+
+// —————————————————————————————— Simulation ——————————————————————————————— //
+
+type Command = Box<dyn FnOnce(&mut MockState, &mut Handle<Domain>) -> Box<dyn Any + Send> + Send>;
+
+/// A step of a simulated core, sent by the test.
+enum Step {
+    /// Executes a command, as if the current domain trapped into the monitor.
+    Command(Command),
+    /// Delivers the pending IPI of the core, if any.
+    Interrupt,
+}
+
+/// An event of a simulated core, reported to the test.
+enum Event {
+    /// The core sent an IPI to the given cores, and waits for them to process it.
+    Ipi(Vec<usize>),
+    /// The step is done, with the result of the command (unit for interrupts).
+    Done(Box<dyn Any + Send>),
+}
+
+struct SimulatedCore {
+    steps: Sender<Step>,
+    events: Receiver<Event>,
+    /// Whether the core is executing a step.
+    busy: Cell<bool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// A simulated machine, running the monitor on a given number of cores.
+pub struct Simulation {
+    cores: Vec<SimulatedCore>,
+    memory: Box<[Page]>,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+impl Simulation {
+    /// Boots the monitor on `nb_cores` simulated cores, with the initial domain running on all
+    /// cores.
+    pub fn new(nb_cores: usize) -> Self {
+        Self::with_flow_rules(nb_cores, &[])
+    }
+
+    /// Boots the monitor as [Simulation::new], with the given information-flow policy.
+    pub fn with_flow_rules(nb_cores: usize, rules: &[FlowRule]) -> Self {
+        assert!(0 < nb_cores && nb_cores <= NB_CORES);
+        let lock = SIMULATION_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        reset_platform(nb_cores);
+        set_flow_rules(rules);
+
+        let memory = vec![EMPTY_PAGE; NB_MEMORY_PAGES].into_boxed_slice();
+        let memory_range = memory.as_ptr_range();
+        let memory_end = memory_range.end as usize;
+        MEMORY_START.store(memory_range.start as usize, Ordering::SeqCst);
+        MEMORY_END.store(memory_end, Ordering::SeqCst);
+
+        let cores = (0..nb_cores)
+            .map(|core| SimulatedCore::spawn(core, nb_cores, memory_end))
+            .collect();
+        Simulation {
+            cores,
+            memory,
+            _lock: lock,
+        }
+    }
+
+    /// Runs `f` on the given core, as if the current domain of that core trapped into the
+    /// monitor, and returns its result once the core is done.
+    ///
+    /// Pending core updates are applied before returning, as on a VM exit.
+    pub fn on<R, F>(&self, core: usize, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut MockState, &mut Handle<Domain>) -> R + Send + 'static,
+    {
+        let command: Command = Box::new(move |state, domain| Box::new(f(state, domain)));
+        let result = self.step(core, Step::Command(command));
+        *result.downcast::<R>().unwrap()
+    }
+
+    /// Sends a step to a core and waits for it, delivering the IPIs it sends on the way.
+    fn step(&self, core: usize, step: Step) -> Box<dyn Any + Send> {
+        let simulated = &self.cores[core];
+        simulated.busy.set(true);
+        simulated.steps.send(step).expect("Simulated core is dead");
+        let result = loop {
+            match simulated.events.recv().expect("Simulated core panicked") {
+                Event::Ipi(targets) => self.deliver_ipis(&targets),
+                Event::Done(result) => break result,
+            }
+        };
+        simulated.busy.set(false);
+        result
+    }
+
+    /// Delivers an IPI to the target cores, in increasing order.
+    ///
+    /// All the targets are interrupted before waiting for any of them, as they might synchronize
+    /// with each other. Busy targets process their IPI at the end of their current step.
+    fn deliver_ipis(&self, targets: &[usize]) {
+        let idle: Vec<usize> = targets
+            .iter()
+            .copied()
+            .filter(|&core| !self.cores[core].busy.get())
+            .collect();
+        for &core in &idle {
+            self.cores[core].busy.set(true);
+            self.cores[core]
+                .steps
+                .send(Step::Interrupt)
+                .expect("Simulated core is dead");
+        }
+        for &core in &idle {
+            let simulated = &self.cores[core];
+            loop {
+                match simulated.events.recv().expect("Simulated core panicked") {
+                    Event::Ipi(targets) => self.deliver_ipis(&targets),
+                    Event::Done(_) => break,
+                }
+            }
+            simulated.busy.set(false);
+        }
+    }
+
+    /// Issues a monitor call from the current domain of the given core.
+    pub fn call(&self, core: usize, call: usize, args: [usize; 6]) -> Result<[usize; 6], Error> {
+        self.on(core, move |state, domain| {
+            let mut res = [0; 6];
+            MockMonitor::do_monitor_call(state, domain, call, &args, &mut res).map(|_| res)
+        })
+    }
+
+    /// The domain currently running on the given core.
+    pub fn current(&self, core: usize) -> Handle<Domain> {
+        self.on(core, |_, domain| *domain)
+    }
+
+    /// The domain started on all cores at boot.
+    pub fn initial_domain(&self) -> Handle<Domain> {
+        INITIAL_DOMAIN
+            .lock()
+            .expect("The monitor is not initialized")
+    }
+
+    /// Sets the value of the simulated timestamp counter.
+    pub fn set_timestamp(&self, now: u64) {
+        TIMESTAMP.store(now, Ordering::SeqCst);
+    }
+
+    /// The number of rounds of IPIs sent so far.
+    pub fn ipi_rounds(&self) -> usize {
+        IPI_ROUNDS.load(Ordering::SeqCst)
+    }
+
+    /// The simulated physical memory.
+    pub fn memory(&self) -> (usize, usize) {
+        let range = self.memory.as_ptr_range();
+        (range.start as usize, range.end as usize)
+    }
+
+    /// Returns the local capability of a region of `domain` covering [start, end).
+    pub fn find_region(
+        &self,
+        domain: Handle<Domain>,
+        start: usize,
+        end: usize,
+    ) -> Option<LocalCapa> {
+        let mut engine = CAPA_ENGINE.lock();
+        let mut token = NextCapaToken::new();
+        while let Some((info, next, idx)) = engine.enumerate(domain, token) {
+            token = next;
+            if let CapaInfo::Region {
+                start: s, end: e, ..
+            } = info
+            {
+                if s <= start && end <= e {
+                    return Some(LocalCapa::new(idx));
+                }
+            }
+        }
+        None
+    }
+
+    /// Checks the invariants of the monitor, panics if one of them does not hold:
+    ///
+    /// - The internal structures of the engine are consistent.
+    /// - The permissions installed for each domain match the ones computed by the engine.
+    /// - The memory of a confidential region is not accessible to any other domain.
+    pub fn check_invariants(&self) {
+        let mut engine = CAPA_ENGINE.lock();
+        engine.check_invariants();
+        let mut domains = Vec::new();
+        let mut token = NextCapaToken::new();
+        while let Some((domain, next)) = engine.enumerate_domains(token) {
+            token = next;
+            let expected = coalesce(
+                engine
+                    .get_domain_permissions(domain)
+                    .unwrap()
+                    .filter(|p| !p.ops.is_empty())
+                    .map(|p| (p.start, p.end, p.ops)),
+            );
+            let installed = coalesce(MockState::get_domain(domain).permissions.iter().copied());
+            assert_eq!(
+                installed, expected,
+                "Stale permissions for domain {}",
+                domain
+            );
+            domains.push((domain, installed));
+        }
+
+        for (domain, permissions) in &domains {
+            let mut token = NextCapaToken::new();
+            while let Some((info, next, idx)) = engine.enumerate(*domain, token) {
+                token = next;
+                let CapaInfo::Region {
+                    start,
+                    end,
+                    unique: true,
+                    ..
+                } = info
+                else {
+                    continue;
+                };
+                // Parts of the region might have been carved, only check the accessible ones.
+                let accessible = permissions.iter().filter_map(|&(s, e, _)| {
+                    Some((s.max(start), e.min(end))).filter(|(s, e)| s < e)
+                });
+                for (s, e) in accessible {
+                    for (other, other_permissions) in &domains {
+                        if other == domain {
+                            continue;
+                        }
+                        assert!(
+                            !other_permissions
+                                .iter()
+                                .any(|&(os, oe, _)| os < e && s < oe),
+                            "Confidential region {} of domain {} is accessible to domain {}",
+                            idx,
+                            domain,
+                            other
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        for core in &mut self.cores {
+            let (dead, _) = mpsc::channel();
+            core.steps = dead;
+            if let Some(thread) = core.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl SimulatedCore {
+    fn spawn(core: usize, nb_cores: usize, memory_end: usize) -> Self {
+        let (steps, step_rx) = mpsc::channel::<Step>();
+        let (event_tx, events) = mpsc::channel();
+        let (booted_tx, booted) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(format!("core-{}", core))
+            .stack_size(CORE_STACK_SIZE)
+            .spawn(move || {
+                CORE_ID.with(|id| id.set(core));
+                EVENTS.with(|events| *events.borrow_mut() = Some(event_tx.clone()));
+                let mut state = MockState {};
+                let mut domain = if core == 0 {
+                    *CAPA_ENGINE.lock() = CapaEngine::new();
+                    MockMonitor::do_init(&mut state, boot_manifest(nb_cores, memory_end))
+                } else {
+                    MockMonitor::start_initial_domain(&mut state)
+                };
+                booted_tx.send(()).unwrap();
+                // The core only runs when the test sends a step, until the simulation is dropped.
+                while let Ok(step) = step_rx.recv() {
+                    let result: Box<dyn Any + Send> = match step {
+                        Step::Command(command) => command(&mut state, &mut domain),
+                        Step::Interrupt => Box::new(()),
+                    };
+                    // Pending updates are applied on the way out of the monitor, including the
+                    // ones of an IPI received while busy.
+                    IPIS[core].store(false, Ordering::SeqCst);
+                    MockMonitor::expire_lease_deadlines(&mut state, &mut domain);
+                    MockMonitor::apply_core_updates(&mut state, &mut domain, core);
+                    event_tx.send(Event::Done(result)).unwrap();
+                }
+            })
+            .expect("Failed to spawn simulated core");
+        // Cores boot one after the other, the BSP first.
+        booted.recv().expect("Simulated core failed to boot");
+        SimulatedCore {
+            steps,
+            events,
+            busy: Cell::new(false),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Applies a permission delta to the sorted list of permissions of a domain.
+fn apply_delta(permissions: &mut Vec<(usize, usize, MemOps)>, delta: PermissionDelta) {
+    let (start, end) = (delta.start(), delta.end());
+    let mut result = Vec::with_capacity(permissions.len() + 2);
+    for &(s, e, ops) in permissions.iter() {
+        if e <= start || end <= s {
+            result.push((s, e, ops));
+            continue;
+        }
+        match delta {
+            PermissionDelta::Added { .. } => panic!("Added range was already mapped"),
+            PermissionDelta::Removed { .. } | PermissionDelta::Changed { .. } => (),
+        }
+        // Keep the parts outside of the delta
+        if s < start {
+            result.push((s, start, ops));
+        }
+        if end < e {
+            result.push((end, e, ops));
+        }
+    }
+    match delta {
+        PermissionDelta::Added { ops, .. } | PermissionDelta::Changed { ops, .. } => {
+            result.push((start, end, ops))
+        }
+        PermissionDelta::Removed { .. } => (),
+    }
+    result.sort_by_key(|&(s, _, _)| s);
+    *permissions = result;
+}
+
+/// Merges contiguous ranges with the same permissions.
+fn coalesce(
+    permissions: impl Iterator<Item = (usize, usize, MemOps)>,
+) -> Vec<(usize, usize, MemOps)> {
+    let mut result: Vec<(usize, usize, MemOps)> = Vec::new();
+    for (start, end, ops) in permissions {
+        match result.last_mut() {
+            Some(last) if last.1 == start && last.2 == ops => last.1 = end,
+            _ => result.push((start, end, ops)),
+        }
+    }
+    result
+}
+
+/// The manifest passed by stage 1, re-used by all simulations.
+static mut MANIFEST: Manifest = Manifest {
+    cr3: 0,
+    poffset: 0,
+    voffset: 0,
+    info: GuestInfo::default_config(),
+    vga: VgaInfo::no_vga(),
+    iommu: 0,
+    smp: Smp {
+        smp: 0,
+        mailbox: 0,
+        wakeup_cr3: 0,
+    },
+    pools: Pools::no_pools(),
+    flow: FlowPolicy::no_policy(),
+};
+
+/// The manifest passed by stage 1, with all the memory up to the simulated memory.
+fn boot_manifest(nb_cores: usize, memory_end: usize) -> &'static Manifest {
+    // SAFETY: the manifest is only read by `do_init`, the previous simulation is done with it by
+    // the time we boot.
+    unsafe {
+        MANIFEST.poffset = memory_end as u64;
+        MANIFEST.smp.smp = nb_cores;
+        MANIFEST.pools = boot_pools(EngineConfig::DEFAULT);
+        &MANIFEST
+    }
+}
+
+/// Sets the information-flow policy passed by stage 1 to the next simulation.
+fn set_flow_rules(rules: &[FlowRule]) {
+    // SAFETY: the manifest is only read by `do_init`, and the previous simulation is done with it.
+    unsafe {
+        MANIFEST.flow = FlowPolicy::no_policy();
+        MANIFEST.flow.rules[..rules.len()].copy_from_slice(rules);
+        MANIFEST.flow.nb_rules = rules.len() as u64;
+    }
+}
+
+/// The pools reserved by stage 1, the previous simulation is done with them by the time we boot.
+fn boot_pools(config: EngineConfig) -> Pools {
+    let size = config.memory_size();
+    let start =
+        *POOLS.get_or_init(|| Box::leak(vec![0u8; size].into_boxed_slice()).as_ptr() as usize);
+    Pools {
+        start: start as u64,
+        size: size as u64,
+        nb_domains: config.nb_domains as u64,
+        nb_capas_per_domain: config.nb_capas_per_domain as u64,
+        nb_regions: config.nb_regions as u64,
+        nb_tracker: config.nb_tracker as u64,
+    }
+}
+
+/// Resets the global state of the monitor and platform.
+fn reset_platform(nb_cores: usize) {
+    NB_SIMULATED_CORES.store(nb_cores, Ordering::SeqCst);
+    TIMESTAMP.store(0, Ordering::SeqCst);
+    LEASE_DEADLINE.store(u64::MAX, Ordering::SeqCst);
+    IPI_ROUNDS.store(0, Ordering::SeqCst);
+    for domain in &DOMAINS {
+        *domain.lock() = MockDomain::new();
+    }
+    for contexts in &CONTEXTS {
+        for context in contexts {
+            *context.lock() = MockContext::new();
+        }
+    }
+    for flag in TLB_FLUSH.iter().chain(IPIS.iter()).chain(NOTIFY.iter()) {
+        flag.store(false, Ordering::SeqCst);
+    }
+    for updates in &CORE_UPDATES {
+        while updates.lock().pop().is_some() {}
+    }
+    *INITIAL_DOMAIN.lock() = None;
+    *IO_DOMAIN.lock() = None;
+}
@@ -1,17 +1,42 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 use attestation::hashing::hash_region;
 use attestation::signature;
 use capa_engine::config::NB_CORES;
-use capa_engine::utils::BitmapIterator;
+use capa_engine::flow::{self, Policy, Rule};
+use capa_engine::pool::PoolMemory;
 use capa_engine::{
-    permission, AccessRights, Buffer, CapaEngine, CapaError, CapaInfo, Domain, Handle, LocalCapa,
-    MemOps, NextCapaToken, MEMOPS_ALL, MEMOPS_EXTRAS,
+    permission, AccessRights, Buffer, CapaEngine, CapaError, CapaInfo, CoreSet, Domain,
+    EngineConfig, Handle, Lease, LeaseCondition, LocalCapa, MemOps, Message, MgmtRights,
+    NextCapaToken, Quota, RevokedRegion, MEMOPS_ALL, MEMOPS_EXTRAS,
+};
+use monitor_abi::ring::{self, Completion, Header, Submission};
+use monitor_abi::transaction::{self, Operation};
+use monitor_abi::{
+    channel, labels, lease, notification, report, revocation, status, Args, Results, NB_ARGS,
+    NB_RESULTS,
 };
 use spin::{Mutex, MutexGuard};
-use stage_two_abi::Manifest;
+use stage_two_abi::{FlowPolicy, Manifest, Pools, MAX_FLOW_RULES};
 
 use crate::arch::cpuid;
-use crate::attestation_domain::calculate_attestation_hash;
+use crate::attestation_domain::{attest_domain, attestation_certificate, calculate_attestation_hash};
 use crate::calls;
+use crate::error::{Error, ErrorCode, ERROR_VERSION};
+
+// The lease trap of the ABI is the one raised by the engine.
+const _: () = assert!(lease::TRAP as u64 == permission::trap_bits::LEASE_EXPIRED);
+const _: () = assert!(revocation::TRAP as u64 == permission::trap_bits::REGION_REVOKED);
+const _: () = assert!(channel::TRAP as u64 == permission::trap_bits::CHANNEL_MESSAGE);
+const _: () = assert!(channel::NB_WORDS == capa_engine::config::NB_MESSAGE_WORDS);
+const _: () = assert!(labels::INITIAL as u64 == flow::labels::INITIAL);
+const _: () = assert!(labels::SEALED as u64 == flow::labels::SEALED);
+const _: () = assert!(MAX_FLOW_RULES == capa_engine::config::NB_FLOW_RULES);
+// The notification traps are consecutive, starting with the lease one.
+const _: () = assert!(
+    NOTIFICATION_TRAPS >> permission::trap_bits::LEASE_EXPIRED.trailing_zeros()
+        == (1 << NB_NOTIFICATIONS) - 1
+);
 
 // ———————————————————————————————— Updates ————————————————————————————————— //
 /// Per-core updates
@@ -19,6 +44,8 @@ use crate::calls;
 pub enum CoreUpdate {
     TlbShootdown {
         src_core: usize,
+        /// The peer of a fast switch pair shares the core with its owner.
+        domain: Handle<Domain>,
     },
     Switch {
         domain: Handle<Domain>,
@@ -34,6 +61,123 @@ pub enum CoreUpdate {
         revok: Handle<Domain>,
         next: Handle<Domain>,
     },
+    FastSwitchDisabled {
+        src_core: usize,
+        owner: Handle<Domain>,
+        peer: Handle<Domain>,
+    },
+}
+
+/// Maximum number of TLB shootdowns sharing a round of IPIs.
+const MAX_BATCHED_SHOOTDOWNS: usize = 16;
+
+/// The TLB shootdowns of consecutive permission updates, sent with a single round of IPIs.
+pub struct Shootdowns {
+    batch: [Option<(Handle<Domain>, CoreSet)>; MAX_BATCHED_SHOOTDOWNS],
+    len: usize,
+}
+
+impl Shootdowns {
+    const fn new() -> Self {
+        Shootdowns {
+            batch: [None; MAX_BATCHED_SHOOTDOWNS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, domain: Handle<Domain>, core_map: CoreSet) {
+        self.batch[self.len] = Some((domain, core_map));
+        self.len += 1;
+    }
+
+    fn contains(&self, domain: Handle<Domain>) -> bool {
+        self.iter().any(|(other, _)| other == domain)
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == MAX_BATCHED_SHOOTDOWNS
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Handle<Domain>, CoreSet)> + '_ {
+        self.batch[..self.len].iter().flatten().copied()
+    }
+}
+
+/// The platform side of a transaction operation, carried out once the transaction commits.
+#[derive(Debug, Clone, Copy)]
+pub enum Deferred {
+    /// The platform state of the domain is only reset by the commit.
+    Created(Handle<Domain>),
+    Map {
+        domain: Handle<Domain>,
+        alias: usize,
+        repeat: usize,
+        region: AccessRights,
+    },
+    Context {
+        domain: Handle<Domain>,
+        core: usize,
+    },
+    Measure {
+        domain: Handle<Domain>,
+        switch: Handle<Domain>,
+    },
+}
+
+/// A submission ring registered by a domain, see [calls::REGISTER_RING].
+///
+/// The ring is stored in the platform data of its domain, see [PlatformState::get_ring].
+#[derive(Debug, Clone, Copy)]
+pub struct Ring {
+    /// The ring is dropped once its domain is revoked, even if the data is reused.
+    domain: Handle<Domain>,
+    addr: usize,
+    entries: usize,
+    is_gva: bool,
+}
+
+/// The traps raised as notifications, see [calls::POLL_NOTIFICATIONS].
+const NOTIFICATION_TRAPS: u64 = permission::trap_bits::LEASE_EXPIRED
+    | permission::trap_bits::REGION_REVOKED
+    | permission::trap_bits::CHANNEL_MESSAGE;
+
+/// Number of notification traps, i.e. of information words returned when polling.
+const NB_NOTIFICATIONS: usize = 3;
+
+/// The notifications pending for a domain, see [calls::POLL_NOTIFICATIONS].
+///
+/// The notifications are stored in the platform data of their domain, see
+/// [PlatformState::with_notifications]. They are signaled with an interrupt on the cores running
+/// the domain if it registered a vector, see [calls::SET_NOTIFICATION_VECTOR].
+#[derive(Debug, Clone, Copy)]
+pub struct Notifications {
+    /// The notifications are dropped once their domain is revoked, even if the data is reused.
+    domain: Handle<Domain>,
+    /// The interrupt vector, or [notification::NO_VECTOR] if the notifications are only polled.
+    vector: u8,
+    /// Trap bits of the pending notifications.
+    pending: u64,
+    /// The information of the last notification of each trap, in the order of the trap bits.
+    info: [usize; NB_NOTIFICATIONS],
+    /// Whether the pending notifications are yet to be signaled.
+    undelivered: bool,
+}
+
+impl Notifications {
+    fn new(domain: Handle<Domain>) -> Self {
+        Notifications {
+            domain,
+            vector: notification::NO_VECTOR as u8,
+            pending: 0,
+            info: [0; NB_NOTIFICATIONS],
+            undelivered: false,
+        }
+    }
+
+    /// The index of the information of a notification trap.
+    fn index(trap: u64) -> usize {
+        (trap.trailing_zeros() - permission::trap_bits::LEASE_EXPIRED.trailing_zeros()) as usize
+    }
 }
 
 // ————————————————————————— Statics & Backend Data ————————————————————————— //
@@ -41,9 +185,14 @@ pub static CAPA_ENGINE: Mutex<CapaEngine> = Mutex::new(CapaEngine::new());
 pub static IO_DOMAIN: Mutex<Option<LocalCapa>> = Mutex::new(None);
 pub static INITIAL_DOMAIN: Mutex<Option<Handle<Domain>>> = Mutex::new(None);
 pub static CORE_UPDATES: [Mutex<Buffer<CoreUpdate>>; NB_CORES] = [EMPTY_UPDATE_BUFFER; NB_CORES];
+/// The earliest lease deadline, so that monitor entries only lock the engine once it is past.
+pub static LEASE_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+/// Set for the cores that must signal the notifications of their current domain.
+pub static NOTIFY: [AtomicBool; NB_CORES] = [NO_NOTIFY; NB_CORES];
 
 // —————————————————————— Constants for initialization —————————————————————— //
 const EMPTY_UPDATE_BUFFER: Mutex<Buffer<CoreUpdate>> = Mutex::new(Buffer::new());
+const NO_NOTIFY: AtomicBool = AtomicBool::new(false);
 const TPM_TIS_ADDR: usize = 0xFED4_000;
 const TPM_TIS_SIZE: usize = 0x5000;
 
@@ -60,9 +209,10 @@ pub trait PlatformState {
         len: usize,
         is_gva: bool,
     ) -> Option<usize>;
-    fn remap_core_bitmap(bitmap: u64) -> u64;
     fn remap_core(core: usize) -> usize;
     fn max_cpus() -> usize;
+    /// The value of the timestamp counter, used for lease deadlines.
+    fn timestamp() -> u64;
     fn create_context(
         &mut self,
         engine: MutexGuard<CapaEngine>,
@@ -71,12 +221,39 @@ pub trait PlatformState {
         core: usize,
     ) -> Result<(), CapaError>;
 
+    /// Checks that a context of the domain can be created on the core.
+    fn check_context(&mut self, domain: Handle<Domain>, core: usize) -> Result<(), CapaError>;
+
     fn platform_init_io_mmu(&self, addr: usize);
 
+    /// Allocates the platform per-domain state from the pool memory.
+    fn platform_init_pools(
+        &self,
+        config: &EngineConfig,
+        memory: &mut PoolMemory,
+    ) -> Result<(), CapaError>;
+
     fn get_domain(domain: Handle<Domain>) -> MutexGuard<'static, Self::DomainData>;
 
     fn get_context(domain: Handle<Domain>, core: usize) -> MutexGuard<'static, Self::Context>;
 
+    /// The submission ring registered in the data of the domain, if any.
+    fn get_ring(domain: Handle<Domain>) -> Option<Ring>;
+
+    fn set_ring(domain: Handle<Domain>, ring: Option<Ring>);
+
+    /// Runs `f` on the notifications stored in the data of the domain, see [Notifications].
+    fn with_notifications<R>(
+        domain: Handle<Domain>,
+        f: impl FnOnce(&mut Option<Notifications>) -> R,
+    ) -> R;
+
+    /// Raises the notification interrupt in the context of the domain, running on the core.
+    ///
+    /// Returns false if the domain can't take the interrupt yet, the platform then sets [NOTIFY]
+    /// for the core once it can.
+    fn inject_notification(&mut self, domain: Handle<Domain>, core: usize, vector: u8) -> bool;
+
     fn update_permission(domain: Handle<Domain>, engine: &mut MutexGuard<CapaEngine>) -> bool;
 
     fn create_domain(domain: Handle<Domain>);
@@ -99,7 +276,7 @@ pub trait PlatformState {
         core: usize,
         idx: usize,
         value: usize,
-    ) -> Result<(), CapaError>;
+    ) -> Result<(), Error>;
 
     fn get_core(
         &mut self,
@@ -107,7 +284,7 @@ pub trait PlatformState {
         domain: &Handle<Domain>,
         core: usize,
         idx: usize,
-    ) -> Result<usize, CapaError>;
+    ) -> Result<usize, Error>;
 
     fn get_core_gp(
         &mut self,
@@ -142,6 +319,15 @@ pub trait PlatformState {
         region: &AccessRights,
     ) -> bool;
 
+    /// Checks that `count` more regions can be mapped in the domain, or in a newly created one if
+    /// `fresh`, as its platform state is only reset once the domain is committed.
+    fn check_mappings(
+        &mut self,
+        domain: Handle<Domain>,
+        count: usize,
+        fresh: bool,
+    ) -> Result<(), CapaError>;
+
     fn map_region(
         &mut self,
         engine: &mut MutexGuard<CapaEngine>,
@@ -162,7 +348,7 @@ pub trait PlatformState {
     /// This assumes that the engine is locked!
     fn prepare_notify(domain: &Handle<Domain>, core_count: usize);
 
-    fn notify_cores(domain: &Handle<Domain>, core_id: usize, core_map: usize);
+    fn notify_cores(domain: &Handle<Domain>, core_id: usize, core_map: CoreSet);
 
     fn acknowledge_notify(domain: &Handle<Domain>);
 
@@ -186,6 +372,21 @@ pub trait PlatformState {
         core: usize,
         measurement: &mut [u8; 32],
     ) -> Result<u64, CapaError>;
+
+    /// Lets the pair switch without exits on the current core, through a trampoline mapped at
+    /// `trampoline` in both domains. The owner is entered at `entry` with `stack`.
+    fn enable_fast_switch(
+        &mut self,
+        engine: &mut MutexGuard<CapaEngine>,
+        owner: Handle<Domain>,
+        peer: Handle<Domain>,
+        trampoline: usize,
+        entry: usize,
+        stack: usize,
+    ) -> Result<(), CapaError>;
+
+    /// Moves the peer of a pair running on the core back to the entry point of its owner.
+    fn fast_switch_abort(&mut self, current: &mut Handle<Domain>, core: usize);
 }
 
 pub trait Monitor<T: PlatformState + 'static> {
@@ -204,6 +405,18 @@ pub trait Monitor<T: PlatformState + 'static> {
     fn do_init(state: &mut T, manifest: &'static Manifest) -> Handle<Domain> {
         // No one else is running yet
         let mut engine = CAPA_ENGINE.lock();
+        let config = engine_config(&manifest.pools);
+        // SAFETY: stage 1 reserved the pool memory for the monitor.
+        let mut memory =
+            unsafe { PoolMemory::new(manifest.pools.start as usize, manifest.pools.size as usize) };
+        engine
+            .init(config, &mut memory)
+            .expect("Not enough memory for the engine pools");
+        let policy = flow_policy(&manifest.flow).expect("Invalid information-flow policy");
+        engine.load_flow_policy(policy).unwrap();
+        state
+            .platform_init_pools(&config, &mut memory)
+            .expect("Not enough memory for the platform pools");
         let domain = engine
             .create_manager_domain(permission::monitor_inter_perm::ALL)
             .unwrap();
@@ -324,6 +537,97 @@ pub trait Monitor<T: PlatformState + 'static> {
         Ok(engine.get_domain_permission(*current, bitmap) as usize)
     }
 
+    /// Sets the word `word` of the cores a child domain is allowed to run on, that is whether it
+    /// can run on cores `64 * word` to `64 * word + 63`.
+    fn do_set_core_map(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        domain: LocalCapa,
+        word: usize,
+        value: u64,
+    ) -> Result<(), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        Self::update_core_map(&mut engine, *current, domain, word, value)?;
+        Self::apply_updates(state, &mut engine);
+        Ok(())
+    }
+
+    fn update_core_map(
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        domain: LocalCapa,
+        word: usize,
+        value: u64,
+    ) -> Result<(), CapaError> {
+        if word >= CoreSet::NB_WORDS {
+            return Err(CapaError::InvalidValue);
+        }
+        let mut cores = engine.get_child_core_map(current, domain)?;
+        for bit in 0..64 {
+            // Cores beyond the ones we support can't be used anyway.
+            let core = T::remap_core(word * 64 + bit);
+            if core >= NB_CORES {
+                continue;
+            }
+            if value & (1 << bit) != 0 {
+                cores.insert(core);
+            } else {
+                cores.remove(core);
+            }
+        }
+        engine.set_child_core_map(current, domain, cores)
+    }
+
+    fn do_set_quota(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        domain: LocalCapa,
+        quota: Quota,
+    ) -> Result<(), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        engine.set_child_quota(*current, domain, quota)
+    }
+
+    fn do_set_labels(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        domain: LocalCapa,
+        labels: u64,
+    ) -> Result<u64, CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        engine.set_child_labels(*current, domain, labels)?;
+        engine.get_child_labels(*current, domain)
+    }
+
+    fn do_enable_fast_switch(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        capa: LocalCapa,
+        trampoline: usize,
+        entry: usize,
+        stack: usize,
+    ) -> Result<(), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        let peer = engine.enable_fast_switch(*current, cpuid(), capa)?;
+        let result =
+            state.enable_fast_switch(&mut engine, *current, peer, trampoline, entry, stack);
+        if result.is_err() {
+            engine.disable_fast_switch(*current).unwrap();
+            Self::apply_updates(state, &mut engine);
+        }
+        result
+    }
+
+    fn do_disable_fast_switch(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+    ) -> Result<(), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        engine.disable_fast_switch(*current)?;
+        Self::apply_updates(state, &mut engine);
+        Ok(())
+    }
+
     fn do_set_core(
         state: &mut T,
         current: &mut Handle<Domain>,
@@ -331,18 +635,13 @@ pub trait Monitor<T: PlatformState + 'static> {
         core: usize,
         idx: usize,
         value: usize,
-    ) -> Result<(), CapaError> {
+    ) -> Result<(), Error> {
         let mut engine = Self::lock_engine(state, current);
         // Check the core is valid.
-        let cores = engine.get_child_permission(
-            *current,
-            domain,
-            permission::PermissionIndex::AllowedCores,
-        )?;
-        if cores & (1 << core) == 0 {
-            return Err(CapaError::InvalidCore);
+        if !engine.get_child_core_map(*current, domain)?.contains(core) {
+            return Err(CapaError::InvalidCore.into());
         }
-        let domain = engine.get_domain_capa(*current, domain)?;
+        let domain = engine.get_child_domain(*current, domain, MgmtRights::WRITE_REGS)?;
         state.set_core(&mut engine, &domain, core, idx, value)
     }
 
@@ -352,18 +651,13 @@ pub trait Monitor<T: PlatformState + 'static> {
         domain: LocalCapa,
         core: usize,
         idx: usize,
-    ) -> Result<usize, CapaError> {
+    ) -> Result<usize, Error> {
         let mut engine = Self::lock_engine(state, current);
         // Check the core is valid.
-        let cores = engine.get_child_permission(
-            *current,
-            domain,
-            permission::PermissionIndex::AllowedCores,
-        )?;
-        if cores & (1 << core) == 0 {
-            return Err(CapaError::InvalidCore);
+        if !engine.get_child_core_map(*current, domain)?.contains(core) {
+            return Err(CapaError::InvalidCore.into());
         }
-        let domain = engine.get_domain_capa(*current, domain)?;
+        let domain = engine.get_child_domain(*current, domain, MgmtRights::READ_REGS)?;
         state.get_core(&mut engine, &domain, core, idx)
     }
 
@@ -374,15 +668,10 @@ pub trait Monitor<T: PlatformState + 'static> {
         core: usize,
     ) -> Result<(), CapaError> {
         let mut engine = Self::lock_engine(state, current);
-        let core_map = engine.get_child_permission(
-            *current,
-            domain,
-            permission::PermissionIndex::AllowedCores,
-        )?;
-        if core_map & (1 << core) == 0 {
+        if !engine.get_child_core_map(*current, domain)?.contains(core) {
             return Err(CapaError::InvalidCore);
         }
-        let domain = engine.get_domain_capa(*current, domain)?;
+        let domain = engine.get_child_domain(*current, domain, MgmtRights::READ_REGS)?;
         let result: &mut [usize] = &mut [0; 15];
         state.get_core_gp(&mut engine, &domain, core, result)?;
         state.dump_in_gp(&mut engine, current, cpuid(), &result)?;
@@ -394,19 +683,14 @@ pub trait Monitor<T: PlatformState + 'static> {
         current: &mut Handle<Domain>,
         domain: LocalCapa,
         core: usize,
-    ) -> Result<(), CapaError> {
+    ) -> Result<(), Error> {
         let mut engine = Self::lock_engine(state, current);
-        let core_map = engine.get_child_permission(
-            *current,
-            domain,
-            permission::PermissionIndex::AllowedCores,
-        )?;
-        if core_map & (1 << core) == 0 {
-            return Err(CapaError::InvalidCore);
+        if !engine.get_child_core_map(*current, domain)?.contains(core) {
+            return Err(CapaError::InvalidCore.into());
         }
         let mut values: [(usize, usize); 6] = [(0, 0); 6];
         state.extract_from_gp(&mut engine, current, cpuid(), &mut values)?;
-        let domain = engine.get_domain_capa(*current, domain)?;
+        let domain = engine.get_child_domain(*current, domain, MgmtRights::WRITE_REGS)?;
         for e in values {
             // Signal to skip.
             if e.0 == !(0 as usize) {
@@ -427,23 +711,34 @@ pub trait Monitor<T: PlatformState + 'static> {
         //TODO: fix that.
         let capa = engine.seal(*current, core, domain)?;
         if let Ok(domain_capa) = engine.get_domain_capa(*current, domain) {
-            // Tyche's capability-hashing attestation method.
-            calculate_attestation_hash(&mut engine, domain_capa);
-
-            // Argos attestation measuring enclave memory directly.
             let switch_capa = engine.get_switch_capa(*current, capa)?;
-            let measurement: &mut [u8; 32] = &mut [0u8; 32];
-
-            // TODO: Get core id of domain that we're switching to. For now, 1 for qemu, 2 for hw (optiplex 7050)
-            let core = if cfg!(feature = "bare_metal") { 2 } else { 1 };
-            state.measure(&mut engine, *current, switch_capa, core, measurement)?;
-            engine.argos_set_measurement(domain_capa, measurement);
+            Self::measure_sealed(state, &mut engine, *current, domain_capa, switch_capa)?;
         }
 
         Self::apply_updates(state, &mut engine);
         Ok(capa)
     }
 
+    fn measure_sealed(
+        state: &mut T,
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        domain_capa: Handle<Domain>,
+        switch_capa: Handle<Domain>,
+    ) -> Result<(), CapaError> {
+        // Tyche's capability-hashing attestation method.
+        calculate_attestation_hash(engine, domain_capa);
+
+        // Argos attestation measuring enclave memory directly.
+        let measurement: &mut [u8; 32] = &mut [0u8; 32];
+
+        // TODO: Get core id of domain that we're switching to. For now, 1 for qemu, 2 for hw (optiplex 7050)
+        let core = if cfg!(feature = "bare_metal") { 2 } else { 1 };
+        state.measure(engine, current, switch_capa, core, measurement)?;
+        engine.argos_set_measurement(domain_capa, measurement);
+        Ok(())
+    }
+
     fn do_segment_region(
         state: &mut T,
         current: &mut Handle<Domain>,
@@ -452,6 +747,21 @@ pub trait Monitor<T: PlatformState + 'static> {
         start: usize,
         end: usize,
         prot: usize,
+    ) -> Result<(LocalCapa, LocalCapa), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        let capas = Self::segment_region(&mut engine, *current, capa, is_shared, start, end, prot)?;
+        Self::apply_updates(state, &mut engine);
+        Ok(capas)
+    }
+
+    fn segment_region(
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        capa: LocalCapa,
+        is_shared: bool,
+        start: usize,
+        end: usize,
+        prot: usize,
     ) -> Result<(LocalCapa, LocalCapa), CapaError> {
         let prot = MemOps::from_usize(prot)?;
         log::trace!("segment_region: {:x}->{:x}, {:?}", start, end, prot);
@@ -459,19 +769,17 @@ pub trait Monitor<T: PlatformState + 'static> {
             log::error!("Invalid prots for segment region {:?}", prot);
             return Err(CapaError::InvalidOperation);
         }
-        let mut engine = Self::lock_engine(state, current);
         let access = AccessRights {
             start,
             end,
             ops: prot,
         };
         let to_send = if is_shared {
-            engine.alias_region(*current, capa, access)?
+            engine.alias_region(current, capa, access)?
         } else {
-            engine.carve_region(*current, capa, access)?
+            engine.carve_region(current, capa, access)?
         };
-        let to_revoke = engine.create_revoke_capa(*current, to_send)?;
-        Self::apply_updates(state, &mut engine);
+        let to_revoke = engine.create_revoke_capa(current, to_send)?;
         Ok((to_send, to_revoke))
     }
 
@@ -484,8 +792,8 @@ pub trait Monitor<T: PlatformState + 'static> {
         let mut engine = Self::lock_engine(state, current);
         // Send is not allowed for region capa.
         // Use do_send_region instead.
-        match engine.get_region_capa(*current, capa)? {
-            Some(_) => return Err(CapaError::InvalidCapa),
+        match engine.get_region_capa(*current, capa) {
+            Ok(Some(_)) => return Err(CapaError::InvalidCapa),
             _ => {}
         }
         engine.send(*current, capa, to)?;
@@ -502,34 +810,23 @@ pub trait Monitor<T: PlatformState + 'static> {
         is_repeat: bool,
         size: usize,
         extra_rights: usize,
+        lease: Option<LeaseCondition>,
     ) -> Result<(), CapaError> {
         let mut engine = Self::lock_engine(state, current);
-        let flags = MemOps::from_usize(extra_rights)?;
-        if !flags.is_empty() && !flags.is_only_hcv() {
-            log::error!("Invalid send region flags received: {:?}", flags);
-            return Err(CapaError::InvalidPermissions);
-        }
-        // Get the capa first.
-        let region_info = engine
-            .get_region_capa(*current, capa)?
-            .ok_or(CapaError::InvalidCapa)?
-            .get_access_rights();
-        let repeat = if is_repeat {
-            let region_size = region_info.end - region_info.start;
-            if size == 0 || (size % region_size) != 0 {
-                return Err(CapaError::InvalidValue);
-            }
-            size / region_size
-        } else {
-            // Not a repeat, spans the entire thing.
-            1
-        };
+        let (region_info, repeat, flags) =
+            Self::region_to_send(&mut engine, *current, capa, is_repeat, size, extra_rights)?;
+        let lease = lease.map(|condition| Lease {
+            condition,
+            alias,
+            size: repeat * (region_info.end - region_info.start),
+        });
         // Check for an overlap first.
         {
             let target = engine.get_domain_capa(*current, to)?;
             if state.check_overlaps(&mut engine, target, alias, repeat, &region_info) {
                 return Err(CapaError::AlreadyAliased);
             }
+            state.check_mappings(target, 1, false)?;
         }
 
         if !flags.is_empty() {
@@ -550,9 +847,9 @@ pub trait Monitor<T: PlatformState + 'static> {
             // };
             let hash = None;
             let opt_flags = if flags.is_empty() { None } else { Some(flags) };
-            let _ = engine.send_with_flags(*current, capa, to, opt_flags, hash);
+            let _ = engine.send_with_flags(*current, capa, to, opt_flags, hash, lease);
         } else {
-            let _ = engine.send(*current, capa, to)?;
+            let _ = engine.send_with_flags(*current, capa, to, None, None, lease)?;
         }
         {
             let target = engine.get_domain_capa(*current, to)?;
@@ -562,6 +859,38 @@ pub trait Monitor<T: PlatformState + 'static> {
         Ok(())
     }
 
+    /// Returns the region to send, how many times it is repeated, and the extra send flags.
+    fn region_to_send(
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        capa: LocalCapa,
+        is_repeat: bool,
+        size: usize,
+        extra_rights: usize,
+    ) -> Result<(AccessRights, usize, MemOps), CapaError> {
+        let flags = MemOps::from_usize(extra_rights)?;
+        if !flags.is_empty() && !flags.is_only_hcv() {
+            log::error!("Invalid send region flags received: {:?}", flags);
+            return Err(CapaError::InvalidPermissions);
+        }
+        // Get the capa first.
+        let region_info = engine
+            .get_region_capa(current, capa)?
+            .ok_or(CapaError::InvalidCapa)?
+            .get_access_rights();
+        let repeat = if is_repeat {
+            let region_size = region_info.end - region_info.start;
+            if size == 0 || (size % region_size) != 0 {
+                return Err(CapaError::InvalidValue);
+            }
+            size / region_size
+        } else {
+            // Not a repeat, spans the entire thing.
+            1
+        };
+        Ok((region_info, repeat, flags))
+    }
+
     fn do_enumerate(
         state: &mut T,
         current: &mut Handle<Domain>,
@@ -604,9 +933,10 @@ pub trait Monitor<T: PlatformState + 'static> {
         state: &mut T,
         current: &mut Handle<Domain>,
         capa: LocalCapa,
+        rights: MgmtRights,
     ) -> Result<LocalCapa, CapaError> {
         let mut engine = Self::lock_engine(state, current);
-        let new_capa = engine.duplicate(*current, capa)?;
+        let new_capa = engine.duplicate_with_rights(*current, capa, rights)?;
         Self::apply_updates(state, &mut engine);
         Ok(new_capa)
     }
@@ -661,7 +991,8 @@ pub trait Monitor<T: PlatformState + 'static> {
         signature_addr: usize,
         signature_len: usize,
         is_gva: bool,
-    ) -> Result<usize, CapaError> {
+        nonce: u64,
+    ) -> Result<(usize, usize), CapaError> {
         let engine = Self::lock_engine(state, domain_handle);
         let attestation_buff = T::find_buff(state, &engine, *domain_handle, attestation_addr, attestation_len, is_gva);
         let Some(attestation_buff) = attestation_buff else {
@@ -675,8 +1006,17 @@ pub trait Monitor<T: PlatformState + 'static> {
             return Err(CapaError::InsufficientPermissions);
         };
         let signature_buff = unsafe { core::slice::from_raw_parts_mut(signature_buff as *mut u8, signature_len) };
-        engine.serialize_attestation(attestation_buff);    
-        Ok(wolftpm_sys::hash_and_sign(attestation_buff, signature_buff) as usize)
+        // The nonce follows the serialized attestation, so that the signature covers both.
+        let written = engine.serialize_attestation(attestation_buff)?;
+        let nonce = nonce.to_le_bytes();
+        let Some(nonce_buff) = attestation_buff.get_mut(written..written + nonce.len()) else {
+            log::info!("No space left for the nonce in the attestation buffer");
+            return Err(CapaError::OutOfMemory);
+        };
+        nonce_buff.copy_from_slice(&nonce);
+        let attestation = &attestation_buff[..written + nonce.len()];
+        let signature_written = wolftpm_sys::hash_and_sign(attestation, signature_buff) as usize;
+        Ok((signature_written, attestation.len()))
     }
     
     fn do_get_signing_key(
@@ -696,6 +1036,57 @@ pub trait Monitor<T: PlatformState + 'static> {
         Ok(wolftpm_sys::get_signing_key(buff))
     }
 
+    fn do_enclave_attestation(
+        state: &mut T,
+        domain_handle: &mut Handle<Domain>,
+        nonce: usize,
+        mode: usize,
+        res: &mut [usize; 6],
+    ) -> Result<(), Error> {
+        let mut engine = Self::lock_engine(state, domain_handle);
+        let attested = attest_domain(&mut engine, *domain_handle, nonce, mode)?;
+        // The report does not fit in the result registers, it is returned in two calls.
+        let signature = attested.signed_enclave_data.as_ref();
+        let mut bytes = [0u8; 6 * 8];
+        if mode == report::CREATE {
+            bytes[..32].copy_from_slice(attested.public_key.as_ref());
+            bytes[32..].copy_from_slice(&signature[..16]);
+        } else {
+            bytes.copy_from_slice(&signature[16..]);
+        }
+        for (result, word) in res.iter_mut().zip(bytes.chunks(8)) {
+            *result = usize::from_le_bytes(word.try_into().unwrap());
+        }
+        Ok(())
+    }
+
+    fn do_read_attestation_certificate(
+        state: &mut T,
+        domain_handle: &mut Handle<Domain>,
+        addr: usize,
+        len: usize,
+        is_gva: bool,
+    ) -> Result<usize, Error> {
+        let Some(certificate) = attestation_certificate() else {
+            log::info!("No attestation key to read the certificate of");
+            return Err(ErrorCode::AttestationUnavailable.into());
+        };
+        let certificate = certificate.signature();
+        let engine = Self::lock_engine(state, domain_handle);
+        let buff = T::find_buff(state, &engine, *domain_handle, addr, len, is_gva);
+        let Some(buff) = buff else {
+            log::info!("Invalid buffer while reading the attestation certificate");
+            return Err(CapaError::InsufficientPermissions.into());
+        };
+        let buff = unsafe { core::slice::from_raw_parts_mut(buff as *mut u8, len) };
+        let Some(buff) = buff.get_mut(..certificate.len()) else {
+            log::info!("Buffer too small for the attestation certificate");
+            return Err(CapaError::OutOfMemory.into());
+        };
+        buff.copy_from_slice(certificate);
+        Ok(certificate.len())
+    }
+
     fn do_tpm_sign(
         state: &mut T,
         domain_handle: &mut Handle<Domain>,
@@ -846,6 +1237,19 @@ pub trait Monitor<T: PlatformState + 'static> {
         domain: LocalCapa,
         core: usize,
     ) -> Result<LocalCapa, CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        let (capa, domain) = Self::child_context_switch(&mut engine, *current, domain, core)?;
+        T::create_context(state, engine, *current, domain, core)?;
+        return Ok(capa);
+    }
+
+    /// Creates the switch capability to a child's context on `core`, before the context itself.
+    fn child_context_switch(
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        domain: LocalCapa,
+        core: usize,
+    ) -> Result<(LocalCapa, Handle<Domain>), CapaError> {
         if core > T::max_cpus() {
             log::error!(
                 "Attempt to set context on unallowed core {} max_cpus {}",
@@ -855,14 +1259,12 @@ pub trait Monitor<T: PlatformState + 'static> {
             return Err(CapaError::InvalidCore);
         }
 
-        let mut engine = Self::lock_engine(state, current);
-        let capa = engine.create_switch_on_core(*current, core, domain)?;
+        let capa = engine.create_switch_on_core(current, core, domain)?;
         // HACK: create switch capability that the seal operation looks for
         //       when creating a new domain
-        let _ = engine.create_switch_on_core(*current, cpuid(), domain)?;
-        let domain = engine.get_domain_capa(*current, domain)?;
-        T::create_context(state, engine, *current, domain, core)?;
-        return Ok(capa);
+        let _ = engine.create_switch_on_core(current, cpuid(), domain)?;
+        let domain = engine.get_child_domain(current, domain, MgmtRights::CONFIGURE)?;
+        Ok((capa, domain))
     }
 
     fn do_get_hpa(
@@ -875,24 +1277,471 @@ pub trait Monitor<T: PlatformState + 'static> {
         state.find_hpa(&mut engine, *current, start, size)
     }
 
-    fn do_monitor_call(
+    fn do_poll_revocation(state: &mut T, current: &mut Handle<Domain>) -> Option<RevokedRegion> {
+        let mut engine = Self::lock_engine(state, current);
+        engine.next_revocation(*current)
+    }
+
+    fn do_create_channel(
         state: &mut T,
-        domain: &mut Handle<Domain>,
-        call: usize,
-        args: &[usize; 6],
-        res: &mut [usize; 6],
-    ) -> Result<bool, CapaError> {
-        match call {
-            calls::CREATE_DOMAIN => {
-                let capa = Self::do_create_domain(state, domain)?;
-                res[0] = capa.as_usize();
-                return Ok(true);
-            }
-            calls::SEAL_DOMAIN => {
-                log::trace!("Seal Domain on core {}", cpuid());
-                let capa = Self::do_seal(state, domain, LocalCapa::new(args[0]))?;
-                res[0] = capa.as_usize();
-                return Ok(true);
+        current: &mut Handle<Domain>,
+        capa: Option<LocalCapa>,
+    ) -> Result<LocalCapa, CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        let channel = engine.create_channel(*current, capa)?;
+        Self::apply_updates(state, &mut engine);
+        Ok(channel)
+    }
+
+    fn do_channel_send(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        channel: LocalCapa,
+        region: Option<LocalCapa>,
+        words: [usize; channel::NB_WORDS],
+        alias: usize,
+    ) -> Result<(), CapaError> {
+        let mut engine = Self::lock_engine(state, current);
+        // The transferred region is mapped at `alias` in the receiver, check for an overlap first.
+        let mapping = match region {
+            Some(capa) => {
+                let (region_info, repeat, _) =
+                    Self::region_to_send(&mut engine, *current, capa, false, 0, 0)?;
+                let target = engine.get_domain_capa(*current, channel)?;
+                if state.check_overlaps(&mut engine, target, alias, repeat, &region_info) {
+                    return Err(CapaError::AlreadyAliased);
+                }
+                Some((target, region_info))
+            }
+            None => None,
+        };
+        engine.channel_send(*current, channel, words, region)?;
+        if let Some((target, region_info)) = mapping {
+            state.map_region(&mut engine, target, alias, 1, &region_info)?;
+        }
+        Self::apply_updates(state, &mut engine);
+        Ok(())
+    }
+
+    fn do_set_notification_vector(current: Handle<Domain>, vector: usize) -> Result<(), CapaError> {
+        let valid = notification::MIN_VECTOR..=notification::MAX_VECTOR;
+        if vector != notification::NO_VECTOR && !valid.contains(&vector) {
+            return Err(CapaError::InvalidValue);
+        }
+        Self::notifications(current, |notifications| notifications.vector = vector as u8);
+        // The notifications already pending are signaled right away.
+        NOTIFY[cpuid()].store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns the pending notifications and their information, and clears them.
+    fn do_poll_notifications(current: Handle<Domain>) -> (u64, [usize; NB_NOTIFICATIONS]) {
+        Self::notifications(current, |notifications| {
+            let polled = (notifications.pending, notifications.info);
+            notifications.pending = 0;
+            notifications.info = [0; NB_NOTIFICATIONS];
+            notifications.undelivered = false;
+            polled
+        })
+    }
+
+    /// Returns the oldest pending message, and how many messages were pending.
+    fn do_channel_receive(state: &mut T, current: &mut Handle<Domain>) -> Option<(Message, usize)> {
+        let mut engine = Self::lock_engine(state, current);
+        let pending = engine.pending_messages(*current);
+        engine
+            .next_message(*current)
+            .map(|message| (message, pending))
+    }
+
+    fn do_transaction(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        addr: usize,
+        count: usize,
+        is_gva: bool,
+    ) -> Result<(), Error> {
+        if count > transaction::MAX_OPERATIONS || addr % core::mem::align_of::<Operation>() != 0 {
+            return Err(CapaError::InvalidValue.into());
+        }
+        let len = count * core::mem::size_of::<Operation>();
+        let mut engine = Self::lock_engine(state, current);
+        let Some(buff) = T::find_buff(state, &engine, *current, addr, len, is_gva) else {
+            log::info!("Invalid buffer in transaction");
+            return Err(CapaError::InsufficientPermissions.into());
+        };
+        // Work on a copy, the operations might hand the buffer over to another domain.
+        let mut ops = [Operation::default(); transaction::MAX_OPERATIONS];
+        let ops = &mut ops[..count];
+        let submitted = unsafe { core::slice::from_raw_parts(buff as *const Operation, count) };
+        ops.copy_from_slice(submitted);
+
+        let mut deferred: [Option<Deferred>; transaction::MAX_OPERATIONS] =
+            [None; transaction::MAX_OPERATIONS];
+        engine.begin_transaction()?;
+        for idx in 0..count {
+            let call = ops[idx].call;
+            let result = Self::transaction_args(ops, idx).and_then(|args| {
+                Self::transaction_op(state, &mut engine, *current, call, &args, &deferred)
+            });
+            match result {
+                Ok((res, action)) => {
+                    ops[idx].res = res;
+                    deferred[idx] = action;
+                }
+                Err(err) => {
+                    log::trace!("Transaction aborted by operation {}: {:?}", idx, err);
+                    engine.abort_transaction()?;
+                    return Err(Error::with_details(err.code, idx));
+                }
+            }
+        }
+        engine.commit_transaction()?;
+        Self::apply_updates(state, &mut engine);
+
+        // The engine state is committed, platform failures can no longer be rolled back. The maps
+        // and contexts were checked by their operation, the measurement reads the memory of the
+        // domain once its permissions are installed.
+        for (idx, action) in deferred[..count].iter().enumerate() {
+            let result = match *action {
+                Some(Deferred::Map {
+                    domain,
+                    alias,
+                    repeat,
+                    region,
+                }) => state.map_region(&mut engine, domain, alias, repeat, &region),
+                Some(Deferred::Context { domain, core }) => {
+                    let result = T::create_context(state, engine, *current, domain, core);
+                    engine = Self::lock_engine(state, current);
+                    result
+                }
+                Some(Deferred::Measure { domain, switch }) => {
+                    Self::measure_sealed(state, &mut engine, *current, domain, switch)
+                }
+                Some(Deferred::Created(_)) | None => Ok(()),
+            };
+            if let Err(err) = result {
+                log::error!("Failed to apply transaction operation {}: {:?}", idx, err);
+            }
+        }
+        Self::apply_updates(state, &mut engine);
+
+        match T::find_buff(state, &engine, *current, addr, len, is_gva) {
+            Some(buff) => {
+                let out = unsafe { core::slice::from_raw_parts_mut(buff as *mut Operation, count) };
+                for (out, op) in out.iter_mut().zip(ops.iter()) {
+                    out.res = op.res;
+                }
+            }
+            None => log::info!("Transaction results can not be written back"),
+        }
+        Ok(())
+    }
+
+    /// Returns the arguments of operation `idx`, with links replaced by the results they refer to.
+    fn transaction_args(ops: &[Operation], idx: usize) -> Result<Args, Error> {
+        let op = &ops[idx];
+        if op.links >> NB_ARGS != 0 {
+            return Err(CapaError::InvalidValue.into());
+        }
+        let mut args = op.args;
+        for (i, arg) in args.iter_mut().enumerate() {
+            if op.links & (1 << i) == 0 {
+                continue;
+            }
+            // Only the results of previous operations are known.
+            let (source, result) = (*arg / NB_RESULTS, *arg % NB_RESULTS);
+            if source >= idx {
+                return Err(CapaError::InvalidValue.into());
+            }
+            *arg = ops[source].res[result];
+        }
+        Ok(args)
+    }
+
+    /// Applies a single operation of a transaction to the engine, the platform changes are checked
+    /// and returned to be applied once the transaction commits.
+    fn transaction_op(
+        state: &mut T,
+        engine: &mut MutexGuard<CapaEngine>,
+        current: Handle<Domain>,
+        call: usize,
+        args: &Args,
+        deferred: &[Option<Deferred>],
+    ) -> Result<(Results, Option<Deferred>), Error> {
+        let mut res = [0; NB_RESULTS];
+        let action = match call {
+            calls::CREATE_DOMAIN => {
+                let capa = engine.create_domain(current)?;
+                res[0] = capa.as_usize();
+                Some(Deferred::Created(engine.get_domain_capa(current, capa)?))
+            }
+            calls::SEAL_DOMAIN => {
+                let domain = LocalCapa::new(args[0]);
+                let capa = engine.seal(current, cpuid(), domain)?;
+                res[0] = capa.as_usize();
+                Some(Deferred::Measure {
+                    domain: engine.get_domain_capa(current, domain)?,
+                    switch: engine.get_switch_capa(current, capa)?,
+                })
+            }
+            calls::SEND => {
+                let capa = LocalCapa::new(args[0]);
+                if let Ok(Some(_)) = engine.get_region_capa(current, capa) {
+                    return Err(CapaError::InvalidCapa.into());
+                }
+                engine.send(current, capa, LocalCapa::new(args[1]))?;
+                None
+            }
+            calls::SEND_REGION => {
+                let (capa, to, alias) = (LocalCapa::new(args[0]), LocalCapa::new(args[1]), args[2]);
+                let (region, repeat, flags) =
+                    Self::region_to_send(engine, current, capa, args[3] != 0, args[4], args[5])?;
+                let domain = engine.get_domain_capa(current, to)?;
+                // Domains created by the transaction have no platform state yet, but the regions
+                // mapped earlier in the transaction must be accounted for.
+                let end = alias + repeat * (region.end - region.start);
+                let mut fresh = false;
+                let mut overlaps = false;
+                let mut mapped_regions = 0;
+                for action in deferred.iter().flatten() {
+                    match *action {
+                        Deferred::Created(created) => fresh |= created == domain,
+                        Deferred::Map {
+                            domain: target,
+                            alias: mapped,
+                            repeat: mapped_repeat,
+                            region: mapped_region,
+                        } if target == domain => {
+                            let size = mapped_repeat * (mapped_region.end - mapped_region.start);
+                            overlaps |= mapped < end && alias < mapped + size;
+                            mapped_regions += 1;
+                        }
+                        _ => (),
+                    }
+                }
+                if overlaps
+                    || (!fresh && state.check_overlaps(engine, domain, alias, repeat, &region))
+                {
+                    return Err(CapaError::AlreadyAliased.into());
+                }
+                state.check_mappings(domain, mapped_regions + 1, fresh)?;
+                let flags = if flags.is_empty() { None } else { Some(flags) };
+                engine.send_with_flags(current, capa, to, flags, None, None)?;
+                // The API expects the revocation handle in the first result.
+                res[0] = args[0];
+                Some(Deferred::Map {
+                    domain,
+                    alias,
+                    repeat,
+                    region,
+                })
+            }
+            calls::SEGMENT_REGION => {
+                let (to_send, to_revoke) = Self::segment_region(
+                    engine,
+                    current,
+                    LocalCapa::new(args[0]),
+                    args[1] != 0,
+                    args[2],
+                    args[3],
+                    args[4],
+                )?;
+                res[0] = to_send.as_usize();
+                res[1] = to_revoke.as_usize();
+                None
+            }
+            calls::DUPLICATE => {
+                let rights = MgmtRights::from_bits_truncate(args[1] as u8);
+                let capa = engine.duplicate_with_rights(current, LocalCapa::new(args[0]), rights);
+                res[0] = capa?.as_usize();
+                None
+            }
+            calls::CONFIGURE => {
+                let bitmap = permission::PermissionIndex::from_usize(args[0])
+                    .ok_or(CapaError::InvalidValue)?;
+                let capa = LocalCapa::new(args[1]);
+                if bitmap == permission::PermissionIndex::AllowedCores {
+                    Self::update_core_map(engine, current, capa, 0, args[2] as u64)?;
+                } else {
+                    engine.set_child_permission(current, capa, bitmap, args[2] as u64)?;
+                }
+                None
+            }
+            calls::CONFIGURE_CORE_MAP => {
+                let capa = LocalCapa::new(args[0]);
+                Self::update_core_map(engine, current, capa, args[1], args[2] as u64)?;
+                None
+            }
+            calls::CONFIGURE_QUOTA => {
+                let quota = Quota {
+                    max_domains: args[1],
+                    max_regions: args[2],
+                    max_trackers: args[3],
+                    max_memory: args[4],
+                };
+                engine.set_child_quota(current, LocalCapa::new(args[0]), quota)?;
+                None
+            }
+            calls::CONFIGURE_LABELS => {
+                let capa = LocalCapa::new(args[0]);
+                engine.set_child_labels(current, capa, args[1] as u64)?;
+                res[0] = engine.get_child_labels(current, capa)? as usize;
+                None
+            }
+            calls::ALLOC_CORE_CONTEXT => {
+                let core = T::remap_core(args[1]);
+                let (capa, domain) =
+                    Self::child_context_switch(engine, current, LocalCapa::new(args[0]), core)?;
+                state.check_context(domain, core)?;
+                res[0] = capa.as_usize();
+                Some(Deferred::Context { domain, core })
+            }
+            calls::CREATE_CHANNEL => {
+                let capa = (args[1] == 0).then(|| LocalCapa::new(args[0]));
+                res[0] = engine.create_channel(current, capa)?.as_usize();
+                None
+            }
+            _ => {
+                log::info!("Operation {} is not allowed in transactions", call);
+                return Err(CapaError::InvalidOperation.into());
+            }
+        };
+        Ok((res, action))
+    }
+
+    fn do_register_ring(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        addr: usize,
+        entries: usize,
+        is_gva: bool,
+    ) -> Result<(), CapaError> {
+        if addr == 0 {
+            T::set_ring(*current, None);
+            return Ok(());
+        }
+        if !entries.is_power_of_two()
+            || entries > ring::MAX_ENTRIES
+            || addr % core::mem::align_of::<Header>() != 0
+        {
+            return Err(CapaError::InvalidValue);
+        }
+        let ring = Ring {
+            domain: *current,
+            addr,
+            entries,
+            is_gva,
+        };
+        if Self::find_ring(state, current, &ring).is_none() {
+            log::info!("Invalid buffer for the submission ring");
+            return Err(CapaError::InsufficientPermissions);
+        }
+        T::set_ring(*current, Some(ring));
+        Ok(())
+    }
+
+    /// Processes the calls submitted to the ring of the current domain, returns how many were
+    /// processed.
+    fn do_ring_enter(
+        state: &mut T,
+        current: &mut Handle<Domain>,
+        max: usize,
+    ) -> Result<usize, CapaError> {
+        let ring = T::get_ring(*current)
+            .filter(|ring| ring.domain == *current)
+            .ok_or(CapaError::InvalidOperation)?;
+        let max = if max == 0 { ring.entries } else { max };
+        let mut processed = 0;
+        while processed < max {
+            let Some(header) = Self::find_ring(state, current, &ring) else {
+                break;
+            };
+            let Header {
+                sq_head,
+                sq_tail,
+                cq_head,
+                cq_tail,
+            } = unsafe { core::ptr::read_volatile(header) };
+            if sq_head == sq_tail || cq_tail.wrapping_sub(cq_head) >= ring.entries {
+                break;
+            }
+            let submission = unsafe {
+                let submissions = header.add(1) as *const Submission;
+                core::ptr::read_volatile(submissions.add(sq_head % ring.entries))
+            };
+
+            let mut completion = Completion {
+                user_data: submission.user_data,
+                status: status::SUCCESS,
+                res: [0; NB_RESULTS],
+            };
+            let result = match submission.call {
+                calls::SWITCH
+                | calls::RETURN_TO_MANAGER
+                | calls::EXIT
+                | calls::READ_ALL_GP
+                | calls::WRITE_ALL_GP
+                | calls::WRITE_FIELDS
+                | calls::SELF_CONFIG
+                | calls::REGISTER_RING
+                | calls::RING_ENTER => Err(CapaError::InvalidOperation.into()),
+                call => Self::do_monitor_call(
+                    state,
+                    current,
+                    call,
+                    &submission.args,
+                    &mut completion.res,
+                ),
+            };
+            if let Err(err) = result {
+                completion.status = status::FAILURE;
+                err.encode(&mut completion.res);
+            }
+
+            // The call might have changed the memory of the domain, look the ring up again.
+            let Some(header) = Self::find_ring(state, current, &ring) else {
+                log::info!("Submission ring lost by dom {}", current.idx());
+                break;
+            };
+            unsafe {
+                let submissions = header.add(1) as *mut Submission;
+                let completions = submissions.add(ring.entries) as *mut Completion;
+                core::ptr::write_volatile(completions.add(cq_tail % ring.entries), completion);
+                core::ptr::write_volatile(&mut (*header).sq_head, sq_head.wrapping_add(1));
+                core::ptr::write_volatile(&mut (*header).cq_tail, cq_tail.wrapping_add(1));
+            }
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    /// Returns where the monitor can access the ring, if the domain still can.
+    fn find_ring(state: &mut T, current: &mut Handle<Domain>, ring: &Ring) -> Option<*mut Header> {
+        let engine = Self::lock_engine(state, current);
+        let size = ring::size(ring.entries);
+        T::find_buff(state, &engine, *current, ring.addr, size, ring.is_gva)
+            .map(|buff| buff as *mut Header)
+    }
+
+    fn do_monitor_call(
+        state: &mut T,
+        domain: &mut Handle<Domain>,
+        call: usize,
+        args: &[usize; 6],
+        res: &mut [usize; 6],
+    ) -> Result<bool, Error> {
+        match call {
+            calls::CREATE_DOMAIN => {
+                let capa = Self::do_create_domain(state, domain)?;
+                res[0] = capa.as_usize();
+                return Ok(true);
+            }
+            calls::SEAL_DOMAIN => {
+                log::trace!("Seal Domain on core {}", cpuid());
+                let capa = Self::do_seal(state, domain, LocalCapa::new(args[0]))?;
+                res[0] = capa.as_usize();
+                return Ok(true);
             }
             calls::SEND => {
                 log::trace!("Send on core {}", cpuid());
@@ -915,11 +1764,34 @@ pub trait Monitor<T: PlatformState + 'static> {
                     args[3] != 0,
                     args[4],
                     args[5],
+                    None,
                 )?;
                 // The API expects the revocation handle in the first arg.
                 res[0] = args[0];
                 return Ok(true);
             }
+            calls::SEND_REGION_LEASED => {
+                log::trace!("Send leased region on core {}", cpuid());
+                let condition = match args[3] {
+                    lease::ON_RETURN => LeaseCondition::OnReturn,
+                    lease::SWITCHES => LeaseCondition::Switches(args[4]),
+                    lease::DEADLINE => LeaseCondition::Deadline(args[4] as u64),
+                    _ => return Err(CapaError::InvalidValue.into()),
+                };
+                Self::do_send_region(
+                    state,
+                    domain,
+                    LocalCapa::new(args[0]),
+                    LocalCapa::new(args[1]),
+                    args[2],
+                    false,
+                    0,
+                    args[5],
+                    Some(condition),
+                )?;
+                res[0] = args[0];
+                return Ok(true);
+            }
             calls::SEGMENT_REGION => {
                 log::trace!("Segment region on core {}", cpuid());
                 let (to_send, to_revoke) = Self::do_segment_region(
@@ -942,7 +1814,8 @@ pub trait Monitor<T: PlatformState + 'static> {
             }
             calls::DUPLICATE => {
                 log::trace!("Duplicate");
-                let capa = Self::do_duplicate(state, domain, LocalCapa::new(args[0]))?;
+                let rights = MgmtRights::from_bits_truncate(args[1] as u8);
+                let capa = Self::do_duplicate(state, domain, LocalCapa::new(args[0]), rights)?;
                 res[0] = capa.as_usize();
                 return Ok(true);
             }
@@ -993,11 +1866,15 @@ pub trait Monitor<T: PlatformState + 'static> {
                 log::trace!("Configure on core {}", cpuid());
                 let result = if let Some(bitmap) = permission::PermissionIndex::from_usize(args[0])
                 {
-                    let mut value = args[2] as u64;
-                    if bitmap == permission::PermissionIndex::AllowedCores {
-                        value = T::remap_core_bitmap(value);
-                    }
-                    match Self::do_set(state, domain, LocalCapa::new(args[1]), bitmap, value) {
+                    let capa = LocalCapa::new(args[1]);
+                    let value = args[2] as u64;
+                    let result = if bitmap == permission::PermissionIndex::AllowedCores {
+                        // Only the first 64 cores, see `CONFIGURE_CORE_MAP` for the others.
+                        Self::do_set_core_map(state, domain, capa, 0, value)
+                    } else {
+                        Self::do_set(state, domain, capa, bitmap, value)
+                    };
+                    match result {
                         Ok(_) => 0,
                         Err(e) => {
                             log::error!("Configuration error: {:?}", e);
@@ -1011,6 +1888,45 @@ pub trait Monitor<T: PlatformState + 'static> {
                 res[0] = result;
                 return Ok(true);
             }
+            calls::CONFIGURE_CORE_MAP => {
+                log::trace!("Configure core map on core {}", cpuid());
+                Self::do_set_core_map(
+                    state,
+                    domain,
+                    LocalCapa::new(args[0]),
+                    args[1],
+                    args[2] as u64,
+                )?;
+                return Ok(true);
+            }
+            calls::CONFIGURE_QUOTA => {
+                log::trace!("Configure quota on core {}", cpuid());
+                let quota = Quota {
+                    max_domains: args[1],
+                    max_regions: args[2],
+                    max_trackers: args[3],
+                    max_memory: args[4],
+                };
+                Self::do_set_quota(state, domain, LocalCapa::new(args[0]), quota)?;
+                return Ok(true);
+            }
+            calls::CONFIGURE_LABELS => {
+                log::trace!("Configure labels on core {}", cpuid());
+                let capa = LocalCapa::new(args[0]);
+                res[0] = Self::do_set_labels(state, domain, capa, args[1] as u64)? as usize;
+                return Ok(true);
+            }
+            calls::ENABLE_FAST_SWITCH => {
+                log::trace!("Enable fast switch on core {}", cpuid());
+                let capa = LocalCapa::new(args[0]);
+                Self::do_enable_fast_switch(state, domain, capa, args[1], args[2], args[3])?;
+                return Ok(true);
+            }
+            calls::DISABLE_FAST_SWITCH => {
+                log::trace!("Disable fast switch on core {}", cpuid());
+                Self::do_disable_fast_switch(state, domain)?;
+                return Ok(true);
+            }
             calls::CONFIGURE_CORE => {
                 Self::do_set_core(
                     state,
@@ -1088,9 +2004,10 @@ pub trait Monitor<T: PlatformState + 'static> {
                 return Ok(true);
             }
             calls::SIGNED_ATTESTATION => {
-                let written = Self::do_signed_attestation(state, domain, args[0], args[1], args[2], args[3], args[4] != 0)?;
+                let (written, attestation_written) = Self::do_signed_attestation(state, domain, args[0], args[1], args[2], args[3], args[4] != 0, args[5] as u64)?;
                 log::trace!("Wrote {} bytes of signature", written);
                 res[0] = written;
+                res[1] = attestation_written;
                 return Ok(true);
             }
             calls::GET_SIGNING_KEY => {
@@ -1099,6 +2016,14 @@ pub trait Monitor<T: PlatformState + 'static> {
                 res[0] = written;
                 return Ok(true);
             }
+            calls::ENCLAVE_ATTESTATION => {
+                if args[1] == report::READ_CERTIFICATE {
+                    res[0] = Self::do_read_attestation_certificate(state, domain, args[2], args[3], args[4] != 0)?;
+                } else {
+                    Self::do_enclave_attestation(state, domain, args[0], args[1], res)?;
+                }
+                return Ok(true);
+            }
             calls::TPM_SIGN => {
                 let written = Self::do_tpm_sign(state, domain, args[0], args[1], args[2], args[3], args[4] != 0)?;
                 log::trace!("Wrote {} bytes of signature", written);
@@ -1134,9 +2059,82 @@ pub trait Monitor<T: PlatformState + 'static> {
                 res[1] = size;
                 return Ok(true);
             }
+            calls::POLL_REVOCATION => {
+                log::trace!("Poll revocation on core {}", cpuid());
+                if let Some(revoked) = Self::do_poll_revocation(state, domain) {
+                    res[0] = revoked.capa.as_usize();
+                    res[1] = revoked.start;
+                    res[2] = revoked.end;
+                    res[3] = 1;
+                } else {
+                    res[3] = 0;
+                }
+                return Ok(true);
+            }
+            calls::TRANSACTION => {
+                log::trace!("Transaction on core {}", cpuid());
+                Self::do_transaction(state, domain, args[0], args[1], args[2] != 0)?;
+                return Ok(true);
+            }
+            calls::REGISTER_RING => {
+                log::trace!("Register ring on core {}", cpuid());
+                Self::do_register_ring(state, domain, args[0], args[1], args[2] != 0)?;
+                return Ok(true);
+            }
+            calls::RING_ENTER => {
+                res[0] = Self::do_ring_enter(state, domain, args[0])?;
+                return Ok(true);
+            }
+            calls::CREATE_CHANNEL => {
+                log::trace!("Create channel on core {}", cpuid());
+                let capa = (args[1] == 0).then(|| LocalCapa::new(args[0]));
+                res[0] = Self::do_create_channel(state, domain, capa)?.as_usize();
+                return Ok(true);
+            }
+            calls::CHANNEL_SEND => {
+                log::trace!("Channel send on core {}", cpuid());
+                let region = match args[1] {
+                    channel::NO_REGION => None,
+                    region => Some(LocalCapa::new(region)),
+                };
+                let words = [args[2], args[3], args[4]];
+                let channel = LocalCapa::new(args[0]);
+                Self::do_channel_send(state, domain, channel, region, words, args[5])?;
+                return Ok(true);
+            }
+            calls::CHANNEL_RECEIVE => {
+                log::trace!("Channel receive on core {}", cpuid());
+                if let Some((message, pending)) = Self::do_channel_receive(state, domain) {
+                    res[0] = pending;
+                    res[1] = message.from;
+                    res[2] = message.region.map_or(channel::NO_REGION, |r| r.as_usize());
+                    res[3..].copy_from_slice(&message.words);
+                } else {
+                    res[0] = 0;
+                }
+                return Ok(true);
+            }
+            calls::TEST_CALL => {
+                return Ok(true);
+            }
+            calls::GET_ERROR_VERSION => {
+                res[0] = ERROR_VERSION;
+                return Ok(true);
+            }
+            calls::SET_NOTIFICATION_VECTOR => {
+                log::trace!("Set notification vector on core {}", cpuid());
+                Self::do_set_notification_vector(*domain, args[0])?;
+                return Ok(true);
+            }
+            calls::POLL_NOTIFICATIONS => {
+                let (pending, info) = Self::do_poll_notifications(*domain);
+                res[0] = pending as usize;
+                res[1..=NB_NOTIFICATIONS].copy_from_slice(&info);
+                return Ok(true);
+            }
             _ => {
                 log::info!("The invalid operation: {}", call);
-                return Err(CapaError::InvalidOperation);
+                return Err(ErrorCode::UnknownCall.into());
             }
         }
     }
@@ -1144,46 +2142,56 @@ pub trait Monitor<T: PlatformState + 'static> {
     fn do_handle_violation(state: &mut T, current: &mut Handle<Domain>) -> Result<(), CapaError> {
         let mut engine = Self::lock_engine(state, current);
         let core = cpuid();
+        // The peer of a pair can't leave the core on its own, the exit is handled by the owner.
+        let pair = engine[*current].fast_switch();
+        if pair.is_some_and(|pair| pair.core == core && !pair.is_owner) {
+            state.fast_switch_abort(current, core);
+        }
         state.context_interrupted(current, core);
         engine.handle_violation(*current, core)?;
         Self::apply_updates(state, &mut engine);
         Ok(())
     }
 
+    /// Revokes the leases whose deadline is past, must be called on every monitor entry.
+    ///
+    /// Deadlines are thus enforced on the first monitor entry after them, on any core.
+    fn expire_lease_deadlines(state: &mut T, current: &mut Handle<Domain>) {
+        if T::timestamp() < LEASE_DEADLINE.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut engine = Self::lock_engine(state, current);
+        Self::apply_updates(state, &mut engine);
+    }
+
     fn apply_updates(state: &mut T, engine: &mut MutexGuard<CapaEngine>) {
+        if let Err(err) = engine.expire_leases(T::timestamp(), cpuid()) {
+            log::error!("Failed to revoke expired leases: {:?}", err);
+        }
+        let deadline = engine.next_lease_deadline().unwrap_or(u64::MAX);
+        LEASE_DEADLINE.store(deadline, Ordering::SeqCst);
+        // Consecutive permission updates share their round of IPIs.
+        let mut shootdowns = Shootdowns::new();
         while let Some(update) = engine.pop_update() {
             log::trace!("Update: {}", update);
+            // The other updates might rely on the previous permission updates being in effect.
+            if !matches!(update, capa_engine::Update::PermissionUpdate { .. }) {
+                Self::send_shootdowns(state, &mut shootdowns);
+            }
             match update {
                 capa_engine::Update::PermissionUpdate { domain, core_map } => {
-                    let core_id = cpuid();
                     log::trace!(
                         "cpu {} processes PermissionUpdate with core_map={:b}",
-                        core_id,
+                        cpuid(),
                         core_map
                     );
+                    // The domain can only be updated again once its shootdown completes.
+                    if shootdowns.contains(domain) || shootdowns.is_full() {
+                        Self::send_shootdowns(state, &mut shootdowns);
+                    }
                     // Do we have to process updates
                     if T::update_permission(domain, engine) {
-                        let mut core_count = core_map.count_ones() as usize;
-                        if (1 << core_id) & core_map != 0 {
-                            state.platform_shootdown(&domain, core_id, true);
-                        } else {
-                            // We will wait on the barrier.
-                            core_count += 1;
-                        }
-                        // Prepare the update.
-                        T::prepare_notify(&domain, core_count);
-                        for core in BitmapIterator::new(core_map) {
-                            if core == core_id {
-                                continue;
-                            }
-                            let mut core_updates = CORE_UPDATES[core as usize].lock();
-                            core_updates
-                                .push(CoreUpdate::TlbShootdown { src_core: core_id })
-                                .unwrap();
-                        }
-                        T::notify_cores(&domain, core_id, core_map as usize);
-                        T::acknowledge_notify(&domain);
-                        T::finish_notify(&domain);
+                        shootdowns.push(domain, core_map);
                     }
                 }
                 capa_engine::Update::Cleanup { start, end } => {
@@ -1203,7 +2211,7 @@ pub trait Monitor<T: PlatformState + 'static> {
                     domain,
                 } => {
                     let cores = engine.get_domain_cores(domain).unwrap();
-                    let mut count = cores.count_ones() as usize;
+                    let mut count = cores.count();
                     let core_id = cpuid();
                     // RevokeDomain updates never happen if the domain is not running.
                     if count == 0 {
@@ -1221,16 +2229,15 @@ pub trait Monitor<T: PlatformState + 'static> {
                     // 4) The main thread can safely update other
                     //    cores engine state.
                     // 5) The main thread notifies everyone to resume.
-                    let manager_core_map = engine
-                        .get_domain_permission(manager, permission::PermissionIndex::AllowedCores);
+                    let manager_core_map = engine.get_domain_core_map(manager);
                     T::prepare_notify(&domain, count);
                     T::prepare_notify(&manager, count);
-                    for core in BitmapIterator::new(cores) {
+                    for core in cores.iter() {
                         if core == core_id {
                             continue;
                         }
                         // Check that the manager can run on that core.
-                        if manager_core_map & (1 << core) == 0 {
+                        if !manager_core_map.contains(core) {
                             panic!("The manager cannot run on the target core!");
                         }
                         let mut core_updates = CORE_UPDATES[core as usize].lock();
@@ -1241,20 +2248,46 @@ pub trait Monitor<T: PlatformState + 'static> {
                             })
                             .unwrap();
                     }
-                    T::notify_cores(&domain, core_id, cores as usize);
+                    T::notify_cores(&domain, core_id, cores);
                     T::acknowledge_notify(&domain);
                     // All cores should have stopped now and are blocking on the manager's signal.
-                    for core in BitmapIterator::new(cores) {
+                    for core in cores.iter() {
                         // Change the domain on the core.
                         engine.partial_switch(domain, manager, core).unwrap();
                     }
                     // Check the domain's cores have been preempted.
-                    assert_eq!(engine.get_domain_cores(domain), Ok(0));
+                    assert_eq!(engine.get_domain_cores(domain), Ok(CoreSet::NONE));
                     engine.revoke(manager, mgmt_capa).unwrap();
                     // Free the threads
                     T::acknowledge_notify(&manager);
                 }
                 capa_engine::Update::CreateDomain { domain } => T::create_domain(domain),
+                capa_engine::Update::FastSwitchDisabled { owner, peer, core } => {
+                    // The pair is released by its core, which might be running the peer.
+                    let core_id = cpuid();
+                    CORE_UPDATES[core]
+                        .lock()
+                        .push(CoreUpdate::FastSwitchDisabled {
+                            src_core: core_id,
+                            owner,
+                            peer,
+                        })
+                        .unwrap();
+                    if core != core_id {
+                        T::prepare_notify(&peer, 2);
+                        T::notify_cores(&peer, core_id, CoreSet::single(core));
+                        T::acknowledge_notify(&peer);
+                    }
+                }
+                capa_engine::Update::LeaseExpired {
+                    holder,
+                    alias,
+                    size,
+                } => {
+                    if let Err(err) = state.unmap_region(engine, holder, alias, size) {
+                        log::error!("Failed to unmap expired lease: {:?}", err);
+                    }
+                }
                 capa_engine::Update::Switch {
                     domain,
                     return_capa,
@@ -1270,6 +2303,25 @@ pub trait Monitor<T: PlatformState + 'static> {
                         })
                         .unwrap();
                 }
+                capa_engine::Update::RegionRevoked { domain, start, .. } => {
+                    // The details are polled by the domain, only the last start is notified.
+                    let trap = permission::trap_bits::REGION_REVOKED;
+                    Self::notify(engine, domain, trap, start);
+                }
+                capa_engine::Update::ChannelMessage { domain, pending } => {
+                    // The messages are polled by the domain, only the latest count is notified.
+                    let trap = permission::trap_bits::CHANNEL_MESSAGE;
+                    Self::notify(engine, domain, trap, pending);
+                }
+                capa_engine::Update::Trap {
+                    manager,
+                    trap,
+                    info,
+                    ..
+                } if trap & NOTIFICATION_TRAPS != 0 => {
+                    // Signaled on the cores running the domain, not the one raising the trap.
+                    Self::notify(engine, manager, trap, info as usize);
+                }
                 capa_engine::Update::Trap {
                     manager,
                     trap,
@@ -1287,21 +2339,167 @@ pub trait Monitor<T: PlatformState + 'static> {
                 }
             }
         }
+        Self::send_shootdowns(state, &mut shootdowns);
+
+        #[cfg(feature = "check_invariants")]
+        engine.check_invariants();
+    }
+
+    /// Carries out a batch of TLB shootdowns with a single round of IPIs, and waits for all the
+    /// cores to complete them.
+    fn send_shootdowns(state: &mut T, shootdowns: &mut Shootdowns) {
+        let Some((first, _)) = shootdowns.iter().next() else {
+            return;
+        };
+        let core_id = cpuid();
+        let mut cores = CoreSet::NONE;
+        for (domain, core_map) in shootdowns.iter() {
+            let mut core_count = core_map.count();
+            if core_map.contains(core_id) {
+                state.platform_shootdown(&domain, core_id, true);
+            } else {
+                // We will wait on the barrier.
+                core_count += 1;
+            }
+            // Prepare the update.
+            T::prepare_notify(&domain, core_count);
+            for core in core_map.iter() {
+                if core != core_id {
+                    cores.insert(core);
+                }
+            }
+        }
+        // A core might process its updates before the IPI, waiting on the barriers with its queue
+        // locked: all of them are pushed at once, in the order of the batch.
+        for core in cores.iter() {
+            let mut core_updates = CORE_UPDATES[core as usize].lock();
+            for (domain, core_map) in shootdowns.iter() {
+                if core_map.contains(core) {
+                    core_updates
+                        .push(CoreUpdate::TlbShootdown {
+                            src_core: core_id,
+                            domain,
+                        })
+                        .unwrap();
+                }
+            }
+        }
+        // A single round of IPIs for the whole batch.
+        T::notify_cores(&first, core_id, cores);
+        for (domain, _) in shootdowns.iter() {
+            T::acknowledge_notify(&domain);
+            T::finish_notify(&domain);
+        }
+        *shootdowns = Shootdowns::new();
+    }
+
+    /// Runs `f` on the notifications of the domain, reset if they belonged to a revoked one.
+    fn notifications<R>(domain: Handle<Domain>, f: impl FnOnce(&mut Notifications) -> R) -> R {
+        T::with_notifications(domain, |notifications| {
+            if !notifications.is_some_and(|n| n.domain == domain) {
+                *notifications = Some(Notifications::new(domain));
+            }
+            f(notifications.as_mut().unwrap())
+        })
+    }
+
+    /// Raises a notification if the domain is allowed the trap, and signals it on the cores running
+    /// the domain. A domain that is not running is signaled on its next switch in.
+    fn notify(engine: &MutexGuard<CapaEngine>, domain: Handle<Domain>, trap: u64, info: usize) {
+        if engine[domain].traps() & trap == 0 {
+            return;
+        }
+        let signaled = Self::notifications(domain, |notifications| {
+            notifications.pending |= trap;
+            notifications.info[Notifications::index(trap)] = info;
+            notifications.undelivered = true;
+            notifications.vector != notification::NO_VECTOR as u8
+        });
+        if !signaled {
+            return;
+        }
+        // Cores yet to signal a previous notification are not interrupted again.
+        let mut cores = CoreSet::NONE;
+        for core in engine[domain].cores().iter() {
+            if !NOTIFY[core].swap(true, Ordering::SeqCst) {
+                cores.insert(core);
+            }
+        }
+        if !cores.is_empty() {
+            T::notify_cores(&domain, cpuid(), cores);
+        }
     }
+
+    /// Signals the pending notifications of the current domain of the core.
+    fn deliver_notifications(state: &mut T, current: Handle<Domain>, core: usize) {
+        let vector = Self::notifications(current, |notifications| {
+            let vector = notifications.vector;
+            if !notifications.undelivered || vector == notification::NO_VECTOR as u8 {
+                return None;
+            }
+            notifications.undelivered = false;
+            Some(vector)
+        });
+        let Some(vector) = vector else {
+            return;
+        };
+        if !state.inject_notification(current, core, vector) {
+            // Signaled again once the domain can take the interrupt, unless polled in between.
+            Self::notifications(current, |notifications| {
+                notifications.undelivered = notifications.pending != 0;
+            });
+        }
+    }
+
     fn apply_core_updates(state: &mut T, current: &mut Handle<Domain>, core_id: usize) {
         let core = cpuid();
-        let mut update_queue = CORE_UPDATES[core_id].lock();
-        while let Some(update) = update_queue.pop() {
-            state.apply_core_update(current, core, &update);
+        let previous = *current;
+        {
+            let mut update_queue = CORE_UPDATES[core_id].lock();
+            while let Some(update) = update_queue.pop() {
+                state.apply_core_update(current, core, &update);
+            }
         }
+        // The domain switched to might have been notified while it was not running.
+        if NOTIFY[core].swap(false, Ordering::SeqCst) || *current != previous {
+            Self::deliver_notifications(state, *current, core);
+        }
+    }
+}
+
+/// The engine pool sizes requested by stage 1.
+fn engine_config(pools: &Pools) -> EngineConfig {
+    EngineConfig {
+        nb_domains: pools.nb_domains as usize,
+        nb_capas_per_domain: pools.nb_capas_per_domain as usize,
+        nb_regions: pools.nb_regions as usize,
+        nb_tracker: pools.nb_tracker as usize,
     }
 }
 
+/// Returns the information-flow policy passed by stage 1.
+fn flow_policy(policy: &FlowPolicy) -> Result<Policy, CapaError> {
+    let nb_rules = policy.nb_rules as usize;
+    let mut rules = [Rule::EMPTY; MAX_FLOW_RULES];
+    for (rule, raw) in rules.iter_mut().zip(policy.rules.iter()) {
+        *rule = Rule {
+            transfers: raw.transfers,
+            from: raw.from,
+            to: raw.to,
+            region: raw.region,
+            ops: raw.ops,
+        };
+    }
+    Policy::new(rules.get(..nb_rules).ok_or(CapaError::InvalidValue)?)
+}
+
 // ———————————————————————————————— Display ————————————————————————————————— //
 impl core::fmt::Display for CoreUpdate {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            CoreUpdate::TlbShootdown { src_core } => write!(f, "TLB Shootdown {}", src_core),
+            CoreUpdate::TlbShootdown { src_core, domain } => {
+                write!(f, "TLB Shootdown {} from {}", domain, src_core)
+            }
             CoreUpdate::Switch { domain, .. } => write!(f, "Switch({})", domain),
             CoreUpdate::Trap {
                 manager,
@@ -1316,6 +2514,934 @@ impl core::fmt::Display for CoreUpdate {
                 revok.idx(),
                 next.idx()
             ),
+            CoreUpdate::FastSwitchDisabled { owner, peer, .. } => {
+                write!(f, "Fast Switch Disabled({}, {})", owner, peer)
+            }
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use attestation::signature::{
+        certificate_data, AttestationPublicKey, AttestationSignature, ATTESTATION_DATA_SZ,
+        ATTESTATION_SEED_SZ,
+    };
+    use capa_engine::config::NB_CORES;
+    use capa_engine::permission::{self, PermissionIndex};
+    use capa_engine::{flow, CoreSet, Domain, Handle, MemOps, MgmtRights};
+    use monitor_abi::ring::{self, Completion, Header, Submission};
+    use monitor_abi::transaction::{self, Operation};
+    use monitor_abi::{channel, labels, lease, report, status};
+    use stage_two_abi::FlowRule;
+
+    use super::{CAPA_ENGINE, INITIAL_DOMAIN};
+    use crate::attestation_domain::init_attestation_identity;
+    use crate::calls;
+    use crate::error::{Error, ErrorCode, ERROR_VERSION};
+    use crate::mock::{MockState, Simulation, NB_ALIASES};
+    use crate::monitor::PlatformState;
+
+    const PAGE_SIZE: usize = crate::allocator::PAGE_SIZE as usize;
+    const RW: usize = MemOps::READ.union(MemOps::WRITE).bits() as usize;
+
+    /// A sealed child of the initial domain, that can run on core 1.
+    struct Child {
+        mgmt: usize,
+        switch: usize,
+        start: usize,
+        end: usize,
+    }
+
+    fn initial_domain() -> Handle<Domain> {
+        INITIAL_DOMAIN.lock().unwrap()
+    }
+
+    fn create_child(sim: &Simulation) -> Child {
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 16 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+        sim.call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap();
+        let configured = sim
+            .call(
+                0,
+                calls::CONFIGURE,
+                [PermissionIndex::AllowedCores as usize, mgmt, 0b11, 0, 0, 0],
+            )
+            .unwrap();
+        assert_eq!(configured[0], 0);
+        let switch = sim
+            .call(0, calls::ALLOC_CORE_CONTEXT, [mgmt, 1, 0, 0, 0, 0])
+            .unwrap()[0];
+        sim.call(0, calls::SEAL_DOMAIN, [mgmt, 0, 0, 0, 0, 0])
+            .unwrap();
+
+        Child {
+            mgmt,
+            switch,
+            start,
+            end,
+        }
+    }
+
+    fn child_handle(mgmt: usize) -> Handle<Domain> {
+        let engine = CAPA_ENGINE.lock();
+        engine
+            .get_domain_capa(initial_domain(), capa_engine::LocalCapa::new(mgmt))
+            .unwrap()
+    }
+
+    /// Returns true if the installed permissions of `domain` cover [start, end).
+    fn can_access(domain: Handle<Domain>, start: usize, end: usize) -> bool {
+        MockState::get_domain(domain)
+            .permissions
+            .iter()
+            .any(|&(s, e, _)| s <= start && end <= e)
+    }
+
+    #[test]
+    fn unknown_call() {
+        let sim = Simulation::new(1);
+        let err = sim.call(0, 0xbad, [0; 6]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnknownCall);
+
+        // Clients check that they decode errors with the same error space
+        let res = sim.call(0, calls::GET_ERROR_VERSION, [0; 6]).unwrap();
+        assert_eq!(res[0], ERROR_VERSION);
+    }
+
+    #[test]
+    fn send_region_and_seal() {
+        let sim = Simulation::new(2);
+        let child = create_child(&sim);
+        let domain = child_handle(child.mgmt);
+
+        assert!(can_access(domain, child.start, child.end));
+        let manager = initial_domain();
+        assert!(!can_access(manager, child.start, child.start + PAGE_SIZE));
+        assert!(!can_access(manager, child.end - PAGE_SIZE, child.end));
+        assert!(CAPA_ENGINE.lock().is_domain_sealed(domain));
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn tlb_shootdown() {
+        let sim = Simulation::new(2);
+        let manager = initial_domain();
+        let (start, _) = sim.memory();
+        let root = sim.find_region(manager, start, start + PAGE_SIZE).unwrap();
+
+        // Carving from core 0 updates the permissions of the domain running on both cores.
+        sim.call(
+            0,
+            calls::SEGMENT_REGION,
+            [root.as_usize(), 0, start, start + PAGE_SIZE, RW, 0],
+        )
+        .unwrap();
+
+        let version = MockState::get_domain(manager).version;
+        assert!(version > 0);
+        for core in 0..2 {
+            assert_eq!(MockState::get_context(manager, core).tlb_version, version);
+        }
+    }
+
+    #[test]
+    fn coalesced_shootdowns() {
+        let sim = Simulation::new(3);
+        let child = create_child(&sim);
+        let domain = child_handle(child.mgmt);
+        let manager = initial_domain();
+        sim.call(1, calls::SWITCH, [child.switch, 0, 0, 0, 0, 0])
+            .unwrap();
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 40 * PAGE_SIZE;
+        let end = start + PAGE_SIZE;
+        let root = sim.find_region(manager, start, end).unwrap();
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+
+        // The manager loses the region and the child gains it, both flushed with one IPI round.
+        let rounds = sim.ipi_rounds();
+        sim.call(0, calls::SEND_REGION, [region, child.mgmt, start, 0, 0, 0])
+            .unwrap();
+        assert_eq!(sim.ipi_rounds(), rounds + 1);
+        assert!(can_access(domain, start, end));
+        assert!(!can_access(manager, start, end));
+        for (domain, core) in [(manager, 2), (domain, 1)] {
+            assert_eq!(
+                MockState::get_context(domain, core).tlb_version,
+                MockState::get_domain(domain).version
+            );
+        }
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn revoke_running_domain() {
+        let sim = Simulation::new(2);
+        let child = create_child(&sim);
+        let domain = child_handle(child.mgmt);
+        let manager = initial_domain();
+
+        sim.call(1, calls::SWITCH, [child.switch, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(sim.current(1), domain);
+
+        // Core 0 revokes the child while it runs on core 1, which must be sent back to the manager.
+        sim.call(0, calls::REVOKE, [child.mgmt, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(sim.current(1), manager);
+        assert!(MockState::get_context(domain, 1).interrupted);
+        let ctx = MockState::get_context(manager, 1);
+        assert_eq!(ctx.gp[0], status::FAILURE);
+        assert_eq!(ctx.gp[1], ErrorCode::DomainRevoked as usize);
+        drop(ctx);
+        assert!(can_access(manager, child.start, child.end));
+        assert_eq!(
+            MockState::get_context(manager, 1).tlb_version,
+            MockState::get_domain(manager).version
+        );
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn fast_switch() {
+        let sim = Simulation::new(2);
+        let child = create_child(&sim);
+        let domain = child_handle(child.mgmt);
+        let manager = initial_domain();
+        let trampoline = 0x4000_0000;
+
+        // The trampoline must be page aligned and can't overlap the memory of the pair.
+        for addr in [trampoline + 8, child.start] {
+            let err = sim
+                .call(
+                    1,
+                    calls::ENABLE_FAST_SWITCH,
+                    [child.switch, addr, 0, 0, 0, 0],
+                )
+                .unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidValue);
+            assert!(CAPA_ENGINE.lock()[domain].fast_switch().is_none());
+        }
+
+        // The peer runs on the core of its owner until the pair is disabled.
+        let args = [child.switch, trampoline, 0, 0, 0, 0];
+        sim.call(1, calls::ENABLE_FAST_SWITCH, args).unwrap();
+        assert_eq!(
+            CAPA_ENGINE.lock().get_domain_cores(domain),
+            Ok(CoreSet::single(1))
+        );
+        sim.check_invariants();
+        sim.call(1, calls::DISABLE_FAST_SWITCH, [0; 6]).unwrap();
+        assert_eq!(
+            CAPA_ENGINE.lock().get_domain_cores(domain),
+            Ok(CoreSet::NONE)
+        );
+        assert!(MockState::get_domain(domain).fast_switch.is_none());
+
+        // Revoking the running peer from core 0 sends core 1 back to the owner.
+        sim.call(1, calls::ENABLE_FAST_SWITCH, args).unwrap();
+        sim.on(1, move |_, current| *current = domain);
+        sim.call(0, calls::REVOKE, [child.mgmt, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(sim.current(1), manager);
+        let ctx = MockState::get_context(manager, 1);
+        assert_eq!(ctx.gp[0], status::FAILURE);
+        assert_eq!(ctx.gp[1], ErrorCode::FastSwitchAborted as usize);
+        drop(ctx);
+        assert!(MockState::get_domain(manager).fast_switch.is_none());
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn configure_core_map() {
+        let sim = Simulation::new(2);
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let domain = child_handle(mgmt);
+
+        // Cores beyond 64 are configured by groups of 64, the first group is also reachable with
+        // the allowed cores permission.
+        let last = NB_CORES - 1;
+        sim.call(
+            0,
+            calls::CONFIGURE_CORE_MAP,
+            [mgmt, last / 64, 1 << (last % 64), 0, 0, 0],
+        )
+        .unwrap();
+        let configured = sim
+            .call(
+                0,
+                calls::CONFIGURE,
+                [PermissionIndex::AllowedCores as usize, mgmt, 0b10, 0, 0, 0],
+            )
+            .unwrap();
+        assert_eq!(configured[0], 0);
+        let core_map = CAPA_ENGINE.lock().get_domain_core_map(domain);
+        assert_eq!(core_map.iter().collect::<Vec<_>>(), vec![1, last]);
+
+        let err = sim
+            .call(
+                0,
+                calls::CONFIGURE_CORE_MAP,
+                [mgmt, CoreSet::NB_WORDS, 1, 0, 0, 0],
+            )
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+    }
+
+    #[test]
+    fn configure_quota() {
+        let sim = Simulation::new(1);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 8 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let quota = [mgmt, 0, usize::MAX, usize::MAX, 4 * PAGE_SIZE, 0];
+        sim.call(0, calls::CONFIGURE_QUOTA, quota).unwrap();
+
+        // The child can't receive more memory than its quota, and the region stays with the caller
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+        let err = sim
+            .call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::QuotaExceeded);
+        assert!(sim.find_region(initial_domain(), start, end).is_some());
+        sim.check_invariants();
+
+        // A smaller region fits
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [region, 0, start, start + 4 * PAGE_SIZE, RW, 0],
+            )
+            .unwrap()[0];
+        sim.call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap();
+        let child = child_handle(mgmt);
+        assert_eq!(CAPA_ENGINE.lock()[child].usage().memory, 4 * PAGE_SIZE);
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn flow_policy() {
+        const NET: usize = 1 << 0;
+        let rules = [FlowRule {
+            transfers: flow::transfer::SEND_REGION,
+            to: NET as u64,
+            ops: MemOps::EXEC.bits() as u64,
+            ..FlowRule::default()
+        }];
+        let sim = Simulation::with_flow_rules(1, &rules);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let configured = sim
+            .call(0, calls::CONFIGURE_LABELS, [mgmt, NET, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(configured[0], NET);
+        let err = sim
+            .call(
+                0,
+                calls::CONFIGURE_LABELS,
+                [mgmt, labels::SEALED, 0, 0, 0, 0],
+            )
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+
+        // Executable memory can't be sent to the network domain
+        let rwx = RW | MemOps::EXEC.bits() as usize;
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, rwx, 0],
+            )
+            .unwrap()[0];
+        let err = sim
+            .call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::DeniedByPolicy);
+        assert!(sim.find_region(initial_domain(), start, end).is_some());
+
+        let region = sim
+            .call(0, calls::SEGMENT_REGION, [region, 0, start, end, RW, 0])
+            .unwrap()[0];
+        sim.call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap();
+        assert!(can_access(child_handle(mgmt), start, end));
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn attenuated_management() {
+        let sim = Simulation::new(1);
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        sim.call(0, calls::CONFIGURE_CORE_MAP, [mgmt, 0, 1, 0, 0, 0])
+            .unwrap();
+        let switch_only = MgmtRights::SWITCH.bits() as usize;
+        let delegated = sim
+            .call(0, calls::DUPLICATE, [mgmt, switch_only, 0, 0, 0, 0])
+            .unwrap()[0];
+
+        // The attenuated capability can neither read registers nor configure the domain
+        let err = sim
+            .call(0, calls::GET_CONFIG_CORE, [delegated, 0, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InsufficientPermissions);
+        let err = sim
+            .call(0, calls::CONFIGURE_CORE_MAP, [delegated, 0, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InsufficientPermissions);
+
+        // Revoking it leaves the domain alive
+        sim.call(0, calls::REVOKE, [delegated, 0, 0, 0, 0, 0])
+            .unwrap();
+        sim.call(0, calls::CONFIGURE_CORE_MAP, [mgmt, 0, 1, 0, 0, 0])
+            .unwrap();
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn send_region_leased() {
+        let sim = Simulation::new(1);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let child = child_handle(mgmt);
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+
+        let err = sim
+            .call(0, calls::SEND_REGION_LEASED, [region, mgmt, start, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+        let lease = [region, mgmt, start, lease::DEADLINE, 100, 0];
+        sim.call(0, calls::SEND_REGION_LEASED, lease).unwrap();
+        assert!(can_access(child, start, end));
+        assert_eq!(MockState::get_domain(child).aliases.len(), 1);
+
+        // The first monitor entry after the deadline hands the region back to the sender
+        sim.set_timestamp(99);
+        sim.current(0);
+        assert!(can_access(child, start, end));
+        sim.set_timestamp(100);
+        sim.current(0);
+        assert!(!can_access(child, start, end));
+        assert!(MockState::get_domain(child).aliases.is_empty());
+        assert!(sim.find_region(initial_domain(), start, end).is_some());
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn lease_notification() {
+        let sim = Simulation::new(2);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let holder = child_handle(mgmt);
+        let traps = PermissionIndex::AllowedTraps as usize;
+        sim.call(0, calls::CONFIGURE, [traps, mgmt, lease::TRAP, 0, 0, 0])
+            .unwrap();
+        sim.call(0, calls::CONFIGURE_CORE_MAP, [mgmt, 0, 0b10, 0, 0, 0])
+            .unwrap();
+        let switch = sim
+            .call(0, calls::ALLOC_CORE_CONTEXT, [mgmt, 1, 0, 0, 0, 0])
+            .unwrap()[0];
+        sim.call(0, calls::SEAL_DOMAIN, [mgmt, 0, 0, 0, 0, 0])
+            .unwrap();
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+        let lease = [region, mgmt, start, lease::DEADLINE, 100, 0];
+        sim.call(0, calls::SEND_REGION_LEASED, lease).unwrap();
+        sim.call(1, calls::SWITCH, [switch, 0, 0, 0, 0, 0]).unwrap();
+
+        // Vectors reserved for exceptions are rejected
+        let err = sim
+            .call(1, calls::SET_NOTIFICATION_VECTOR, [31, 0, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+        sim.call(1, calls::SET_NOTIFICATION_VECTOR, [0x40, 0, 0, 0, 0, 0])
+            .unwrap();
+        let polled = sim.call(1, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[0], 0);
+
+        // The lease expires on core 0, the holder is interrupted on core 1
+        sim.set_timestamp(100);
+        sim.current(0);
+        assert!(!can_access(holder, start, end));
+        assert_eq!(MockState::get_domain(holder).interrupts, [0x40]);
+        let polled = sim.call(1, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[..2], [lease::TRAP, start]);
+        let polled = sim.call(1, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[..2], [0, 0]);
+        assert_eq!(MockState::get_domain(holder).interrupts, [0x40]);
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn revocation_notification() {
+        let sim = Simulation::new(2);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let domain = child_handle(mgmt);
+        let traps = PermissionIndex::AllowedTraps as usize;
+        let trap = permission::trap_bits::REGION_REVOKED as usize;
+        sim.call(0, calls::CONFIGURE, [traps, mgmt, trap, 0, 0, 0])
+            .unwrap();
+        sim.call(0, calls::CONFIGURE_CORE_MAP, [mgmt, 0, 0b10, 0, 0, 0])
+            .unwrap();
+        let switch = sim
+            .call(0, calls::ALLOC_CORE_CONTEXT, [mgmt, 1, 0, 0, 0, 0])
+            .unwrap()[0];
+        sim.call(0, calls::SEAL_DOMAIN, [mgmt, 0, 0, 0, 0, 0])
+            .unwrap();
+        let [region, revoke, ..] = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 1, start, end, RW, 0],
+            )
+            .unwrap();
+        sim.call(0, calls::SEND_REGION, [region, mgmt, start, 0, 0, 0])
+            .unwrap();
+        sim.call(1, calls::SWITCH, [switch, 0, 0, 0, 0, 0]).unwrap();
+        sim.call(1, calls::SET_NOTIFICATION_VECTOR, [0x40, 0, 0, 0, 0, 0])
+            .unwrap();
+
+        // Nothing is pending until the region is revoked from under the child
+        let polled = sim.call(1, calls::POLL_REVOCATION, [0; 6]).unwrap();
+        assert_eq!(polled[3], 0);
+        sim.call(0, calls::REVOKE, [revoke, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(MockState::get_domain(domain).interrupts, [0x40]);
+        let polled = sim.call(1, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[..3], [trap, 0, start]);
+        let polled = sim.call(1, calls::POLL_REVOCATION, [0; 6]).unwrap();
+        assert_eq!(polled[1..4], [start, end, 1]);
+        let polled = sim.call(1, calls::POLL_REVOCATION, [0; 6]).unwrap();
+        assert_eq!(polled[3], 0);
+        assert!(MockState::get_domain(domain).traps.is_empty());
+        assert!(!can_access(domain, start, end));
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn channel_messages() {
+        let sim = Simulation::new(3);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let sender_mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let receiver_mgmt = sim.call(0, calls::CREATE_DOMAIN, [0; 6]).unwrap()[0];
+        let sender = child_handle(sender_mgmt);
+        let receiver = child_handle(receiver_mgmt);
+        let interface = PermissionIndex::MonitorInterface as usize;
+        let send = permission::monitor_inter_perm::SEND as usize;
+        sim.call(0, calls::CONFIGURE, [interface, sender_mgmt, send, 0, 0, 0])
+            .unwrap();
+        let traps = PermissionIndex::AllowedTraps as usize;
+        let trap = channel::TRAP;
+        sim.call(0, calls::CONFIGURE, [traps, receiver_mgmt, trap, 0, 0, 0])
+            .unwrap();
+        let mut switches = [0; 2];
+        for (core, mgmt) in [(1, sender_mgmt), (2, receiver_mgmt)] {
+            sim.call(0, calls::CONFIGURE_CORE_MAP, [mgmt, 0, 1 << core, 0, 0, 0])
+                .unwrap();
+            switches[core - 1] = sim
+                .call(0, calls::ALLOC_CORE_CONTEXT, [mgmt, core, 0, 0, 0, 0])
+                .unwrap()[0];
+        }
+
+        // The initial domain hands over a channel to the receiver and a region to the sender,
+        // which become the sender's first two capabilities.
+        let to_receiver = sim
+            .call(0, calls::CREATE_CHANNEL, [receiver_mgmt, 0, 0, 0, 0, 0])
+            .unwrap()[0];
+        sim.call(0, calls::SEND, [to_receiver, sender_mgmt, 0, 0, 0, 0])
+            .unwrap();
+        let region = sim
+            .call(
+                0,
+                calls::SEGMENT_REGION,
+                [root.as_usize(), 0, start, end, RW, 0],
+            )
+            .unwrap()[0];
+        sim.call(0, calls::SEND_REGION, [region, sender_mgmt, start, 0, 0, 0])
+            .unwrap();
+        for mgmt in [sender_mgmt, receiver_mgmt] {
+            sim.call(0, calls::SEAL_DOMAIN, [mgmt, 0, 0, 0, 0, 0])
+                .unwrap();
+        }
+        sim.call(1, calls::SWITCH, [switches[0], 0, 0, 0, 0, 0])
+            .unwrap();
+
+        // The region is transferred right away, the receiver is notified once it runs
+        sim.call(1, calls::CHANNEL_SEND, [0, 1, 1, 2, 3, start])
+            .unwrap();
+        assert!(!can_access(sender, start, end));
+        assert!(can_access(receiver, start, end));
+        sim.call(2, calls::SWITCH, [switches[1], 0, 0, 0, 0, 0])
+            .unwrap();
+        assert!(MockState::get_domain(receiver).interrupts.is_empty());
+        sim.call(2, calls::SET_NOTIFICATION_VECTOR, [0x40, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(MockState::get_domain(receiver).interrupts, [0x40]);
+        let polled = sim.call(2, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[..4], [trap, 0, 0, 1]);
+        let sender_id = CAPA_ENGINE.lock()[sender].id();
+        let received = sim.call(2, calls::CHANNEL_RECEIVE, [0; 6]).unwrap();
+        assert_eq!(received[..2], [1, sender_id]);
+        assert_ne!(received[2], channel::NO_REGION);
+        assert_eq!(received[3..], [1, 2, 3]);
+
+        // A running receiver is notified right away
+        let no_region = channel::NO_REGION;
+        sim.call(1, calls::CHANNEL_SEND, [0, no_region, 4, 5, 6, 0])
+            .unwrap();
+        let received = sim.call(2, calls::CHANNEL_RECEIVE, [0; 6]).unwrap();
+        assert_eq!(received, [1, sender_id, no_region, 4, 5, 6]);
+        assert_eq!(MockState::get_domain(receiver).interrupts, [0x40, 0x40]);
+        let polled = sim.call(2, calls::POLL_NOTIFICATIONS, [0; 6]).unwrap();
+        assert_eq!(polled[..4], [trap, 0, 0, 1]);
+        assert!(MockState::get_domain(receiver).traps.is_empty());
+        let received = sim.call(2, calls::CHANNEL_RECEIVE, [0; 6]).unwrap();
+        assert_eq!(received[0], 0);
+
+        // The transferred region is no longer the sender's
+        let err = sim
+            .call(1, calls::CHANNEL_SEND, [0, 1, 0, 0, 0, start])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::CapabilityDoesNotExist);
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn transaction() {
+        let sim = Simulation::new(2);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + 16 * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let child = transaction::link(0, 0);
+        let mut ops = [
+            operation(calls::CREATE_DOMAIN, 0, [0; 6]),
+            operation(
+                calls::SEGMENT_REGION,
+                0,
+                [root.as_usize(), 0, start, end, RW, 0],
+            ),
+            operation(
+                calls::SEND_REGION,
+                0b11,
+                [transaction::link(1, 0), child, start, 0, 0, 0],
+            ),
+            operation(
+                calls::CONFIGURE,
+                0b10,
+                [PermissionIndex::AllowedCores as usize, child, 0b11, 0, 0, 0],
+            ),
+            operation(calls::ALLOC_CORE_CONTEXT, 0b1, [child, 1, 0, 0, 0, 0]),
+            operation(calls::SEAL_DOMAIN, 0b1, [child, 0, 0, 0, 0, 0]),
+            // Revocations can't be rolled back
+            operation(calls::REVOKE, 0b1, [child, 0, 0, 0, 0, 0]),
+        ];
+
+        // The failed operation rolls back the previous ones
+        let err = submit(&sim, &mut ops).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidOperation);
+        assert_eq!(err.details, 6);
+        assert!(ops.iter().all(|op| op.res == [0; 6]));
+        assert!(sim.find_region(initial_domain(), start, end).is_some());
+        assert!(can_access(initial_domain(), start, end));
+        sim.check_invariants();
+
+        // Links must refer to previous operations
+        ops[1].links = 0b1;
+        ops[1].args[0] = transaction::link(2, 0);
+        let err = submit(&sim, &mut ops[..2]).unwrap_err();
+        assert_eq!((err.code, err.details), (ErrorCode::InvalidValue, 1));
+        ops[1].links = 0;
+        ops[1].args[0] = root.as_usize();
+
+        submit(&sim, &mut ops[..6]).unwrap();
+        let domain = child_handle(ops[0].res[0]);
+        assert_eq!(ops[2].res[0], ops[1].res[0]);
+        assert!(can_access(domain, start, end));
+        assert!(!can_access(initial_domain(), start, start + PAGE_SIZE));
+        assert!(CAPA_ENGINE.lock().is_domain_sealed(domain));
+        sim.call(1, calls::SWITCH, [ops[4].res[0], 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(sim.current(1), domain);
+        sim.check_invariants();
+    }
+
+    #[test]
+    fn transaction_platform_failure() {
+        let sim = Simulation::new(1);
+        let (mem_start, _) = sim.memory();
+        let start = mem_start + 16 * PAGE_SIZE;
+        let end = start + (NB_ALIASES + 1) * PAGE_SIZE;
+        let root = sim.find_region(initial_domain(), start, end).unwrap();
+        let child = transaction::link(0, 0);
+        let mut ops = vec![operation(calls::CREATE_DOMAIN, 0, [0; 6])];
+        for page in 0..=NB_ALIASES {
+            let (region, alias) = (start + page * PAGE_SIZE, page * PAGE_SIZE);
+            let segment = transaction::link(ops.len(), 0);
+            ops.push(operation(
+                calls::SEGMENT_REGION,
+                0,
+                [root.as_usize(), 0, region, region + PAGE_SIZE, RW, 0],
+            ));
+            ops.push(operation(
+                calls::SEND_REGION,
+                0b11,
+                [segment, child, alias, 0, 0, 0],
+            ));
+        }
+
+        // The domain can't map the last region, nothing is applied
+        let err = submit(&sim, &mut ops).unwrap_err();
+        assert_eq!(err.code, ErrorCode::OutOfMemory);
+        assert_eq!(err.details, ops.len() - 1);
+        assert!(ops.iter().all(|op| op.res == [0; 6]));
+        assert!(sim.find_region(initial_domain(), start, end).is_some());
+        assert!(can_access(initial_domain(), start, end));
+        sim.check_invariants();
+
+        let last = ops.len() - 2;
+        submit(&sim, &mut ops[..last]).unwrap();
+        let domain = child_handle(ops[0].res[0]);
+        assert_eq!(MockState::get_domain(domain).aliases.len(), NB_ALIASES);
+        assert!(can_access(domain, start, end - PAGE_SIZE));
+        assert!(can_access(initial_domain(), end - PAGE_SIZE, end));
+        sim.check_invariants();
+    }
+
+    fn operation(call: usize, links: usize, args: [usize; 6]) -> Operation {
+        Operation {
+            call,
+            links,
+            args,
+            res: [0; 6],
+        }
+    }
+
+    /// Submits a transaction from the initial domain, through the start of the simulated memory.
+    fn submit(sim: &Simulation, ops: &mut [Operation]) -> Result<(), Error> {
+        let (mem_start, _) = sim.memory();
+        let buff = mem_start as *mut Operation;
+        unsafe { core::ptr::copy_nonoverlapping(ops.as_ptr(), buff, ops.len()) };
+        let result = sim.call(0, calls::TRANSACTION, [mem_start, ops.len(), 0, 0, 0, 0]);
+        unsafe { core::ptr::copy_nonoverlapping(buff, ops.as_mut_ptr(), ops.len()) };
+        result.map(|_| ())
+    }
+
+    #[test]
+    fn submission_ring() {
+        let sim = Simulation::new(1);
+        let (mem_start, _) = sim.memory();
+        let entries = 4;
+        let ring = ring_at(mem_start, entries);
+        let err = sim.call(0, calls::RING_ENTER, [0; 6]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidOperation);
+        let err = sim
+            .call(0, calls::REGISTER_RING, [mem_start, 3, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+        sim.call(0, calls::REGISTER_RING, [mem_start, entries, 0, 0, 0, 0])
+            .unwrap();
+
+        // Submit more calls than the completion ring can hold
+        let calls = [
+            calls::CREATE_DOMAIN,
+            calls::TEST_CALL,
+            calls::SWITCH,
+            calls::CREATE_DOMAIN,
+        ];
+        for (idx, &call) in calls.iter().enumerate() {
+            ring.submit(Submission {
+                call,
+                user_data: idx,
+                args: [0; 6],
+            });
+        }
+        let processed = sim.call(0, calls::RING_ENTER, [2, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(processed[0], 2);
+        let processed = sim.call(0, calls::RING_ENTER, [0; 6]).unwrap();
+        assert_eq!(processed[0], 2);
+
+        let completions: [Completion; 4] = core::array::from_fn(|_| ring.complete().unwrap());
+        assert!(ring.complete().is_none());
+        assert_eq!(completions.map(|c| c.user_data), [0, 1, 2, 3]);
+        assert_eq!(completions[0].status, status::SUCCESS);
+        assert_eq!(completions[1].status, status::SUCCESS);
+        assert_eq!(completions[2].status, status::FAILURE);
+        assert_eq!(
+            Error::decode(&completions[2].res).unwrap().code,
+            ErrorCode::InvalidOperation
+        );
+        assert_eq!(completions[3].status, status::SUCCESS);
+        assert_ne!(completions[0].res[0], completions[3].res[0]);
+        sim.check_invariants();
+
+        // The ring must remain accessible
+        sim.call(0, calls::REGISTER_RING, [0; 6]).unwrap();
+        let err = sim.call(0, calls::RING_ENTER, [0; 6]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidOperation);
+    }
+
+    /// The domain side of a submission ring in the simulated memory.
+    struct TestRing {
+        header: *mut Header,
+        entries: usize,
+    }
+
+    fn ring_at(addr: usize, entries: usize) -> TestRing {
+        unsafe { core::ptr::write_bytes(addr as *mut u8, 0, ring::size(entries)) };
+        TestRing {
+            header: addr as *mut Header,
+            entries,
+        }
+    }
+
+    impl TestRing {
+        fn submit(&self, submission: Submission) {
+            unsafe {
+                let tail = (*self.header).sq_tail;
+                let submissions = self.header.add(1) as *mut Submission;
+                submissions.add(tail % self.entries).write(submission);
+                (*self.header).sq_tail = tail + 1;
+            }
+        }
+
+        fn complete(&self) -> Option<Completion> {
+            unsafe {
+                let head = (*self.header).cq_head;
+                if head == (*self.header).cq_tail {
+                    return None;
+                }
+                let submissions = self.header.add(1) as *mut Submission;
+                let completions = submissions.add(self.entries) as *mut Completion;
+                (*self.header).cq_head = head + 1;
+                Some(completions.add(head % self.entries).read())
+            }
+        }
+    }
+
+    #[test]
+    fn enclave_attestation() {
+        let sim = Simulation::new(1);
+        // The device signature is the certified data itself, so that the chain can be checked.
+        init_attestation_identity([7; ATTESTATION_SEED_SZ], |data, dest| {
+            dest[..data.len()].copy_from_slice(data);
+            data.len()
+        });
+        let nonce = 0x1234;
+
+        let start = sim
+            .call(
+                0,
+                calls::ENCLAVE_ATTESTATION,
+                [nonce, report::CREATE, 0, 0, 0, 0],
+            )
+            .unwrap();
+        let end = sim
+            .call(
+                0,
+                calls::ENCLAVE_ATTESTATION,
+                [nonce, report::READ, 0, 0, 0, 0],
+            )
+            .unwrap();
+        let bytes = |words: &[usize]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        let key = AttestationPublicKey::from_slice(&bytes(&start[..4])).unwrap();
+        let signature = [bytes(&start[4..]), bytes(&end)].concat();
+        let signature = AttestationSignature::from_slice(&signature).unwrap();
+        let mut data = [0; ATTESTATION_DATA_SZ];
+        CAPA_ENGINE.lock()[initial_domain()]
+            .get_hash()
+            .to_byte_arr(&mut data, 0);
+        data[32..].copy_from_slice(&nonce.to_le_bytes());
+        key.verify(data, &signature).unwrap();
+
+        let (mem_start, _) = sim.memory();
+        let err = sim
+            .call(
+                0,
+                calls::ENCLAVE_ATTESTATION,
+                [0, report::READ_CERTIFICATE, mem_start, 8, 0, 0],
+            )
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::OutOfMemory);
+        let written = sim
+            .call(
+                0,
+                calls::ENCLAVE_ATTESTATION,
+                [0, report::READ_CERTIFICATE, mem_start, PAGE_SIZE, 0, 0],
+            )
+            .unwrap()[0];
+        let certificate = unsafe { core::slice::from_raw_parts(mem_start as *const u8, written) };
+        assert_eq!(certificate, certificate_data(&key));
+
+        let err = sim
+            .call(0, calls::ENCLAVE_ATTESTATION, [nonce, 3, 0, 0, 0, 0])
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidValue);
+    }
+
+    #[test]
+    fn max_cores() {
+        let sim = Simulation::new(NB_CORES.min(4));
+        for core in 0..NB_CORES.min(4) {
+            assert_eq!(sim.current(core), initial_domain());
         }
     }
 }
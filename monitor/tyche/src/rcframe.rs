@@ -1,4 +1,3 @@
-use capa_engine::config::{NB_CORES, NB_DOMAINS};
 use capa_engine::{CapaError, GenArena, Handle};
 use mmu::FrameAllocator;
 use utils::{Frame, HostPhysAddr};
@@ -37,7 +36,7 @@ impl RCFrame {
 // ————————————————————— Create a pool for such objects ————————————————————— //
 
 #[allow(dead_code)]
-pub type RCFramePool = GenArena<RCFrame, { NB_DOMAINS * NB_CORES }>;
+pub type RCFramePool = GenArena<RCFrame>;
 
 #[allow(dead_code)]
 pub fn drop_rc(pool: &mut RCFramePool, v: Handle<RCFrame>) {
@@ -0,0 +1,57 @@
+//! Hardware entropy, used to derive per-boot secrets.
+
+use attestation::signature::ATTESTATION_SEED_SZ;
+use riscv_tyche::{RVManifest, SEED_MAGIC, SEED_SIZE};
+
+const _: () = assert!(SEED_SIZE == ATTESTATION_SEED_SZ);
+
+/// Returns the seed provided by stage 1 in the manifest, if any.
+pub fn manifest_seed(manifest: &RVManifest) -> Option<[u8; ATTESTATION_SEED_SZ]> {
+    (manifest.seed_magic == SEED_MAGIC).then_some(manifest.attestation_seed)
+}
+
+/// Returns a seed from the `seed` CSR of the Zkr extension.
+///
+/// Accessing the CSR traps on harts without Zkr, it is therefore only used when the `zkr` feature
+/// is enabled.
+#[cfg(feature = "zkr")]
+pub fn hardware_seed() -> Option<[u8; ATTESTATION_SEED_SZ]> {
+    use core::arch::asm;
+
+    /// Entropy status, in bits 31:30 of the seed CSR.
+    const OPST_SHIFT: usize = 30;
+    const OPST_ES16: usize = 0b10;
+    const OPST_DEAD: usize = 0b11;
+    /// Number of attempts before giving up.
+    const MAX_RETRIES: usize = 1 << 16;
+
+    let mut seed = [0; ATTESTATION_SEED_SZ];
+    for chunk in seed.chunks_mut(2) {
+        let mut retries = 0;
+        let entropy = loop {
+            let value: usize;
+            // SAFETY: the `zkr` feature guarantees the CSR is implemented, it must be accessed with
+            // a read-write instruction.
+            unsafe { asm!("csrrw {}, 0x015, x0", out(reg) value) };
+            match (value >> OPST_SHIFT) & 0b11 {
+                OPST_ES16 => break value as u16,
+                OPST_DEAD => return None,
+                // BIST or WAIT, try again.
+                _ => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return None;
+                    }
+                    core::hint::spin_loop();
+                }
+            }
+        };
+        chunk.copy_from_slice(&entropy.to_le_bytes());
+    }
+    Some(seed)
+}
+
+#[cfg(not(feature = "zkr"))]
+pub fn hardware_seed() -> Option<[u8; ATTESTATION_SEED_SZ]> {
+    None
+}
@@ -8,7 +8,8 @@ use riscv_utils::{
     NUM_HARTS_AVAILABLE,
 };
 
-use super::{arch, launch_guest};
+use super::{arch, hardware_seed, launch_guest, manifest_seed};
+use crate::attestation_domain::init_attestation_identity;
 use crate::debug::qemu;
 use crate::riscv::cpuid;
 use crate::riscv::platform::MonitorRiscv;
@@ -45,6 +46,12 @@ pub fn arch_entry_point(hartid: usize, manifest: RVManifest, log_level: log::Lev
         arch::init(hartid);
         MonitorRiscv::init();
 
+        // There is no device key on RISC-V yet, the attestation key remains uncertified.
+        match hardware_seed().or_else(|| manifest_seed(&manifest)) {
+            Some(seed) => init_attestation_identity(seed, |_, _| 0),
+            None => log::warn!("No entropy source or manifest seed, attestation is disabled"),
+        }
+
         let mut domain = MonitorRiscv::start_initial_domain_on_cpu();
 
         log::info!("Initial domain is ready.");
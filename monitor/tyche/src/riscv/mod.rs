@@ -5,12 +5,14 @@
 
 mod arch;
 mod context;
+mod entropy;
 mod filtered_fields;
 mod init;
 mod platform;
 mod state;
 use core::arch::asm;
 
+pub use entropy::{hardware_seed, manifest_seed};
 pub use init::arch_entry_point;
 use mmu::FrameAllocator;
 use riscv_csrs::mstatus;
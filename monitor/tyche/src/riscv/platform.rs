@@ -1,10 +1,13 @@
 use core::arch::asm;
 use core::sync::atomic::Ordering;
 
-use capa_engine::utils::BitmapIterator;
+use capa_engine::config::NB_DOMAINS;
+use capa_engine::pool::PoolMemory;
 use capa_engine::{
-    permission, AccessRights, CapaEngine, CapaError, Domain, Handle, MemOps, MEMOPS_ALL,
+    permission, AccessRights, CapaEngine, CapaError, CoreSet, Domain, EngineConfig, Handle, MemOps,
+    MEMOPS_ALL,
 };
+use monitor_abi::status;
 use riscv_csrs::{mcause, *};
 use riscv_pmp::{
     clear_pmp, pmp_write_compute, PMPAddressingMode, PMPErrorCode, PMPWriteResponse,
@@ -20,7 +23,10 @@ use riscv_utils::*;
 use spin::{Mutex, MutexGuard};
 
 use crate::arch::cpuid;
-use crate::monitor::{CoreUpdate, Monitor, PlatformState, CAPA_ENGINE, INITIAL_DOMAIN};
+use crate::error::Error;
+use crate::monitor::{
+    CoreUpdate, Monitor, Notifications, PlatformState, Ring, CAPA_ENGINE, INITIAL_DOMAIN,
+};
 use crate::riscv::context::ContextRiscv;
 use crate::riscv::filtered_fields::RiscVField;
 use crate::riscv::state::{DataRiscv, StateRiscv, CONTEXTS, DOMAINS, MONITOR_IPI_SYNC};
@@ -30,6 +36,9 @@ use crate::riscv::state::{DataRiscv, StateRiscv, CONTEXTS, DOMAINS, MONITOR_IPI_
 const XWR_PERM: usize = 7;
 const EMPTY_ACTIVE_DOMAIN: Mutex<Option<Handle<Domain>>> = Mutex::new(None);
 static ACTIVE_DOMAIN: [Mutex<Option<Handle<Domain>>>; NUM_HARTS] = [EMPTY_ACTIVE_DOMAIN; NUM_HARTS];
+/// Memory for the engine pools, there is no boot configuration on RISC-V yet.
+const POOLS_SIZE: usize = EngineConfig::DEFAULT.memory_size();
+static mut POOLS: [u8; POOLS_SIZE] = [0; POOLS_SIZE];
 
 // ————————————————————————— Platform specific code ————————————————————————— //
 
@@ -499,6 +508,18 @@ impl PlatformState for StateRiscv {
         todo!();
     }
 
+    fn platform_init_pools(
+        &self,
+        config: &EngineConfig,
+        _memory: &mut PoolMemory,
+    ) -> Result<(), CapaError> {
+        // The platform state is static on RISC-V
+        if config.nb_domains > NB_DOMAINS {
+            return Err(CapaError::OutOfMemory);
+        }
+        Ok(())
+    }
+
     fn get_domain(domain: Handle<Domain>) -> MutexGuard<'static, Self::DomainData> {
         DOMAINS[domain.idx()].lock()
     }
@@ -507,31 +528,52 @@ impl PlatformState for StateRiscv {
         CONTEXTS[domain.idx()][core].lock()
     }
 
-    #[cfg(not(feature = "visionfive2"))]
-    fn remap_core(core: usize) -> usize {
-        core
+    fn get_ring(domain: Handle<Domain>) -> Option<Ring> {
+        Self::get_domain(domain).ring
     }
 
-    #[cfg(not(feature = "visionfive2"))]
-    fn remap_core_bitmap(bitmap: u64) -> u64 {
-        bitmap
+    fn set_ring(domain: Handle<Domain>, ring: Option<Ring>) {
+        Self::get_domain(domain).ring = ring;
     }
 
-    #[cfg(feature = "visionfive2")]
+    fn with_notifications<R>(
+        domain: Handle<Domain>,
+        f: impl FnOnce(&mut Option<Notifications>) -> R,
+    ) -> R {
+        f(&mut Self::get_domain(domain).notifications)
+    }
+
+    fn inject_notification(&mut self, _domain: Handle<Domain>, _core: usize, _vector: u8) -> bool {
+        // TODO: raise a supervisor interrupt, the notifications are only polled for now.
+        false
+    }
+
+    #[cfg(not(feature = "visionfive2"))]
     fn remap_core(core: usize) -> usize {
-        (core + 1) //For linux, hart 1 is cpu 0.
+        core
     }
 
     #[cfg(feature = "visionfive2")]
-    fn remap_core_bitmap(bitmap: u64) -> u64 {
-        let new_bitmap: u64 = bitmap << 1;
-        new_bitmap
+    fn remap_core(core: usize) -> usize {
+        (core + 1) //For linux, hart 1 is cpu 0.
     }
 
     fn max_cpus() -> usize {
         NUM_HARTS_AVAILABLE.load(Ordering::SeqCst)
     }
 
+    fn timestamp() -> u64 {
+        let time: u64;
+        unsafe {
+            asm!("rdtime {}", out(reg) time);
+        }
+        time
+    }
+
+    fn check_context(&mut self, _domain: Handle<Domain>, _core: usize) -> Result<(), CapaError> {
+        Ok(())
+    }
+
     fn create_context(
         &mut self,
         _engine: MutexGuard<CapaEngine>,
@@ -648,7 +690,7 @@ impl PlatformState for StateRiscv {
     ) {
         log::debug!("Core Update: {}", update);
         match *update {
-            CoreUpdate::TlbShootdown { src_core } => {
+            CoreUpdate::TlbShootdown { src_core, .. } => {
                 log::debug!("TLB Shootdown on core {} from src {}", core_id, src_core);
                 // Rewrite the PMPs
                 let domain = StateRiscv::get_domain(*current_domain);
@@ -682,7 +724,7 @@ impl PlatformState for StateRiscv {
                     domain,
                 );
 
-                next_ctx.reg_state.a0 = 0x0;
+                next_ctx.reg_state.a0 = status::SUCCESS as isize;
                 next_ctx.reg_state.a1 = return_capa.as_usize() as isize;
                 *current_domain = domain;
             }
@@ -694,6 +736,8 @@ impl PlatformState for StateRiscv {
                 log::debug!("Trap {} on core {}", trap, core_id);
             }
             CoreUpdate::DomainRevocation { .. } => todo!("Not implemented on riscv"),
+            // Pairs are never enabled on riscv, there is nothing to release.
+            CoreUpdate::FastSwitchDisabled { .. } => {}
         }
     }
 
@@ -709,7 +753,7 @@ impl PlatformState for StateRiscv {
         core: usize,
         idx: usize,
         value: usize,
-    ) -> Result<(), CapaError> {
+    ) -> Result<(), Error> {
         let mut ctx = Self::get_context(*domain, core);
         //TODO: we need to unify this with the permission bits.
         //For the moment just allow everything that's within the RiscvField
@@ -729,7 +773,7 @@ impl PlatformState for StateRiscv {
         domain: &Handle<Domain>,
         core: usize,
         idx: usize,
-    ) -> Result<usize, CapaError> {
+    ) -> Result<usize, Error> {
         let ctx = Self::get_context(*domain, core);
         //TODO: same as above, unify the implementation with permissions.
         if !RiscVField::is_valid(idx) {
@@ -788,6 +832,16 @@ impl PlatformState for StateRiscv {
         false
     }
 
+    fn check_mappings(
+        &mut self,
+        _domain: Handle<Domain>,
+        _count: usize,
+        _fresh: bool,
+    ) -> Result<(), CapaError> {
+        // Aliases are not mapped on riscv.
+        Ok(())
+    }
+
     fn map_region(
         &mut self,
         engine: &mut MutexGuard<CapaEngine>,
@@ -816,9 +870,9 @@ impl PlatformState for StateRiscv {
         }
     }
 
-    fn notify_cores(_domain: &Handle<Domain>, core_id: usize, core_map: usize) {
+    fn notify_cores(_domain: &Handle<Domain>, core_id: usize, core_map: CoreSet) {
         let src_hartid = cpuid();
-        for hart in BitmapIterator::new(core_map as u64) {
+        for hart in core_map.iter() {
             if hart != src_hartid {
                 log::debug!("Sending IPI from hart {} to hart {}", src_hartid, hart);
                 aclint_mswi_send_ipi(hart);
@@ -852,6 +906,24 @@ impl PlatformState for StateRiscv {
     ) -> Result<(usize, usize), CapaError> {
         Ok(gpa, size)
     }
+
+    // No equivalent to VMFUNC on RISC-V
+    fn enable_fast_switch(
+        &mut self,
+        _engine: &mut MutexGuard<CapaEngine>,
+        _owner: Handle<Domain>,
+        _peer: Handle<Domain>,
+        _trampoline: usize,
+        _entry: usize,
+        _stack: usize,
+    ) -> Result<(), CapaError> {
+        Err(CapaError::PlatformError)
+    }
+
+    fn fast_switch_abort(&mut self, _current: &mut Handle<Domain>, _core: usize) {
+        // Fast switches are never enabled on RISC-V, there is no pair to abort.
+        unreachable!("No fast switch pair on RISC-V");
+    }
 }
 
 // ————————————————————————— Monitor Implementation ————————————————————————— //
@@ -863,6 +935,11 @@ impl Monitor<StateRiscv> for MonitorRiscv {}
 impl MonitorRiscv {
     pub fn init() {
         let mut engine = CAPA_ENGINE.lock();
+        // SAFETY: the pool memory is used only by the engine, and init is called only once.
+        let mut memory = unsafe { PoolMemory::new(POOLS.as_mut_ptr() as usize, POOLS_SIZE) };
+        engine
+            .init(EngineConfig::DEFAULT, &mut memory)
+            .expect("Not enough memory for the engine pools");
         let domain = engine
             .create_manager_domain(permission::monitor_inter_perm::ALL)
             .unwrap();
@@ -1102,6 +1179,13 @@ impl MonitorRiscv {
             //Default - just print whatever information you can about the trap.
         }
 
+        // Enforce the lease deadlines on every trap.
+        if let Some(mut domain) = Self::get_active_dom(hartid) {
+            let mut state = StateRiscv {};
+            MonitorRiscv::expire_lease_deadlines(&mut state, &mut domain);
+            MonitorRiscv::apply_core_updates(&mut state, &mut domain, hartid);
+        }
+
         log::trace!("Returning from Trap on Hart {}", hartid);
         // Return to the next instruction after the trap.
         // i.e. mepc += 4
@@ -1140,14 +1224,13 @@ impl MonitorRiscv {
         let mut state = StateRiscv {};
         let args: [usize; 6] = [arg_1, arg_2, arg_3, arg_4, arg_5, arg_6];
         let mut res: [usize; 6] = [0; 6];
-        // TODO match the result
         let success =
             Self::do_monitor_call(&mut state, &mut active_dom, tyche_call, &args, &mut res);
         let mut ctx = StateRiscv::get_context(active_dom, hartid);
         match success {
             Ok(true) => {
                 log::debug!("Monitor call success");
-                ctx.reg_state.a0 = 0;
+                ctx.reg_state.a0 = status::SUCCESS as isize;
                 ctx.reg_state.a1 = res[0] as isize;
                 ctx.reg_state.a2 = res[1];
                 ctx.reg_state.a3 = res[2];
@@ -1157,7 +1240,11 @@ impl MonitorRiscv {
             }
             Ok(false) => { /*Nothing to do*/ }
             Err(e) => {
-                panic!("Error in Tyche call {:?}", e);
+                log::error!("Error in Tyche call {:?}: {:?}", tyche_call, e);
+                e.encode(&mut res);
+                ctx.reg_state.a0 = status::FAILURE as isize;
+                ctx.reg_state.a1 = res[0] as isize;
+                ctx.reg_state.a2 = res[1];
             }
         }
         drop(ctx);
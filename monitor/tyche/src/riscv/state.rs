@@ -14,7 +14,7 @@ use riscv_utils::{
 };
 use spin::{Mutex, MutexGuard};
 
-use crate::monitor::{PlatformState, CAPA_ENGINE};
+use crate::monitor::{Notifications, PlatformState, Ring, CAPA_ENGINE};
 use crate::riscv::context::ContextRiscv;
 // ———————————————————————————————— Globals ————————————————————————————————— //
 
@@ -29,6 +29,8 @@ const EMPTY_DOMAIN: Mutex<DataRiscv> = Mutex::new(DataRiscv {
     data_init_done: false,
     pmpaddr: [0; PMP_ENTRIES],
     pmpcfg: [0; PMP_CFG_ENTRIES],
+    ring: None,
+    notifications: None,
 });
 
 const EMPTY_CONTEXT: Mutex<ContextRiscv> = Mutex::new(ContextRiscv {
@@ -49,6 +51,10 @@ pub struct DataRiscv {
     pub data_init_done: bool,
     pub pmpaddr: [usize; PMP_ENTRIES],
     pub pmpcfg: [usize; PMP_CFG_ENTRIES],
+    /// The submission ring registered by the domain, see [Ring].
+    pub ring: Option<Ring>,
+    /// The notifications pending for the domain, see [Notifications].
+    pub notifications: Option<Notifications>,
 }
 
 pub struct StateRiscv {}
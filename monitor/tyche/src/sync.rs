@@ -3,8 +3,12 @@
 // We don't use the new barrier implementation on RISC-V yet, remove this once we do.
 #![allow(dead_code)]
 
+use core::ops::Deref;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use capa_engine::pool::PoolSlice;
+use spin::Once;
+
 pub struct Barrier {
     counter: AtomicUsize,
     unblocked: AtomicUsize,
@@ -72,3 +76,31 @@ impl Barrier {
         self.unblocked.fetch_sub(1, Ordering::SeqCst);
     }
 }
+
+// —————————————————————————————— Static Pool ——————————————————————————————— //
+
+/// A pool of objects allocated at boot time and shared by all cores.
+///
+/// The pool must be initialized once with [StaticPool::init] before being accessed.
+pub struct StaticPool<T> {
+    pool: Once<PoolSlice<T>>,
+}
+
+impl<T> StaticPool<T> {
+    pub const fn new() -> Self {
+        Self { pool: Once::new() }
+    }
+
+    pub fn init(&self, pool: PoolSlice<T>) {
+        assert!(!self.pool.is_completed(), "Pool is already initialized");
+        self.pool.call_once(|| pool);
+    }
+}
+
+impl<T> Deref for StaticPool<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        self.pool.get().expect("Pool is not initialized")
+    }
+}
@@ -818,6 +818,11 @@ pub struct Contextx86 {
     >,
     // State.
     pub interrupted: bool,
+    /// The VPID might still tag the mappings of a previous context, to flush before running.
+    pub stale_vpid: bool,
+    /// Interrupt-window exiting was enabled to signal a notification, see
+    /// [crate::monitor::Notifications].
+    pub notification_window: bool,
     pub sched_info: SchedInfo,
     pub vmcs: Handle<RCFrame>,
     pub nb_active_cpuid_entries: usize,
@@ -879,6 +884,23 @@ impl Contextx86 {
         Ok(self.regs.get(group, idx).unwrap())
     }
 
+    /// Reads a field as the guest will see it on the next entry: the value pending to be flushed
+    /// if any, the one of the vcpu otherwise.
+    pub fn get_next(&mut self, field: VmcsField, vcpu: &ActiveVmcs) -> Result<usize, VmxError> {
+        let (group, idx) = Self::translate_field(field);
+        let pending = match group {
+            RegisterGroup::Reg16 => self.regs.state_16.dirty.is_on(idx),
+            RegisterGroup::Reg32 => self.regs.state_32.dirty.is_on(idx),
+            RegisterGroup::Reg64 => self.regs.state_64.dirty.is_on(idx),
+            RegisterGroup::RegNat => self.regs.state_nat.dirty.is_on(idx),
+            RegisterGroup::RegGp => true,
+        };
+        if pending {
+            return Ok(self.regs.get(group, idx).unwrap());
+        }
+        self.get(field, Some(vcpu))
+    }
+
     /// Read context, write vcpu.
     pub fn flush(&mut self, vcpu: &mut ActiveVmcs) {
         let update = |g: RegisterGroup, idx: usize, value: usize| {
@@ -974,6 +996,8 @@ impl Contextx86 {
     pub fn reset(&mut self) {
         self.regs.reset();
         self.interrupted = false;
+        self.stale_vpid = false;
+        self.notification_window = false;
         self.sched_info.timed = false;
         self.sched_info.saved_ctrls = 0;
         self.sched_info.budget = 0;
@@ -0,0 +1,82 @@
+//! Hardware entropy, used to derive per-boot secrets.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use attestation::signature::ATTESTATION_SEED_SZ;
+
+/// Number of attempts before giving up on RDSEED or RDRAND, as recommended by Intel.
+const MAX_RETRIES: usize = 10;
+
+fn has_rdseed() -> bool {
+    let cpuid = __cpuid_count(0x07, 0);
+    cpuid.ebx & (1 << 18) != 0
+}
+
+fn has_rdrand() -> bool {
+    let cpuid = __cpuid(0x01);
+    cpuid.ecx & (1 << 30) != 0
+}
+
+fn rdseed() -> Option<u64> {
+    for _ in 0..MAX_RETRIES {
+        let value: u64;
+        let success: u8;
+        // SAFETY: we checked that RDSEED is supported.
+        unsafe {
+            asm!(
+                "rdseed {value}",
+                "setc {success}",
+                value = out(reg) value,
+                success = out(reg_byte) success,
+                options(nomem, nostack),
+            );
+        }
+        if success != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+fn rdrand() -> Option<u64> {
+    for _ in 0..MAX_RETRIES {
+        let value: u64;
+        let success: u8;
+        // SAFETY: we checked that RDRAND is supported.
+        unsafe {
+            asm!(
+                "rdrand {value}",
+                "setc {success}",
+                value = out(reg) value,
+                success = out(reg_byte) success,
+                options(nomem, nostack),
+            );
+        }
+        if success != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Returns a seed from the hardware entropy source.
+///
+/// RDSEED is preferred as it provides full entropy, RDRAND is used as a fallback. Returns `None`
+/// if neither is available.
+pub fn hardware_seed() -> Option<[u8; ATTESTATION_SEED_SZ]> {
+    let (rdseed_ok, rdrand_ok) = (has_rdseed(), has_rdrand());
+    let mut seed = [0; ATTESTATION_SEED_SZ];
+    for chunk in seed.chunks_mut(8) {
+        let value = match (rdseed_ok, rdrand_ok) {
+            (true, true) => rdseed().or_else(rdrand),
+            (true, false) => rdseed(),
+            (false, true) => rdrand(),
+            (false, false) => None,
+        }?;
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    Some(seed)
+}
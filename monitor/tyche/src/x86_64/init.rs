@@ -7,8 +7,9 @@ use stage_two_abi::Manifest;
 use vmx::fields::VmcsField;
 pub use vmx::ActiveVmcs;
 
-use super::{arch, cpuid};
+use super::{arch, cpuid, hardware_seed};
 use crate::allocator;
+use crate::attestation_domain::init_attestation_identity;
 use crate::debug::qemu;
 use crate::statics::get_manifest;
 use crate::x86_64::platform::MonitorX86;
@@ -59,6 +60,14 @@ pub fn arch_entry_point(log_level: log::LevelFilter) -> ! {
         let ret = wolftpm_sys::self_test();
         log::info!("wolftpm_sys::self_test: {:?}", ret);
 
+        // Generate the attestation key of this boot and certify it with the TPM.
+        match hardware_seed() {
+            Some(seed) => init_attestation_identity(seed, |data, signature| {
+                wolftpm_sys::hash_and_sign(data, signature) as usize
+            }),
+            None => log::warn!("No hardware entropy source, attestation is disabled"),
+        }
+
         log::info!("Stage 2 initialized");
 
         // Mark the BSP as ready to launch guest on all APs.
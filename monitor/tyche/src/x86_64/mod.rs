@@ -3,6 +3,7 @@
 mod arch;
 mod context;
 mod cpuid_filter;
+mod entropy;
 mod init;
 mod platform;
 mod state;
@@ -10,6 +11,7 @@ mod vmx_helper;
 
 use core::arch::asm;
 
+pub use entropy::hardware_seed;
 pub use init::arch_entry_point;
 pub use vmx::{ActiveVmcs, VmxError as BackendError};
 
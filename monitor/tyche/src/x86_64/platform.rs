@@ -3,16 +3,16 @@
 use core::arch::asm;
 use core::sync::atomic::Ordering;
 
-use capa_engine::config::NB_CAPAS_PER_DOMAIN;
+use capa_engine::config::NB_CORES;
 use capa_engine::context::RegisterGroup;
-use capa_engine::utils::BitmapIterator;
+use capa_engine::pool::{PoolMemory, PoolSlice};
 use capa_engine::{
-    permission, AccessRights, CapaEngine, CapaError, CapaInfo, Domain, Handle, LocalCapa, MemOps, NextCapaToken, Region, MEMOPS_ALL
+    permission, AccessRights, CapaEngine, CapaError, CapaInfo, CoreSet, Domain, EngineConfig, Handle, LocalCapa, MemOps, MgmtRights, NextCapaToken, Region, MEMOPS_ALL
 };
 
 use mmu::eptmapper::EPT_ROOT_FLAGS;
 use mmu::ioptmapper::{ADDRESS_MASK, PAGE_SIZE};
-use mmu::FrameAllocator;
+use mmu::{EptMapper, FrameAllocator};
 use mmu::PtMapper;
 use mmu::PtFlag;
 use mmu::walker::{Level, WalkNext};
@@ -20,27 +20,37 @@ use spin::MutexGuard;
 use stage_two_abi::{GuestInfo, Manifest};
 use utils::HostPhysAddr;
 use utils::{GuestPhysAddr, GuestVirtAddr};
-use vmx::bitmaps::exit_qualification;
+use vmx::bitmaps::{
+    exit_qualification, EntryInterruptionInformationField, EptCapability, PrimaryControls,
+    SecondaryControls, VmFuncControls,
+};
+use vmx::ept::EptpList;
 use vmx::fields::VmcsField;
-use vmx::VmxExitReason;
+use vmx::{InterruptionType, VmxExitReason};
 
 use attestation::hashing::TycheHasher;
 use attestation::hashset::{ArgosHashSet, CAPACITY};
 
 use debug::rdtscp;
+use monitor_abi::fast_switch::SIZE as FAST_SWITCH_SIZE;
+use monitor_abi::status;
 
 use super::context::{ContextGpx86, Contextx86};
 use super::cpuid_filter::{filter_mpk, filter_tpause};
 use super::init::NB_BOOTED_CORES;
-use super::state::{DataX86, StateX86, VmxState, CONTEXTS, DOMAINS, IOMMU, RC_VMCS, TLB_FLUSH};
+use super::state::{
+    context_vpid, DataX86, FastSwitchx86, StateX86, VmxState, CONTEXTS, DOMAINS,
+    EPT_LARGE_PAGES, IOMMU, IOPT_LARGE_PAGES, RC_VMCS, TLB_FLUSH, TLB_INVALIDATIONS,
+};
 use super::vmx_helper::{dump_host_state, load_host_state};
 use super::{cpuid, vmx_helper};
 use crate::allocator::{self, allocator};
-use crate::monitor::{CoreUpdate, Monitor, PlatformState};
+use crate::monitor::{CoreUpdate, Monitor, Notifications, PlatformState, Ring, NOTIFY};
 use crate::rcframe::{drop_rc, RCFrame};
 use crate::x86_64::context::CpuidEntry;
 use crate::x86_64::state::TLB_FLUSH_BARRIERS;
-use crate::{calls, MonitorErrors};
+use crate::calls;
+use crate::error::{Error, ErrorCode};
 
 #[derive(PartialEq, Debug)]
 pub enum HandlerResult {
@@ -49,16 +59,14 @@ pub enum HandlerResult {
     Crash,
 }
 
+/// The interrupt enable flag of RFLAGS.
+const RFLAGS_IF: usize = 1 << 9;
+
 #[cfg(not(feature = "bare_metal"))]
 pub fn remap_core(core: usize) -> usize {
     core
 }
 
-#[cfg(not(feature = "bare_metal"))]
-pub fn remap_core_bitmap(bitmap: u64) -> u64 {
-    bitmap
-}
-
 #[cfg(feature = "bare_metal")]
 pub fn remap_core(core: usize) -> usize {
     // Our harware has hyper-threads, and renames all co-located threads
@@ -69,21 +77,8 @@ pub fn remap_core(core: usize) -> usize {
     }
 }
 
-#[cfg(feature = "bare_metal")]
-pub fn remap_core_bitmap(bitmap: u64) -> u64 {
-    let mut new_bitmap = 0;
-    for idx in 0..16 {
-        if bitmap & (1 << idx) != 0 {
-            new_bitmap |= 1 << remap_core(idx);
-        }
-    }
-
-    new_bitmap
-}
-
 static mut UNIQUE_MEM: ArgosHashSet = ArgosHashSet { data: [None; CAPACITY] };
-const REGION_CAPAS_REPEAT: Option<CapaInfo> = None;
-static mut REGION_CAPAS: [Option<CapaInfo>; NB_CAPAS_PER_DOMAIN] = [REGION_CAPAS_REPEAT; NB_CAPAS_PER_DOMAIN];
+static mut REGION_CAPAS: PoolSlice<Option<CapaInfo>> = PoolSlice::empty();
 static mut LAST_CAPA: Option<&CapaInfo> = None;
 
 impl PlatformState for StateX86 {
@@ -282,9 +277,7 @@ impl PlatformState for StateX86 {
         // Clear the hashset, list of region capabilities, cached capa
         unsafe {
             UNIQUE_MEM.clear();
-            for i in 0..NB_CAPAS_PER_DOMAIN {
-                REGION_CAPAS[i] = None;
-            }
+            REGION_CAPAS.fill(None);
             LAST_CAPA = None;
         };
 
@@ -326,16 +319,24 @@ impl PlatformState for StateX86 {
         let addr = if is_gva {
             let cr3 = self.vcpu.get(VmcsField::GuestCr3).unwrap();
             let mut ptm = PtMapper::new(0, 0, GuestPhysAddr::new(cr3));
-            let gpa = ptm.translate(GuestVirtAddr::new(addr));
-            let Some(gpa) = gpa else {
-                return None;
-            };
-            gpa.as_usize() + (addr & 0xfff)
+            let gpa = ptm.translate(GuestVirtAddr::new(addr))?.as_usize();
+            // The buffer is accessed through its physical address, so the pages it spans must be
+            // contiguous in guest physical memory too.
+            let first_page = addr & !(PAGE_SIZE - 1);
+            let last_page = addr.checked_add(len.max(1) - 1)? & !(PAGE_SIZE - 1);
+            for page in (first_page..=last_page).step_by(PAGE_SIZE).skip(1) {
+                let page_gpa = ptm.translate(GuestVirtAddr::new(page))?.as_usize();
+                if page_gpa != gpa + (page - first_page) {
+                    log::info!("Buffer at gva {:#x} is not physically contiguous", addr);
+                    return None;
+                }
+            }
+            gpa + (addr & (PAGE_SIZE - 1))
         } else {
             addr
         };
 
-        let end = addr + len;
+        let end = addr.checked_add(len)?;
 
         for range in domain.remapper.remap(permission_iter.clone()) {
             let range_start = range.gpa;
@@ -361,6 +362,20 @@ impl PlatformState for StateX86 {
     fn platform_init_io_mmu(&self, addr: usize) {
         let mut iommu = IOMMU.lock();
         iommu.set_addr(addr);
+        let large_pages = iommu.get_capability().large_pages();
+        log::info!("I/O MMU large pages: {:?}", large_pages);
+        IOPT_LARGE_PAGES.store(large_pages.bits(), Ordering::Relaxed);
+    }
+
+    fn platform_init_pools(
+        &self,
+        config: &EngineConfig,
+        memory: &mut PoolMemory,
+    ) -> Result<(), CapaError> {
+        StateX86::init_pools(config, memory)?;
+        // SAFETY: the pools are initialized by the BSP, before other cores run.
+        unsafe { REGION_CAPAS = memory.carve(config.nb_capas_per_domain, |_| None)? };
+        Ok(())
     }
 
     fn get_domain(domain: Handle<Domain>) -> MutexGuard<'static, Self::DomainData> {
@@ -368,21 +383,72 @@ impl PlatformState for StateX86 {
     }
 
     fn get_context(domain: Handle<Domain>, core: usize) -> MutexGuard<'static, Self::Context> {
-        CONTEXTS[domain.idx()][core].lock()
+        assert!(core < NB_CORES, "Invalid core");
+        CONTEXTS[domain.idx() * NB_CORES + core].lock()
     }
 
-    fn remap_core(core: usize) -> usize {
-        return remap_core(core);
+    fn get_ring(domain: Handle<Domain>) -> Option<Ring> {
+        Self::get_domain(domain).ring
     }
 
-    fn remap_core_bitmap(bitmap: u64) -> u64 {
-        return remap_core_bitmap(bitmap);
+    fn set_ring(domain: Handle<Domain>, ring: Option<Ring>) {
+        Self::get_domain(domain).ring = ring;
+    }
+
+    fn with_notifications<R>(
+        domain: Handle<Domain>,
+        f: impl FnOnce(&mut Option<Notifications>) -> R,
+    ) -> R {
+        f(&mut Self::get_domain(domain).notifications)
+    }
+
+    fn inject_notification(&mut self, domain: Handle<Domain>, core: usize, vector: u8) -> bool {
+        let mut context = Self::get_context(domain, core);
+        let vcpu = &self.vcpu;
+        let rflags = context.get_next(VmcsField::GuestRflags, vcpu).unwrap();
+        let blocking = context.get_next(VmcsField::GuestInterruptibilityInfo, vcpu).unwrap();
+        let injected = context.get_next(VmcsField::VmEntryIntrInfoField, vcpu).unwrap();
+        let valid = EntryInterruptionInformationField::VALID.bits() as usize;
+        // The interrupt is blocked by the guest (RFLAGS.IF, STI or MOV SS), or another event is
+        // being injected: exit as soon as the guest can take it.
+        if rflags & RFLAGS_IF == 0 || blocking & 0b11 != 0 || injected & valid != 0 {
+            let ctrls = context.get_next(VmcsField::CpuBasedVmExecControl, vcpu).unwrap();
+            let window = PrimaryControls::INTERRUPT_WINDOW_EXITING.bits() as usize;
+            if ctrls & window == 0 {
+                context.set(VmcsField::CpuBasedVmExecControl, ctrls | window, None).unwrap();
+                context.notification_window = true;
+            }
+            return false;
+        }
+        let external = (InterruptionType::ExternalInterrupt.as_u32() << 8) as usize;
+        let info = vector as usize | external | valid;
+        context.set(VmcsField::VmEntryIntrInfoField, info, None).unwrap();
+        true
+    }
+
+    fn remap_core(core: usize) -> usize {
+        return remap_core(core);
     }
 
     fn max_cpus() -> usize {
         NB_BOOTED_CORES.load(core::sync::atomic::Ordering::SeqCst) + 1
     }
 
+    fn timestamp() -> u64 {
+        rdtscp()
+    }
+
+    fn check_context(&mut self, domain: Handle<Domain>, core: usize) -> Result<(), CapaError> {
+        // The pair relies on the VMCS of the owner.
+        if Self::get_domain(domain)
+            .fast_switch
+            .is_some_and(|pair| pair.core == core)
+        {
+            return Err(CapaError::InvalidOperation);
+        }
+        Ok(())
+    }
+
     fn create_context(
         &mut self,
         _engine: MutexGuard<CapaEngine>,
@@ -390,11 +456,14 @@ impl PlatformState for StateX86 {
         domain: Handle<Domain>,
         core: usize,
     ) -> Result<(), CapaError> {
+        self.check_context(domain, core)?;
         let allocator = allocator();
         let mut rcvmcs = RC_VMCS.lock();
         let dest = &mut Self::get_context(domain, core);
         // Reset all the values inside the dest.
         dest.reset();
+        // The context's core flushes the mappings of the previous one before running it.
+        dest.stale_vpid = true;
         let frame = allocator.allocate_frame().unwrap();
         let rc = RCFrame::new(frame);
         drop_rc(&mut *rcvmcs, dest.vmcs);
@@ -413,9 +482,9 @@ impl PlatformState for StateX86 {
             // Init to the default values.
             let info: GuestInfo = Default::default();
             vmx_helper::default_vmcs_config(&mut self.vcpu, &info, false);
-            let vpid = (domain.idx() + 1) as u16; // VPID 0 is reserved for VMX root execution
+            let vpid = context_vpid(domain, core);
             self.vcpu.set_vpid(vpid).expect("Failled to install VPID");
-            log::trace!("Configured VPID {} on CPU {} for domain {}", vpid, cpuid(), domain.idx());
+            log::trace!("Configured VPID {} on CPU {} for domain {}", vpid, core, domain.idx());
 
             // Load the default values.
             load_host_state(&mut self.vcpu, &mut values).or(Err(CapaError::InvalidValue))?;
@@ -462,12 +531,14 @@ impl PlatformState for StateX86 {
         let vcpu = &mut self.vcpu;
         log::trace!("Core Update: {} on core {}", update, core);
         match update {
-            CoreUpdate::TlbShootdown { src_core: _ } => {
+            CoreUpdate::TlbShootdown { src_core: _, domain } => {
                 // Into a separate function so that we can drop the domain lock before starting to
                 // wait on the TLB_FLUSH_BARRIER
-                self.platform_shootdown(current_domain, core, false);
+                // The domain might be the inactive member of a fast switch pair, in which case the
+                // VMCS belongs to the other one.
+                self.platform_shootdown(domain, core, *domain != *current_domain);
                 log::trace!("core {} waits on tlb flush barrier", core);
-                TLB_FLUSH_BARRIERS[current_domain.idx()].wait();
+                TLB_FLUSH_BARRIERS[domain.idx()].wait();
                 log::trace!("core {} done waiting", core);
             }
             CoreUpdate::Switch {
@@ -526,13 +597,14 @@ impl PlatformState for StateX86 {
                     // Notify that we preemted the domain.
                     // This has to be done after the switch to override the exit
                     // reason.
-                    next_ctx.set(VmcsField::GuestRax, 1, None).unwrap();
                     next_ctx
-                        .set(
-                            VmcsField::GuestR8,
-                            MonitorErrors::DomainRevoked as usize,
-                            None,
-                        )
+                        .set(VmcsField::GuestRax, status::FAILURE, None)
+                        .unwrap();
+                    next_ctx
+                        .set(VmcsField::GuestRdi, ErrorCode::DomainRevoked as usize, None)
+                        .unwrap();
+                    next_ctx
+                        .set(VmcsField::GuestR8, status::DOMAIN_REVOKED, None)
                         .unwrap();
                 }
                 *current_domain = *next;
@@ -540,6 +612,19 @@ impl PlatformState for StateX86 {
                 // Wait for the main thread to finish updating the engine.
                 TLB_FLUSH_BARRIERS[next.idx()].wait();
             }
+            CoreUpdate::FastSwitchDisabled {
+                src_core,
+                owner,
+                peer,
+            } => {
+                if *current_domain == *peer {
+                    self.fast_switch_abort(current_domain, core);
+                }
+                self.release_fast_switch(*current_domain, *owner, core);
+                if *src_core != core {
+                    TLB_FLUSH_BARRIERS[peer.idx()].wait();
+                }
+            }
         }
     }
 
@@ -555,6 +640,12 @@ impl PlatformState for StateX86 {
                 .set(VmcsField::EptPointer, new_epts, Some(&mut self.vcpu))
                 .unwrap();
         }
+        // The EPT might have been updated in place.
+        self.flush_ept(dom.ept.unwrap());
+        // The previous entry EPT is freed once all cores are done.
+        if let Some(entry_ept) = dom.entry_ept_old {
+            self.flush_ept(entry_ept);
+        }
     }
 
     fn set_core(
@@ -564,7 +655,7 @@ impl PlatformState for StateX86 {
         core: usize,
         idx: usize,
         value: usize,
-    ) -> Result<(), CapaError> {
+    ) -> Result<(), Error> {
         let mut ctxt = Self::get_context(*domain, core);
         let field = VmcsField::from_u32(idx as u32).ok_or(CapaError::InvalidValue)?;
         let (group, idx) = Contextx86::translate_field(field);
@@ -573,10 +664,9 @@ impl PlatformState for StateX86 {
         let bitmap = engine.get_domain_permission(*domain, perm_write);
         // Not allowed.
         if engine.is_domain_sealed(*domain) && ((1 << idx) & bitmap == 0) {
-            return Err(CapaError::InsufficientPermissions);
+            return Err(CapaError::InsufficientPermissions.into());
         }
-        ctxt.set(field, value, None)
-            .or(Err(CapaError::PlatformError))
+        Ok(ctxt.set(field, value, None)?)
     }
 
     fn get_core(
@@ -585,7 +675,7 @@ impl PlatformState for StateX86 {
         domain: &Handle<Domain>,
         core: usize,
         idx: usize,
-    ) -> Result<usize, CapaError> {
+    ) -> Result<usize, Error> {
         let mut ctxt = Self::get_context(*domain, core);
         let field = VmcsField::from_u32(idx as u32).ok_or(CapaError::InvalidValue)?;
         let (group, idx) = Contextx86::translate_field(field);
@@ -594,9 +684,9 @@ impl PlatformState for StateX86 {
         let bitmap = engine.get_domain_permission(*domain, perm_read);
         // Not allowed.
         if engine.is_domain_sealed(*domain) && ((1 << idx) & bitmap == 0) {
-            return Err(CapaError::InsufficientPermissions);
+            return Err(CapaError::InsufficientPermissions.into());
         }
-        ctxt.get(field, None).or(Err(CapaError::PlatformError))
+        Ok(ctxt.get(field, None)?)
     }
 
     fn get_core_gp(
@@ -680,6 +770,16 @@ impl PlatformState for StateX86 {
             .overlaps(alias, repeat * (region.end - region.start))
     }
 
+    fn check_mappings(
+        &mut self,
+        domain: Handle<Domain>,
+        count: usize,
+        _fresh: bool,
+    ) -> Result<(), CapaError> {
+        // Each region takes a segment of the remapper, which is kept when the domain is created.
+        Self::get_domain(domain).remapper.has_capacity_for(count)
+    }
+
     fn map_region(
         &mut self,
         engine: &mut MutexGuard<CapaEngine>,
@@ -693,6 +793,7 @@ impl PlatformState for StateX86 {
             .remapper
             .map_range(region.start, alias, region.end - region.start, repeat)
             .unwrap(); // Overlap is checked again but should not be triggered.
+        dom_dat.remapped = true;
         engine.conditional_permission_update(domain);
         Ok(())
     }
@@ -706,6 +807,7 @@ impl PlatformState for StateX86 {
     ) -> Result<(), CapaError> {
         let mut data = Self::get_domain(domain);
         let _ = data.remapper.unmap_gpa_range(alias, size).unwrap();
+        data.remapped = true;
         Ok(())
     }
 
@@ -713,8 +815,8 @@ impl PlatformState for StateX86 {
         TLB_FLUSH_BARRIERS[domain.idx()].set_count(core_count);
     }
 
-    fn notify_cores(_domain: &Handle<Domain>, core_id: usize, core_map: usize) {
-        for core in BitmapIterator::new(core_map as u64) {
+    fn notify_cores(_domain: &Handle<Domain>, core_id: usize, core_map: CoreSet) {
+        for core in core_map.iter() {
             if core == core_id {
                 continue;
             }
@@ -733,6 +835,10 @@ impl PlatformState for StateX86 {
             unsafe { Self::free_ept(ept, allocator) };
         }
         dom.ept_old = None;
+        if let Some(entry_ept) = dom.entry_ept_old.take() {
+            unsafe { Self::free_ept(entry_ept, allocator) };
+        }
+        unsafe { Self::free_garbage(dom.ept_garbage.take(), allocator) };
         TLB_FLUSH[domain.idx()].store(false, Ordering::SeqCst);
     }
 
@@ -765,6 +871,112 @@ impl PlatformState for StateX86 {
         // Not found, we assume the result is Identity mapped.
         Ok((gpa, size))
     }
+
+    fn enable_fast_switch(
+        &mut self,
+        engine: &mut MutexGuard<CapaEngine>,
+        owner: Handle<Domain>,
+        peer: Handle<Domain>,
+        trampoline: usize,
+        entry: usize,
+        stack: usize,
+    ) -> Result<(), CapaError> {
+        let vmfuncs = vmx::available_vmfuncs().or(Err(CapaError::PlatformError))?;
+        if !vmfuncs.contains(VmFuncControls::EPTP_SWITCHING) {
+            log::error!("EPTP switching is not supported");
+            return Err(CapaError::PlatformError);
+        }
+        if trampoline % PAGE_SIZE != 0 {
+            return Err(CapaError::InvalidValue);
+        }
+        let end = trampoline
+            .checked_add(FAST_SWITCH_SIZE)
+            .ok_or(CapaError::InvalidValue)?;
+        for domain in [owner, peer] {
+            if Self::maps_guest_range(domain, engine, trampoline, end) {
+                return Err(CapaError::InvalidValue);
+            }
+        }
+
+        let allocator = allocator();
+        let frame = || {
+            allocator
+                .allocate_frame()
+                .expect("Failed to allocate fast switch frame")
+                .zeroed()
+        };
+        let pair = FastSwitchx86 {
+            owner,
+            peer,
+            core: cpuid(),
+            list: frame(),
+            gpa: trampoline,
+            code: frame(),
+            data: frame(),
+        };
+        // The owner is running, the peer's state is in its context.
+        let owner_cr3 = self
+            .vcpu
+            .get(VmcsField::GuestCr3)
+            .or(Err(CapaError::PlatformError))?;
+        let peer_record = {
+            let mut ctx = Self::get_context(peer, pair.core);
+            [VmcsField::GuestRip, VmcsField::GuestRsp, VmcsField::GuestCr3]
+                .map(|field| ctx.get(field, None).unwrap())
+        };
+        pair.write_trampoline([[entry, stack, owner_cr3], peer_record]);
+        Self::install_fast_switch(pair, engine);
+
+        let mut ctx = Self::get_context(owner, pair.core);
+        let mut ctrls = self
+            .vcpu
+            .get_secondary_ctrls()
+            .or(Err(CapaError::PlatformError))?;
+        ctrls.insert(SecondaryControls::ENABLE_VM_FUNCTIONS);
+        ctx.set(
+            VmcsField::SecondaryVmExecControl,
+            ctrls.bits() as usize,
+            Some(&mut self.vcpu),
+        )
+        .or(Err(CapaError::PlatformError))?;
+        ctx.set(
+            VmcsField::VmFunctionControl,
+            VmFuncControls::EPTP_SWITCHING.bits() as usize,
+            Some(&mut self.vcpu),
+        )
+        .or(Err(CapaError::PlatformError))?;
+        ctx.set(
+            VmcsField::EptpListAddress,
+            EptpList::new(pair.list).get_ptr().as_usize(),
+            Some(&mut self.vcpu),
+        )
+        .or(Err(CapaError::PlatformError))?;
+        Ok(())
+    }
+
+    fn fast_switch_abort(&mut self, current: &mut Handle<Domain>, core: usize) {
+        let (pair, owner_ept) = {
+            let pair = Self::get_domain(*current)
+                .fast_switch
+                .expect("The domain is not part of a pair");
+            (pair, Self::get_domain(pair.owner).ept.unwrap())
+        };
+        let [entry, stack, cr3] = pair.record(pair.owner);
+        // The VMCS is the owner's, move it to the entry point as the trampoline would.
+        let mut ctx = Self::get_context(pair.owner, core);
+        let vcpu = &mut self.vcpu;
+        ctx.set(VmcsField::GuestRip, entry, Some(vcpu)).unwrap();
+        ctx.set(VmcsField::GuestRsp, stack, Some(vcpu)).unwrap();
+        if cr3 != 0 {
+            ctx.set(VmcsField::GuestCr3, cr3, Some(vcpu)).unwrap();
+        }
+        let eptp = owner_ept.as_usize() | EPT_ROOT_FLAGS;
+        ctx.set(VmcsField::EptPointer, eptp, Some(vcpu)).unwrap();
+        ctx.set(VmcsField::GuestRax, status::FAILURE, None).unwrap();
+        ctx.set(VmcsField::GuestRdi, ErrorCode::FastSwitchAborted as usize, None)
+            .unwrap();
+        *current = pair.owner;
+    }
 }
 
 // ————————————————————— Monitor Implementation on X86 —————————————————————— //
@@ -793,6 +1005,11 @@ impl MonitorX86 {
         let vcpu = vmcs.set_as_active().expect("Failed to set VMCS as active");
         let mut state = VmxState { vcpu, vmxon };
         let domain = if bsp {
+            let large_pages = EptMapper::supported_large_pages();
+            log::info!("EPT large pages: {:?}", large_pages);
+            EPT_LARGE_PAGES.store(large_pages.bits(), Ordering::Relaxed);
+            let invalidations = vmx::ept_capabilities().unwrap_or(EptCapability::empty());
+            TLB_INVALIDATIONS.store(invalidations.bits(), Ordering::Relaxed);
             Self::do_init(&mut state, manifest)
         } else {
             Self::start_initial_domain(&mut state)
@@ -813,7 +1030,10 @@ impl MonitorX86 {
         unsafe {
             vmx_helper::init_vcpu(&mut state.vcpu, &manifest.info, &mut ctx);
         }
-        state.vcpu.set_vpid((domain.idx() + 1) as u16).expect("Failed to set VPID");
+        state
+            .vcpu
+            .set_vpid(context_vpid(domain, cpuid()))
+            .expect("Failed to set VPID");
         (state, domain)
     }
 
@@ -933,11 +1153,16 @@ impl MonitorX86 {
         loop {
             let exit_reason = match result {
                 Ok(exit_reason) => {
-                    let res = self
-                        .handle_exit(&mut state, exit_reason, &mut domain)
-                        .expect("Failed to handle VM exit");
+                    // The domain might have switched with VMFUNC since the last exit.
+                    let res = if state.fast_switch_resync(&mut domain, core_id, exit_reason) {
+                        HandlerResult::Resume
+                    } else {
+                        self.handle_exit(&mut state, exit_reason, &mut domain)
+                            .expect("Failed to handle VM exit")
+                    };
 
                     // Apply core-local updates before returning
+                    Self::expire_lease_deadlines(&mut state, &mut domain);
                     Self::apply_core_updates(&mut state, &mut domain, core_id);
 
                     res
@@ -954,6 +1179,10 @@ impl MonitorX86 {
                 HandlerResult::Resume => {
                     result = unsafe {
                         let mut context = StateX86::get_context(domain, core_id);
+                        if context.stale_vpid {
+                            state.flush_vpid(domain, core_id);
+                            context.stale_vpid = false;
+                        }
                         context.flush(&mut state.vcpu);
                         state.vcpu.run(&mut context.regs.state_gp.values)
                     };
@@ -998,8 +1227,8 @@ impl MonitorX86 {
                     calls::EXIT => return Ok(HandlerResult::Exit),
                     calls::SET_CPUID_ENTRY => {
                         let engine = Self::lock_engine(vs, domain);
-                        let target = engine.get_domain_capa(*domain, LocalCapa::new(args[0])).expect("Invalid capa for SET_CPUID_ENTRY");
-                        self.install_cpuid_entry(target, &args)
+                        let target = engine.get_child_domain(*domain, LocalCapa::new(args[0]), MgmtRights::CONFIGURE);
+                        target.map_err(Error::from).and_then(|target| self.install_cpuid_entry(target, &args).map_err(Error::from))
                     }
                     _ => Self::do_monitor_call(vs, domain, vmcall, &args, &mut res)
                 };
@@ -1007,7 +1236,7 @@ impl MonitorX86 {
                 let mut context = StateX86::get_context(*domain, cpuid());
                 match success {
                     Ok(true) => {
-                          context.set(VmcsField::GuestRax, 0, None).unwrap();
+                          context.set(VmcsField::GuestRax, status::SUCCESS, None).unwrap();
                           context.set(VmcsField::GuestRdi, res[0], None).unwrap();
                           context.set(VmcsField::GuestRsi, res[1], None).unwrap();
                           context.set(VmcsField::GuestRdx, res[2], None).unwrap();
@@ -1018,7 +1247,10 @@ impl MonitorX86 {
                     Ok(false) => {},
                     Err(e) => {
                         log::error!("Failure monitor call: {:?}, call: {:?} for dom {} on core {}", e, vmcall, domain.idx(), cpuid());
-                        context.set(VmcsField::GuestRax, 1, None).unwrap();
+                        e.encode(&mut res);
+                        context.set(VmcsField::GuestRax, status::FAILURE, None).unwrap();
+                        context.set(VmcsField::GuestRdi, res[0], None).unwrap();
+                        context.set(VmcsField::GuestRsi, res[1], None).unwrap();
                         log::debug!("The vcpu: {:#x?}", vs.vcpu);
                         drop(context);
                         let callback = |dom: Handle<Domain>, engine: &mut CapaEngine| {
@@ -1184,6 +1416,16 @@ impl MonitorX86 {
                 Ok(HandlerResult::Resume)
             }
         }
+        // Requested to signal a notification, see `inject_notification`.
+        VmxExitReason::InterruptWindow if StateX86::get_context(*domain, cpuid()).notification_window => {
+            let mut context = StateX86::get_context(*domain, cpuid());
+            let ctrls = context.get_next(VmcsField::CpuBasedVmExecControl, &vs.vcpu).or(Err(CapaError::PlatformError))?;
+            let window = PrimaryControls::INTERRUPT_WINDOW_EXITING.bits() as usize;
+            context.set(VmcsField::CpuBasedVmExecControl, ctrls & !window, None).or(Err(CapaError::PlatformError))?;
+            context.notification_window = false;
+            NOTIFY[cpuid()].store(true, Ordering::SeqCst);
+            Ok(HandlerResult::Resume)
+        }
         // Routing exits to the manager domains.
         VmxExitReason::EptViolation
         | VmxExitReason::ExternalInterrupt
@@ -1203,6 +1445,7 @@ impl MonitorX86 {
         | VmxExitReason::VmxPreemptionTimerExpired
         | VmxExitReason::AccessToGdtrOrIdtr
         | VmxExitReason::AccessToLdtrOrTr
+        | VmxExitReason::Vmfunc
         | VmxExitReason::Hlt => {
             log::trace!("Handling {:?} for dom {} on core {}", reason, domain.idx(), cpuid());
             if reason == VmxExitReason::Exception {
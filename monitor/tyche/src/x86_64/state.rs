@@ -1,23 +1,31 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 
-use capa_engine::config::{NB_CORES, NB_DOMAINS, NB_REMAP_REGIONS};
+use capa_engine::config::{NB_CORES, NB_REMAP_REGIONS};
 use capa_engine::context::{RegisterContext, RegisterState};
-use capa_engine::{CapaEngine, CapaError, Domain, GenArena, Handle, LocalCapa, MemOps, Remapper};
+use capa_engine::pool::PoolMemory;
+use capa_engine::{
+    CapaEngine, CapaError, DeltaIterator, Domain, EngineConfig, GenArena, Handle, LocalCapa,
+    MemOps, PermissionDelta, Remapper,
+};
 use mmu::eptmapper::EPT_ROOT_FLAGS;
-use mmu::{EptMapper, FrameAllocator, IoPtFlag, IoPtMapper};
+use mmu::ioptmapper::PAGE_SIZE;
+use mmu::{EptMapper, FrameAllocator, IoPtFlag, IoPtMapper, LargePages};
+use monitor_abi::fast_switch;
 use spin::{Mutex, MutexGuard};
-use utils::{GuestPhysAddr, HostPhysAddr, HostVirtAddr};
-use vmx::bitmaps::{EptEntryFlags, PinbasedControls};
+use utils::{Frame, GuestPhysAddr, HostPhysAddr, HostVirtAddr};
+use vmx::bitmaps::{EptCapability, EptEntryFlags, PinbasedControls, SecondaryControls};
+use vmx::ept::EptpList;
 use vmx::fields::VmcsField;
 use vmx::{ActiveVmcs, VmxExitReason, Vmxon};
-use vtd::Iommu;
+use vtd::{Capability, Iommu};
 
 use super::context::{Contextx86, CpuidEntry, SchedInfo, MAX_CPUID_ENTRIES};
 use super::vmx_helper::{dump_host_state, load_host_state};
 use crate::allocator::allocator;
-use crate::monitor::PlatformState;
+use crate::monitor::{Notifications, PlatformState, Ring};
 use crate::rcframe::{RCFrame, RCFramePool, EMPTY_RCFRAME};
-use crate::sync::Barrier;
+use crate::sync::{Barrier, StaticPool};
 
 /// VMXState encapsulates the vmxon and current vcpu.
 /// The vcpu is subject to changes, but the vmxon remains the same
@@ -27,17 +35,27 @@ pub struct VmxState {
     pub vmxon: Vmxon,
 }
 
-/// Static values
-pub static DOMAINS: [Mutex<DataX86>; NB_DOMAINS] = [EMPTY_DOMAIN; NB_DOMAINS];
-pub static RC_VMCS: Mutex<RCFramePool> =
-    Mutex::new(GenArena::new([EMPTY_RCFRAME; { NB_DOMAINS * NB_CORES }]));
-pub static CONTEXTS: [[Mutex<Contextx86>; NB_CORES]; NB_DOMAINS] =
-    [EMPTY_CONTEXT_ARRAY; NB_DOMAINS];
+/// Static values, the per-domain pools are allocated at boot time (see `StateX86::init_pools`).
+pub static DOMAINS: StaticPool<Mutex<DataX86>> = StaticPool::new();
+pub static RC_VMCS: Mutex<RCFramePool> = Mutex::new(GenArena::empty());
+/// Contexts, indexed by `domain * NB_CORES + core`.
+pub static CONTEXTS: StaticPool<Mutex<Contextx86>> = StaticPool::new();
 pub static IOMMU: Mutex<Iommu> =
     Mutex::new(unsafe { Iommu::new(HostVirtAddr::new(usize::max_value())) });
 pub const FALSE: AtomicBool = AtomicBool::new(false);
-pub static TLB_FLUSH_BARRIERS: [Barrier; NB_DOMAINS] = [Barrier::NEW; NB_DOMAINS];
-pub static TLB_FLUSH: [AtomicBool; NB_DOMAINS] = [FALSE; NB_DOMAINS];
+pub static TLB_FLUSH_BARRIERS: StaticPool<Barrier> = StaticPool::new();
+pub static TLB_FLUSH: StaticPool<AtomicBool> = StaticPool::new();
+/// Large pages supported by the EPTs and I/O page tables, detected at boot.
+pub static EPT_LARGE_PAGES: AtomicU8 = AtomicU8::new(0);
+pub static IOPT_LARGE_PAGES: AtomicU8 = AtomicU8::new(0);
+/// INVEPT and INVVPID types supported by the CPU, detected at boot.
+pub static TLB_INVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the VPID of the context of `domain` on `core`, VPID 0 is reserved for VMX root
+/// execution.
+pub fn context_vpid(domain: Handle<Domain>, core: usize) -> u16 {
+    (domain.idx() * NB_CORES + core + 1) as u16
+}
 
 // —————————————————————————————— Empty values —————————————————————————————— //
 
@@ -50,7 +68,6 @@ const EMPTY_CPUID_ENTRY: CpuidEntry = CpuidEntry {
     ecx: 0,
     edx: 0,
 };
-const EMPTY_CONTEXT_ARRAY: [Mutex<Contextx86>; NB_CORES] = [EMPTY_CONTEXT; NB_CORES];
 const EMPTY_CONTEXT: Mutex<Contextx86> = Mutex::new(Contextx86 {
     regs: RegisterContext {
         dirty: capa_engine::context::Cache { bitmap: 0 },
@@ -61,6 +78,8 @@ const EMPTY_CONTEXT: Mutex<Contextx86> = Mutex::new(Contextx86 {
         state_gp: RegisterState::new(),
     },
     interrupted: false,
+    stale_vpid: false,
+    notification_window: false,
     sched_info: SchedInfo {
         timed: false,
         budget: 0,
@@ -73,21 +92,83 @@ const EMPTY_CONTEXT: Mutex<Contextx86> = Mutex::new(Contextx86 {
 const EMPTY_DOMAIN: Mutex<DataX86> = Mutex::new(DataX86 {
     ept: None,
     ept_old: None,
+    ept_garbage: None,
     iopt: None,
-    remapper: Remapper::new(),
+    remapper: Remapper::empty(),
+    remapped: false,
+    fast_switch: None,
+    entry_ept: None,
+    entry_ept_old: None,
+    ring: None,
+    notifications: None,
 });
 
 /// Domain data on x86
 pub struct DataX86 {
     pub ept: Option<HostPhysAddr>,
     pub ept_old: Option<HostPhysAddr>,
+    /// EPT pages unmapped in place, freed once the TLBs are flushed (see [GarbageFrames]).
+    pub ept_garbage: Option<HostPhysAddr>,
     pub iopt: Option<HostPhysAddr>,
-    pub remapper: Remapper<NB_REMAP_REGIONS>,
+    pub remapper: Remapper,
+    /// Whether the remapper changed since the EPT was built, in which case it must be rebuilt.
+    pub remapped: bool,
+    /// The fast switch pair the domain belongs to, if any.
+    pub fast_switch: Option<FastSwitchx86>,
+    /// The EPT the domain is entered with through the trampoline of its pair, see [FastSwitchx86].
+    pub entry_ept: Option<HostPhysAddr>,
+    pub entry_ept_old: Option<HostPhysAddr>,
+    /// The submission ring registered by the domain, see [Ring].
+    pub ring: Option<Ring>,
+    /// The notifications pending for the domain, see [Notifications].
+    pub notifications: Option<Notifications>,
+}
+
+/// A pair of domains switching with VMFUNC on a core, stored in the data of both domains.
+///
+/// The trampoline is mapped at `gpa` in both EPTs, it shadows the memory of the domains at that
+/// address. Its code page holds a stub per domain (see `monitor_abi::fast_switch`), which switches
+/// to the domain's EPT and jumps to its entry point, as described by the domain's record in the
+/// read-only data page.
+///
+/// VMFUNC can be executed anywhere, so the EPTP list does not point to the EPTs of the domains but
+/// to their entry EPTs, which only allow executing the trampoline. Jumping to the entry point
+/// faults, and the monitor only installs the EPT of the domain if the fault happened at the entry
+/// point with the stack and CR3 of the record (see [StateX86::fast_switch_resync]).
+#[derive(Clone, Copy)]
+pub struct FastSwitchx86 {
+    pub owner: Handle<Domain>,
+    pub peer: Handle<Domain>,
+    pub core: usize,
+    /// The EPTP list, with the owner's EPT at index 0 and the peer's at index 1.
+    pub list: Frame,
+    pub gpa: usize,
+    pub code: Frame,
+    pub data: Frame,
 }
 
 pub type StateX86 = VmxState;
 
 impl StateX86 {
+    /// Allocates the per-domain state for `config.nb_domains` domains.
+    pub fn init_pools(config: &EngineConfig, memory: &mut PoolMemory) -> Result<(), CapaError> {
+        let nb_domains = config.nb_domains;
+        // Each context gets its own VPID.
+        if nb_domains * NB_CORES >= u16::MAX as usize {
+            return Err(CapaError::InvalidValue);
+        }
+        let mut domains = memory.carve(nb_domains, |_| EMPTY_DOMAIN)?;
+        for domain in domains.iter_mut() {
+            domain.get_mut().remapper = Remapper::new(memory, NB_REMAP_REGIONS)?;
+        }
+        DOMAINS.init(domains);
+        CONTEXTS.init(memory.carve(nb_domains * NB_CORES, |_| EMPTY_CONTEXT)?);
+        TLB_FLUSH_BARRIERS.init(memory.carve(nb_domains, |_| Barrier::new())?);
+        TLB_FLUSH.init(memory.carve(nb_domains, |_| FALSE)?);
+        *RC_VMCS.lock() = GenArena::new(memory, nb_domains * NB_CORES, |_| EMPTY_RCFRAME)?;
+        Ok(())
+    }
+
     pub unsafe fn free_ept(ept: HostPhysAddr, allocator: &impl FrameAllocator) {
         let mapper = EptMapper::new(allocator.get_physical_offset().as_usize(), ept);
         mapper.free_all(allocator);
@@ -98,22 +179,68 @@ impl StateX86 {
         mapper.free_all(allocator);
     }
 
+    /// Frees a list of frames built by [GarbageFrames].
+    pub unsafe fn free_garbage(garbage: Option<HostPhysAddr>, allocator: &impl FrameAllocator) {
+        let mut next = garbage;
+        while let Some(frame) = next {
+            next = next_garbage(frame, allocator);
+            allocator
+                .free_frame(frame)
+                .expect("Failed to free EPT page");
+        }
+    }
+
+    /// Invalidates the cached mappings derived from the EPT `ept` on this core.
+    ///
+    /// INVEPT has no individual-address type, guest-physical mappings can at best be invalidated
+    /// for a single EPT. CPUs without single-context INVEPT invalidate all EPTs.
+    pub fn flush_ept(&self, ept: HostPhysAddr) {
+        let eptp = HostPhysAddr::new(ept.as_usize() | EPT_ROOT_FLAGS);
+        let invalidations =
+            EptCapability::from_bits_truncate(TLB_INVALIDATIONS.load(Ordering::Relaxed));
+        if invalidations.contains(EptCapability::SINGLE_CTX_INVEPT) {
+            self.vmxon.invept_single_context(eptp)
+        } else {
+            self.vmxon.invept_all_contexts()
+        }
+        .expect("Failed to invalidate EPT mappings");
+    }
+
+    /// Invalidates the linear mappings cached for the context of `domain` on this core, stale
+    /// since a previous context with the same VPID.
+    pub fn flush_vpid(&self, domain: Handle<Domain>, core: usize) {
+        let vpid = context_vpid(domain, core);
+        let invalidations =
+            EptCapability::from_bits_truncate(TLB_INVALIDATIONS.load(Ordering::Relaxed));
+        if invalidations.contains(EptCapability::SINGLE_CTX_INVVPID) {
+            self.vmxon.invvpid_single_context(vpid)
+        } else {
+            self.vmxon.invvpid_all_contexts()
+        }
+        .expect("Failed to invalidate VPID mappings");
+    }
+
     pub fn update_domain_iopt(
         domain_handle: Handle<Domain>,
         engine: &mut MutexGuard<CapaEngine>,
     ) -> bool {
         let mut domain = Self::get_domain(domain_handle);
-        let allocator = allocator();
-        if let Some(iopt) = domain.iopt {
-            unsafe { Self::free_iopt(iopt, allocator) };
-            // TODO: global invalidate context cache, PASID cache, and flush the IOTLB
+
+        // Apply the permission changes in place if possible, otherwise build new I/O page tables.
+        let deltas = engine.get_domain_permission_deltas(domain_handle).unwrap();
+        if let (Some(iopt), Some(deltas)) = (domain.iopt, deltas) {
+            Self::apply_iopt_deltas(iopt, deltas);
+            engine.acknowledge_permission_deltas(domain_handle).unwrap();
+            return false;
         }
+        engine.acknowledge_permission_deltas(domain_handle).unwrap();
 
+        let allocator = allocator();
         let iopt_root = allocator
             .allocate_frame()
             .expect("Failed to allocate I/O PT root")
             .zeroed();
-        let mut iopt_mapper = IoPtMapper::new(
+        let mut iopt_mapper = iopt_mapper(
             allocator.get_physical_offset().as_usize(),
             iopt_root.phys_addr,
         );
@@ -134,7 +261,7 @@ impl StateX86 {
             )
         }
 
-        domain.iopt = Some(iopt_root.phys_addr);
+        let iopt_old = domain.iopt.replace(iopt_root.phys_addr);
 
         // Update the IOMMU
         // TODO: @yuchen ideally we only need to change the 2nd stage page translation pointer on the
@@ -146,28 +273,154 @@ impl StateX86 {
                 vtd::setup_iommu_context(iopt_mapper.get_root(), allocator);
             iommu.set_root_table_addr(root_addr.as_u64() | (0b00 << 10)); // Set legacy mode
             iommu.update_root_table_addr();
+            iommu.invalidate_context_cache();
+            iommu.invalidate_iotlb_all();
             iommu.enable_translation();
             log::info!("I/O MMU: {:?}", iommu.get_global_status());
             log::warn!("I/O MMU Fault: {:?}", iommu.get_fault_status());
         }
 
+        // The I/O MMU no longer caches translations from the previous page tables.
+        if let Some(iopt) = iopt_old {
+            unsafe { Self::free_iopt(iopt, allocator) };
+        }
+
         false
     }
 
+    /// Applies the permission deltas to the I/O page tables in place, and invalidates the IOTLB
+    /// for the changed ranges. The pages unmapped in place are freed once the I/O MMU no longer
+    /// caches them.
+    fn apply_iopt_deltas(iopt: HostPhysAddr, deltas: DeltaIterator) {
+        let garbage = GarbageFrames::new(allocator(), None);
+        let mut mapper = iopt_mapper(garbage.get_physical_offset().as_usize(), iopt);
+        let mut iommu = IOMMU.lock();
+        let enabled = iommu.get_addr() as usize != 0;
+        // In caching mode the I/O MMU also caches non-present entries, usually when emulated.
+        let caching_mode = enabled && iommu.get_capability().contains(Capability::CACHING_MODE);
+        for delta in deltas {
+            log::trace!("I/O PT delta: {}", delta);
+            let (start, size) = (delta.start(), delta.end() - delta.start());
+            let ops = match delta {
+                PermissionDelta::Added { ops, .. } => ops,
+                PermissionDelta::Removed { .. } => MemOps::NONE,
+                PermissionDelta::Changed { ops, .. } => ops,
+            };
+            // The I/O page tables don't encode the permissions, mappings are only replaced.
+            if ops.contains(MemOps::READ) {
+                mapper.map_range(
+                    &garbage,
+                    GuestPhysAddr::new(start),
+                    HostPhysAddr::new(start),
+                    size,
+                    IoPtFlag::READ | IoPtFlag::WRITE | IoPtFlag::EXECUTE,
+                );
+            } else {
+                if !ops.is_empty() {
+                    log::error!("there is a region without read permission: {}", delta);
+                }
+                if matches!(delta, PermissionDelta::Added { .. }) {
+                    continue;
+                }
+                mapper.unmap_range(&garbage, GuestPhysAddr::new(start), size);
+            }
+            if enabled && (caching_mode || !matches!(delta, PermissionDelta::Added { .. })) {
+                iommu.invalidate_iotlb_range(vtd::DOMAIN_ID, start, size);
+            }
+        }
+        unsafe { Self::free_garbage(garbage.into_garbage(), allocator()) };
+    }
+
     pub fn update_domain_ept(
         domain_handle: Handle<Domain>,
         engine: &mut MutexGuard<CapaEngine>,
     ) -> bool {
         let mut domain = Self::get_domain(domain_handle);
-        let allocator = allocator();
         if domain.ept_old.is_some() {
             panic!("We will replace an ept old that's not empty");
         }
+
+        // Apply the permission changes in place if possible, otherwise build a new EPT.
+        let deltas = engine.get_domain_permission_deltas(domain_handle).unwrap();
+        let flush = match (domain.ept, deltas) {
+            (Some(ept), Some(deltas)) if !domain.remapped => {
+                Some(Self::apply_ept_deltas(&mut domain, ept, deltas))
+            }
+            _ => None,
+        };
+        engine.acknowledge_permission_deltas(domain_handle).unwrap();
+        let ept_root = match flush {
+            // Translations leading to EPT violations are not cached, nothing to flush, unless the
+            // entry EPT is replaced.
+            Some(false) if domain.fast_switch.is_none() => return false,
+            Some(_) => None,
+            None => Some(Self::build_domain_ept(
+                &domain,
+                domain_handle,
+                engine,
+                false,
+            )),
+        };
+
+        loop {
+            match TLB_FLUSH[domain_handle.idx()].compare_exchange(
+                false,
+                true,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(false) => break,
+                _ => continue,
+            }
+        }
+
+        // The core needs exclusive access before updating the domain's EPT. Otherwise, we might have
+        // miss freeing some EPT roots.
+        // The contexts per core will be updated in the permission change update.
+        if let Some(ept_root) = ept_root {
+            domain.ept_old = domain.ept;
+            domain.ept = Some(ept_root);
+            domain.remapped = false;
+        }
+        if domain.fast_switch.is_some() {
+            Self::update_entry_ept(&mut domain, domain_handle, engine);
+        }
+
+        true
+    }
+
+    /// Rebuilds the entry EPT of a domain of a fast switch pair, and points the EPTP list to it.
+    /// The previous one is freed once the TLBs are flushed.
+    fn update_entry_ept(
+        domain: &mut DataX86,
+        domain_handle: Handle<Domain>,
+        engine: &MutexGuard<CapaEngine>,
+    ) {
+        let pair = domain
+            .fast_switch
+            .expect("The domain is not part of a pair");
+        if domain.entry_ept_old.is_some() {
+            panic!("We will replace an entry ept old that's not empty");
+        }
+        let entry_ept = Self::build_domain_ept(domain, domain_handle, engine, true);
+        pair.set_ept(domain_handle, entry_ept);
+        domain.entry_ept_old = domain.entry_ept.replace(entry_ept);
+    }
+
+    /// Builds a new EPT with all the permissions of the domain, returns its root. The entry EPT of
+    /// a fast switch pair only allows executing the trampoline.
+    fn build_domain_ept(
+        domain: &DataX86,
+        domain_handle: Handle<Domain>,
+        engine: &MutexGuard<CapaEngine>,
+        entry: bool,
+    ) -> HostPhysAddr {
+        let allocator = allocator();
         let ept_root = allocator
             .allocate_frame()
             .expect("Failled to allocate EPT root")
             .zeroed();
-        let mut mapper = EptMapper::new(
+        let mut mapper = ept_mapper(
             allocator.get_physical_offset().as_usize(),
             ept_root.phys_addr,
         );
@@ -177,16 +430,9 @@ impl StateX86 {
                 log::error!("there is a region without read permission: {}", range);
                 continue;
             }
-            let mut flags = EptEntryFlags::READ;
-            if range.ops.contains(MemOps::WRITE) {
-                flags |= EptEntryFlags::WRITE;
-            }
-            if range.ops.contains(MemOps::EXEC) {
-                if range.ops.contains(MemOps::SUPER) {
-                    flags |= EptEntryFlags::SUPERVISOR_EXECUTE;
-                } else {
-                    flags |= EptEntryFlags::USER_EXECUTE;
-                }
+            let mut flags = ept_flags(range.ops);
+            if entry {
+                flags.remove(EptEntryFlags::SUPERVISOR_EXECUTE | EptEntryFlags::USER_EXECUTE);
             }
             mapper.map_range(
                 allocator,
@@ -196,25 +442,226 @@ impl StateX86 {
                 flags,
             );
         }
+        if let Some(pair) = domain.fast_switch {
+            pair.map(&mut mapper, allocator);
+        }
+        ept_root.phys_addr
+    }
 
-        loop {
-            match TLB_FLUSH[domain_handle.idx()].compare_exchange(
-                false,
-                true,
-                Ordering::SeqCst,
-                Ordering::SeqCst,
-            ) {
-                Ok(false) => break,
-                _ => continue,
+    /// Applies the permission deltas to the EPT in place, returns whether mappings were removed or
+    /// changed, in which case the TLBs must be flushed.
+    fn apply_ept_deltas(domain: &mut DataX86, ept: HostPhysAddr, deltas: DeltaIterator) -> bool {
+        let allocator = GarbageFrames::new(allocator(), domain.ept_garbage);
+        let offset = allocator.get_physical_offset().as_usize();
+        let mut mapper = ept_mapper(offset, ept);
+        let mut flush = false;
+        for delta in deltas {
+            log::trace!("EPT delta: {}", delta);
+            let ops = match delta {
+                PermissionDelta::Added { ops, .. } => ops,
+                PermissionDelta::Removed { .. } => MemOps::NONE,
+                PermissionDelta::Changed { ops, .. } => ops,
+            };
+            if !matches!(delta, PermissionDelta::Added { .. }) {
+                flush = true;
+                for_each_remapped(
+                    &domain.remapper,
+                    delta.start(),
+                    delta.end(),
+                    |gpa, _, size| mapper.unmap_range(&allocator, GuestPhysAddr::new(gpa), size),
+                );
             }
+            if ops.is_empty() {
+                continue;
+            }
+            if !ops.contains(MemOps::READ) {
+                log::error!("there is a region without read permission: {}", delta);
+                continue;
+            }
+            for_each_remapped(
+                &domain.remapper,
+                delta.start(),
+                delta.end(),
+                |gpa, hpa, size| {
+                    mapper.map_range(
+                        &allocator,
+                        GuestPhysAddr::new(gpa),
+                        HostPhysAddr::new(hpa),
+                        size,
+                        ept_flags(ops),
+                    )
+                },
+            );
+        }
+        // The deltas might have replaced the trampoline.
+        if let Some(pair) = domain.fast_switch {
+            pair.map(&mut mapper, &allocator);
         }
+        domain.ept_garbage = allocator.into_garbage();
+        flush
+    }
 
-        // The core needs exclusive access before updating the domain's EPT. Otherwise, we might have
-        // miss freeing some EPT roots.
-        // The contexts per core will be updated in the permission change update.
-        domain.ept_old = domain.ept;
-        domain.ept = Some(ept_root.phys_addr);
+    /// Maps the trampoline of a new fast switch pair in the EPT of both domains, builds their entry
+    /// EPTs, and points the EPTP list to them.
+    pub fn install_fast_switch(pair: FastSwitchx86, engine: &MutexGuard<CapaEngine>) {
+        let allocator = allocator();
+        let offset = allocator.get_physical_offset().as_usize();
+        for domain_handle in [pair.owner, pair.peer] {
+            let mut domain = Self::get_domain(domain_handle);
+            let ept = domain.ept.expect("Fast switch between domains without EPT");
+            pair.map(&mut ept_mapper(offset, ept), allocator);
+            domain.fast_switch = Some(pair);
+            let entry_ept = Self::build_domain_ept(&domain, domain_handle, engine, true);
+            pair.set_ept(domain_handle, entry_ept);
+            domain.entry_ept = Some(entry_ept);
+        }
+    }
+
+    /// Unmaps the trampoline of a fast switch pair and frees it, along with the EPTP list and the
+    /// entry EPTs. The TLBs of the pair's core must be flushed before the domains run again.
+    pub fn remove_fast_switch(pair: FastSwitchx86) {
+        let allocator = allocator();
+        let offset = allocator.get_physical_offset().as_usize();
+        for domain_handle in [pair.owner, pair.peer] {
+            let mut domain = Self::get_domain(domain_handle);
+            domain.fast_switch = None;
+            // The caller flushed the entry EPT, the previous one (if any) is freed once the
+            // shootdown in progress completes.
+            if let Some(entry_ept) = domain.entry_ept.take() {
+                unsafe { Self::free_ept(entry_ept, allocator) };
+            }
+            if let Some(ept) = domain.ept {
+                let garbage = GarbageFrames::new(allocator, domain.ept_garbage);
+                ept_mapper(offset, ept).unmap_range(
+                    &garbage,
+                    GuestPhysAddr::new(pair.gpa),
+                    fast_switch::SIZE,
+                );
+                domain.ept_garbage = garbage.into_garbage();
+                // The memory shadowed by the trampoline, if any, is mapped back by the next rebuild.
+                domain.remapped = true;
+            }
+        }
+        for frame in [pair.list, pair.code, pair.data] {
+            unsafe { allocator.free_frame(frame.phys_addr) }
+                .expect("Failed to free fast switch frame");
+        }
+    }
+
+    /// Returns whether the domain maps memory in the guest physical range `[start, end)`.
+    pub fn maps_guest_range(
+        domain_handle: Handle<Domain>,
+        engine: &MutexGuard<CapaEngine>,
+        start: usize,
+        end: usize,
+    ) -> bool {
+        let domain = Self::get_domain(domain_handle);
+        let permission_iter = engine.get_domain_permissions(domain_handle).unwrap();
+        domain
+            .remapper
+            .remap(permission_iter)
+            .any(|range| range.gpa < end && start < range.gpa + range.size)
+    }
+
+    /// Releases the fast switch pair of `owner` on the current core, if the platform enabled it.
+    /// The peer must not be running.
+    pub fn release_fast_switch(
+        &mut self,
+        current: Handle<Domain>,
+        owner: Handle<Domain>,
+        core: usize,
+    ) {
+        let Some(pair) = Self::get_domain(owner).fast_switch else {
+            return;
+        };
+        {
+            // The owner's VMCS is only loaded while it runs, the changes are flushed on resume.
+            let mut ctx = Self::get_context(owner, core);
+            let vcpu = (current == owner).then_some(&self.vcpu);
+            let ctrls = ctx.get(VmcsField::SecondaryVmExecControl, vcpu).unwrap();
+            let mut ctrls = SecondaryControls::from_bits_truncate(ctrls as u32);
+            ctrls.remove(SecondaryControls::ENABLE_VM_FUNCTIONS);
+            ctx.set(
+                VmcsField::SecondaryVmExecControl,
+                ctrls.bits() as usize,
+                None,
+            )
+            .unwrap();
+            ctx.set(VmcsField::VmFunctionControl, 0, None).unwrap();
+            ctx.set(VmcsField::EptpListAddress, 0, None).unwrap();
+        }
+        for domain in [pair.owner, pair.peer] {
+            let Some(entry_ept) = Self::get_domain(domain).entry_ept else {
+                continue;
+            };
+            self.flush_ept(entry_ept);
+        }
+        Self::remove_fast_switch(pair);
+        for domain in [pair.owner, pair.peer] {
+            let Some(ept) = Self::get_domain(domain).ept else {
+                continue;
+            };
+            self.flush_ept(ept);
+        }
+    }
+
+    /// Follows the switches of a fast switch pair since the last exit: if the other domain of the
+    /// pair is being entered, its context takes over the exit and it becomes the `domain`.
+    ///
+    /// A domain being entered runs with its entry EPT. If the exit is the fault at the entry point
+    /// of its record, the monitor installs its EPT and the exit is consumed, otherwise the fast
+    /// switch is aborted. Returns whether the exit was consumed.
+    pub fn fast_switch_resync(
+        &mut self,
+        domain: &mut Handle<Domain>,
+        core: usize,
+        reason: VmxExitReason,
+    ) -> bool {
+        let pair = match Self::get_domain(*domain).fast_switch {
+            Some(pair) if pair.core == core => pair,
+            _ => return false,
+        };
+        let eptp = self.vcpu.get(VmcsField::EptPointer).unwrap();
+        let root = Some(HostPhysAddr::new(eptp & !(PAGE_SIZE - 1)));
+        let is_entering = |handle: Handle<Domain>| {
+            // The core might not have moved to the new entry EPT of the domain yet.
+            let data = Self::get_domain(handle);
+            root == data.entry_ept || root == data.entry_ept_old
+        };
+        let other = pair.other(*domain);
+        if is_entering(other) {
+            let ctx = Self::get_context(*domain, core);
+            let mut next = Self::get_context(other, core);
+            next.regs.state_gp.values = ctx.regs.state_gp.values;
+            next.load(&self.vcpu);
+            *domain = other;
+        } else if !is_entering(*domain) {
+            return false;
+        }
 
+        let [entry, stack, cr3] = pair.record(*domain);
+        let vcpu = &self.vcpu;
+        let at_entry = reason == VmxExitReason::EptViolation
+            && vcpu.get(VmcsField::GuestRip).unwrap() == entry
+            && vcpu.get(VmcsField::GuestRsp).unwrap() == stack
+            && (cr3 == 0 || vcpu.get(VmcsField::GuestCr3).unwrap() == cr3);
+        if !at_entry {
+            log::debug!(
+                "Fast switch to dom {} outside of its entry point",
+                domain.idx()
+            );
+            self.fast_switch_abort(domain, core);
+            // Faults on the entry EPT are not faults of the domain.
+            return reason == VmxExitReason::EptViolation;
+        }
+        let ept = Self::get_domain(*domain).ept.unwrap();
+        let mut ctx = Self::get_context(*domain, core);
+        ctx.set(
+            VmcsField::EptPointer,
+            ept.as_usize() | EPT_ROOT_FLAGS,
+            Some(&mut self.vcpu),
+        )
+        .unwrap();
         true
     }
 
@@ -323,3 +770,219 @@ impl StateX86 {
         Ok(())
     }
 }
+
+// ————————————————————————————— Fast Switches —————————————————————————————— //
+
+/// The code entering a domain of a fast switch pair, its displacements are patched to point to the
+/// domain's record (see [FastSwitchx86::write_trampoline]).
+#[rustfmt::skip]
+const STUB: [u8; 40] = [
+    0x48, 0x8b, 0x0d, 0, 0, 0, 0,   // mov rcx, [rip + index]
+    0x31, 0xc0,                     // xor eax, eax
+    0x0f, 0x01, 0xd4,               // vmfunc
+    0x48, 0x8b, 0x05, 0, 0, 0, 0,   // mov rax, [rip + cr3]
+    0x48, 0x85, 0xc0,               // test rax, rax
+    0x74, 0x03,                     // jz 1f
+    0x0f, 0x22, 0xd8,               // mov cr3, rax
+    0x48, 0x8b, 0x25, 0, 0, 0, 0,   // 1: mov rsp, [rip + stack]
+    0xff, 0x25, 0, 0, 0, 0,         // jmp [rip + entry]
+];
+
+/// Offsets of the stub displacements, each ending its instruction, and of the record fields they
+/// point to.
+const STUB_DISPLACEMENTS: [(usize, usize); 4] = [(3, 0), (15, 24), (30, 16), (36, 8)];
+
+/// A record is the EPTP index, entry point, stack, and CR3 (0 to keep the current one) of a domain.
+const RECORD_WORDS: usize = 4;
+
+impl FastSwitchx86 {
+    /// Returns the index of the domain in the EPTP list.
+    pub fn index(&self, domain: Handle<Domain>) -> usize {
+        if domain == self.owner {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Returns the other domain of the pair.
+    pub fn other(&self, domain: Handle<Domain>) -> Handle<Domain> {
+        if domain == self.owner {
+            self.peer
+        } else {
+            self.owner
+        }
+    }
+
+    /// Points the domain's entry of the EPTP list to an EPT.
+    fn set_ept(&self, domain: Handle<Domain>, ept: HostPhysAddr) {
+        let mut list = EptpList::new(self.list);
+        let eptp = HostPhysAddr::new(ept.as_usize() | EPT_ROOT_FLAGS);
+        // SAFETY: the old EPT is only freed once the core flushed its TLBs.
+        unsafe { list.set_entry(self.index(domain), eptp) };
+    }
+
+    /// Writes the trampoline, given the entry point, stack and CR3 of the owner and of the peer.
+    pub fn write_trampoline(&self, records: [[usize; 3]; 2]) {
+        let (mut code, mut data) = (self.code, self.data);
+        for (index, [entry, stack, cr3]) in records.into_iter().enumerate() {
+            let record = &mut data.as_array_page()[index * RECORD_WORDS..][..RECORD_WORDS];
+            record.copy_from_slice(&[index as u64, entry as u64, stack as u64, cr3 as u64]);
+
+            let offset = match index {
+                0 => fast_switch::TO_OWNER,
+                _ => fast_switch::TO_PEER,
+            };
+            let stub = &mut code.as_mut()[offset..][..STUB.len()];
+            stub.copy_from_slice(&STUB);
+            for (displacement, field) in STUB_DISPLACEMENTS {
+                // The data page follows the code page.
+                let target = PAGE_SIZE + index * RECORD_WORDS * 8 + field;
+                let next_instruction = offset + displacement + 4;
+                let relative = (target - next_instruction) as u32;
+                stub[displacement..][..4].copy_from_slice(&relative.to_le_bytes());
+            }
+        }
+    }
+
+    /// Returns the entry point, stack and CR3 of the domain, as written in the trampoline.
+    pub fn record(&self, domain: Handle<Domain>) -> [usize; 3] {
+        let mut data = self.data;
+        let record = &data.as_array_page()[self.index(domain) * RECORD_WORDS..];
+        [record[1] as usize, record[2] as usize, record[3] as usize]
+    }
+
+    /// Maps the trampoline in an EPT.
+    fn map(&self, mapper: &mut EptMapper, allocator: &impl FrameAllocator) {
+        mapper.map_range(
+            allocator,
+            GuestPhysAddr::new(self.gpa),
+            self.code.phys_addr,
+            PAGE_SIZE,
+            EptEntryFlags::READ | EptEntryFlags::SUPERVISOR_EXECUTE | EptEntryFlags::USER_EXECUTE,
+        );
+        mapper.map_range(
+            allocator,
+            GuestPhysAddr::new(self.gpa + PAGE_SIZE),
+            self.data.phys_addr,
+            PAGE_SIZE,
+            EptEntryFlags::READ,
+        );
+    }
+}
+
+// ———————————————————————————————— Helpers ————————————————————————————————— //
+
+/// Returns an EPT mapper using the large pages supported by the processor.
+fn ept_mapper(host_offset: usize, root: HostPhysAddr) -> EptMapper {
+    let large_pages = LargePages::from_bits_truncate(EPT_LARGE_PAGES.load(Ordering::Relaxed));
+    EptMapper::new(host_offset, root).with_large_pages(large_pages)
+}
+
+/// Returns an I/O page table mapper using the large pages supported by the I/O MMU.
+fn iopt_mapper(host_offset: usize, root: HostPhysAddr) -> IoPtMapper {
+    let large_pages = LargePages::from_bits_truncate(IOPT_LARGE_PAGES.load(Ordering::Relaxed));
+    IoPtMapper::new(host_offset, root).with_large_pages(large_pages)
+}
+
+/// Returns the EPT flags for the given memory permissions.
+fn ept_flags(ops: MemOps) -> EptEntryFlags {
+    let mut flags = EptEntryFlags::READ;
+    if ops.contains(MemOps::WRITE) {
+        flags |= EptEntryFlags::WRITE;
+    }
+    if ops.contains(MemOps::EXEC) {
+        if ops.contains(MemOps::SUPER) {
+            flags |= EptEntryFlags::SUPERVISOR_EXECUTE;
+        } else {
+            flags |= EptEntryFlags::USER_EXECUTE;
+        }
+    }
+    flags
+}
+
+/// Calls `f(gpa, hpa, size)` for each part of the host range `[start, end)`, at the guest address
+/// the remapper maps it to. The host memory not covered by a remapper segment is identity mapped.
+fn for_each_remapped(
+    remapper: &Remapper,
+    start: usize,
+    end: usize,
+    mut f: impl FnMut(usize, usize, usize),
+) {
+    let mut cursor = start;
+    for segment in remapper.iter_segments() {
+        let segment_end = segment.hpa + segment.size;
+        if segment.hpa >= end {
+            break;
+        }
+        if segment.hpa > cursor {
+            f(cursor, cursor, segment.hpa - cursor);
+        }
+        let overlap_start = core::cmp::max(start, segment.hpa);
+        let overlap_end = core::cmp::min(end, segment_end);
+        if overlap_start < overlap_end {
+            let gpa = segment.gpa + (overlap_start - segment.hpa);
+            f(gpa, overlap_start, overlap_end - overlap_start);
+        }
+        cursor = core::cmp::max(cursor, segment_end);
+    }
+    if cursor < end {
+        f(cursor, cursor, end - cursor);
+    }
+}
+
+/// A frame allocator that keeps the freed frames in a list instead of freeing them.
+///
+/// Pages unmapped from a live EPT might still be referenced by the paging-structure caches of
+/// other cores, they can only be freed once all the cores flushed their TLBs. The frames are
+/// linked through their first word.
+struct GarbageFrames<'a, A: FrameAllocator> {
+    allocator: &'a A,
+    garbage: Cell<Option<HostPhysAddr>>,
+}
+
+impl<'a, A: FrameAllocator> GarbageFrames<'a, A> {
+    fn new(allocator: &'a A, garbage: Option<HostPhysAddr>) -> Self {
+        Self {
+            allocator,
+            garbage: Cell::new(garbage),
+        }
+    }
+
+    fn into_garbage(self) -> Option<HostPhysAddr> {
+        self.garbage.get()
+    }
+}
+
+/// Returns the frame following `frame` in a list built by [GarbageFrames].
+unsafe fn next_garbage(
+    frame: HostPhysAddr,
+    allocator: &impl FrameAllocator,
+) -> Option<HostPhysAddr> {
+    let link = (frame.as_usize() + allocator.get_physical_offset().as_usize()) as *const u64;
+    match *link {
+        u64::MAX => None,
+        next => Some(HostPhysAddr::new(next as usize)),
+    }
+}
+
+unsafe impl<'a, A: FrameAllocator> FrameAllocator for GarbageFrames<'a, A> {
+    fn allocate_frame(&self) -> Option<Frame> {
+        self.allocator.allocate_frame()
+    }
+
+    unsafe fn free_frame(&self, frame: HostPhysAddr) -> Result<(), ()> {
+        let link = (frame.as_usize() + self.get_physical_offset().as_usize()) as *mut u64;
+        *link = self.garbage.get().map_or(u64::MAX, |next| next.as_u64());
+        self.garbage.set(Some(frame));
+        Ok(())
+    }
+
+    fn get_boundaries(&self) -> (usize, usize) {
+        self.allocator.get_boundaries()
+    }
+
+    fn get_physical_offset(&self) -> HostVirtAddr {
+        self.allocator.get_physical_offset()
+    }
+}